@@ -0,0 +1,266 @@
+// benchmarks of the storage engines, mvcc and the sql layer
+// run with: cargo bench --bench workloads
+// the ycsb workloads run on a preloaded table of RECORDS keys:
+//   a: 50% read, 50% update
+//   b: 95% read, 5% update
+//   c: 100% read
+//   e: short scans of up to 100 keys
+use std::{hint::black_box, path::PathBuf};
+
+use criterion::{
+    criterion_group, criterion_main, measurement::WallTime, BatchSize, BenchmarkGroup, BenchmarkId, Criterion, Throughput,
+};
+use sharkdb::{
+    sql::{
+        engine::{kv::KVEngine, Engine as _, Transaction},
+        parser::Parser,
+        plan::Plan,
+        types::Value,
+    },
+    storage::{
+        disk::{DiskEngine, DiskEngineConfig, Durability},
+        engine::Engine,
+        memory::MemoryEngine,
+        mvcc::Mvcc,
+    },
+};
+
+const RECORDS: u64 = 10_000;
+const VALUE_SIZE: usize = 100;
+
+// xorshift, the benchmarks should not depend on a random crate
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+fn key(i: u64) -> Vec<u8> {
+    format!("user{:010}", i).into_bytes()
+}
+
+fn disk_path() -> PathBuf {
+    tempfile::tempdir().unwrap().into_path().join("sqldb-log")
+}
+
+// never sync, so the numbers measure the engine instead of the disk
+fn disk_engine() -> DiskEngine {
+    DiskEngineConfig::new(disk_path()).durability(Durability::Never).open().unwrap()
+}
+
+fn load(engine: &mut impl Engine) {
+    for i in 0..RECORDS {
+        engine.set(key(i), vec![b'x'; VALUE_SIZE]).unwrap();
+    }
+}
+
+fn ycsb<E: Engine>(c: &mut Criterion, name: &str, mut engine: E) {
+    load(&mut engine);
+    let mut group = c.benchmark_group(format!("ycsb/{}", name));
+    group.throughput(Throughput::Elements(1));
+    for (workload, read_percent) in [("a", 50), ("b", 95), ("c", 100)] {
+        let mut rng = Rng(42);
+        group.bench_function(workload, |b| {
+            b.iter(|| {
+                let k = key(rng.below(RECORDS));
+                if rng.below(100) < read_percent {
+                    black_box(engine.get(k).unwrap());
+                } else {
+                    engine.set(k, vec![b'y'; VALUE_SIZE]).unwrap();
+                }
+            })
+        });
+    }
+    let mut rng = Rng(42);
+    group.bench_function("e", |b| {
+        b.iter(|| {
+            let start = key(rng.below(RECORDS));
+            let len = rng.below(100) as usize + 1;
+            black_box(engine.scan(start..).take(len).count());
+        })
+    });
+    group.finish();
+}
+
+fn bench_ycsb(c: &mut Criterion) {
+    ycsb(c, "memory", MemoryEngine::new());
+    ycsb(c, "disk", disk_engine());
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.throughput(Throughput::Elements(1000));
+    group.sample_size(10);
+    group.bench_function("memory", |b| {
+        b.iter_batched(MemoryEngine::new, |mut engine| {
+            for i in 0..1000 {
+                engine.set(key(i), vec![b'x'; VALUE_SIZE]).unwrap();
+            }
+        }, BatchSize::LargeInput)
+    });
+    group.bench_function("disk", |b| {
+        b.iter_batched(disk_engine, |mut engine| {
+            for i in 0..1000 {
+                engine.set(key(i), vec![b'x'; VALUE_SIZE]).unwrap();
+            }
+        }, BatchSize::LargeInput)
+    });
+    group.bench_function("sql", |b| {
+        b.iter_batched(|| {
+            let engine = KVEngine::new(MemoryEngine::new()).unwrap();
+            engine.session().unwrap().execute("create table t (a int, b text);").unwrap();
+            engine
+        }, |engine| {
+            let mut s = engine.session().unwrap();
+            for i in 0..1000 {
+                s.execute(&format!("insert into t values ({}, 'value');", i)).unwrap();
+            }
+        }, BatchSize::LargeInput)
+    });
+    // one transaction, row by row and as one bulk insert
+    let setup = || {
+        let engine = KVEngine::new(MemoryEngine::new()).unwrap();
+        engine.session().unwrap().execute("create table t (a int, b text);").unwrap();
+        let rows = (0..1000).map(|i| vec![Value::Integer(i), Value::String("value".to_string())]).collect::<Vec<_>>();
+        (engine, rows)
+    };
+    group.bench_function("create_row", |b| {
+        b.iter_batched(setup, |(engine, rows)| {
+            let mut txn = engine.begin().unwrap();
+            let table = txn.must_get_table("t".to_string()).unwrap();
+            for row in rows {
+                txn.create_row(&table, row).unwrap();
+            }
+            txn.commit().unwrap();
+        }, BatchSize::LargeInput)
+    });
+    group.bench_function("bulk_insert", |b| {
+        b.iter_batched(setup, |(engine, rows)| {
+            let mut txn = engine.begin().unwrap();
+            let table = txn.must_get_table("t".to_string()).unwrap();
+            assert!(txn.bulk_insert(&table, rows).unwrap().is_empty());
+            txn.commit().unwrap();
+        }, BatchSize::LargeInput)
+    });
+    group.finish();
+}
+
+// one transaction writing a few keys, commit included
+fn bench_mvcc_commit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mvcc_commit");
+    for writes in [1, 10] {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let mut n = 0;
+        group.bench_with_input(BenchmarkId::new("memory", writes), &writes, |b, &writes| {
+            b.iter(|| {
+                let txn = mvcc.begin().unwrap();
+                for _ in 0..writes {
+                    n += 1;
+                    txn.set(key(n), vec![b'x'; VALUE_SIZE]).unwrap();
+                }
+                txn.commit().unwrap();
+            })
+        });
+        // commits wait for fsync here, the default durability of the disk engine
+        let mvcc = Mvcc::new(DiskEngine::new(disk_path()).unwrap());
+        group.bench_with_input(BenchmarkId::new("disk", writes), &writes, |b, &writes| {
+            b.iter(|| {
+                let txn = mvcc.begin().unwrap();
+                for _ in 0..writes {
+                    n += 1;
+                    txn.set(key(n), vec![b'x'; VALUE_SIZE]).unwrap();
+                }
+                txn.commit().unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+// a transaction scanning all RECORDS keys, each written in 4 versions
+fn bench_mvcc_scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mvcc_scan");
+    group.throughput(Throughput::Elements(RECORDS));
+    group.sample_size(10);
+    let mvcc = Mvcc::new(MemoryEngine::new());
+    for _ in 0..4 {
+        let txn = mvcc.begin().unwrap();
+        for i in 0..RECORDS {
+            txn.set(key(i), vec![b'x'; VALUE_SIZE]).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+    group.bench_function("memory", |b| {
+        b.iter(|| {
+            let txn = mvcc.begin().unwrap();
+            black_box(txn.scan_prefix(b"user".to_vec()).unwrap().len());
+            txn.rollback().unwrap();
+        })
+    });
+    group.finish();
+}
+
+// one key written in many versions, a read or a write of it only reads the newest ones
+fn bench_hot_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hot_key");
+    hot_key(&mut group, "memory", MemoryEngine::new());
+    hot_key(&mut group, "disk", disk_engine());
+    group.finish();
+}
+
+fn hot_key<E: Engine>(group: &mut BenchmarkGroup<WallTime>, name: &str, engine: E) {
+    let mvcc = Mvcc::new(engine);
+    // a long running transaction, writers check every version written after it for conflicts
+    let old = mvcc.begin().unwrap();
+    for _ in 0..RECORDS {
+        let txn = mvcc.begin().unwrap();
+        txn.set(key(0), vec![b'x'; VALUE_SIZE]).unwrap();
+        txn.commit().unwrap();
+    }
+    // an active writer, readers skip its version
+    let writer = mvcc.begin().unwrap();
+    writer.set(key(0), vec![b'x'; VALUE_SIZE]).unwrap();
+    group.bench_function(BenchmarkId::new("get", name), |b| {
+        b.iter(|| {
+            let txn = mvcc.begin().unwrap();
+            black_box(txn.get(key(0)).unwrap());
+            txn.rollback().unwrap();
+        })
+    });
+    writer.rollback().unwrap();
+    group.bench_function(BenchmarkId::new("set", name), |b| {
+        b.iter(|| {
+            let txn = mvcc.begin().unwrap();
+            txn.set(key(0), vec![b'y'; VALUE_SIZE]).unwrap();
+            txn.rollback().unwrap();
+        })
+    });
+    old.rollback().unwrap();
+}
+
+fn bench_parse_plan(c: &mut Criterion) {
+    let sqls = [
+        ("create", "create table t (a int not null, b text default 'x', c float, d boolean);"),
+        ("insert", "insert into t (a, b, c) values (1, 'a', 1.5), (2, 'b', 2.5), (3, 'c', 3.5);"),
+        ("select", "select * from t;"),
+    ];
+    let mut group = c.benchmark_group("parse_plan");
+    let txn = KVEngine::new(MemoryEngine::new()).unwrap().begin().unwrap();
+    for (name, sql) in sqls {
+        group.bench_function(BenchmarkId::new("parse", name), |b| {
+            b.iter(|| black_box(Parser::new(black_box(sql)).parse().unwrap()))
+        });
+        group.bench_function(BenchmarkId::new("plan", name), |b| {
+            b.iter_batched(|| Parser::new(sql).parse().unwrap(), |stmt| black_box(Plan::build(stmt, &txn)), BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_ycsb, bench_insert, bench_mvcc_commit, bench_mvcc_scan, bench_hot_key, bench_parse_plan);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+// random and mutated sql into the parser and a session, see src/sql/fuzz.rs
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| sharkdb::sql::fuzz::fuzz(data));
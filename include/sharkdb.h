@@ -0,0 +1,57 @@
+/* c api of sharkdb, see src/ffi.rs
+ * a panic inside a call returns SHARKDB_ERROR, or the zero value of an accessor */
+#ifndef SHARKDB_H
+#define SHARKDB_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Sharkdb sharkdb;
+typedef struct SharkdbStmt sharkdb_stmt;
+
+#define SHARKDB_OK 0
+#define SHARKDB_ERROR 1
+#define SHARKDB_MISUSE 21
+#define SHARKDB_ROW 100
+#define SHARKDB_DONE 101
+
+#define SHARKDB_INTEGER 1
+#define SHARKDB_FLOAT 2
+#define SHARKDB_TEXT 3
+#define SHARKDB_BOOLEAN 4
+#define SHARKDB_NULL 5
+
+/* path NULL opens an in memory database, *db is NULL if opening fails */
+int sharkdb_open(const char *path, sharkdb **db);
+int sharkdb_close(sharkdb *db);
+const char *sharkdb_errmsg(sharkdb *db);
+/* rows inserted or selected by the last statement */
+int64_t sharkdb_changes(sharkdb *db);
+
+/* run a statement and drop its rows */
+int sharkdb_exec(sharkdb *db, const char *sql);
+
+/* run a statement, then step through its rows */
+int sharkdb_prepare(sharkdb *db, const char *sql, sharkdb_stmt **stmt);
+int sharkdb_step(sharkdb_stmt *stmt);
+int sharkdb_finalize(sharkdb_stmt *stmt);
+
+int sharkdb_column_count(sharkdb_stmt *stmt);
+const char *sharkdb_column_name(sharkdb_stmt *stmt, int i);
+int sharkdb_column_type(sharkdb_stmt *stmt, int i);
+int64_t sharkdb_column_int64(sharkdb_stmt *stmt, int i);
+double sharkdb_column_double(sharkdb_stmt *stmt, int i);
+int sharkdb_column_bool(sharkdb_stmt *stmt, int i);
+/* valid until the next step or finalize, NULL for a null value */
+const char *sharkdb_column_text(sharkdb_stmt *stmt, int i);
+/* the value as json text, "null" for a null value, valid until the next step or finalize */
+const char *sharkdb_column_json(sharkdb_stmt *stmt, int i);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
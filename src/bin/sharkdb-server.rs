@@ -0,0 +1,128 @@
+use std::{net::TcpListener, path::PathBuf};
+
+use sharkdb::{
+    config::Config,
+    error::{Error, Result},
+    server::{mysql::MysqlServer, tls, Server},
+    sql::engine::Engine,
+};
+
+// usage: sharkdb-server [--config sharkdb.toml] [listen address] [data file] [mysql listen address]
+// the settings are those of the config file, see sharkdb::config, the arguments and the variables below override them
+// the mysql protocol is only served when its address is given
+// set SHARKDB_TLS_CERT and SHARKDB_TLS_KEY to pem files to accept tls connections only
+// SHARKDB_MAX_SESSIONS limits open sessions, SHARKDB_IDLE_TIMEOUT closes idle connections after some seconds
+// queries are logged to stdout, SHARKDB_SLOW_QUERY_MS logs the plan of queries slower than that
+// SHARKDB_VACUUM_INTERVAL deletes the expired rows of tables with ttl every some seconds
+// SHARKDB_QUERY_MEMORY_LIMIT fails queries holding more bytes of rows than that, like big sorts
+// SHARKDB_CONFLICT_POLICY=first-committer lets transactions writing the same key run on, the later commit fails
+// SIGTERM and SIGINT stop it, open transactions are rolled back and the data file is flushed and unlocked
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let mut config = match args.next_if(|arg| arg == "--config") {
+        Some(_) => Config::load(args.next().ok_or(Error::Config("--config needs a file".to_string()))?.as_ref())?,
+        None => Config::default(),
+    };
+    if let Some(addr) = args.next() {
+        config.server.listen = addr;
+    }
+    let data_file = args.next().map(PathBuf::from).unwrap_or(config.data_file());
+    if let Some(mysql_addr) = args.next() {
+        config.server.mysql_listen = Some(mysql_addr);
+    }
+    match (std::env::var_os("SHARKDB_TLS_CERT"), std::env::var_os("SHARKDB_TLS_KEY")) {
+        (Some(cert), Some(key)) => {
+            config.auth.tls_cert = Some(cert.into());
+            config.auth.tls_key = Some(key.into());
+        }
+        (None, None) => {}
+        _ => return Err(Error::Config("SHARKDB_TLS_CERT and SHARKDB_TLS_KEY must be set together".to_string())),
+    }
+    if let Some(n) = env_number("SHARKDB_MAX_SESSIONS")? {
+        config.server.max_sessions = Some(n as usize);
+    }
+    if let Some(n) = env_number("SHARKDB_IDLE_TIMEOUT")? {
+        config.server.idle_timeout = Some(n);
+    }
+    if let Some(n) = env_number("SHARKDB_SLOW_QUERY_MS")? {
+        config.server.slow_query_ms = Some(n);
+    }
+    if let Some(n) = env_number("SHARKDB_VACUUM_INTERVAL")? {
+        config.storage.vacuum_interval = Some(n);
+    }
+    if let Some(n) = env_number("SHARKDB_QUERY_MEMORY_LIMIT")? {
+        config.storage.query_memory_limit = Some(n as usize);
+    }
+    if let Ok(policy) = std::env::var("SHARKDB_CONFLICT_POLICY") {
+        config.storage.conflict_policy = policy;
+    }
+    config.validate()?;
+
+    tracing_subscriber::fmt().with_max_level(config.log_level()?).init();
+    let tls = match (&config.auth.tls_cert, &config.auth.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::server_config(cert, key)?),
+        _ => None,
+    };
+    let idle_timeout = config.idle_timeout();
+    let engine = config.open_file(&data_file)?;
+    if let Some(interval) = config.vacuum_interval() {
+        let engine = engine.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            match engine.vacuum() {
+                Ok(count) => tracing::info!(target: "sharkdb::vacuum", count, "expired rows deleted"),
+                Err(err) => tracing::warn!(target: "sharkdb::vacuum", %err, "vacuum failed"),
+            }
+        });
+    }
+    #[cfg(unix)]
+    {
+        let engine = engine.clone();
+        let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT])?;
+        std::thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                tracing::info!(signal, "shutting down");
+                let code = match engine.close() {
+                    Ok(versions) => {
+                        tracing::info!(rolled_back = versions.len(), "data file closed");
+                        0
+                    }
+                    Err(err) => {
+                        tracing::error!(%err, "close of the data file failed");
+                        1
+                    }
+                };
+                std::process::exit(code);
+            }
+        });
+    }
+    if let Some(mysql_addr) = config.server.mysql_listen {
+        let listener = TcpListener::bind(&mysql_addr)?;
+        println!("sharkdb mysql protocol listening on {}", mysql_addr);
+        let mut server = MysqlServer::new(engine.clone());
+        if let Some(config) = tls.clone() {
+            server = server.with_tls(config);
+        }
+        if let Some(timeout) = idle_timeout {
+            server = server.with_idle_timeout(timeout);
+        }
+        std::thread::spawn(move || server.serve(listener));
+    }
+    let listener = TcpListener::bind(&config.server.listen)?;
+    println!("sharkdb listening on {}, data file {}, tls {}", config.server.listen, data_file.display(), tls.is_some());
+    let mut server = Server::new(engine);
+    if let Some(config) = tls {
+        server = server.with_tls(config);
+    }
+    if let Some(timeout) = idle_timeout {
+        server = server.with_idle_timeout(timeout);
+    }
+    server.serve(listener)
+}
+
+fn env_number(name: &str) -> Result<Option<u64>> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value.parse().map_err(|_| Error::Config(format!("{} must be a number", name)))?)),
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use sharkdb::{
+    error::{Error, Result},
+    export::{self, codegen::codegen, json::write_ndjson},
+    import::{
+        csv::{import_csv, CsvOptions},
+        generate::generate,
+        json::{import_ndjson, JsonOptions},
+        ImportOptions, ImportReport,
+    },
+    migrate::Migrator,
+    sql::engine::kv::KVEngine,
+    storage::disk::DiskEngine,
+};
+
+const USAGE: &str = "usage:
+  sharkdb import <data file> <table> <input file> [--format csv|ndjson] [--batch-size N] [--bad-rows PATH]
+                 [--delimiter C] [--no-header] [--null TEXT] [--coerce] [--ignore-unknown]
+  sharkdb export <data file> <select statement | table> [--format ndjson|parquet] [--output PATH]
+  sharkdb gen <data file> <table> <rows> [--seed N] [--batch-size N]
+  sharkdb codegen <data file> [table ...] [--output PATH]
+  sharkdb backfill <data file> <table> [--batch-size N]";
+
+// offline tools working on a data file directly, the server must not be running on it
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("import") => import(&args[1..]),
+        Some("export") => export(&args[1..]),
+        Some("gen") => gen(&args[1..]),
+        Some("codegen") => codegen_structs(&args[1..]),
+        Some("backfill") => backfill(&args[1..]),
+        _ => Err(Error::Config(USAGE.to_string())),
+    }
+}
+
+// the format follows the file extension unless --format is given
+// .ndjson and .jsonl files are json, .tsv files are tab separated
+fn import(args: &[String]) -> Result<()> {
+    let mut positional = Vec::new();
+    let mut options = ImportOptions::default();
+    let mut csv = CsvOptions::default();
+    let mut json = JsonOptions::default();
+    let mut format = None;
+    let mut delimiter = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(Error::Config(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "--format" => format = Some(value()?.clone()),
+            "--batch-size" => {
+                options.batch_size = value()?.parse().map_err(|_| Error::Config("--batch-size must be a number".to_string()))?
+            }
+            "--delimiter" => {
+                delimiter = match value()?.as_str() {
+                    "\\t" | "tab" => Some(b'\t'),
+                    v if v.len() == 1 => Some(v.as_bytes()[0]),
+                    _ => return Err(Error::Config("--delimiter must be one byte".to_string())),
+                }
+            }
+            "--no-header" => csv.has_header = false,
+            "--null" => csv.null_value = value()?.clone(),
+            "--coerce" => json.coerce = true,
+            "--ignore-unknown" => json.ignore_unknown = true,
+            "--bad-rows" => options.bad_rows = Some(PathBuf::from(value()?)),
+            _ => positional.push(arg),
+        }
+    }
+    let [data_file, table, input] = positional[..] else {
+        return Err(Error::Config(USAGE.to_string()));
+    };
+    let format = format.unwrap_or(match input.rsplit('.').next() {
+        Some("ndjson" | "jsonl" | "json") => "ndjson".to_string(),
+        _ => "csv".to_string(),
+    });
+    csv.delimiter = delimiter.unwrap_or(if input.ends_with(".tsv") { b'\t' } else { b',' });
+
+    let engine = KVEngine::new(DiskEngine::new(PathBuf::from(data_file))?)?;
+    let reader = BufReader::new(File::open(input)?);
+    let start = Instant::now();
+    let report = match format.as_str() {
+        "csv" => import_csv(&engine, table, reader, &csv, &options, progress)?,
+        "ndjson" => import_ndjson(&engine, table, reader, &json, &options, progress)?,
+        _ => return Err(Error::Config(format!("unknown format {}", format))),
+    };
+    eprintln!();
+    println!(
+        "imported {} rows into {} in {:.1}s, rejected {}",
+        report.imported,
+        table,
+        start.elapsed().as_secs_f64(),
+        report.rejected
+    );
+    Ok(())
+}
+
+// random rows into an existing table, the same seed gives the same rows
+fn gen(args: &[String]) -> Result<()> {
+    let mut positional = Vec::new();
+    let mut options = ImportOptions::default();
+    let mut seed = 0;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(Error::Config(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "--seed" => seed = value()?.parse().map_err(|_| Error::Config("--seed must be a number".to_string()))?,
+            "--batch-size" => {
+                options.batch_size = value()?.parse().map_err(|_| Error::Config("--batch-size must be a number".to_string()))?
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let [data_file, table, n] = positional[..] else {
+        return Err(Error::Config(USAGE.to_string()));
+    };
+    let n = n.parse().map_err(|_| Error::Config("rows must be a number".to_string()))?;
+
+    let engine = KVEngine::new(DiskEngine::new(PathBuf::from(data_file))?)?;
+    let start = Instant::now();
+    let report = generate(&engine, table, n, seed, &options, progress)?;
+    eprintln!();
+    println!("generated {} rows into {} in {:.1}s", report.imported, table, start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+// rust structs of the tables, all of them unless some are named, to stdout unless --output is given
+fn codegen_structs(args: &[String]) -> Result<()> {
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(args.next().ok_or(Error::Config(format!("{} needs a value", arg)))?),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    let Some((data_file, tables)) = positional.split_first() else {
+        return Err(Error::Config(USAGE.to_string()));
+    };
+    let engine = KVEngine::new(DiskEngine::new(PathBuf::from(data_file))?)?;
+    let code = codegen(&engine, tables)?;
+    match output {
+        Some(path) => std::fs::write(path, code)?,
+        None => print!("{}", code),
+    }
+    Ok(())
+}
+
+// write the defaults of columns added by ALTER TABLE into the rows stored before them
+fn backfill(args: &[String]) -> Result<()> {
+    let mut positional = Vec::new();
+    let mut batch_size = 1000;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--batch-size" => {
+                batch_size = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or(Error::Config("--batch-size must be a number".to_string()))?
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let [data_file, table] = positional[..] else {
+        return Err(Error::Config(USAGE.to_string()));
+    };
+    let engine = KVEngine::new(DiskEngine::new(PathBuf::from(data_file))?)?;
+    let start = Instant::now();
+    let report = Migrator::new(engine).backfill(table, batch_size, |r| {
+        eprint!("\rscanned {} rows, rewritten {}, {} batches", r.scanned, r.rewritten, r.batches);
+    })?;
+    eprintln!();
+    println!(
+        "rewrote {} of {} rows of {} in {:.1}s",
+        report.rewritten,
+        report.scanned,
+        table,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+fn progress(r: &ImportReport) {
+    eprint!("\rimported {} rows, rejected {}, {} batches", r.imported, r.rejected, r.batches);
+}
+
+// query results or a whole table, ndjson goes to stdout unless --output is given
+// parquet needs the parquet feature and an output file
+fn export(args: &[String]) -> Result<()> {
+    let mut positional = Vec::new();
+    let mut format = "ndjson".to_string();
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(Error::Config(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "--format" => format = value()?.clone(),
+            "--output" => output = Some(value()?.clone()),
+            _ => positional.push(arg),
+        }
+    }
+    let [data_file, source] = positional[..] else {
+        return Err(Error::Config(USAGE.to_string()));
+    };
+    let engine = KVEngine::new(DiskEngine::new(PathBuf::from(data_file))?)?;
+    let sql = if source.to_lowercase().starts_with("select") {
+        if source.trim_end().ends_with(';') { source.clone() } else { format!("{};", source) }
+    } else {
+        format!("select * from {};", source)
+    };
+    let n = match format.as_str() {
+        "ndjson" => {
+            let (columns, rows) = export::query(&engine, &sql)?;
+            let mut w: Box<dyn Write> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(BufWriter::new(std::io::stdout().lock())),
+            };
+            write_ndjson(&mut w, &columns, &rows)?
+        }
+        #[cfg(feature = "parquet")]
+        "parquet" => {
+            let output = output.ok_or(Error::Config("--output is required for parquet".to_string()))?;
+            let w = BufWriter::new(File::create(output)?);
+            if source.to_lowercase().starts_with("select") {
+                let (columns, rows) = export::query(&engine, &sql)?;
+                export::parquet::write_parquet(w, &columns, &rows)?
+            } else {
+                export::parquet::export_table(&engine, source, w)?
+            }
+        }
+        _ => return Err(Error::Config(format!("unknown format {}", format))),
+    };
+    eprintln!("exported {} rows", n);
+    Ok(())
+}
@@ -0,0 +1,347 @@
+// settings of a database and its server, from a toml file
+// every key is optional, a file with only some of them keeps the defaults of the rest
+//
+//   data_dir = "sharkdb-data"          # the log is data_dir/sqldb-log
+//   log_level = "info"                 # error, warn, info, debug or trace
+//
+//   [storage]
+//   durability = "on-commit"           # always, on-commit, never, or every-100ms
+//   cache_size = 8388608               # bytes of hot values kept in memory, 0 for none
+//   compression = "none"               # none or lz4
+//   mmap = false
+//   conflict_policy = "first-writer"   # or first-committer
+//   query_memory_limit = 268435456     # bytes of rows a query may hold
+//   vacuum_interval = 3600             # seconds between deletes of expired rows
+//
+//   [server]
+//   listen = "127.0.0.1:9605"
+//   mysql_listen = "127.0.0.1:3306"    # the mysql protocol is only served when it is set
+//   max_sessions = 100
+//   idle_timeout = 600                 # seconds
+//   slow_query_ms = 500
+//
+//   [auth]
+//   tls_cert = "cert.pem"              # with tls_key, connections must use tls
+//   tls_key = "key.pem"
+//   require_users = true               # refuse to start while anyone may log in, see Engine::authenticate
+//
+// unknown keys are errors, so a misspelled one is not silently ignored
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    sql::engine::{kv::KVEngine, Engine, Transaction},
+    storage::{
+        disk::{Compression, DiskEngine, DiskEngineConfig, Durability},
+        mvcc::ConflictPolicy,
+    },
+};
+
+// the name of the log in the data directory
+pub const DATA_FILE_NAME: &str = "sqldb-log";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    pub log_level: String,
+    pub storage: StorageConfig,
+    pub server: ServerConfig,
+    pub auth: AuthConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub durability: String,
+    pub cache_size: usize,
+    pub compression: String,
+    pub mmap: bool,
+    pub conflict_policy: String,
+    pub query_memory_limit: Option<usize>,
+    pub vacuum_interval: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: String,
+    pub mysql_listen: Option<String>,
+    pub max_sessions: Option<usize>,
+    pub idle_timeout: Option<u64>,
+    pub slow_query_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub require_users: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("sharkdb-data"),
+            log_level: "info".to_string(),
+            storage: StorageConfig::default(),
+            server: ServerConfig::default(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            durability: "on-commit".to_string(),
+            cache_size: 8 * 1024 * 1024,
+            compression: "none".to_string(),
+            mmap: false,
+            conflict_policy: "first-writer".to_string(),
+            query_memory_limit: None,
+            vacuum_interval: None,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:9605".to_string(),
+            mysql_listen: None,
+            max_sessions: None,
+            idle_timeout: None,
+            slow_query_ms: None,
+        }
+    }
+}
+
+impl Config {
+    // the file read and checked, errors name the file and the key
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| Error::Config(format!("can not read config file {}: {}", path.display(), err)))?;
+        let config = Self::parse(&text).map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let config: Self = toml::from_str(text).map_err(|err| Error::Config(err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // every value that is not checked by its type alone
+    pub fn validate(&self) -> Result<()> {
+        self.log_level()?;
+        self.durability()?;
+        self.compression()?;
+        self.conflict_policy()?;
+        check_address("server.listen", &self.server.listen)?;
+        if let Some(addr) = &self.server.mysql_listen {
+            check_address("server.mysql_listen", addr)?;
+            if *addr == self.server.listen {
+                return Err(Error::Config(format!("server.listen and server.mysql_listen are both {}", addr)));
+            }
+        }
+        if self.server.max_sessions == Some(0) {
+            return Err(Error::Config("server.max_sessions must be positive".to_string()));
+        }
+        if self.storage.vacuum_interval == Some(0) {
+            return Err(Error::Config("storage.vacuum_interval must be positive".to_string()));
+        }
+        if self.auth.tls_cert.is_some() != self.auth.tls_key.is_some() {
+            return Err(Error::Config("auth.tls_cert and auth.tls_key must be set together".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn data_file(&self) -> PathBuf {
+        self.data_dir.join(DATA_FILE_NAME)
+    }
+
+    pub fn log_level(&self) -> Result<tracing::Level> {
+        self.log_level.parse().map_err(|_| {
+            Error::Config(format!("log_level must be error, warn, info, debug or trace, got {}", self.log_level))
+        })
+    }
+
+    // always, on-commit, never, or every-<n>ms (a background thread syncs every n ms)
+    pub fn durability(&self) -> Result<Durability> {
+        let durability = &self.storage.durability;
+        Ok(match durability.as_str() {
+            "always" => Durability::Always,
+            "on-commit" => Durability::OnCommit,
+            "never" => Durability::Never,
+            every => match every.strip_prefix("every-").and_then(|ms| ms.strip_suffix("ms")).map(str::parse) {
+                Some(Ok(ms)) if ms > 0 => Durability::EveryNms(ms),
+                _ => {
+                    return Err(Error::Config(format!(
+                        "storage.durability must be always, on-commit, never or every-<n>ms, got {}",
+                        durability
+                    )))
+                }
+            },
+        })
+    }
+
+    pub fn compression(&self) -> Result<Compression> {
+        match self.storage.compression.as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            other => Err(Error::Config(format!("storage.compression must be none or lz4, got {}", other))),
+        }
+    }
+
+    pub fn conflict_policy(&self) -> Result<ConflictPolicy> {
+        match self.storage.conflict_policy.as_str() {
+            "first-writer" => Ok(ConflictPolicy::FirstWriterWins),
+            "first-committer" => Ok(ConflictPolicy::FirstCommitterWins),
+            other => Err(Error::Config(format!(
+                "storage.conflict_policy must be first-writer or first-committer, got {}",
+                other
+            ))),
+        }
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.server.idle_timeout.map(Duration::from_secs)
+    }
+
+    pub fn vacuum_interval(&self) -> Option<Duration> {
+        self.storage.vacuum_interval.map(Duration::from_secs)
+    }
+
+    // the engine over the log in the data directory, created with the directory if there is none,
+    // with the limits of its sessions set
+    pub fn open(&self) -> Result<KVEngine<DiskEngine>> {
+        self.open_file(&self.data_file())
+    }
+
+    // the same over a log somewhere else, for a data file given on the command line
+    pub fn open_file(&self, path: &Path) -> Result<KVEngine<DiskEngine>> {
+        self.validate()?;
+        let disk = DiskEngineConfig::new(path.to_path_buf())
+            .durability(self.durability()?)
+            .cache_capacity(self.storage.cache_size)
+            .compression(self.compression()?)
+            .mmap(self.storage.mmap)
+            .open()?;
+        let engine = KVEngine::new(disk)?;
+        engine.kv.set_conflict_policy(self.conflict_policy()?)?;
+        engine.sessions().set_max_sessions(self.server.max_sessions)?;
+        engine.sessions().set_slow_query_threshold(self.server.slow_query_ms.map(Duration::from_millis))?;
+        engine.sessions().set_query_memory_limit(self.storage.query_memory_limit)?;
+        if self.auth.require_users {
+            let txn = engine.begin()?;
+            let has_users = txn.has_users();
+            txn.rollback()?;
+            if !has_users? {
+                return Err(Error::Config(format!(
+                    "auth.require_users is set, but the database in {} has no users, create one first",
+                    path.display()
+                )));
+            }
+        }
+        Ok(engine)
+    }
+}
+
+// host:port, the host is resolved when the server binds it
+fn check_address(key: &str, addr: &str) -> Result<()> {
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => Err(Error::Config(format!("{} must be host:port, got {}", key, addr))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        error::{Error, Result},
+        sql::engine::Engine,
+        storage::disk::{Compression, Durability},
+    };
+
+    use super::Config;
+
+    #[test]
+    fn test_config_parse() -> Result<()> {
+        assert_eq!(Config::parse("")?, Config::default());
+        let config = Config::parse(
+            r#"
+            data_dir = "/var/lib/sharkdb"
+            log_level = "debug"
+
+            [storage]
+            durability = "every-100ms"
+            compression = "lz4"
+
+            [server]
+            mysql_listen = "0.0.0.0:3306"
+            max_sessions = 10
+            "#,
+        )?;
+        assert_eq!(config.data_file(), PathBuf::from("/var/lib/sharkdb/sqldb-log"));
+        assert_eq!(config.log_level()?, tracing::Level::DEBUG);
+        assert_eq!(config.durability()?, Durability::EveryNms(100));
+        assert_eq!(config.compression()?, Compression::Lz4);
+        // 没写的键保持默认值
+        assert_eq!(config.server.listen, "127.0.0.1:9605");
+        assert_eq!(config.storage.cache_size, 8 * 1024 * 1024);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let error = |text: &str| match Config::parse(text) {
+            Err(Error::Config(message)) => message,
+            result => panic!("expected a config error, got {:?}", result),
+        };
+        // 拼错的键、错误的类型和非法的值都给出键名
+        assert!(error("data_dri = \"x\"").contains("data_dri"));
+        assert!(error("[storage]\ncache_size = \"big\"").contains("cache_size"));
+        assert!(error("[storage]\ndurability = \"sometimes\"").contains("storage.durability"));
+        assert!(error("[storage]\ndurability = \"every-0ms\"").contains("storage.durability"));
+        assert!(error("log_level = \"loud\"").contains("log_level"));
+        assert!(error("[server]\nlisten = \"9605\"").contains("server.listen"));
+        assert!(error("[server]\nmysql_listen = \"127.0.0.1:9605\"").contains("server.mysql_listen"));
+        assert!(error("[auth]\ntls_cert = \"cert.pem\"").contains("auth.tls_key"));
+    }
+
+    #[test]
+    fn test_config_open() -> Result<()> {
+        let dir = tempfile::tempdir()?.into_path();
+        let config = Config::parse(&format!(
+            "data_dir = {:?}\n[server]\nmax_sessions = 1\n[auth]\nrequire_users = true",
+            dir.join("data")
+        ))?;
+        // 还没有用户时拒绝启动
+        assert!(matches!(config.open(), Err(Error::Config(message)) if message.contains("no users")));
+
+        let mut open = config.clone();
+        open.auth.require_users = false;
+        let engine = open.open()?;
+        let mut session = engine.session()?;
+        assert!(engine.session().is_err());
+        session.execute("create user bob password 'secret';")?;
+        drop(session);
+        drop(engine);
+
+        // 嵌入的数据库用同一份配置打开
+        let mut db = crate::Database::open_with_config(&config)?;
+        db.execute("create table t (a int);")?;
+        drop(db);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+// the api for embedding the database, without the engine, transaction and storage types under it
+//   let mut db = sharkdb::Database::open("data/sqldb-log")?;  // Database::open_memory() for one in memory
+//   db.execute("create table t (a int);")?;
+//   let mut other = db.connect()?;
+// a Database is one session, SET and cursors are its own, connect opens another on the same data
+// the disk file is closed once the last one of them is dropped, or by close
+#[cfg(feature = "disk")]
+use std::path::Path;
+
+#[cfg(feature = "disk")]
+use crate::storage::disk::DiskEngine;
+use crate::{
+    error::Result,
+    sql::{
+        engine::{kv::KVEngine, Engine, Session},
+        executor::{ResultSet, Warning},
+    },
+    storage::memory::MemoryEngine,
+};
+
+pub struct Database {
+    session: Inner,
+}
+
+enum Inner {
+    Memory(Session<KVEngine<MemoryEngine>>, KVEngine<MemoryEngine>),
+    #[cfg(feature = "disk")]
+    Disk(Session<KVEngine<DiskEngine>>, KVEngine<DiskEngine>),
+}
+
+impl Database {
+    // the database in the disk file at path, created if there is none
+    #[cfg(feature = "disk")]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let engine = KVEngine::new(DiskEngine::new(path.as_ref().to_path_buf())?)?;
+        Ok(Self { session: Inner::Disk(engine.session()?, engine) })
+    }
+
+    // the database of a config file, with its storage settings and session limits, see sharkdb::config
+    #[cfg(feature = "config")]
+    pub fn open_with_config(config: &crate::config::Config) -> Result<Self> {
+        let engine = config.open()?;
+        Ok(Self { session: Inner::Disk(engine.session()?, engine) })
+    }
+
+    // an empty database that lives as long as its sessions
+    pub fn open_memory() -> Result<Self> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        Ok(Self { session: Inner::Memory(engine.session()?, engine) })
+    }
+
+    // another session of the same database
+    pub fn connect(&self) -> Result<Self> {
+        Ok(Self {
+            session: match &self.session {
+                Inner::Memory(_, engine) => Inner::Memory(engine.session()?, engine.clone()),
+                #[cfg(feature = "disk")]
+                Inner::Disk(_, engine) => Inner::Disk(engine.session()?, engine.clone()),
+            },
+        })
+    }
+
+    // roll back open transactions, then flush, fsync and unlock the disk file
+    // it closes the database for the sessions of connect too, their statements fail with Error::Closed
+    // dropping the last session flushes the same way, but its errors are lost
+    pub fn close(self) -> Result<()> {
+        match self.session {
+            Inner::Memory(_, engine) => engine.close()?,
+            #[cfg(feature = "disk")]
+            Inner::Disk(_, engine) => engine.close()?,
+        };
+        Ok(())
+    }
+
+    // one statement, ended by ";"
+    pub fn execute(&mut self, sql: &str) -> Result<ResultSet> {
+        match &mut self.session {
+            Inner::Memory(session, _) => session.execute(sql),
+            #[cfg(feature = "disk")]
+            Inner::Disk(session, _) => session.execute(sql),
+        }
+    }
+
+    // the statements in one transaction, see Session::execute_batch
+    pub fn execute_batch(&mut self, sqls: &[&str]) -> Result<Vec<ResultSet>> {
+        match &mut self.session {
+            Inner::Memory(session, _) => session.execute_batch(sqls),
+            #[cfg(feature = "disk")]
+            Inner::Disk(session, _) => session.execute_batch(sqls),
+        }
+    }
+
+    // of the last execute, see Session::warnings
+    pub fn warnings(&self) -> &[Warning] {
+        match &self.session {
+            Inner::Memory(session, _) => session.warnings(),
+            #[cfg(feature = "disk")]
+            Inner::Disk(session, _) => session.warnings(),
+        }
+    }
+
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        match &mut self.session {
+            Inner::Memory(session, _) => session.set_memory_limit(limit),
+            #[cfg(feature = "disk")]
+            Inner::Disk(session, _) => session.set_memory_limit(limit),
+        }
+    }
+
+    pub fn set_max_result_rows(&mut self, max: Option<usize>) {
+        match &mut self.session {
+            Inner::Memory(session, _) => session.set_max_result_rows(max),
+            #[cfg(feature = "disk")]
+            Inner::Disk(session, _) => session.set_max_result_rows(max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Result, sql::executor::ResultSet};
+    #[cfg(feature = "disk")]
+    use crate::error::Error;
+
+    use super::Database;
+
+    #[test]
+    fn test_database_memory() -> Result<()> {
+        let mut db = Database::open_memory()?;
+        db.execute("create table t (a int);")?;
+        db.execute_batch(&["insert into t values (1);", "insert into t values (2);"])?;
+        // 另一个会话看到同一份数据，但会话变量各自独立
+        let mut other = db.connect()?;
+        other.execute("set max_result_rows = 1;")?;
+        assert!(matches!(other.execute("select * from t;")?, ResultSet::Scan { row, .. } if row.len() == 1));
+        assert_eq!(other.warnings().len(), 1);
+        assert!(matches!(db.execute("select * from t;")?, ResultSet::Scan { row, .. } if row.len() == 2));
+        assert!(db.warnings().is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "disk")]
+    #[test]
+    fn test_database_disk() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        {
+            let mut db = Database::open(&p)?;
+            db.execute("create table t (a int);")?;
+            db.execute("insert into t values (1);")?;
+        }
+        let mut db = Database::open(&p)?;
+        assert!(matches!(db.execute("select * from t;")?, ResultSet::Scan { row, .. } if row.len() == 1));
+        // close 之后其他会话也不能再用，文件锁已经释放，不用等它们 drop 就能再打开
+        let mut other = db.connect()?;
+        db.close()?;
+        assert!(matches!(other.execute("insert into t values (2);"), Err(Error::Closed)));
+        let mut db = Database::open(&p)?;
+        assert!(matches!(db.execute("select * from t;")?, ResultSet::Scan { row, .. } if row.len() == 1));
+        drop(other);
+        drop(db);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+}
@@ -1,15 +1,59 @@
 use std::{array::TryFromSliceError, fmt::Display, sync::PoisonError};
 
 use bincode::ErrorKind;
-use serde::{de, ser};
+use serde::{de, ser, Deserialize, Serialize};
+
+use crate::sql::{
+    parser::Position,
+    types::{DataType, Value},
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Error {
     Parse(String),
     Internal(String),
     WriteConflict,
+    // checksum mismatch of the disk log entry at offset
+    Corruption { offset: u64 },
+    // invalid engine config
+    Config(String),
+    // key or value exceeds the configured max size of the storage engine
+    KeyTooLarge { size: usize, max: usize },
+    ValueTooLarge { size: usize, max: usize },
+    // write to a storage engine opened read-only
+    ReadOnly,
+    // unknown user or wrong password
+    AccessDenied(String),
+    // the user lacks a privilege, like SELECT ON t, or ADMIN
+    PermissionDenied { user: String, privilege: String },
+    // a query holds more rows in memory than its limit allows
+    MemoryLimitExceeded { limit: usize },
+    // the engine already has the max number of sessions
+    TooManySessions { max: usize },
+    // the statement was stopped by KILL
+    QueryCancelled { id: u64 },
+    // sql errors callers may want to handle
+    TableNotFound(String),
+    DuplicateTable(String),
+    // the position of the column in the sql, when it is known
+    ColumnNotFound { table: String, column: String, position: Option<Position> },
+    // a value of the wrong type for a column, or an operand of the wrong type, named like a select column
+    TypeMismatch { column: String, expected: DataType, got: DataType },
+    // a column that cannot be null got null, or no value and no default
+    NotNullViolation { column: String },
+    // invalid CREATE TABLE
+    DuplicateColumn { table: String, column: String },
+    TooManyColumns { table: String, max: usize },
+    // a row of the table needs expected values, but got more or less
+    ValueCountMismatch { table: String, expected: usize, got: usize },
+    // a row with the same key already exists
+    UniqueViolation { table: String, key: Value },
+    // a value an ENUM column does not allow
+    CheckViolation { column: String, value: Value },
+    // the database was closed, by Database::close or the shutdown of the server
+    Closed,
 }
 
 impl From<std::num::ParseIntError> for Error {
@@ -68,6 +112,71 @@ impl Display for Error {
             Error::Parse(err) => write!(f, "parse error {}", err),
             Error::Internal(err) => write!(f, "internal error {}", err),
             Error::WriteConflict => write!(f, "write conflict, try transaction"),
+            Error::Corruption { offset } => write!(f, "data corrupted at offset {}", offset),
+            Error::Config(err) => write!(f, "invalid config {}", err),
+            Error::KeyTooLarge { size, max } => {
+                write!(f, "key size {} exceeds the max size {}", size, max)
+            }
+            Error::ValueTooLarge { size, max } => {
+                write!(f, "value size {} exceeds the max size {}", size, max)
+            }
+            Error::ReadOnly => write!(f, "storage engine is read only"),
+            Error::AccessDenied(user) => write!(f, "access denied for user {}", user),
+            Error::PermissionDenied { user, privilege } => {
+                write!(f, "user {} does not have the privilege {}", user, privilege)
+            }
+            Error::MemoryLimitExceeded { limit } => {
+                write!(f, "query needs more than its memory limit of {} bytes", limit)
+            }
+            Error::TooManySessions { max } => write!(f, "too many sessions, the max is {}", max),
+            Error::QueryCancelled { id } => write!(f, "query {} was cancelled", id),
+            Error::TableNotFound(table) => write!(f, "table {} does not exist", table),
+            Error::DuplicateTable(table) => write!(f, "table {} already exists", table),
+            Error::ColumnNotFound { table, column, position: None } => {
+                write!(f, "column {} does not exist in table {}", column, table)
+            }
+            Error::ColumnNotFound { table, column, position: Some(position) } => {
+                write!(f, "column {} does not exist in table {} at {}", column, table, position)
+            }
+            Error::TypeMismatch { column, expected, got } => {
+                write!(f, "expected {} for column {}, but got {}", expected, column, got)
+            }
+            Error::NotNullViolation { column } => write!(f, "column {} cannot be null", column),
+            Error::DuplicateColumn { table, column } => write!(f, "column {} appears twice in table {}", column, table),
+            Error::TooManyColumns { table, max } => write!(f, "table {} has more than {} columns", table, max),
+            Error::ValueCountMismatch { table, expected, got } => {
+                write!(f, "table {} expects {} values, but got {}", table, expected, got)
+            }
+            Error::UniqueViolation { table, key } => write!(f, "duplicate key {} in table {}", key, table),
+            Error::CheckViolation { column, value } => write!(f, "value {} is not allowed for column {}", value, column),
+            Error::Closed => write!(f, "database is closed"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "import")]
+impl From<csv::Error> for Error {
+    fn from(value: csv::Error) -> Self {
+        Error::Parse(value.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Parse(value.to_string())
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(value: parquet::errors::ParquetError) -> Self {
+        Error::Internal(value.to_string())
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<arrow_schema::ArrowError> for Error {
+    fn from(value: arrow_schema::ArrowError) -> Self {
+        Error::Internal(value.to_string())
+    }
+}
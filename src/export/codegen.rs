@@ -0,0 +1,171 @@
+// rust structs matching the tables, for applications embedding the database
+//   create table user_accounts (id int not null, name text, scores vector(3));
+// becomes
+//   #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+//   pub struct UserAccounts {
+//       pub id: i64,
+//       pub name: Option<String>,
+//       pub scores: Option<Vec<f32>>,
+//   }
+// fields are named and ordered like the columns, so the rows of the ndjson export deserialize into them
+use std::fmt::Write;
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{Engine, Transaction},
+        schema::{Column, Table},
+        types::DataType,
+    },
+};
+
+const HEADER: &str = "// generated by sharkdb codegen from the table schemas, do not edit\n\
+                      // run it again after changing the tables\n\
+                      use serde::{Deserialize, Serialize};\n";
+
+// raw identifiers can not be these, the fields get a trailing _ and a serde rename instead
+const RESERVED: &[&str] = &["crate", "self", "super", "Self"];
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn", "else", "enum",
+    "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro", "match", "mod",
+    "move", "mut", "override", "priv", "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+// the structs of the named tables, all tables when none are named
+pub fn codegen<E: Engine>(engine: &E, tables: &[String]) -> Result<String> {
+    let txn = engine.begin()?;
+    let result = (|| {
+        let names = match tables.is_empty() {
+            true => txn.table_names()?,
+            false => tables.to_vec(),
+        };
+        names.into_iter().map(|name| txn.must_get_table(name)).collect::<Result<Vec<_>>>()
+    })();
+    txn.rollback()?;
+    rust_structs(&result?)
+}
+
+pub fn rust_structs(tables: &[Table]) -> Result<String> {
+    let mut out = HEADER.to_string();
+    for table in tables {
+        write_struct(&mut out, table).map_err(|err| Error::Internal(err.to_string()))?;
+    }
+    Ok(out)
+}
+
+fn write_struct(out: &mut String, table: &Table) -> std::fmt::Result {
+    writeln!(out)?;
+    writeln!(out, "// table {}", table.name)?;
+    writeln!(out, "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]")?;
+    let name = struct_name(&table.name);
+    if name != table.name {
+        writeln!(out, "#[serde(rename = \"{}\")]", table.name)?;
+    }
+    writeln!(out, "pub struct {} {{", name)?;
+    for (i, column) in table.columns.iter().enumerate() {
+        if i == 0 {
+            writeln!(out, "    // primary key")?;
+        }
+        let (field, renamed) = field_name(&column.name);
+        if renamed {
+            writeln!(out, "    #[serde(rename = \"{}\")]", column.name)?;
+        }
+        writeln!(out, "    pub {}: {},", field, field_type(column))?;
+    }
+    writeln!(out, "}}")
+}
+
+// user_accounts -> UserAccounts
+fn struct_name(table: &str) -> String {
+    let mut name = table
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect::<String>();
+    if !name.starts_with(|c: char| c.is_alphabetic()) {
+        name.insert(0, 'T');
+    }
+    name
+}
+
+// the field and whether it needs a serde rename to match the column
+fn field_name(column: &str) -> (String, bool) {
+    if RESERVED.contains(&column) {
+        (format!("{}_", column), true)
+    } else if KEYWORDS.contains(&column) {
+        (format!("r#{}", column), false)
+    } else if column.starts_with(|c: char| c.is_numeric()) {
+        (format!("_{}", column), true)
+    } else {
+        (column.to_string(), false)
+    }
+}
+
+fn field_type(column: &Column) -> String {
+    let ty = match column.datatype {
+        DataType::Boolean => "bool",
+        DataType::Integer => "i64",
+        DataType::Float => "f64",
+        DataType::String | DataType::Enum(_) => "String",
+        DataType::Vector(_) => "Vec<f32>",
+    };
+    match column.nullable {
+        true => format!("Option<{}>", ty),
+        false => ty.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{codegen, field_name, struct_name};
+    use crate::{
+        error::Result,
+        sql::engine::{kv::KVEngine, Engine},
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_codegen() -> Result<()> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = engine.session()?;
+        s.execute("create table user_accounts (id int not null, name text, active bool not null default true, score float, emb vector(3));")?;
+        s.execute("create table t2 (type int not null, self text);")?;
+
+        let code = codegen(&engine, &["user_accounts".to_string()])?;
+        assert!(code.contains(
+            "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = \"user_accounts\")]
+pub struct UserAccounts {
+    // primary key
+    pub id: i64,
+    pub name: Option<String>,
+    pub active: bool,
+    pub score: Option<f64>,
+    pub emb: Option<Vec<f32>>,
+}"
+        ));
+        assert!(!code.contains("T2"));
+
+        // 不指定表时生成全部，关键字做字段名
+        let code = codegen(&engine, &[])?;
+        assert!(code.contains("pub struct T2 {") && code.contains("pub struct UserAccounts {"));
+        assert!(code.contains("    pub r#type: i64,\n    #[serde(rename = \"self\")]\n    pub self_: Option<String>,"));
+        assert!(codegen(&engine, &["missing".to_string()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_codegen_names() {
+        assert_eq!(struct_name("orders"), "Orders");
+        assert_eq!(struct_name("order__items_"), "OrderItems");
+        assert_eq!(struct_name("_1st"), "T1st");
+        assert_eq!(field_name("match"), ("r#match".to_string(), false));
+        assert_eq!(field_name("crate"), ("crate_".to_string(), true));
+        assert_eq!(field_name("name"), ("name".to_string(), false));
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::Write;
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::{
+    error::Result,
+    sql::types::{Row, Value},
+};
+
+// one row as a json object, keys in column order
+struct JsonRow<'a> {
+    columns: &'a [String],
+    row: &'a Row,
+}
+
+impl Serialize for JsonRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for (column, value) in self.columns.iter().zip(self.row) {
+            match value {
+                Value::Null => map.serialize_entry(column, &())?,
+                Value::Boolean(b) => map.serialize_entry(column, b)?,
+                Value::Integer(i) => map.serialize_entry(column, i)?,
+                // json has no nan or infinity, serde_json writes them as null
+                Value::Float(f) => map.serialize_entry(column, f)?,
+                Value::String(s) => map.serialize_entry(column, s)?,
+                Value::Vector(v) => map.serialize_entry(column, v)?,
+            }
+        }
+        map.end()
+    }
+}
+
+// writes one json object per line, returns the number of rows
+pub fn write_ndjson(w: &mut impl Write, columns: &[String], rows: &[Row]) -> Result<u64> {
+    for row in rows {
+        serde_json::to_writer(&mut *w, &JsonRow { columns, row })?;
+        w.write_all(b"\n")?;
+    }
+    w.flush()?;
+    Ok(rows.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_ndjson;
+    use crate::{
+        error::Result,
+        export::query,
+        sql::engine::{kv::KVEngine, Engine},
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_export_ndjson() -> Result<()> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = engine.session()?;
+        s.execute("create table t (b int, a text, c float, d boolean);")?;
+        s.execute("insert into t values (1, 'x\"y', 1.5, true), (2, null, 2.0, false);")?;
+
+        let (columns, rows) = query(&engine, "select * from t;")?;
+        let mut out = Vec::new();
+        assert_eq!(write_ndjson(&mut out, &columns, &rows)?, 2);
+        // 字段按列的顺序输出
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "{\"b\":1,\"a\":\"x\\\"y\",\"c\":1.5,\"d\":true}\n{\"b\":2,\"a\":null,\"c\":2.0,\"d\":false}\n"
+        );
+        assert!(query(&engine, "show status;").is_err());
+
+        // 导出的结果可以再导入
+        #[cfg(feature = "import")]
+        {
+            use crate::import::{json::{import_ndjson, JsonOptions}, ImportOptions};
+            s.execute("create table t2 (b int, a text, c float, d boolean);")?;
+            import_ndjson(&engine, "t2", out.as_slice(), &JsonOptions::default(), &ImportOptions::default(), |_| {})?;
+            assert_eq!(query(&engine, "select * from t2;")?.1, rows);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+use crate::{
+    error::{Error, Result},
+    sql::{engine::Engine, executor::ResultSet, types::Row},
+};
+
+pub mod codegen;
+pub mod json;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+
+// runs a query and returns its columns and rows for the exporters
+pub fn query<E: Engine>(engine: &E, sql: &str) -> Result<(Vec<String>, Vec<Row>)> {
+    match engine.session()?.execute(sql)? {
+        ResultSet::Scan { columns, row } => Ok((columns, row)),
+        _ => Err(Error::Internal("only the results of select can be exported".to_string())),
+    }
+}
@@ -0,0 +1,187 @@
+use std::{io::Write, sync::Arc};
+
+use arrow_array::{
+    types::Float32Type, Array, ArrayRef, BooleanArray, FixedSizeListArray, Float64Array, Int64Array, RecordBatch,
+    StringArray,
+};
+use arrow_schema::{DataType as ArrowType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{Engine, Transaction},
+        types::{DataType, Row, Value},
+    },
+};
+
+// rows converted to arrow and written per record batch
+const BATCH_ROWS: usize = 8192;
+
+// writes query results, the type of a column is the type of its first non null value
+// columns with only nulls are written as strings
+pub fn write_parquet<W: Write + Send>(w: W, columns: &[String], rows: &[Row]) -> Result<u64> {
+    let fields = columns
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let datatype = rows.iter().find_map(|row| row[i].datatype()).unwrap_or(DataType::String);
+            Field::new(name, arrow_type(&datatype), true)
+        })
+        .collect::<Vec<_>>();
+    write(w, Schema::new(fields), rows)
+}
+
+// writes a whole table with the column types and nullability of its schema
+pub fn export_table<E: Engine, W: Write + Send>(engine: &E, table_name: &str, w: W) -> Result<u64> {
+    let txn = engine.begin()?;
+    let scan = txn
+        .must_get_table(table_name.to_string())
+        .and_then(|table| Ok((table, txn.scan_table(table_name.to_string())?)));
+    txn.rollback()?;
+    let (table, rows) = scan?;
+    let fields = table
+        .columns
+        .iter()
+        .map(|c| Field::new(&c.name, arrow_type(&c.datatype), c.nullable))
+        .collect::<Vec<_>>();
+    write(w, Schema::new(fields), &rows)
+}
+
+fn arrow_type(datatype: &DataType) -> ArrowType {
+    match datatype {
+        DataType::Boolean => ArrowType::Boolean,
+        DataType::Integer => ArrowType::Int64,
+        DataType::Float => ArrowType::Float64,
+        DataType::String | DataType::Enum(_) => ArrowType::Utf8,
+        // the item field is the one FixedSizeListArray::from_iter_primitive makes
+        DataType::Vector(n) => {
+            ArrowType::FixedSizeList(Arc::new(Field::new("item", ArrowType::Float32, true)), *n as i32)
+        }
+    }
+}
+
+fn write<W: Write + Send>(w: W, schema: Schema, rows: &[Row]) -> Result<u64> {
+    let schema = Arc::new(schema);
+    let mut writer = ArrowWriter::try_new(w, schema.clone(), None)?;
+    for chunk in rows.chunks(BATCH_ROWS) {
+        let arrays = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(i, field)| to_array(chunk, i, field))
+            .collect::<Result<Vec<_>>>()?;
+        writer.write(&RecordBatch::try_new(schema.clone(), arrays)?)?;
+    }
+    writer.close()?;
+    Ok(rows.len() as u64)
+}
+
+// the i-th column of the rows as an arrow array of the field type
+fn to_array(rows: &[Row], i: usize, field: &Field) -> Result<ArrayRef> {
+    let mismatch = |value: &Value| Error::Internal(format!("column {} has {:?} and {:?} values", field.name(), field.data_type(), value));
+    let array: ArrayRef = match field.data_type() {
+        ArrowType::Boolean => Arc::new(column(rows, i, |v| match v {
+            Value::Boolean(b) => Ok(Some(*b)),
+            Value::Null => Ok(None),
+            v => Err(mismatch(v)),
+        })?.into_iter().collect::<BooleanArray>()),
+        ArrowType::Int64 => Arc::new(column(rows, i, |v| match v {
+            Value::Integer(n) => Ok(Some(*n)),
+            Value::Null => Ok(None),
+            v => Err(mismatch(v)),
+        })?.into_iter().collect::<Int64Array>()),
+        ArrowType::Float64 => Arc::new(column(rows, i, |v| match v {
+            Value::Float(f) => Ok(Some(*f)),
+            Value::Null => Ok(None),
+            v => Err(mismatch(v)),
+        })?.into_iter().collect::<Float64Array>()),
+        ArrowType::FixedSizeList(_, n) => {
+            let n = *n;
+            let values = column(rows, i, |v| match v {
+                Value::Vector(x) if x.len() == n as usize => Ok(Some(x.iter().map(|x| Some(*x)).collect::<Vec<_>>())),
+                Value::Null => Ok(None),
+                v => Err(mismatch(v)),
+            })?;
+            Arc::new(FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(values, n))
+        }
+        _ => Arc::new(column(rows, i, |v| match v {
+            Value::String(s) => Ok(Some(s.as_str())),
+            Value::Null => Ok(None),
+            v => Err(mismatch(v)),
+        })?.into_iter().collect::<StringArray>()),
+    };
+    if !field.is_nullable() && array.null_count() > 0 {
+        return Err(Error::NotNullViolation { column: field.name().clone() });
+    }
+    Ok(array)
+}
+
+fn column<'a, T>(rows: &'a [Row], i: usize, f: impl Fn(&'a Value) -> Result<Option<T>>) -> Result<Vec<Option<T>>> {
+    rows.iter().map(|row| f(&row[i])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use arrow_array::{Array, BooleanArray, FixedSizeListArray, Float32Array, Float64Array, Int64Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::{export_table, write_parquet};
+    use crate::{
+        error::Result,
+        export::query,
+        sql::engine::{kv::KVEngine, Engine},
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_export_parquet() -> Result<()> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = engine.session()?;
+        s.execute("create table t (a int not null, b text, c float, d boolean, e text);")?;
+        s.execute("insert into t values (1, 'x', 1.5, true, null), (2, null, 2.5, false, null);")?;
+        let p = tempfile::tempdir()?.into_path().join("t.parquet");
+
+        // 整张表按表结构导出
+        assert_eq!(export_table(&engine, "t", File::create(&p)?)?, 2);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&p)?)?.build()?;
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert!(!batch.schema().field(0).is_nullable());
+        let a = batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(a.values(), &[1, 2]);
+        let b = batch.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!((b.value(0), b.is_null(1)), ("x", true));
+        let c = batch.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(c.values(), &[1.5, 2.5]);
+        let d = batch.column(3).as_any().downcast_ref::<BooleanArray>().unwrap();
+        assert!(d.value(0) && !d.value(1));
+
+        // 查询结果按第一个非空值推断类型，全为空的列写成字符串
+        let (columns, rows) = query(&engine, "select * from t;")?;
+        assert_eq!(write_parquet(File::create(&p)?, &columns, &rows)?, 2);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&p)?)?.build()?;
+        let batch = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap().remove(0);
+        assert!(batch.schema().field(0).is_nullable());
+        assert_eq!(batch.column(4).null_count(), 2);
+        assert!(batch.column(4).as_any().downcast_ref::<StringArray>().is_some());
+
+        // 向量列写成定长的 f32 列表
+        s.execute("create table v (id int, e vector(2));")?;
+        s.execute("insert into v values (1, [0.5, -1]), (2, null);")?;
+        assert_eq!(export_table(&engine, "v", File::create(&p)?)?, 2);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&p)?)?.build()?;
+        let batch = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap().remove(0);
+        let e = batch.column(1).as_any().downcast_ref::<FixedSizeListArray>().unwrap();
+        assert_eq!(e.value_length(), 2);
+        assert_eq!(e.value(0).as_any().downcast_ref::<Float32Array>().unwrap().values(), &[0.5, -1.0]);
+        assert!(e.is_null(1));
+
+        assert!(export_table(&engine, "nope", File::create(&p)?).is_err());
+        fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+}
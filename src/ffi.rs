@@ -0,0 +1,425 @@
+// c api in the style of sqlite, declared in include/sharkdb.h
+// link the cdylib or staticlib of this crate
+//
+//   sharkdb *db;
+//   sharkdb_stmt *stmt;
+//   sharkdb_open("data/sqldb-log", &db);   // NULL for an in memory database
+//   sharkdb_prepare(db, "select * from t;", &stmt);
+//   while (sharkdb_step(stmt) == SHARKDB_ROW) {
+//       int64_t a = sharkdb_column_int64(stmt, 0);
+//   }
+//   sharkdb_finalize(stmt);
+//   sharkdb_close(db);
+//
+// a statement runs in prepare, step walks through the rows it returned
+// strings returned by the api are owned by the database or the statement,
+// they stay valid until the next call on the same handle
+// a panic inside a call returns SHARKDB_ERROR, with its message in sharkdb_errmsg when there
+// is a database, or the zero value of the accessors
+use std::{
+    ffi::{c_char, c_int, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        executor::ResultSet,
+        types::{json::to_json, Row, Value},
+    },
+    Database,
+};
+
+pub const SHARKDB_OK: c_int = 0;
+pub const SHARKDB_ERROR: c_int = 1;
+pub const SHARKDB_MISUSE: c_int = 21;
+pub const SHARKDB_ROW: c_int = 100;
+pub const SHARKDB_DONE: c_int = 101;
+
+// column types
+pub const SHARKDB_INTEGER: c_int = 1;
+pub const SHARKDB_FLOAT: c_int = 2;
+pub const SHARKDB_TEXT: c_int = 3;
+pub const SHARKDB_BOOLEAN: c_int = 4;
+pub const SHARKDB_NULL: c_int = 5;
+
+pub struct Sharkdb {
+    db: Database,
+    errmsg: CString,
+    // rows inserted or selected by the last statement
+    changes: i64,
+}
+
+impl Sharkdb {
+    fn execute(&mut self, sql: *const c_char) -> Result<ResultSet> {
+        if sql.is_null() {
+            return Err(Error::Internal("sql is null".to_string()));
+        }
+        let sql = unsafe { CStr::from_ptr(sql) }.to_str().map_err(|err| Error::Parse(err.to_string()))?;
+        let result = self.db.execute(sql);
+        self.changes = match &result {
+            Ok(ResultSet::Insert { count }) => *count as i64,
+            Ok(ResultSet::Scan { row, .. }) => row.len() as i64,
+            _ => 0,
+        };
+        result
+    }
+
+    // keeps the message for sharkdb_errmsg and returns the error code
+    fn fail(&mut self, err: Error) -> c_int {
+        self.errmsg = to_cstring(err.to_string());
+        SHARKDB_ERROR
+    }
+}
+
+pub struct SharkdbStmt {
+    columns: Vec<CString>,
+    rows: std::vec::IntoIter<Row>,
+    row: Option<Row>,
+    // text and json of the current row, converted when asked for
+    texts: Vec<Option<CString>>,
+    jsons: Vec<Option<CString>>,
+}
+
+impl SharkdbStmt {
+    fn value(&self, i: c_int) -> Option<&Value> {
+        self.row.as_ref()?.get(usize::try_from(i).ok()?)
+    }
+}
+
+// interior nul bytes cannot cross the c api, the string ends there
+fn to_cstring(s: String) -> CString {
+    CString::new(s).unwrap_or_else(|err| {
+        let pos = err.nul_position();
+        let mut bytes = err.into_vec();
+        bytes.truncate(pos);
+        CString::new(bytes).unwrap_or_default()
+    })
+}
+
+// a panic must not unwind into c, the function returns failed instead
+fn catch<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(failed)
+}
+
+// catch for a call on a database, the message of the panic is kept for sharkdb_errmsg
+fn catch_db(db: *mut Sharkdb, f: impl FnOnce() -> c_int) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let msg = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(msg), _) => msg.to_string(),
+            (_, Some(msg)) => msg.clone(),
+            _ => "unknown panic".to_string(),
+        };
+        match unsafe { db.as_mut() } {
+            Some(db) => db.fail(Error::Internal(format!("panic: {}", msg))),
+            None => SHARKDB_ERROR,
+        }
+    })
+}
+
+fn open(path: *const c_char) -> Result<Database> {
+    if path.is_null() {
+        return Database::open_memory();
+    }
+    let path = unsafe { CStr::from_ptr(path) }.to_str().map_err(|err| Error::Config(err.to_string()))?;
+    Database::open(path)
+}
+
+/// # Safety
+/// path is NULL or a nul terminated string, db points to writable memory
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_open(path: *const c_char, db: *mut *mut Sharkdb) -> c_int {
+    if db.is_null() {
+        return SHARKDB_MISUSE;
+    }
+    *db = ptr::null_mut();
+    catch(SHARKDB_ERROR, || match open(path) {
+        Ok(database) => {
+            *db = Box::into_raw(Box::new(Sharkdb { db: database, errmsg: CString::default(), changes: 0 }));
+            SHARKDB_OK
+        }
+        Err(_) => SHARKDB_ERROR,
+    })
+}
+
+/// # Safety
+/// db comes from sharkdb_open and is not used afterwards, statements of it must be finalized
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_close(db: *mut Sharkdb) -> c_int {
+    catch(SHARKDB_ERROR, || {
+        if !db.is_null() {
+            drop(Box::from_raw(db));
+        }
+        SHARKDB_OK
+    })
+}
+
+/// # Safety
+/// db comes from sharkdb_open
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_errmsg(db: *mut Sharkdb) -> *const c_char {
+    catch(c"unknown error".as_ptr(), || match db.as_ref() {
+        Some(db) => db.errmsg.as_ptr(),
+        None => c"out of memory or bad handle".as_ptr(),
+    })
+}
+
+/// # Safety
+/// db comes from sharkdb_open
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_changes(db: *mut Sharkdb) -> i64 {
+    catch(0, || db.as_ref().map_or(0, |db| db.changes))
+}
+
+// run a statement and drop its rows
+/// # Safety
+/// db comes from sharkdb_open, sql is a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_exec(db: *mut Sharkdb, sql: *const c_char) -> c_int {
+    catch_db(db, || {
+        let Some(db) = db.as_mut() else {
+            return SHARKDB_MISUSE;
+        };
+        match db.execute(sql) {
+            Ok(_) => SHARKDB_OK,
+            Err(err) => db.fail(err),
+        }
+    })
+}
+
+/// # Safety
+/// db comes from sharkdb_open, sql is a nul terminated string, stmt points to writable memory
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_prepare(db: *mut Sharkdb, sql: *const c_char, stmt: *mut *mut SharkdbStmt) -> c_int {
+    catch_db(db, || {
+        let (Some(db), false) = (db.as_mut(), stmt.is_null()) else {
+            return SHARKDB_MISUSE;
+        };
+        *stmt = ptr::null_mut();
+        let (columns, rows) = match db.execute(sql) {
+            Ok(ResultSet::Scan { columns, row }) => (columns, row),
+            Ok(_) => (Vec::new(), Vec::new()),
+            Err(err) => return db.fail(err),
+        };
+        *stmt = Box::into_raw(Box::new(SharkdbStmt {
+            columns: columns.into_iter().map(to_cstring).collect(),
+            rows: rows.into_iter(),
+            row: None,
+            texts: Vec::new(),
+            jsons: Vec::new(),
+        }));
+        SHARKDB_OK
+    })
+}
+
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_step(stmt: *mut SharkdbStmt) -> c_int {
+    catch(SHARKDB_ERROR, || {
+        let Some(stmt) = stmt.as_mut() else {
+            return SHARKDB_MISUSE;
+        };
+        stmt.row = stmt.rows.next();
+        stmt.texts = vec![None; stmt.columns.len()];
+        stmt.jsons = vec![None; stmt.columns.len()];
+        if stmt.row.is_some() {
+            SHARKDB_ROW
+        } else {
+            SHARKDB_DONE
+        }
+    })
+}
+
+/// # Safety
+/// stmt comes from sharkdb_prepare and is not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_finalize(stmt: *mut SharkdbStmt) -> c_int {
+    catch(SHARKDB_ERROR, || {
+        if !stmt.is_null() {
+            drop(Box::from_raw(stmt));
+        }
+        SHARKDB_OK
+    })
+}
+
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_column_count(stmt: *mut SharkdbStmt) -> c_int {
+    catch(0, || stmt.as_ref().map_or(0, |stmt| stmt.columns.len() as c_int))
+}
+
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_column_name(stmt: *mut SharkdbStmt, i: c_int) -> *const c_char {
+    catch(ptr::null(), || {
+        stmt.as_ref()
+            .and_then(|stmt| stmt.columns.get(usize::try_from(i).ok()?))
+            .map_or(ptr::null(), |name| name.as_ptr())
+    })
+}
+
+// type of the value in the current row, SHARKDB_NULL if there is no such column
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_column_type(stmt: *mut SharkdbStmt, i: c_int) -> c_int {
+    catch(SHARKDB_NULL, || match stmt.as_ref().and_then(|stmt| stmt.value(i)) {
+        Some(Value::Integer(_)) => SHARKDB_INTEGER,
+        Some(Value::Float(_)) => SHARKDB_FLOAT,
+        Some(Value::String(_)) | Some(Value::Vector(_)) => SHARKDB_TEXT,
+        Some(Value::Boolean(_)) => SHARKDB_BOOLEAN,
+        Some(Value::Null) | None => SHARKDB_NULL,
+    })
+}
+
+// the accessors convert between numbers and booleans, other values read as 0
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_column_int64(stmt: *mut SharkdbStmt, i: c_int) -> i64 {
+    catch(0, || match stmt.as_ref().and_then(|stmt| stmt.value(i)) {
+        Some(Value::Integer(v)) => *v,
+        Some(Value::Float(v)) => *v as i64,
+        Some(Value::Boolean(v)) => *v as i64,
+        _ => 0,
+    })
+}
+
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_column_double(stmt: *mut SharkdbStmt, i: c_int) -> f64 {
+    catch(0.0, || match stmt.as_ref().and_then(|stmt| stmt.value(i)) {
+        Some(Value::Integer(v)) => *v as f64,
+        Some(Value::Float(v)) => *v,
+        Some(Value::Boolean(v)) => *v as i64 as f64,
+        _ => 0.0,
+    })
+}
+
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_column_bool(stmt: *mut SharkdbStmt, i: c_int) -> c_int {
+    catch(0, || (sharkdb_column_int64(stmt, i) != 0) as c_int)
+}
+
+// NULL for a null value, other values are formatted as text
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_column_text(stmt: *mut SharkdbStmt, i: c_int) -> *const c_char {
+    catch(ptr::null(), || {
+        let Some(stmt) = stmt.as_mut() else {
+            return ptr::null();
+        };
+        let text = match stmt.value(i) {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Integer(v)) => v.to_string(),
+            Some(Value::Float(v)) => v.to_string(),
+            Some(Value::Boolean(v)) => v.to_string(),
+            Some(v @ Value::Vector(_)) => v.to_string(),
+            Some(Value::Null) | None => return ptr::null(),
+        };
+        stmt.texts[i as usize].get_or_insert_with(|| to_cstring(text)).as_ptr()
+    })
+}
+
+// any value as json text, null for a null value, NULL if there is no such column
+// integers and floats stay apart, see types::json
+/// # Safety
+/// stmt comes from sharkdb_prepare
+#[no_mangle]
+pub unsafe extern "C" fn sharkdb_column_json(stmt: *mut SharkdbStmt, i: c_int) -> *const c_char {
+    catch(ptr::null(), || {
+        let Some(stmt) = stmt.as_mut() else {
+            return ptr::null();
+        };
+        let Some(json) = stmt.value(i).map(|value| to_json(value).to_string()) else {
+            return ptr::null();
+        };
+        stmt.jsons[i as usize].get_or_insert_with(|| to_cstring(json)).as_ptr()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{CStr, CString},
+        ptr,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_capi() {
+        unsafe {
+            let mut db = ptr::null_mut();
+            assert_eq!(sharkdb_open(ptr::null(), &mut db), SHARKDB_OK);
+            assert_eq!(sharkdb_exec(db, c"create table t (a int, b text, c float, d boolean);".as_ptr()), SHARKDB_OK);
+            assert_eq!(sharkdb_exec(db, c"insert into t values (1, 'x', 1.5, true), (2, null, 2.5, false);".as_ptr()), SHARKDB_OK);
+            assert_eq!(sharkdb_changes(db), 2);
+
+            // 语句出错时通过 errmsg 获取错误信息
+            assert_eq!(sharkdb_exec(db, c"select * from nope;".as_ptr()), SHARKDB_ERROR);
+            assert!(CStr::from_ptr(sharkdb_errmsg(db)).to_str().unwrap().contains("nope"));
+
+            let mut stmt = ptr::null_mut();
+            assert_eq!(sharkdb_prepare(db, c"select * from t;".as_ptr(), &mut stmt), SHARKDB_OK);
+            assert_eq!(sharkdb_column_count(stmt), 4);
+            assert_eq!(CStr::from_ptr(sharkdb_column_name(stmt, 1)), c"b");
+            assert!(sharkdb_column_name(stmt, 4).is_null());
+
+            assert_eq!(sharkdb_step(stmt), SHARKDB_ROW);
+            assert_eq!(sharkdb_column_type(stmt, 0), SHARKDB_INTEGER);
+            assert_eq!(sharkdb_column_int64(stmt, 0), 1);
+            assert_eq!(CStr::from_ptr(sharkdb_column_text(stmt, 1)), c"x");
+            assert_eq!(sharkdb_column_double(stmt, 2), 1.5);
+            assert_eq!(sharkdb_column_type(stmt, 3), SHARKDB_BOOLEAN);
+            assert_eq!(sharkdb_column_bool(stmt, 3), 1);
+            // 非字符串的值按文本返回
+            assert_eq!(CStr::from_ptr(sharkdb_column_text(stmt, 0)), c"1");
+            assert_eq!(CStr::from_ptr(sharkdb_column_json(stmt, 1)), c"\"x\"");
+            assert_eq!(CStr::from_ptr(sharkdb_column_json(stmt, 2)), c"1.5");
+
+            assert_eq!(sharkdb_step(stmt), SHARKDB_ROW);
+            assert_eq!(sharkdb_column_type(stmt, 1), SHARKDB_NULL);
+            assert!(sharkdb_column_text(stmt, 1).is_null());
+            assert_eq!(CStr::from_ptr(sharkdb_column_json(stmt, 1)), c"null");
+            assert!(sharkdb_column_json(stmt, 4).is_null());
+            assert_eq!(sharkdb_step(stmt), SHARKDB_DONE);
+            assert_eq!(sharkdb_column_type(stmt, 0), SHARKDB_NULL);
+            assert_eq!(sharkdb_finalize(stmt), SHARKDB_OK);
+
+            // 空指针不会导致崩溃
+            assert_eq!(sharkdb_step(ptr::null_mut()), SHARKDB_MISUSE);
+            assert_eq!(sharkdb_open(ptr::null(), ptr::null_mut()), SHARKDB_MISUSE);
+
+            // panic 不会传到 c 代码里，变成 SHARKDB_ERROR 和错误信息
+            assert_eq!(catch_db(db, || panic!("boom")), SHARKDB_ERROR);
+            assert!(CStr::from_ptr(sharkdb_errmsg(db)).to_str().unwrap().contains("panic: boom"));
+            assert_eq!(catch(SHARKDB_NULL, || panic!("boom")), SHARKDB_NULL);
+            assert_eq!(sharkdb_close(db), SHARKDB_OK);
+
+            // 磁盘数据库关闭后再打开，数据仍然存在
+            let p = tempfile::tempdir().unwrap().into_path().join("sqldb-log");
+            let path = CString::new(p.to_str().unwrap()).unwrap();
+            let mut db = ptr::null_mut();
+            assert_eq!(sharkdb_open(path.as_ptr(), &mut db), SHARKDB_OK);
+            sharkdb_exec(db, c"create table t (a int);".as_ptr());
+            sharkdb_exec(db, c"insert into t values (7);".as_ptr());
+            sharkdb_close(db);
+            assert_eq!(sharkdb_open(path.as_ptr(), &mut db), SHARKDB_OK);
+            sharkdb_prepare(db, c"select * from t;".as_ptr(), &mut stmt);
+            assert_eq!(sharkdb_step(stmt), SHARKDB_ROW);
+            assert_eq!(sharkdb_column_int64(stmt, 0), 7);
+            sharkdb_finalize(stmt);
+            sharkdb_close(db);
+            std::fs::remove_dir_all(p.parent().unwrap()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::Read;
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::Engine,
+        executor::{make_row, pad_row},
+        schema::Table,
+        types::{Row, Value},
+    },
+};
+
+use super::{ImportOptions, ImportReport, Importer};
+
+pub struct CsvOptions {
+    pub delimiter: u8,
+    // the first record names the columns, otherwise fields follow the table columns
+    pub has_header: bool,
+    // fields equal to this are null
+    pub null_value: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self { delimiter: b',', has_header: true, null_value: String::new() }
+    }
+}
+
+impl CsvOptions {
+    pub fn tsv() -> Self {
+        Self { delimiter: b'\t', ..Default::default() }
+    }
+}
+
+// load csv or tsv records into an existing table
+pub fn import_csv<E: Engine>(
+    engine: &E,
+    table_name: &str,
+    reader: impl Read,
+    csv: &CsvOptions,
+    options: &ImportOptions,
+    mut progress: impl FnMut(&ImportReport),
+) -> Result<ImportReport> {
+    let mut importer = Importer::new(engine, table_name, options, &mut progress)?;
+    let mut reader = ::csv::ReaderBuilder::new()
+        .delimiter(csv.delimiter)
+        .has_headers(csv.has_header)
+        .flexible(true)
+        .from_reader(reader);
+
+    let columns = if csv.has_header {
+        let columns: Vec<String> = reader.headers()?.iter().map(|h| h.trim().to_string()).collect();
+        if let Some(name) = columns.iter().find(|name| importer.table().columns.iter().all(|c| &c.name != *name)) {
+            return Err(Error::ColumnNotFound { table: table_name.to_string(), column: name.clone(), position: None });
+        }
+        Some(columns)
+    } else {
+        None
+    };
+
+    let mut record = ::csv::StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            // a line that is not valid csv, e.g. bad utf-8
+            Err(err) => {
+                let line = err.position().map_or(0, |p| p.line());
+                importer.push(line, &[], Err(err.into()))?;
+                continue;
+            }
+        }
+        let line = record.position().map_or(0, |p| p.line());
+        let row = parse_record(&record, columns.as_deref(), csv, importer.table());
+        importer.push(line, &write_record(&record, csv.delimiter)?, row)?;
+    }
+    importer.finish()
+}
+
+fn parse_record(
+    record: &::csv::StringRecord,
+    columns: Option<&[String]>,
+    csv: &CsvOptions,
+    table: &Table,
+) -> Result<Row> {
+    let parse = |field: &str, name: &str| -> Result<Value> {
+        if field == csv.null_value {
+            return Ok(Value::Null);
+        }
+        let col = table.columns.iter().find(|c| c.name == name).unwrap();
+        Value::parse_as(field, &col.datatype)
+            .map_err(|err| Error::Parse(format!("column {}: {}", name, err)))
+    };
+    match columns {
+        Some(columns) => {
+            if record.len() != columns.len() {
+                return Err(Error::Parse(format!("expect {} fields, got {}", columns.len(), record.len())));
+            }
+            let values = record.iter().zip(columns).map(|(f, name)| parse(f, name)).collect::<Result<Row>>()?;
+            make_row(table, columns, &values)
+        }
+        None => {
+            if record.len() > table.columns.len() {
+                return Err(Error::Parse(format!("expect at most {} fields, got {}", table.columns.len(), record.len())));
+            }
+            let values = record.iter().zip(&table.columns).map(|(f, col)| parse(f, &col.name)).collect::<Result<Row>>()?;
+            pad_row(table, &values)
+        }
+    }
+}
+
+// the record as it would appear in the input, for the bad rows file
+fn write_record(record: &::csv::StringRecord, delimiter: u8) -> Result<Vec<u8>> {
+    let mut w = ::csv::WriterBuilder::new().delimiter(delimiter).from_writer(Vec::new());
+    w.write_record(record)?;
+    w.into_inner().map_err(|err| Error::Internal(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{import_csv, CsvOptions};
+    use crate::{
+        error::{Error, Result},
+        import::{ImportOptions, ImportReport},
+        sql::{engine::{kv::KVEngine, Engine}, executor::ResultSet, types::Value},
+        storage::memory::MemoryEngine,
+    };
+
+    fn setup() -> Result<KVEngine<MemoryEngine>> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        engine.session()?.execute("create table t (a int, b text, c boolean default true, d float not null);")?;
+        Ok(engine)
+    }
+
+    fn rows(engine: &KVEngine<MemoryEngine>) -> Result<Vec<Vec<Value>>> {
+        match engine.session()?.execute("select * from t;")? {
+            ResultSet::Scan { row, .. } => Ok(row),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_import_csv() -> Result<()> {
+        let engine = setup()?;
+        let data = "a,b,d\n1,x,1.5\n2,,2.5\n3,\"y,z\",3.5\n4,w,4.5\n5,v,5.5\n";
+        let mut progress = Vec::new();
+        let options = ImportOptions { batch_size: 2, bad_rows: None };
+        let report = import_csv(&engine, "t", data.as_bytes(), &CsvOptions::default(), &options, |r| {
+            progress.push(r.clone())
+        })?;
+        assert_eq!(report, ImportReport { imported: 5, rejected: 0, batches: 3 });
+        // 每提交一批汇报一次进度
+        assert_eq!(progress.iter().map(|r| r.imported).collect::<Vec<_>>(), vec![2, 4, 5]);
+
+        // 按表头对应列，缺少的列使用默认值，空字段为 null
+        let rows = rows(&engine)?;
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], vec![Value::Integer(1), Value::String("x".into()), Value::Boolean(true), Value::Float(1.5)]);
+        assert_eq!(rows[1][1], Value::Null);
+        assert_eq!(rows[2][1], Value::String("y,z".into()));
+
+        // 表头中的列不存在
+        let err = import_csv(&engine, "t", "a,e\n1,2\n".as_bytes(), &CsvOptions::default(), &options, |_| {});
+        assert!(matches!(err, Err(Error::ColumnNotFound { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_bad_rows() -> Result<()> {
+        let engine = setup()?;
+        let p = tempfile::tempdir()?.into_path().join("bad-rows.tsv");
+        // 类型错误、字段过多、不能为空的列没有默认值、不能为空的列为空、主键重复
+        let data = "1\ta\tfalse\t0.5\nx\tb\ttrue\t1\n2\tc\t1\t2\t3\n3\td\n4\te\t0\t4\n5\tf\t1\t\n1\tg\t1\t1\n";
+        let options = ImportOptions { batch_size: 10, bad_rows: Some(p.clone()) };
+        let csv = CsvOptions { has_header: false, ..CsvOptions::tsv() };
+        let report = import_csv(&engine, "t", data.as_bytes(), &csv, &options, |_| {})?;
+        assert_eq!(report, ImportReport { imported: 2, rejected: 5, batches: 1 });
+        assert_eq!(fs::read_to_string(&p)?, "x\tb\ttrue\t1\n2\tc\t1\t2\t3\n3\td\n5\tf\t1\t\n1\tg\t1\t1\n");
+
+        let rows = rows(&engine)?;
+        assert_eq!(rows[0], vec![Value::Integer(1), Value::String("a".into()), Value::Boolean(false), Value::Float(0.5)]);
+        assert_eq!(rows[1], vec![Value::Integer(4), Value::String("e".into()), Value::Boolean(false), Value::Float(4.0)]);
+
+        fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+// random rows for load tests and demos
+// values follow the column type and loosely the column name, like name, email, age or price
+// the first column is the primary key, its values are unique and never null
+// nullable columns are null one time in ten
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{Engine, Transaction},
+        schema::{Column, Table},
+        types::{DataType, Row, Value},
+    },
+};
+
+use super::{ImportOptions, ImportReport, Importer};
+
+const FIRST_NAMES: &[&str] = &[
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi", "ivan", "judy", "mallory", "nina", "oscar",
+    "peggy", "rupert", "sybil", "trent", "victor", "wendy", "zoe",
+];
+const LAST_NAMES: &[&str] = &[
+    "smith", "johnson", "williams", "brown", "jones", "garcia", "miller", "davis", "wang", "li", "zhang", "liu",
+    "chen", "yang", "huang", "zhao", "wu", "zhou", "kim", "lee",
+];
+const CITIES: &[&str] = &[
+    "beijing", "shanghai", "shenzhen", "hangzhou", "london", "paris", "berlin", "tokyo", "seoul", "new york",
+    "san francisco", "toronto", "sydney", "singapore",
+];
+const WORDS: &[&str] = &[
+    "lorem", "ipsum", "dolor", "sit", "amet", "shark", "ocean", "reef", "tide", "wave", "coral", "deep", "blue",
+    "swift", "quiet", "storm", "harbor", "anchor", "drift", "current",
+];
+
+// splitmix64, the same seed always gives the same rows
+pub struct Generator {
+    state: u64,
+    // rows generated so far, keys are derived from it
+    seq: u64,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed, seq: 0 }
+    }
+
+    // keys continue after offset, to add rows to a table generated before
+    pub fn start_at(mut self, offset: u64) -> Self {
+        self.seq = offset;
+        self
+    }
+
+    pub fn row(&mut self, table: &Table) -> Result<Row> {
+        self.seq += 1;
+        let mut row = Vec::with_capacity(table.columns.len());
+        for (i, col) in table.columns.iter().enumerate() {
+            row.push(if i == 0 {
+                self.key(col)?
+            } else if col.nullable && self.below(10) == 0 {
+                Value::Null
+            } else {
+                self.value(col)
+            });
+        }
+        Ok(row)
+    }
+
+    pub fn rows(&mut self, table: &Table, n: u64) -> Result<Vec<Row>> {
+        (0..n).map(|_| self.row(table)).collect()
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    // uniform in [min, max]
+    fn between(&mut self, min: i64, max: i64) -> i64 {
+        min + self.below((max - min + 1) as u64) as i64
+    }
+
+    // uniform in [min, max)
+    fn float(&mut self, min: f64, max: f64) -> f64 {
+        min + (self.next() >> 11) as f64 / (1u64 << 53) as f64 * (max - min)
+    }
+
+    fn pick(&mut self, items: &[&'static str]) -> &'static str {
+        items[self.below(items.len() as u64) as usize]
+    }
+
+    // unique by seq
+    fn key(&mut self, col: &Column) -> Result<Value> {
+        Ok(match col.datatype {
+            DataType::Integer => Value::Integer(self.seq as i64),
+            DataType::Float => Value::Float(self.seq as f64),
+            DataType::String => match self.value(col) {
+                Value::String(s) if col.name.to_lowercase().contains("email") => {
+                    Value::String(s.replacen('@', &format!("{}@", self.seq), 1))
+                }
+                Value::String(s) => Value::String(format!("{}-{}", s, self.seq)),
+                value => value,
+            },
+            DataType::Boolean if self.seq <= 2 => Value::Boolean(self.seq == 2),
+            DataType::Boolean => {
+                return Err(Error::Internal(format!("Boolean key column {} has only 2 unique values", col.name)))
+            }
+            DataType::Vector(_) => return Err(Error::Internal(format!("Vector column {} can not be a key", col.name))),
+            DataType::Enum(ref labels) => match (self.seq as usize).checked_sub(1).and_then(|i| labels.get(i)) {
+                Some(label) => Value::String(label.clone()),
+                None => {
+                    return Err(Error::Internal(format!(
+                        "Enum key column {} has only {} unique values",
+                        col.name,
+                        labels.len()
+                    )))
+                }
+            },
+        })
+    }
+
+    fn value(&mut self, col: &Column) -> Value {
+        let name = col.name.to_lowercase();
+        let is = |words: &[&str]| words.iter().any(|w| name.contains(w));
+        match col.datatype {
+            DataType::Boolean => Value::Boolean(self.below(2) == 0),
+            DataType::Integer => Value::Integer(if is(&["age"]) {
+                self.between(18, 90)
+            } else if is(&["year"]) {
+                self.between(1970, 2025)
+            } else if is(&["count", "qty", "quantity", "num"]) {
+                self.between(0, 100)
+            } else {
+                self.between(0, 1_000_000)
+            }),
+            DataType::Float => Value::Float(if is(&["price", "amount", "cost", "salary", "balance"]) {
+                (self.float(1.0, 10_000.0) * 100.0).round() / 100.0
+            } else if is(&["lat"]) {
+                self.float(-90.0, 90.0)
+            } else if is(&["lon", "lng"]) {
+                self.float(-180.0, 180.0)
+            } else {
+                self.float(0.0, 1.0)
+            }),
+            DataType::String => Value::String(if is(&["email"]) {
+                format!("{}.{}@example.com", self.pick(FIRST_NAMES), self.pick(LAST_NAMES))
+            } else if is(&["first"]) {
+                self.pick(FIRST_NAMES).to_string()
+            } else if is(&["last"]) {
+                self.pick(LAST_NAMES).to_string()
+            } else if is(&["name", "user", "author"]) {
+                format!("{} {}", self.pick(FIRST_NAMES), self.pick(LAST_NAMES))
+            } else if is(&["city"]) {
+                self.pick(CITIES).to_string()
+            } else if is(&["phone"]) {
+                format!("555-{:04}", self.below(10_000))
+            } else {
+                let n = self.between(1, 3);
+                (0..n).map(|_| self.pick(WORDS)).collect::<Vec<_>>().join(" ")
+            }),
+            DataType::Vector(n) => Value::Vector((0..n).map(|_| self.float(-1.0, 1.0) as f32).collect()),
+            DataType::Enum(ref labels) => Value::String(labels[self.below(labels.len() as u64) as usize].clone()),
+        }
+    }
+}
+
+// insert n generated rows into an existing table, in batches like an import
+// keys continue after the rows already in the table
+pub fn generate<E: Engine>(
+    engine: &E,
+    table_name: &str,
+    n: u64,
+    seed: u64,
+    options: &ImportOptions,
+    mut progress: impl FnMut(&ImportReport),
+) -> Result<ImportReport> {
+    let txn = engine.begin()?;
+    let rows = txn.scan_table(table_name.to_string());
+    txn.rollback()?;
+    let rows = rows?;
+    let offset = rows
+        .iter()
+        .map(|row| match row.first() {
+            Some(Value::Integer(i)) => *i as u64,
+            Some(Value::Float(f)) => *f as u64,
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+        .max(rows.len() as u64);
+
+    let mut importer = Importer::new(engine, table_name, options, &mut progress)?;
+    let mut generator = Generator::new(seed).start_at(offset);
+    for line in 1..=n {
+        let row = generator.row(importer.table())?;
+        importer.push(line, b"", Ok(row))?;
+    }
+    importer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Result,
+        import::ImportOptions,
+        sql::{
+            engine::{kv::KVEngine, Engine, Transaction},
+            executor::ResultSet,
+            parser::Parser,
+            plan::Plan,
+            schema::Table,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    use super::{generate, Generator};
+
+    fn table(sql: &str) -> Result<Table> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        let mut txn = engine.begin()?;
+        let name = match Plan::build(Parser::new(sql).parse()?, &txn)?.execute(&mut txn)? {
+            ResultSet::CreateTable { table_name } => table_name,
+            _ => unreachable!(),
+        };
+        txn.must_get_table(name)
+    }
+
+    #[test]
+    fn test_generate_rows() -> Result<()> {
+        let t = table("create table users (email text not null, name text not null, age int, score float, active bool);")?;
+        let rows = Generator::new(7).rows(&t, 500)?;
+        for row in &rows {
+            t.check_row(row)?;
+            match &row[2] {
+                Value::Integer(age) => assert!((18..=90).contains(age)),
+                v => assert_eq!(v, &Value::Null),
+            }
+        }
+        // 主键唯一
+        let mut keys = rows.iter().map(|r| format!("{:?}", r[0])).collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 500);
+        // 可空列有空值，非空列没有
+        assert!(rows.iter().any(|r| r[3] == Value::Null));
+        assert!(rows.iter().all(|r| r[1] != Value::Null));
+        // 相同种子生成相同数据
+        assert_eq!(Generator::new(7).rows(&t, 500)?, rows);
+        assert_ne!(Generator::new(8).rows(&t, 500)?, rows);
+
+        // 布尔主键只有两个取值
+        let t = table("create table flags (f bool, a int);")?;
+        assert!(Generator::new(1).rows(&t, 2).is_ok());
+        assert!(Generator::new(1).rows(&t, 3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_into_table() -> Result<()> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        engine.session()?.execute("create table t (id int not null, name text, price float not null);")?;
+        let options = ImportOptions { batch_size: 100, ..Default::default() };
+        let report = generate(&engine, "t", 250, 1, &options, |_| {})?;
+        assert_eq!((report.imported, report.rejected, report.batches), (250, 0, 3));
+        // 再次生成时主键接着已有的行，不会覆盖
+        generate(&engine, "t", 250, 1, &options, |_| {})?;
+        match engine.session()?.execute("select * from t;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row.len(), 500),
+            _ => unreachable!(),
+        }
+        assert!(generate(&engine, "missing", 1, 1, &options, |_| {}).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use std::io::BufRead;
+
+use serde_json::Value as Json;
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::Engine,
+        executor::make_row,
+        schema::Table,
+        types::{DataType, Row, Value},
+    },
+};
+
+use super::{ImportOptions, ImportReport, Importer};
+
+#[derive(Default)]
+pub struct JsonOptions {
+    // convert fields to the column type, e.g. "42" into an int column
+    // otherwise only integers are accepted for float columns
+    pub coerce: bool,
+    // drop fields which are not columns of the table instead of rejecting the line
+    pub ignore_unknown: bool,
+}
+
+// load newline-delimited json, one object per line, into an existing table
+// fields map to columns by name, missing fields take the column default
+pub fn import_ndjson<E: Engine>(
+    engine: &E,
+    table_name: &str,
+    mut reader: impl BufRead,
+    json: &JsonOptions,
+    options: &ImportOptions,
+    mut progress: impl FnMut(&ImportReport),
+) -> Result<ImportReport> {
+    let mut importer = Importer::new(engine, table_name, options, &mut progress)?;
+    let mut buf = Vec::new();
+    let mut line = 0;
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        line += 1;
+        if buf.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let row = serde_json::from_slice(&buf)
+            .map_err(Error::from)
+            .and_then(|object| parse_object(object, json, importer.table()));
+        importer.push(line, &buf, row)?;
+    }
+    importer.finish()
+}
+
+fn parse_object(object: Json, json: &JsonOptions, table: &Table) -> Result<Row> {
+    let Json::Object(object) = object else {
+        return Err(Error::Parse("expect a json object".to_string()));
+    };
+    let mut columns = Vec::new();
+    let mut values = Vec::new();
+    for (name, field) in object {
+        let Some(col) = table.columns.iter().find(|c| c.name == name) else {
+            if json.ignore_unknown {
+                continue;
+            }
+            return Err(Error::Parse(format!("column {} does not exist in table {}", name, table.name)));
+        };
+        let value = json_to_value(field, &col.datatype, json.coerce)
+            .map_err(|err| Error::Parse(format!("column {}: {}", name, err)))?;
+        columns.push(name);
+        values.push(value);
+    }
+    make_row(table, &columns, &values)
+}
+
+fn json_to_value(field: Json, datatype: &DataType, coerce: bool) -> Result<Value> {
+    Ok(match (field, datatype) {
+        (Json::Null, _) => Value::Null,
+        (Json::Bool(b), DataType::Boolean) => Value::Boolean(b),
+        (Json::String(s), DataType::String | DataType::Enum(_)) => Value::String(s),
+        (Json::Number(n), DataType::Integer) if n.is_i64() => Value::Integer(n.as_i64().unwrap()),
+        (Json::Number(n), DataType::Float) => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        (Json::Array(items), DataType::Vector(_)) if items.iter().all(Json::is_number) => {
+            Value::Vector(items.iter().map(|n| n.as_f64().unwrap_or(f64::NAN) as f32).collect())
+        }
+        (Json::String(s), datatype) if coerce => Value::parse_as(&s, datatype)?,
+        (Json::Number(n), DataType::Integer) if coerce && whole_i64(&n).is_some() => Value::Integer(whole_i64(&n).unwrap()),
+        (field, DataType::String) if coerce => Value::String(field.to_string()),
+        (Json::Bool(b), DataType::Integer) if coerce => Value::Integer(b as i64),
+        (Json::Number(n), DataType::Boolean) if coerce && (n.as_i64() == Some(0) || n.as_i64() == Some(1)) => {
+            Value::Boolean(n.as_i64() == Some(1))
+        }
+        (field, datatype) => return Err(Error::Parse(format!("cannot convert {} to {}", field, datatype))),
+    })
+}
+
+// a float with no fraction inside the range of i64, e.g. 4.0, not 1.5, 1e30 or a u64 above i64::MAX
+fn whole_i64(n: &serde_json::Number) -> Option<i64> {
+    n.as_i64().or_else(|| {
+        // i64::MAX as f64 is 2^63, one past the largest i64
+        let f = n.as_f64().filter(|f| f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64)?;
+        Some(f as i64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{import_ndjson, json_to_value, JsonOptions};
+    use crate::{
+        error::Result,
+        import::{ImportOptions, ImportReport},
+        sql::{engine::{kv::KVEngine, Engine}, executor::ResultSet, types::{DataType, Value}},
+        storage::memory::MemoryEngine,
+    };
+
+    fn rows(engine: &KVEngine<MemoryEngine>) -> Result<Vec<Vec<Value>>> {
+        match engine.session()?.execute("select * from t;")? {
+            ResultSet::Scan { row, .. } => Ok(row),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_import_ndjson() -> Result<()> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        engine.session()?.execute("create table t (a int, b text, c float, d boolean default false);")?;
+        let p = tempfile::tempdir()?.into_path().join("bad-rows.ndjson");
+        let data = concat!(
+            "{\"a\": 1, \"b\": \"x\", \"c\": 1.5, \"d\": true}\n",
+            "\n",
+            "{\"c\": 2, \"a\": 2}\n",
+            "{\"a\": \"3\", \"b\": \"y\"}\n",
+            "{\"a\": 4, \"e\": 1}\n",
+            "not json\n",
+            "[1, 2]\n",
+        );
+        let options = ImportOptions { batch_size: 10, bad_rows: Some(p.clone()) };
+        let report = import_ndjson(&engine, "t", data.as_bytes(), &JsonOptions::default(), &options, |_| {})?;
+        assert_eq!(report, ImportReport { imported: 2, rejected: 4, batches: 1 });
+        assert_eq!(
+            fs::read_to_string(&p)?,
+            "{\"a\": \"3\", \"b\": \"y\"}\n{\"a\": 4, \"e\": 1}\nnot json\n[1, 2]\n"
+        );
+        let r = rows(&engine)?;
+        assert_eq!(r[0], vec![Value::Integer(1), Value::String("x".into()), Value::Float(1.5), Value::Boolean(true)]);
+        // 缺少的字段使用默认值，整数可以写入浮点列
+        assert_eq!(r[1], vec![Value::Integer(2), Value::Null, Value::Float(2.0), Value::Boolean(false)]);
+
+        // 开启类型转换并忽略多余字段后，之前被拒绝的行可以导入
+        let json = JsonOptions { coerce: true, ignore_unknown: true };
+        let data = "{\"a\": \"3\", \"b\": 7, \"d\": 1}\n{\"a\": 4.0, \"e\": 1, \"b\": {\"k\": [1]}}\n";
+        let report = import_ndjson(&engine, "t", data.as_bytes(), &json, &ImportOptions::default(), |_| {})?;
+        assert_eq!(report, ImportReport { imported: 2, rejected: 0, batches: 1 });
+        let r = rows(&engine)?;
+        assert_eq!(r[2], vec![Value::Integer(3), Value::String("7".into()), Value::Null, Value::Boolean(true)]);
+        assert_eq!(r[3][1], Value::String("{\"k\":[1]}".into()));
+
+        fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_to_value_integer() -> Result<()> {
+        let integer = |json: &str| json_to_value(serde_json::from_str(json)?, &DataType::Integer, true);
+        assert_eq!(integer("4.0")?, Value::Integer(4));
+        assert_eq!(integer("-9223372036854775808")?, Value::Integer(i64::MIN));
+        assert_eq!(integer("9223372036854775807")?, Value::Integer(i64::MAX));
+        // 有小数部分、超出 i64 范围的数字和其他不匹配的字段一样报错，不会被截断
+        for json in ["1.5", "1e30", "9223372036854775808", "18446744073709551615", "-1e19"] {
+            assert!(integer(json).is_err_and(|err| err.to_string().contains("cannot convert")), "{}", json);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    error::Result,
+    sql::{
+        engine::{Engine, Transaction},
+        schema::Table,
+        types::Row,
+    },
+};
+
+pub mod csv;
+pub mod generate;
+pub mod json;
+
+pub struct ImportOptions {
+    // rows committed in one transaction
+    pub batch_size: usize,
+    // rejected input lines are appended to this file
+    pub bad_rows: Option<PathBuf>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self { batch_size: 1000, bad_rows: None }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportReport {
+    pub imported: u64,
+    pub rejected: u64,
+    pub batches: u64,
+}
+
+// loads rows into one table, one transaction and one storage batch per batch of rows
+// bad input and duplicate keys are rejected per row, storage errors abort the import
+// batches committed before the error stay in the table
+pub(crate) struct Importer<'a, E: Engine> {
+    engine: &'a E,
+    table: Table,
+    batch_size: usize,
+    // line, raw input if bad rows are kept, and the row
+    pending: Vec<(u64, Vec<u8>, Row)>,
+    bad_rows: Option<BufWriter<File>>,
+    report: ImportReport,
+    progress: &'a mut dyn FnMut(&ImportReport),
+}
+
+impl<'a, E: Engine> Importer<'a, E> {
+    pub fn new(
+        engine: &'a E,
+        table_name: &str,
+        options: &ImportOptions,
+        progress: &'a mut dyn FnMut(&ImportReport),
+    ) -> Result<Self> {
+        let txn = engine.begin()?;
+        let table = txn.must_get_table(table_name.to_string());
+        txn.rollback()?;
+        let table = table?;
+        let bad_rows = match &options.bad_rows {
+            Some(path) => Some(BufWriter::new(File::create(path)?)),
+            None => None,
+        };
+        Ok(Self {
+            engine,
+            table,
+            batch_size: options.batch_size.max(1),
+            pending: Vec::new(),
+            bad_rows,
+            report: ImportReport::default(),
+            progress,
+        })
+    }
+
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    // raw is the input line as read, written to the bad rows file if the row is rejected
+    pub fn push(&mut self, line: u64, raw: &[u8], row: Result<Row>) -> Result<()> {
+        let row = row.map(|row| self.table.coerce_row(row));
+        let row = match row.and_then(|row| self.table.check_row(&row).map(|()| row)) {
+            Ok(row) => row,
+            Err(err) => return self.reject(line, raw, &err.to_string()),
+        };
+        let raw = match self.bad_rows {
+            Some(_) => raw.to_vec(),
+            None => Vec::new(),
+        };
+        self.pending.push((line, raw, row));
+        if self.pending.len() >= self.batch_size {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn reject(&mut self, line: u64, raw: &[u8], reason: &str) -> Result<()> {
+        tracing::warn!(table = self.table.name, line, reason, "row rejected");
+        self.report.rejected += 1;
+        if let Some(w) = &mut self.bad_rows {
+            w.write_all(raw)?;
+            if !raw.ends_with(b"\n") {
+                w.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let (inputs, rows): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.pending).into_iter().map(|(line, raw, row)| ((line, raw), row)).unzip();
+        let mut txn = self.engine.begin()?;
+        let rejected = match txn.bulk_insert(&self.table, rows).and_then(|rejected| txn.commit().map(|()| rejected)) {
+            Ok(rejected) => rejected,
+            Err(err) => {
+                txn.rollback()?;
+                return Err(err);
+            }
+        };
+        // rows rejected were not written, the rest of the batch was
+        for (i, err) in &rejected {
+            let (line, raw) = &inputs[*i];
+            self.reject(*line, raw, &err.to_string())?;
+        }
+        self.report.imported += (inputs.len() - rejected.len()) as u64;
+        self.report.batches += 1;
+        (self.progress)(&self.report);
+        Ok(())
+    }
+
+    // commits the last batch
+    pub fn finish(mut self) -> Result<ImportReport> {
+        self.commit()?;
+        if let Some(w) = &mut self.bad_rows {
+            w.flush()?;
+        }
+        Ok(self.report.clone())
+    }
+}
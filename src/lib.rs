@@ -1,3 +1,21 @@
 pub mod sql;
 pub mod error;
-pub mod storage;
\ No newline at end of file
+pub mod storage;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "import")]
+pub mod import;
+#[cfg(feature = "export")]
+pub mod export;
+pub mod migrate;
+pub mod database;
+#[cfg(feature = "disk")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "python")]
+mod python;
+
+pub use database::Database;
@@ -0,0 +1,406 @@
+// schema migrations for applications embedding the database
+//   const MIGRATIONS: &[Migration] = &[
+//       Migration { version: 1, name: "users", up: "create table users (id int, name text);", down: Some("drop table users;") },
+//   ];
+//   Migrator::new(engine).run(MIGRATIONS)?;
+// applied versions are recorded in the schema_migrations table,
+// each migration and its record are committed in one transaction
+// a column added by ALTER TABLE is read as its default from the rows stored before it,
+// Migrator::backfill writes it into them after the migration, in batches
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{Engine, Transaction},
+        parser::Parser,
+        plan::Plan,
+        types::{format_row, Value},
+    },
+};
+
+pub const MIGRATIONS_TABLE: &str = "schema_migrations";
+
+const CREATE_MIGRATIONS_TABLE: &str =
+    "create table schema_migrations (version int not null, name text not null, applied_at int not null);";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Migration {
+    // applied in ascending order, never reuse or reorder a released version
+    pub version: u64,
+    pub name: &'static str,
+    // sql statements, each ended by ";"
+    pub up: &'static str,
+    // undoes up, a migration without it can not be rolled back
+    pub down: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BackfillReport {
+    // rows looked at
+    pub scanned: u64,
+    // rows written again with the defaults of the columns they were missing
+    pub rewritten: u64,
+    pub batches: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedMigration {
+    pub version: u64,
+    pub name: String,
+    // unix seconds
+    pub applied_at: u64,
+}
+
+pub struct Migrator<E: Engine> {
+    engine: E,
+}
+
+impl<E: Engine> Migrator<E> {
+    pub fn new(engine: E) -> Self {
+        Self { engine }
+    }
+
+    // migrations recorded in the database, by version
+    pub fn applied(&self) -> Result<Vec<AppliedMigration>> {
+        let txn = self.engine.begin()?;
+        let result = applied(&txn);
+        txn.rollback()?;
+        result
+    }
+
+    // apply the migrations not applied yet, returns their versions
+    // running the same list again does nothing
+    pub fn run(&self, migrations: &[Migration]) -> Result<Vec<u64>> {
+        check_order(migrations)?;
+        let applied = self.applied()?;
+        let latest = applied.last().map(|m| m.version).unwrap_or(0);
+        for m in &applied {
+            match migrations.iter().find(|migration| migration.version == m.version) {
+                Some(migration) if migration.name == m.name => {}
+                Some(migration) => {
+                    return Err(Error::Internal(format!(
+                        "Migration {} was applied as {}, but is now named {}",
+                        m.version, m.name, migration.name
+                    )))
+                }
+                None => return Err(Error::Internal(format!("Applied migration {} {} is missing", m.version, m.name))),
+            }
+        }
+
+        let mut versions = Vec::new();
+        for migration in migrations.iter().filter(|m| !applied.iter().any(|a| a.version == m.version)) {
+            // a migration added below the latest applied one would run against a newer schema
+            if migration.version < latest {
+                return Err(Error::Internal(format!(
+                    "Migration {} {} is older than the applied migration {}",
+                    migration.version, migration.name, latest
+                )));
+            }
+            self.apply(migration.up, |txn| {
+                if txn.get_table(MIGRATIONS_TABLE.to_string())?.is_none() {
+                    execute(txn, CREATE_MIGRATIONS_TABLE)?;
+                }
+                let applied_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                let table = txn.must_get_table(MIGRATIONS_TABLE.to_string())?;
+                txn.create_row(&table, vec![
+                    Value::Integer(migration.version as i64),
+                    Value::String(migration.name.to_string()),
+                    Value::Integer(applied_at as i64),
+                ])
+            })?;
+            tracing::info!(target: "sharkdb::migrate", version = migration.version, name = migration.name, "migration applied");
+            versions.push(migration.version);
+        }
+        Ok(versions)
+    }
+
+    // roll back the applied migrations newer than version, newest first, returns their versions
+    pub fn rollback_to(&self, migrations: &[Migration], version: u64) -> Result<Vec<u64>> {
+        check_order(migrations)?;
+        let mut versions = Vec::new();
+        for m in self.applied()?.into_iter().rev().filter(|m| m.version > version) {
+            let down = match migrations.iter().find(|migration| migration.version == m.version) {
+                Some(Migration { down: Some(down), .. }) => *down,
+                Some(_) => return Err(Error::Internal(format!("Migration {} {} has no down migration", m.version, m.name))),
+                None => return Err(Error::Internal(format!("Applied migration {} {} is missing", m.version, m.name))),
+            };
+            self.apply(down, |txn| txn.delete_row(MIGRATIONS_TABLE.to_string(), &Value::Integer(m.version as i64)))?;
+            tracing::info!(target: "sharkdb::migrate", version = m.version, name = m.name, "migration rolled back");
+            versions.push(m.version);
+        }
+        Ok(versions)
+    }
+
+    // write the defaults of added columns into the rows of the table stored before them,
+    // batch_size rows per transaction, so writers are held up by one batch at a time
+    // batches committed before an error stay written, running it again goes on with the rest
+    pub fn backfill(
+        &self,
+        table_name: &str,
+        batch_size: usize,
+        mut progress: impl FnMut(&BackfillReport),
+    ) -> Result<BackfillReport> {
+        // rows inserted from now on are written with every column, the primary keys are read a batch at
+        // a time after the last one of the batch before, so the table is never held in memory
+        let mut report = BackfillReport::default();
+        let mut after = None;
+        loop {
+            let mut txn = self.engine.begin()?;
+            let result = txn
+                .scan_ids(table_name.to_string(), after.take(), batch_size.max(1))
+                .and_then(|ids| Ok((txn.fill_defaults(table_name.to_string(), &ids)?, ids)));
+            let (rewritten, ids) = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    txn.rollback()?;
+                    return Err(err);
+                }
+            };
+            txn.commit()?;
+            if ids.is_empty() {
+                break;
+            }
+            report.rewritten += rewritten as u64;
+            report.scanned += ids.len() as u64;
+            report.batches += 1;
+            progress(&report);
+            after = ids.into_iter().last();
+        }
+        tracing::info!(
+            target: "sharkdb::migrate", table = table_name, rows = report.scanned, rewritten = report.rewritten, "backfill done"
+        );
+        Ok(report)
+    }
+
+    // run the sql and record it in one transaction, nothing is left behind if any statement fails
+    fn apply<F>(&self, sql: &str, record: F) -> Result<()>
+    where
+        F: FnOnce(&mut E::Transaction) -> Result<()>,
+    {
+        let stmts = Parser::new(sql).parse_all()?;
+        let mut txn = self.engine.begin()?;
+        let result = record(&mut txn).and_then(|_| {
+            for stmt in stmts {
+                Plan::build(stmt, &txn)?.execute(&mut txn)?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => txn.commit(),
+            Err(err) => {
+                txn.rollback()?;
+                Err(err)
+            }
+        }
+    }
+}
+
+fn check_order(migrations: &[Migration]) -> Result<()> {
+    for pair in migrations.windows(2) {
+        if pair[0].version >= pair[1].version {
+            return Err(Error::Internal(format!(
+                "Migration {} {} must come after {} {}",
+                pair[0].version, pair[0].name, pair[1].version, pair[1].name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn execute<T: Transaction>(txn: &mut T, sql: &str) -> Result<()> {
+    Plan::build(Parser::new(sql).parse()?, txn)?.execute(txn)?;
+    Ok(())
+}
+
+fn applied<T: Transaction>(txn: &T) -> Result<Vec<AppliedMigration>> {
+    if txn.get_table(MIGRATIONS_TABLE.to_string())?.is_none() {
+        return Ok(Vec::new());
+    }
+    let mut applied = txn
+        .scan_table(MIGRATIONS_TABLE.to_string())?
+        .into_iter()
+        .map(|row| match row.as_slice() {
+            [Value::Integer(version), Value::String(name), Value::Integer(applied_at)] => Ok(AppliedMigration {
+                version: *version as u64,
+                name: name.clone(),
+                applied_at: *applied_at as u64,
+            }),
+            _ => Err(Error::Internal(format!("Invalid row in {}: {}", MIGRATIONS_TABLE, format_row(&row)))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    applied.sort_by_key(|m| m.version);
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "disk")]
+    use std::fs;
+
+    use crate::{
+        error::Result,
+        sql::engine::{kv::KVEngine, Engine, Transaction},
+        storage::memory::MemoryEngine,
+    };
+    #[cfg(feature = "disk")]
+    use crate::{sql::types::Value, storage::disk::DiskEngine};
+
+    use super::{Migration, Migrator};
+    #[cfg(feature = "disk")]
+    use super::BackfillReport;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "users",
+            up: "create table users (id int not null, name text);",
+            down: Some("drop table users;"),
+        },
+        Migration {
+            version: 2,
+            name: "posts",
+            up: "create table posts (id int not null, title text); insert into posts values (1, 'hello; world');",
+            down: Some("drop table posts;"),
+        },
+    ];
+
+    fn tables<E: Engine>(engine: &E) -> Result<Vec<bool>> {
+        let txn = engine.begin()?;
+        let result = ["users", "posts", "tags"]
+            .into_iter()
+            .map(|name| Ok(txn.get_table(name.to_string())?.is_some()))
+            .collect();
+        txn.rollback()?;
+        result
+    }
+
+    #[test]
+    fn test_migrate_run() -> Result<()> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        let migrator = Migrator::new(engine.clone());
+        assert!(migrator.applied()?.is_empty());
+        assert_eq!(migrator.run(MIGRATIONS)?, vec![1, 2]);
+        assert_eq!(tables(&engine)?, vec![true, true, false]);
+        // 重复执行不会再次应用
+        assert!(migrator.run(MIGRATIONS)?.is_empty());
+        // 只应用新增的迁移
+        let mut more = MIGRATIONS.to_vec();
+        more.push(Migration { version: 3, name: "tags", up: "create table tags (id int);", down: None });
+        assert_eq!(migrator.run(&more)?, vec![3]);
+        let applied = migrator.applied()?;
+        assert_eq!(applied.iter().map(|m| m.version).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(applied[1].name, "posts");
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_checks() -> Result<()> {
+        let migrator = Migrator::new(KVEngine::new(MemoryEngine::new())?);
+        // 版本号必须递增
+        let unordered = [MIGRATIONS[1], MIGRATIONS[0]];
+        assert!(migrator.run(&unordered).is_err());
+        assert!(migrator.applied()?.is_empty());
+
+        migrator.run(&MIGRATIONS[1..])?;
+        // 已应用的迁移被删除或改名
+        assert!(migrator.run(&[]).is_err());
+        let renamed = [Migration { name: "articles", ..MIGRATIONS[1] }];
+        assert!(migrator.run(&renamed).is_err());
+        // 比已应用版本更旧的新迁移
+        assert!(migrator.run(MIGRATIONS).is_err());
+        assert_eq!(migrator.applied()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_failure_rollback() -> Result<()> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        let migrator = Migrator::new(engine.clone());
+        migrator.run(&MIGRATIONS[..1])?;
+        // 第二条语句失败，整个迁移都不会留下
+        let broken = [
+            MIGRATIONS[0],
+            Migration { version: 2, name: "broken", up: "create table tags (id int); insert into missing values (1);", down: None },
+        ];
+        assert!(migrator.run(&broken).is_err());
+        assert_eq!(tables(&engine)?, vec![true, false, false]);
+        assert_eq!(migrator.applied()?.len(), 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "disk")]
+    #[test]
+    fn test_migrate_rollback() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        {
+            let engine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+            Migrator::new(engine).run(MIGRATIONS)?;
+        }
+        let engine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+        let migrator = Migrator::new(engine.clone());
+        assert_eq!(migrator.applied()?.len(), 2);
+        assert_eq!(migrator.rollback_to(MIGRATIONS, 1)?, vec![2]);
+        assert_eq!(tables(&engine)?, vec![true, false, false]);
+        // 重新应用回滚的迁移，旧数据已随表删除
+        assert_eq!(migrator.run(MIGRATIONS)?, vec![2]);
+        let txn = engine.begin()?;
+        assert_eq!(txn.scan_table("posts".to_string())?.len(), 1);
+        txn.rollback()?;
+
+        // 没有 down 的迁移不能回滚
+        let mut more = MIGRATIONS.to_vec();
+        more.push(Migration { version: 3, name: "tags", up: "create table tags (id int);", down: None });
+        migrator.run(&more)?;
+        assert!(migrator.rollback_to(&more, 0).is_err());
+        assert_eq!(migrator.applied()?.len(), 3);
+
+        drop(migrator);
+        drop(engine);
+        fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[cfg(feature = "disk")]
+    #[test]
+    fn test_migrate_backfill() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let engine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+        let migrator = Migrator::new(engine.clone());
+        migrator.run(MIGRATIONS)?;
+        let mut s = engine.session()?;
+        for i in 2..=5 {
+            s.execute(&format!("insert into posts values ({}, 'post {}');", i, i))?;
+        }
+        let mut more = MIGRATIONS.to_vec();
+        more.push(Migration {
+            version: 3,
+            name: "likes",
+            up: "alter table posts add column likes int not null default 0;",
+            down: None,
+        });
+        migrator.run(&more)?;
+        s.execute("insert into posts values (6, 'post 6', 3);")?;
+
+        // 旧行按批写入默认值，新行本来就有所有列
+        let mut reports = Vec::new();
+        let report = migrator.backfill("posts", 2, |r| reports.push(r.clone()))?;
+        assert_eq!(report, BackfillReport { scanned: 6, rewritten: 5, batches: 3 });
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0], BackfillReport { scanned: 2, rewritten: 2, batches: 1 });
+        // 再次执行什么都不用写
+        assert_eq!(migrator.backfill("posts", 100, |_| {})?.rewritten, 0);
+
+        let txn = engine.begin()?;
+        let rows = txn.scan_table("posts".to_string())?;
+        txn.rollback()?;
+        assert_eq!(rows.iter().map(|row| row[2].clone()).collect::<Vec<_>>(), [0, 0, 0, 0, 0, 3].map(Value::Integer));
+        assert!(migrator.backfill("missing", 10, |_| {}).is_err());
+
+        drop(s);
+        drop(migrator);
+        drop(engine);
+        fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+}
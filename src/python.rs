@@ -0,0 +1,138 @@
+// python module, used like sqlite3:
+//   import sharkdb
+//   conn = sharkdb.connect()                 # in memory
+//   conn = sharkdb.connect("data/sqldb-log") # disk file
+//   for row in conn.execute("select * from t"):
+//       print(row)
+use std::path::PathBuf;
+
+use pyo3::{create_exception, exceptions::PyException, prelude::*, types::PyTuple, IntoPyObjectExt};
+
+use crate::{
+    error::Error,
+    sql::{
+        executor::ResultSet,
+        types::{Row, Value},
+    },
+    Database,
+};
+
+create_exception!(sharkdb, DatabaseError, PyException);
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        DatabaseError::new_err(err.to_string())
+    }
+}
+
+#[pyclass(module = "sharkdb")]
+struct Connection {
+    // None after close
+    db: Option<Database>,
+}
+
+#[pymethods]
+impl Connection {
+    // the trailing semicolon is optional, like in sqlite3
+    fn execute(&mut self, sql: &str) -> PyResult<Cursor> {
+        let sql = if sql.trim_end().ends_with(';') { sql.to_string() } else { format!("{};", sql) };
+        let result = match &mut self.db {
+            Some(db) => db.execute(&sql)?,
+            None => return Err(DatabaseError::new_err("connection is closed")),
+        };
+        Ok(Cursor::new(result))
+    }
+
+    // the disk file is closed once the last connection to it is gone
+    fn close(&mut self) {
+        self.db = None;
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __exit__(&mut self, _exc_type: PyObject, _exc_value: PyObject, _traceback: PyObject) {
+        self.close();
+    }
+}
+
+// rows of one statement, rowcount is the number of rows inserted or selected
+#[pyclass(module = "sharkdb")]
+struct Cursor {
+    columns: Vec<String>,
+    rows: std::vec::IntoIter<Row>,
+    #[pyo3(get)]
+    rowcount: i64,
+}
+
+impl Cursor {
+    fn new(result: ResultSet) -> Self {
+        match result {
+            ResultSet::Scan { columns, row } => Self { columns, rowcount: row.len() as i64, rows: row.into_iter() },
+            ResultSet::Insert { count } => Self { columns: Vec::new(), rows: Vec::new().into_iter(), rowcount: count as i64 },
+            _ => Self { columns: Vec::new(), rows: Vec::new().into_iter(), rowcount: -1 },
+        }
+    }
+}
+
+fn to_tuple(py: Python<'_>, row: Row) -> PyResult<Bound<'_, PyTuple>> {
+    let values = row
+        .into_iter()
+        .map(|value| match value {
+            Value::Null => Ok(py.None()),
+            Value::Boolean(b) => b.into_py_any(py),
+            Value::Integer(i) => i.into_py_any(py),
+            Value::Float(f) => f.into_py_any(py),
+            Value::String(s) => s.into_py_any(py),
+            // a list of floats
+            Value::Vector(v) => v.into_py_any(py),
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    PyTuple::new(py, values)
+}
+
+#[pymethods]
+impl Cursor {
+    // column names of a select, empty for other statements
+    #[getter]
+    fn columns(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+
+    fn fetchone<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+        self.rows.next().map(|row| to_tuple(py, row)).transpose()
+    }
+
+    fn fetchall<'py>(&mut self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyTuple>>> {
+        self.rows.by_ref().map(|row| to_tuple(py, row)).collect()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyTuple>>> {
+        self.fetchone(py)
+    }
+}
+
+// in memory database without a path
+#[pyfunction]
+#[pyo3(signature = (path=None))]
+fn connect(path: Option<PathBuf>) -> PyResult<Connection> {
+    let db = match path {
+        Some(path) => Database::open(path)?,
+        None => Database::open_memory()?,
+    };
+    Ok(Connection { db: Some(db) })
+}
+
+#[pymodule]
+fn sharkdb(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(connect, m)?)?;
+    m.add_class::<Connection>()?;
+    m.add_class::<Cursor>()?;
+    m.add("DatabaseError", m.py().get_type::<DatabaseError>())?;
+    Ok(())
+}
@@ -0,0 +1,381 @@
+use std::{
+    io::{BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    error::{Error, Result},
+    sql::{engine::Engine, executor::ResultSet},
+};
+
+pub mod mysql;
+pub mod tls;
+
+// a client connection, plain tcp or tls over tcp
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+// requests larger than this are rejected before allocating the buffer
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+// the login request comes before any authentication, so it gets a much smaller limit
+pub const MAX_LOGIN_FRAME_SIZE: usize = 4 * 1024;
+
+// every message on the wire is a frame
+// +------------+---------+
+// | length (4) | payload |
+// +------------+---------+
+// the first request of a connection logs in
+//   request payload: (username, password) in bincode
+//   response payload: Result<()> in bincode
+// then each request runs one statement
+//   request payload: sql text in utf-8
+//   response payload: Result<ResultSet> in bincode
+pub fn write_frame(w: &mut impl Write, payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(Error::Internal(format!("frame size {} exceeds the max size {}", payload.len(), MAX_FRAME_SIZE)));
+    }
+    // one write, so a tls stream sends one record
+    let mut buf = Vec::with_capacity(4 + payload.len());
+    buf.extend((payload.len() as u32).to_be_bytes());
+    buf.extend(payload);
+    w.write_all(&buf)?;
+    w.flush()?;
+    Ok(())
+}
+
+// None if the peer closed the connection between two frames
+pub fn read_frame(r: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    read_frame_max(r, MAX_FRAME_SIZE)
+}
+
+// read_frame with a lower limit than MAX_FRAME_SIZE
+pub fn read_frame_max(r: &mut impl Read, max_size: usize) -> Result<Option<Vec<u8>>> {
+    let mut len_buf = [0; 4];
+    match r.read_exact(&mut len_buf) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > max_size {
+        return Err(Error::Internal(format!("frame size {} exceeds the max size {}", len, max_size)));
+    }
+    let mut payload = vec![0; len];
+    r.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+// serves sql over tcp, one thread and one session per connection
+// all sessions share the same sql engine
+pub struct Server<E: Engine> {
+    engine: E,
+    // only tls connections are accepted when set
+    tls: Option<Arc<rustls::ServerConfig>>,
+    // close connections which send nothing for this long
+    idle_timeout: Option<Duration>,
+    // connections being served, a connection is refused before it gets a thread
+    // once there are as many as the max number of sessions
+    connections: Arc<AtomicUsize>,
+}
+
+impl<E: Engine + Send + 'static> Server<E> {
+    pub fn new(engine: E) -> Self {
+        Self { engine, tls: None, idle_timeout: None, connections: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    pub fn with_tls(mut self, config: Arc<rustls::ServerConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    // accept connections forever, a failed accept is logged and the next one is served
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream.and_then(|stream| stream.set_read_timeout(self.idle_timeout).map(|()| stream)) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::warn!(error = %err, "accept failed");
+                    // eg: out of file descriptors, give the open connections time to close
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            };
+            if let Some(max) = self.engine.sessions().stats().ok().and_then(|stats| stats.max_sessions) {
+                if self.connections.load(Ordering::Relaxed) >= max {
+                    self.refuse(stream, Error::TooManySessions { max });
+                    continue;
+                }
+            }
+            let engine = self.engine.clone();
+            let tls = self.tls.clone();
+            let connection = Connection::new(self.connections.clone());
+            std::thread::spawn(move || {
+                let _connection = connection;
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                let stream = match tls {
+                    Some(config) => tls::accept(config, stream),
+                    None => Ok(Box::new(stream) as Box<dyn Stream>),
+                };
+                if let Err(err) = stream.and_then(|stream| Self::handle(engine, stream)) {
+                    tracing::warn!(peer, error = %err, "connection closed");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    // answer the login with err and close, without a thread
+    // a tls connection is just closed, the handshake would block the accept loop
+    fn refuse(&self, mut stream: TcpStream, err: Error) {
+        let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+        tracing::warn!(peer, error = %err, "connection refused");
+        if self.tls.is_none() {
+            if let Ok(response) = bincode::serialize(&Err::<(), _>(err)) {
+                let _ = write_frame(&mut stream, &response);
+            }
+        }
+    }
+
+    // errors of a statement are sent back to the client, only io errors end the connection
+    fn handle(engine: E, stream: Box<dyn Stream>) -> Result<()> {
+        let mut stream = BufReader::new(stream);
+        let Some(payload) = read_frame_max(&mut stream, MAX_LOGIN_FRAME_SIZE)? else {
+            return Ok(());
+        };
+        let (username, password): (String, String) = bincode::deserialize(&payload)?;
+        let login = engine.authenticate(&username, &password).and_then(|()| engine.user_session(&username));
+        let response = login.as_ref().map(|_| ()).map_err(Clone::clone);
+        write_frame(stream.get_mut(), &bincode::serialize(&response)?)?;
+        let mut session = login?;
+        while let Some(payload) = read_frame(&mut stream)? {
+            let result = String::from_utf8(payload)
+                .map_err(|err| Error::Parse(err.to_string()))
+                .and_then(|sql| session.execute(&sql));
+            write_frame(stream.get_mut(), &result_payload(&result, MAX_FRAME_SIZE)?)?;
+        }
+        Ok(())
+    }
+}
+
+// the payload of the result of a statement, a result too large for one frame is sent as an error
+fn result_payload(result: &Result<ResultSet>, max_size: usize) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(result)?;
+    if payload.len() <= max_size {
+        return Ok(payload);
+    }
+    let err = Error::Internal(format!(
+        "result size {} exceeds the max frame size {}, select fewer rows or use a cursor",
+        payload.len(),
+        max_size
+    ));
+    Ok(bincode::serialize(&Err::<ResultSet, _>(err))?)
+}
+
+// counts a connection in Server::connections while it is alive
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn new(connections: Arc<AtomicUsize>) -> Self {
+        connections.fetch_add(1, Ordering::Relaxed);
+        Self(connections)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// client of the framed protocol, sends one statement and waits for its result
+pub struct Client {
+    stream: BufReader<Box<dyn Stream>>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs, username: &str, password: &str) -> Result<Self> {
+        Self::login(Box::new(TcpStream::connect(addr)?), username, password)
+    }
+
+    // server_name must match the certificate of the server
+    pub fn connect_tls(
+        addr: impl ToSocketAddrs,
+        server_name: &str,
+        config: Arc<rustls::ClientConfig>,
+        username: &str,
+        password: &str,
+    ) -> Result<Self> {
+        let stream = tls::connect(config, server_name, TcpStream::connect(addr)?)?;
+        Self::login(stream, username, password)
+    }
+
+    fn login(stream: Box<dyn Stream>, username: &str, password: &str) -> Result<Self> {
+        let mut client = Self { stream: BufReader::new(stream) };
+        write_frame(client.stream.get_mut(), &bincode::serialize(&(username, password))?)?;
+        bincode::deserialize::<Result<()>>(&client.read_response()?)??;
+        Ok(client)
+    }
+
+    pub fn execute(&mut self, sql: &str) -> Result<ResultSet> {
+        write_frame(self.stream.get_mut(), sql.as_bytes())?;
+        bincode::deserialize::<Result<ResultSet>>(&self.read_response()?)?
+    }
+
+    fn read_response(&mut self) -> Result<Vec<u8>> {
+        read_frame(&mut self.stream)?.ok_or(Error::Internal("connection closed by server".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    use super::{read_frame, read_frame_max, result_payload, tls, write_frame, Client, Server, MAX_LOGIN_FRAME_SIZE};
+    use crate::{
+        error::{Error, Result},
+        sql::{engine::{kv::KVEngine, Engine}, executor::ResultSet, types::Value},
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_frame() -> Result<()> {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"select * from t;")?;
+        write_frame(&mut buf, b"")?;
+        let mut r = buf.as_slice();
+        assert_eq!(read_frame(&mut r)?, Some(b"select * from t;".to_vec()));
+        assert_eq!(read_frame(&mut r)?, Some(Vec::new()));
+        assert_eq!(read_frame(&mut r)?, None);
+
+        // 长度超过上限的帧直接拒绝
+        let mut r = &u32::MAX.to_be_bytes()[..];
+        assert!(read_frame(&mut r).is_err());
+        let mut r = &(MAX_LOGIN_FRAME_SIZE as u32 + 1).to_be_bytes()[..];
+        assert!(read_frame_max(&mut r, MAX_LOGIN_FRAME_SIZE).is_err());
+
+        // 结果放不进一个帧时返回错误，连接不用断开
+        let result = Ok(ResultSet::Scan { columns: vec!["a".to_string()], row: vec![vec![Value::Integer(1)]; 100] });
+        match bincode::deserialize::<Result<ResultSet>>(&result_payload(&result, 4096)?)? {
+            Ok(ResultSet::Scan { row, .. }) => assert_eq!(row.len(), 100),
+            other => panic!("unexpected {:?}", other),
+        }
+        match bincode::deserialize::<Result<ResultSet>>(&result_payload(&result, 100)?)? {
+            Err(Error::Internal(msg)) => assert!(msg.contains("exceeds the max frame size 100")),
+            other => panic!("unexpected {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_server() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = Server::new(KVEngine::new(MemoryEngine::new())?);
+        std::thread::spawn(move || server.serve(listener));
+
+        let mut c1 = Client::connect(addr, "root", "")?;
+        c1.execute("create table t (a int, b text);")?;
+        c1.execute("insert into t values (1, 'a');")?;
+
+        // 不同连接共享同一个引擎
+        let mut c2 = Client::connect(addr, "root", "")?;
+        c2.execute("insert into t values (2, 'b');")?;
+        match c1.execute("select * from t;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row.len(), 2),
+            _ => unreachable!(),
+        }
+
+        // 语句出错后连接仍然可用
+        assert!(matches!(c2.execute("select * from nope;"), Err(Error::TableNotFound(_))));
+        assert!(matches!(c2.execute("selec"), Err(Error::Parse(_))));
+        assert!(matches!(c2.execute("show status;")?, ResultSet::ShowStatus { .. }));
+
+        // 创建用户之后需要密码才能连接
+        c1.execute("create user alice password 'secret';")?;
+        assert!(matches!(Client::connect(addr, "alice", "wrong"), Err(Error::AccessDenied(_))));
+        assert!(matches!(Client::connect(addr, "root", ""), Err(Error::AccessDenied(_))));
+        let mut c3 = Client::connect(addr, "alice", "secret")?;
+        c3.execute("alter user alice password 'new';")?;
+        assert!(Client::connect(addr, "alice", "secret").is_err());
+        Client::connect(addr, "alice", "new")?;
+
+        // 登录请求不能超过 MAX_LOGIN_FRAME_SIZE，连接直接关闭
+        let mut stream = TcpStream::connect(addr)?;
+        stream.write_all(&(MAX_LOGIN_FRAME_SIZE as u32 + 1).to_be_bytes())?;
+        assert!(!matches!(read_frame(&mut stream), Ok(Some(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_sessions() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        engine.sessions().set_max_sessions(Some(2))?;
+        let server = Server::new(engine).with_idle_timeout(Duration::from_millis(100));
+        std::thread::spawn(move || server.serve(listener));
+
+        let mut c1 = Client::connect(addr, "root", "")?;
+        let _c2 = Client::connect(addr, "root", "")?;
+        assert!(matches!(Client::connect(addr, "root", ""), Err(Error::TooManySessions { max: 2 })));
+        // 自己正在执行 show processlist，另一个连接空闲
+        match c1.execute("show processlist;")? {
+            ResultSet::Scan { columns, row } => {
+                assert_eq!(columns, vec!["id", "user", "state", "time", "txn", "query"]);
+                assert_eq!(row.len(), 2);
+                assert_eq!(row[0][2], Value::String("query".to_string()));
+                assert_eq!(row[0][5], Value::String("show processlist;".to_string()));
+                assert_eq!(row[1][2], Value::String("sleep".to_string()));
+                assert_eq!(row[1][4], Value::Null);
+            }
+            _ => unreachable!(),
+        }
+
+        // 空闲超时之后连接被关闭，session 也随之释放
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(c1.execute("show processlist;").is_err());
+        let mut c3 = Client::connect(addr, "root", "")?;
+        match c3.execute("show processlist;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row.len(), 1),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_tls() -> Result<()> {
+        let (server_config, client_config) = tls::tests::configs()?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = Server::new(KVEngine::new(MemoryEngine::new())?).with_tls(server_config);
+        std::thread::spawn(move || server.serve(listener));
+
+        let mut c = Client::connect_tls(addr, "localhost", client_config.clone(), "root", "")?;
+        c.execute("create table t (a int);")?;
+        c.execute("insert into t values (1);")?;
+        match c.execute("select * from t;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row.len(), 1),
+            _ => unreachable!(),
+        }
+        // 证书里的名字不匹配，以及不使用 tls 的客户端都连不上
+        assert!(Client::connect_tls(addr, "example.com", client_config, "root", "").is_err());
+        assert!(Client::connect(addr, "root", "").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,837 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    error::{Error, Result},
+    sql::{engine::Engine, executor::ResultSet, types::Value},
+};
+
+use super::{tls, Connection, Stream, MAX_FRAME_SIZE, MAX_LOGIN_FRAME_SIZE};
+
+// the subset of the mysql client/server protocol needed by common clients for basic queries
+// text protocol only, no prepared statements
+
+const SERVER_VERSION: &str = "8.0.0-SharkDB";
+const AUTH_PLUGIN: &str = "mysql_native_password";
+// passwords are stored salted, the scramble of mysql_native_password can't be checked against them
+// so the client is asked to send the password itself
+const CLEAR_PASSWORD_PLUGIN: &str = "mysql_clear_password";
+
+// capability flags
+const CLIENT_LONG_PASSWORD: u32 = 0x0000_0001;
+const CLIENT_FOUND_ROWS: u32 = 0x0000_0002;
+const CLIENT_LONG_FLAG: u32 = 0x0000_0004;
+const CLIENT_CONNECT_WITH_DB: u32 = 0x0000_0008;
+const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+const CLIENT_SSL: u32 = 0x0000_0800;
+const CLIENT_TRANSACTIONS: u32 = 0x0000_2000;
+const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x0008_0000;
+const CLIENT_PLUGIN_AUTH_LENENC_DATA: u32 = 0x0020_0000;
+const SERVER_CAPABILITIES: u32 = CLIENT_LONG_PASSWORD
+    | CLIENT_FOUND_ROWS
+    | CLIENT_LONG_FLAG
+    | CLIENT_CONNECT_WITH_DB
+    | CLIENT_PROTOCOL_41
+    | CLIENT_TRANSACTIONS
+    | CLIENT_SECURE_CONNECTION
+    | CLIENT_PLUGIN_AUTH
+    | CLIENT_PLUGIN_AUTH_LENENC_DATA;
+
+const SERVER_STATUS_AUTOCOMMIT: u16 = 0x0002;
+// utf8mb4_general_ci
+const CHARSET_UTF8MB4: u8 = 45;
+
+// commands
+const COM_QUIT: u8 = 0x01;
+const COM_INIT_DB: u8 = 0x02;
+const COM_QUERY: u8 = 0x03;
+const COM_PING: u8 = 0x0e;
+
+// column types
+const TYPE_DOUBLE: u8 = 0x05;
+const TYPE_LONGLONG: u8 = 0x08;
+const TYPE_TINY: u8 = 0x01;
+const TYPE_VAR_STRING: u8 = 0xfd;
+
+// error codes
+const ER_UNKNOWN_ERROR: u16 = 1105;
+const ER_PARSE_ERROR: u16 = 1064;
+const ER_UNKNOWN_COM_ERROR: u16 = 1047;
+const ER_ACCESS_DENIED_ERROR: u16 = 1045;
+const ER_CON_COUNT_ERROR: u16 = 1040;
+const ER_NO_SUCH_TABLE: u16 = 1146;
+const ER_TABLE_EXISTS_ERROR: u16 = 1050;
+const ER_BAD_FIELD_ERROR: u16 = 1054;
+const ER_TRUNCATED_WRONG_VALUE_FOR_FIELD: u16 = 1366;
+const ER_BAD_NULL_ERROR: u16 = 1048;
+const ER_DUP_ENTRY: u16 = 1062;
+const ER_WRONG_VALUE_COUNT_ON_ROW: u16 = 1136;
+const ER_DUP_FIELDNAME: u16 = 1060;
+const ER_TOO_MANY_FIELDS: u16 = 1117;
+const ER_TABLEACCESS_DENIED_ERROR: u16 = 1142;
+const ER_OUTOFMEMORY: u16 = 1037;
+const ER_QUERY_INTERRUPTED: u16 = 1317;
+const ER_CHECK_CONSTRAINT_VIOLATED: u16 = 3819;
+const ER_SERVER_SHUTDOWN: u16 = 1053;
+
+// largest payload of one packet, longer payloads are split
+const MAX_PACKET_SIZE: usize = 0xff_ffff;
+
+// an SSLRequest is the first 32 bytes of a HandshakeResponse41 with CLIENT_SSL set
+const SSL_REQUEST_SIZE: usize = 32;
+
+// every packet starts with a 3 bytes little endian payload length and a sequence id
+// the sequence id restarts from 0 for each command
+// reads are not buffered, so the stream can be switched to tls right after an SSLRequest
+struct PacketStream {
+    stream: Box<dyn Stream>,
+    // packets written since the last flush
+    out: Vec<u8>,
+    seq: u8,
+}
+
+impl PacketStream {
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Self { stream, out: Vec::new(), seq: 0 }
+    }
+
+    // None if the client closed the connection between two packets
+    fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        self.read_max(MAX_FRAME_SIZE)
+    }
+
+    // read with a limit on the payload, checked before each packet of it is allocated
+    fn read_max(&mut self, max_size: usize) -> Result<Option<Vec<u8>>> {
+        let mut payload = Vec::new();
+        loop {
+            let mut header = [0; 4];
+            match self.stream.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof && payload.is_empty() => {
+                    return Ok(None)
+                }
+                Err(err) => return Err(err.into()),
+            }
+            let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+            self.seq = header[3].wrapping_add(1);
+            let start = payload.len();
+            if start + len > max_size {
+                return Err(Error::Internal(format!("packet size {} exceeds the max size {}", start + len, max_size)));
+            }
+            payload.resize(start + len, 0);
+            self.stream.read_exact(&mut payload[start..])?;
+            if len < MAX_PACKET_SIZE {
+                return Ok(Some(payload));
+            }
+        }
+    }
+
+    fn write(&mut self, payload: &[u8]) -> Result<()> {
+        let mut chunks = payload.chunks(MAX_PACKET_SIZE).peekable();
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            self.out.extend(&(chunk.len() as u32).to_le_bytes()[..3]);
+            self.out.push(self.seq);
+            self.out.extend(chunk);
+            self.seq = self.seq.wrapping_add(1);
+            // a payload of exactly n * MAX_PACKET_SIZE ends with an empty packet
+            if chunks.peek().is_none() && chunk.len() < MAX_PACKET_SIZE {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stream.write_all(&self.out)?;
+        self.stream.flush()?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+// the fields of HandshakeResponse41 we care about
+#[derive(Debug, Default, PartialEq)]
+pub struct Handshake {
+    pub username: String,
+    pub auth_response: Vec<u8>,
+    pub database: Option<String>,
+    pub auth_plugin: Option<String>,
+}
+
+impl Handshake {
+    fn decode(mut buf: &[u8]) -> Result<Self> {
+        let caps = read_u32(&mut buf)?;
+        if caps & CLIENT_PROTOCOL_41 == 0 {
+            return Err(Error::Internal("mysql client protocol older than 4.1 is not supported".to_string()));
+        }
+        // max packet size, charset and 23 bytes of filler
+        take(&mut buf, 4 + 1 + 23)?;
+        let username = read_null_str(&mut buf)?;
+        let auth_response = if caps & CLIENT_PLUGIN_AUTH_LENENC_DATA != 0 {
+            let len = read_lenenc_int(&mut buf)? as usize;
+            take(&mut buf, len)?.to_vec()
+        } else if caps & CLIENT_SECURE_CONNECTION != 0 {
+            let len = take(&mut buf, 1)?[0] as usize;
+            take(&mut buf, len)?.to_vec()
+        } else {
+            read_null_str(&mut buf)?.into_bytes()
+        };
+        let mut handshake = Self { username, auth_response, ..Default::default() };
+        if caps & CLIENT_CONNECT_WITH_DB != 0 && !buf.is_empty() {
+            handshake.database = Some(read_null_str(&mut buf)?);
+        }
+        if caps & CLIENT_PLUGIN_AUTH != 0 && !buf.is_empty() {
+            handshake.auth_plugin = Some(read_null_str(&mut buf)?);
+        }
+        Ok(handshake)
+    }
+}
+
+// serves sql over the mysql protocol, one thread and one session per connection
+pub struct MysqlServer<E: Engine> {
+    engine: E,
+    next_conn_id: AtomicU32,
+    // clients must switch to tls with an SSLRequest when set
+    tls: Option<Arc<rustls::ServerConfig>>,
+    // close connections which send nothing for this long
+    idle_timeout: Option<Duration>,
+    // connections being served, a connection is refused before it gets a thread
+    // once there are as many as the max number of sessions
+    connections: Arc<AtomicUsize>,
+}
+
+impl<E: Engine + Send + 'static> MysqlServer<E> {
+    pub fn new(engine: E) -> Self {
+        Self {
+            engine,
+            next_conn_id: AtomicU32::new(1),
+            tls: None,
+            idle_timeout: None,
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn with_tls(mut self, config: Arc<rustls::ServerConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    // accept connections forever, a failed accept is logged and the next one is served
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream.and_then(|stream| stream.set_read_timeout(self.idle_timeout).map(|()| stream)) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::warn!(error = %err, "mysql accept failed");
+                    // eg: out of file descriptors, give the open connections time to close
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            };
+            if let Some(max) = self.engine.sessions().stats().ok().and_then(|stats| stats.max_sessions) {
+                if self.connections.load(Ordering::Relaxed) >= max {
+                    Self::refuse(stream, Error::TooManySessions { max });
+                    continue;
+                }
+            }
+            let engine = self.engine.clone();
+            let conn_id = self.next_conn_id.fetch_add(1, Ordering::Relaxed);
+            let tls = self.tls.clone();
+            let connection = Connection::new(self.connections.clone());
+            std::thread::spawn(move || {
+                let _connection = connection;
+                let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+                if let Err(err) = Self::handle(engine, stream, conn_id, tls) {
+                    tracing::warn!(peer, error = %err, "mysql connection closed");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    // answer with an ERR packet in place of the handshake and close, without a thread
+    // it comes before the SSLRequest, so it is sent in plain text with or without tls
+    fn refuse(stream: TcpStream, err: Error) {
+        let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+        tracing::warn!(peer, error = %err, "mysql connection refused");
+        let mut packets = PacketStream::new(Box::new(stream));
+        let _ = packets.write(&err_packet(&err)).and_then(|()| packets.flush());
+    }
+
+    fn handle(engine: E, stream: TcpStream, conn_id: u32, tls: Option<Arc<rustls::ServerConfig>>) -> Result<()> {
+        let mut packets = PacketStream::new(Box::new(stream.try_clone()?));
+        let scramble = scramble(conn_id);
+        let capabilities = if tls.is_some() { SERVER_CAPABILITIES | CLIENT_SSL } else { SERVER_CAPABILITIES };
+        packets.write(&handshake_packet(conn_id, capabilities, &scramble))?;
+        packets.flush()?;
+        // the packets before the login come from anyone, they get a much smaller limit
+        let Some(mut payload) = packets.read_max(MAX_LOGIN_FRAME_SIZE)? else {
+            return Ok(());
+        };
+        // the password is only asked for in clear text over tls
+        let secure = tls.is_some();
+        if let Some(config) = tls {
+            if payload.len() != SSL_REQUEST_SIZE || read_u32(&mut payload.as_slice())? & CLIENT_SSL == 0 {
+                let err = Error::Internal("connections must use tls".to_string());
+                packets.write(&err_packet(&err))?;
+                packets.flush()?;
+                return Err(err);
+            }
+            // the rest of the handshake goes over tls
+            packets.stream = tls::accept(config, stream)?;
+            payload = match packets.read_max(MAX_LOGIN_FRAME_SIZE)? {
+                Some(payload) => payload,
+                None => return Ok(()),
+            };
+        }
+        let handshake = Handshake::decode(&payload)?;
+        // an empty auth response means an empty password, whatever the plugin is
+        let mut login = engine.authenticate(&handshake.username, "");
+        if login.is_err() && !handshake.auth_response.is_empty() {
+            if !secure {
+                login = Err(Error::Internal("password authentication requires tls".to_string()));
+            } else {
+                let mut switch = vec![0xfe];
+                put_null_str(&mut switch, CLEAR_PASSWORD_PLUGIN);
+                packets.write(&switch)?;
+                packets.flush()?;
+                let Some(payload) = packets.read_max(MAX_LOGIN_FRAME_SIZE)? else {
+                    return Ok(());
+                };
+                let password = payload.strip_suffix(&[0]).unwrap_or(&payload);
+                login = engine.authenticate(&handshake.username, &String::from_utf8_lossy(password));
+            }
+        }
+        let mut session = match login.and_then(|()| engine.user_session(&handshake.username)) {
+            Ok(session) => session,
+            Err(err) => {
+                packets.write(&err_packet(&err))?;
+                packets.flush()?;
+                return Err(err);
+            }
+        };
+        packets.write(&ok_packet(0))?;
+        packets.flush()?;
+        while let Some(payload) = packets.read()? {
+            let Some((&command, body)) = payload.split_first() else {
+                continue;
+            };
+            match command {
+                COM_QUIT => break,
+                // there is only one database
+                COM_PING | COM_INIT_DB => packets.write(&ok_packet(0))?,
+                COM_QUERY => {
+                    let sql = String::from_utf8_lossy(body);
+                    match Self::query(&mut session, &sql) {
+                        Ok(result) => write_result(&mut packets, result)?,
+                        Err(err) => packets.write(&err_packet(&err))?,
+                    }
+                }
+                command => packets.write(&err_packet_with(
+                    ER_UNKNOWN_COM_ERROR,
+                    &format!("unsupported command 0x{:02x}", command),
+                ))?,
+            }
+            packets.flush()?;
+        }
+        Ok(())
+    }
+
+    // drivers send SET NAMES and friends on connect, the session keeps its own variables
+    // and lets the others through with a warning
+    fn query(session: &mut crate::sql::engine::Session<E>, sql: &str) -> Result<ResultSet> {
+        let sql = sql.trim().trim_end_matches(';').trim_end();
+        session.execute(&format!("{};", sql))
+    }
+}
+
+fn write_result(packets: &mut PacketStream, result: ResultSet) -> Result<()> {
+    match result {
+        ResultSet::CreateTable { .. }
+        | ResultSet::DropTable { .. }
+        | ResultSet::AlterTable { .. }
+        | ResultSet::CreateUser { .. }
+        | ResultSet::AlterUser { .. }
+        | ResultSet::CreateRole { .. }
+        | ResultSet::DropRole { .. }
+        | ResultSet::Grant { .. }
+        | ResultSet::Revoke { .. }
+        | ResultSet::CreateProcedure { .. }
+        | ResultSet::DropProcedure { .. }
+        | ResultSet::Attach { .. }
+        | ResultSet::Detach { .. }
+        | ResultSet::Declare { .. }
+        | ResultSet::Close { .. }
+        | ResultSet::Kill { .. }
+        | ResultSet::Set { .. } => packets.write(&ok_packet(0)),
+        ResultSet::Insert { count } | ResultSet::Vacuum { count } => packets.write(&ok_packet(count as u64)),
+        ResultSet::Scan { columns, row } => write_rows(packets, &columns, &row),
+        ResultSet::ShowStatus { status, sessions } => {
+            let columns = vec!["Variable_name".to_string(), "Value".to_string()];
+            let rows = [
+                ("versions", Value::Integer(status.versions as i64)),
+                ("active_txns", Value::Integer(status.active_txns as i64)),
+                ("storage", Value::String(status.storage.name)),
+                ("keys", Value::Integer(status.storage.keys as i64)),
+                ("live_bytes", Value::Integer(status.storage.live_bytes as i64)),
+                ("dead_bytes", Value::Integer(status.storage.dead_bytes as i64)),
+                ("file_size", Value::Integer(status.storage.file_size as i64)),
+                ("sessions", Value::Integer(sessions.active as i64)),
+                ("max_sessions", sessions.max_sessions.map_or(Value::Null, |max| Value::Integer(max as i64))),
+                ("total_sessions", Value::Integer(sessions.total as i64)),
+                ("queries", Value::Integer(sessions.queries as i64)),
+                ("failed_queries", Value::Integer(sessions.failed_queries as i64)),
+                ("slow_queries", Value::Integer(sessions.slow_queries as i64)),
+            ]
+            .into_iter()
+            .map(|(name, value)| vec![Value::String(name.to_string()), value])
+            .collect::<Vec<_>>();
+            write_rows(packets, &columns, &rows)
+        }
+    }
+}
+
+// column count, column definitions, EOF, rows, EOF
+fn write_rows(packets: &mut PacketStream, columns: &[String], rows: &[Vec<Value>]) -> Result<()> {
+    let mut buf = Vec::new();
+    put_lenenc_int(&mut buf, columns.len() as u64);
+    packets.write(&buf)?;
+    for (i, name) in columns.iter().enumerate() {
+        // the result set has no column types, take them from the first non null value
+        let column_type = rows
+            .iter()
+            .map(|row| &row[i])
+            .find(|v| **v != Value::Null)
+            .map_or(TYPE_VAR_STRING, |v| match v {
+                Value::Boolean(_) => TYPE_TINY,
+                Value::Integer(_) => TYPE_LONGLONG,
+                Value::Float(_) => TYPE_DOUBLE,
+                _ => TYPE_VAR_STRING,
+            });
+        packets.write(&column_definition(name, column_type))?;
+    }
+    packets.write(&eof_packet())?;
+    for row in rows {
+        let mut buf = Vec::new();
+        for value in row {
+            match value {
+                Value::Null => buf.push(0xfb),
+                Value::Boolean(b) => put_lenenc_str(&mut buf, if *b { "1" } else { "0" }),
+                Value::Integer(x) => put_lenenc_str(&mut buf, &x.to_string()),
+                Value::Float(f) => put_lenenc_str(&mut buf, &f.to_string()),
+                Value::String(s) => put_lenenc_str(&mut buf, s),
+                Value::Vector(_) => put_lenenc_str(&mut buf, &value.to_string()),
+            }
+        }
+        packets.write(&buf)?;
+    }
+    packets.write(&eof_packet())
+}
+
+// 20 bytes of auth plugin data, must not contain 0
+fn scramble(conn_id: u32) -> [u8; 20] {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let mut seed = nanos ^ ((conn_id as u64) << 32) ^ 0x9e37_79b9_7f4a_7c15;
+    let mut scramble = [0; 20];
+    for b in scramble.iter_mut() {
+        // xorshift
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        *b = (seed % 94) as u8 + 33;
+    }
+    scramble
+}
+
+fn handshake_packet(conn_id: u32, capabilities: u32, scramble: &[u8; 20]) -> Vec<u8> {
+    let mut buf = vec![10];
+    put_null_str(&mut buf, SERVER_VERSION);
+    buf.extend(conn_id.to_le_bytes());
+    buf.extend(&scramble[..8]);
+    buf.push(0);
+    buf.extend(&capabilities.to_le_bytes()[..2]);
+    buf.push(CHARSET_UTF8MB4);
+    buf.extend(SERVER_STATUS_AUTOCOMMIT.to_le_bytes());
+    buf.extend(&capabilities.to_le_bytes()[2..]);
+    buf.push(scramble.len() as u8 + 1);
+    buf.extend([0; 10]);
+    buf.extend(&scramble[8..]);
+    buf.push(0);
+    put_null_str(&mut buf, AUTH_PLUGIN);
+    buf
+}
+
+fn ok_packet(affected_rows: u64) -> Vec<u8> {
+    let mut buf = vec![0x00];
+    put_lenenc_int(&mut buf, affected_rows);
+    // last insert id
+    put_lenenc_int(&mut buf, 0);
+    buf.extend(SERVER_STATUS_AUTOCOMMIT.to_le_bytes());
+    // warnings
+    buf.extend(0_u16.to_le_bytes());
+    buf
+}
+
+fn eof_packet() -> Vec<u8> {
+    let mut buf = vec![0xfe];
+    buf.extend(0_u16.to_le_bytes());
+    buf.extend(SERVER_STATUS_AUTOCOMMIT.to_le_bytes());
+    buf
+}
+
+fn err_packet(err: &Error) -> Vec<u8> {
+    match err {
+        Error::Parse(_) => err_packet_with(ER_PARSE_ERROR, &err.to_string()),
+        Error::AccessDenied(_) => err_packet_with(ER_ACCESS_DENIED_ERROR, &err.to_string()),
+        Error::PermissionDenied { .. } => err_packet_with(ER_TABLEACCESS_DENIED_ERROR, &err.to_string()),
+        Error::MemoryLimitExceeded { .. } => err_packet_with(ER_OUTOFMEMORY, &err.to_string()),
+        Error::TooManySessions { .. } => err_packet_with(ER_CON_COUNT_ERROR, &err.to_string()),
+        Error::QueryCancelled { .. } => err_packet_with(ER_QUERY_INTERRUPTED, &err.to_string()),
+        Error::TableNotFound(_) => err_packet_with(ER_NO_SUCH_TABLE, &err.to_string()),
+        Error::DuplicateTable(_) => err_packet_with(ER_TABLE_EXISTS_ERROR, &err.to_string()),
+        Error::ColumnNotFound { .. } => err_packet_with(ER_BAD_FIELD_ERROR, &err.to_string()),
+        Error::TypeMismatch { .. } => err_packet_with(ER_TRUNCATED_WRONG_VALUE_FOR_FIELD, &err.to_string()),
+        Error::NotNullViolation { .. } => err_packet_with(ER_BAD_NULL_ERROR, &err.to_string()),
+        Error::UniqueViolation { .. } => err_packet_with(ER_DUP_ENTRY, &err.to_string()),
+        Error::CheckViolation { .. } => err_packet_with(ER_CHECK_CONSTRAINT_VIOLATED, &err.to_string()),
+        Error::Closed => err_packet_with(ER_SERVER_SHUTDOWN, &err.to_string()),
+        Error::ValueCountMismatch { .. } => err_packet_with(ER_WRONG_VALUE_COUNT_ON_ROW, &err.to_string()),
+        Error::DuplicateColumn { .. } => err_packet_with(ER_DUP_FIELDNAME, &err.to_string()),
+        Error::TooManyColumns { .. } => err_packet_with(ER_TOO_MANY_FIELDS, &err.to_string()),
+        _ => err_packet_with(ER_UNKNOWN_ERROR, &err.to_string()),
+    }
+}
+
+fn err_packet_with(code: u16, message: &str) -> Vec<u8> {
+    let mut buf = vec![0xff];
+    buf.extend(code.to_le_bytes());
+    buf.extend(b"#HY000");
+    buf.extend(message.as_bytes());
+    buf
+}
+
+fn column_definition(name: &str, column_type: u8) -> Vec<u8> {
+    let mut buf = Vec::new();
+    // catalog, schema, table, org_table, name, org_name
+    for s in ["def", "", "", "", name, name] {
+        put_lenenc_str(&mut buf, s);
+    }
+    // length of the fixed fields below
+    buf.push(0x0c);
+    buf.extend((CHARSET_UTF8MB4 as u16).to_le_bytes());
+    // max column length
+    buf.extend(1024_u32.to_le_bytes());
+    buf.push(column_type);
+    // flags
+    buf.extend(0_u16.to_le_bytes());
+    // decimals
+    buf.push(if column_type == TYPE_DOUBLE { 31 } else { 0 });
+    buf.extend([0; 2]);
+    buf
+}
+
+fn put_lenenc_int(buf: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=250 => buf.push(n as u8),
+        251..=0xffff => {
+            buf.push(0xfc);
+            buf.extend(&n.to_le_bytes()[..2]);
+        }
+        0x1_0000..=0xff_ffff => {
+            buf.push(0xfd);
+            buf.extend(&n.to_le_bytes()[..3]);
+        }
+        _ => {
+            buf.push(0xfe);
+            buf.extend(n.to_le_bytes());
+        }
+    }
+}
+
+fn put_lenenc_str(buf: &mut Vec<u8>, s: &str) {
+    put_lenenc_int(buf, s.len() as u64);
+    buf.extend(s.as_bytes());
+}
+
+fn put_null_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend(s.as_bytes());
+    buf.push(0);
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if buf.len() < n {
+        return Err(Error::Internal("malformed mysql packet".to_string()));
+    }
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+fn read_u32(buf: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(take(buf, 4)?.try_into()?))
+}
+
+fn read_lenenc_int(buf: &mut &[u8]) -> Result<u64> {
+    let n = match take(buf, 1)?[0] {
+        0xfc => 2,
+        0xfd => 3,
+        0xfe => 8,
+        b => return Ok(b as u64),
+    };
+    let mut bytes = [0; 8];
+    bytes[..n].copy_from_slice(take(buf, n)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_null_str(buf: &mut &[u8]) -> Result<String> {
+    let end = buf
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or(Error::Internal("malformed mysql packet".to_string()))?;
+    let s = String::from_utf8_lossy(&buf[..end]).into_owned();
+    *buf = &buf[end + 1..];
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use super::{
+        put_lenenc_int, put_null_str, read_lenenc_int, read_null_str, take, Handshake, MysqlServer,
+        PacketStream, CLIENT_CONNECT_WITH_DB, CLIENT_PLUGIN_AUTH, CLIENT_PROTOCOL_41,
+        CLIENT_SECURE_CONNECTION, CLIENT_SSL, COM_PING, COM_QUERY, COM_QUIT, MAX_LOGIN_FRAME_SIZE,
+        SSL_REQUEST_SIZE,
+    };
+    use crate::{
+        error::Result,
+        server::tls,
+        sql::engine::{kv::KVEngine, Engine},
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_lenenc_int() -> Result<()> {
+        for n in [0, 250, 251, 0xffff, 0x1_0000, 0xff_ffff, 0x100_0000, u64::MAX] {
+            let mut buf = Vec::new();
+            put_lenenc_int(&mut buf, n);
+            let mut r = buf.as_slice();
+            assert_eq!(read_lenenc_int(&mut r)?, n);
+            assert!(r.is_empty());
+        }
+        Ok(())
+    }
+
+    fn handshake_response(username: &str, database: &str) -> Vec<u8> {
+        let caps = CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_CONNECT_WITH_DB | CLIENT_PLUGIN_AUTH;
+        let mut buf = caps.to_le_bytes().to_vec();
+        buf.extend([0; 4 + 1 + 23]);
+        put_null_str(&mut buf, username);
+        buf.push(20);
+        buf.extend([7; 20]);
+        put_null_str(&mut buf, database);
+        put_null_str(&mut buf, "mysql_native_password");
+        buf
+    }
+
+    #[test]
+    fn test_handshake_decode() -> Result<()> {
+        let handshake = Handshake::decode(&handshake_response("root", "db"))?;
+        assert_eq!(
+            handshake,
+            Handshake {
+                username: "root".to_string(),
+                auth_response: vec![7; 20],
+                database: Some("db".to_string()),
+                auth_plugin: Some("mysql_native_password".to_string()),
+            }
+        );
+        Ok(())
+    }
+
+    // 发送一个命令，返回响应的所有包，结果集读到第二个 EOF 为止
+    fn command(packets: &mut PacketStream, command: u8, body: &str) -> Result<Vec<Vec<u8>>> {
+        packets.seq = 0;
+        let mut payload = vec![command];
+        payload.extend(body.as_bytes());
+        packets.write(&payload)?;
+        packets.flush()?;
+        let first = packets.read()?.unwrap();
+        if first[0] == 0x00 || first[0] == 0xff {
+            return Ok(vec![first]);
+        }
+        let mut result = vec![first];
+        let mut eofs = 0;
+        while eofs < 2 {
+            let packet = packets.read()?.unwrap();
+            if packet[0] == 0xfe && packet.len() < 9 {
+                eofs += 1;
+            }
+            result.push(packet);
+        }
+        Ok(result)
+    }
+
+    #[test]
+    fn test_mysql_server() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = MysqlServer::new(KVEngine::new(MemoryEngine::new())?);
+        std::thread::spawn(move || server.serve(listener));
+
+        let mut packets = PacketStream::new(Box::new(TcpStream::connect(addr)?));
+        // 握手包: 协议版本 10，版本号，连接 id
+        let handshake = packets.read()?.unwrap();
+        let mut r = handshake.as_slice();
+        assert_eq!(take(&mut r, 1)?, &[10]);
+        assert_eq!(read_null_str(&mut r)?, "8.0.0-SharkDB");
+        packets.write(&handshake_response("root", ""))?;
+        packets.flush()?;
+        assert_eq!(packets.read()?.unwrap()[0], 0x00);
+
+        assert_eq!(command(&mut packets, COM_PING, "")?[0][0], 0x00);
+        assert_eq!(command(&mut packets, COM_QUERY, "SET NAMES utf8mb4")?[0][0], 0x00);
+        assert_eq!(command(&mut packets, COM_QUERY, "create table t (a int, b text)")?[0][0], 0x00);
+        // OK 包里的影响行数
+        assert_eq!(command(&mut packets, COM_QUERY, "insert into t values (1, 'x'), (2, null);")?[0][..2], [0x00, 2]);
+
+        // 列数，2 个列定义，EOF，2 行，EOF
+        let result = command(&mut packets, COM_QUERY, "select * from t")?;
+        assert_eq!(result.len(), 7);
+        assert_eq!(result[0], vec![2]);
+        assert_eq!(result[4], vec![1, b'1', 1, b'x']);
+        assert_eq!(result[5], vec![1, b'2', 0xfb]);
+
+        // SET 交给会话执行，max_result_rows 截断结果，其他数据库的变量只是警告
+        assert_eq!(command(&mut packets, COM_QUERY, "SET max_result_rows = 1")?[0][0], 0x00);
+        assert_eq!(command(&mut packets, COM_QUERY, "select * from t")?.len(), 6);
+        assert_eq!(command(&mut packets, COM_QUERY, "set autocommit = 1")?[0][0], 0x00);
+        assert_eq!(command(&mut packets, COM_QUERY, "set max_result_rows = default")?[0][0], 0x00);
+        assert_eq!(command(&mut packets, COM_QUERY, "select * from t")?.len(), 7);
+
+        // 语法错误返回 ERR 包，错误码 1064
+        let err = command(&mut packets, COM_QUERY, "selec")?;
+        assert_eq!(err[0][..3], [0xff, 0x28, 0x04]);
+        // 多字节字符开头的语句也只是返回错误
+        assert_eq!(command(&mut packets, COM_QUERY, "日本")?[0][0], 0xff);
+        assert_eq!(command(&mut packets, COM_QUERY, "show status")?[0], vec![2]);
+
+        assert_eq!(command(&mut packets, COM_QUERY, "create user alice password 'secret'")?[0][0], 0x00);
+
+        packets.seq = 0;
+        packets.write(&[COM_QUIT])?;
+        packets.flush()?;
+        assert_eq!(packets.read()?, None);
+
+        // 没有 tls 不能用明文密码登录
+        let mut packets = PacketStream::new(Box::new(TcpStream::connect(addr)?));
+        packets.read()?.unwrap();
+        packets.write(&handshake_response("alice", ""))?;
+        packets.flush()?;
+        let err = packets.read()?.unwrap();
+        assert_eq!(err[0], 0xff);
+        assert!(String::from_utf8_lossy(&err).contains("requires tls"));
+
+        // 登录之前的包不能超过 MAX_LOGIN_FRAME_SIZE，连接直接关闭
+        let mut packets = PacketStream::new(Box::new(TcpStream::connect(addr)?));
+        packets.read()?.unwrap();
+        packets.write(&vec![0; MAX_LOGIN_FRAME_SIZE + 1])?;
+        packets.flush()?;
+        assert!(!matches!(packets.read(), Ok(Some(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mysql_server_sessions() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        engine.sessions().set_max_sessions(Some(1))?;
+        let server = MysqlServer::new(engine);
+        std::thread::spawn(move || server.serve(listener));
+
+        let mut packets = PacketStream::new(Box::new(TcpStream::connect(addr)?));
+        packets.read()?.unwrap();
+        packets.write(&handshake_response("root", ""))?;
+        packets.flush()?;
+        assert_eq!(packets.read()?.unwrap()[0], 0x00);
+
+        // 连接数达到上限，不分配线程，用 ERR 包代替握手包，错误码 1040
+        let mut refused = PacketStream::new(Box::new(TcpStream::connect(addr)?));
+        assert_eq!(refused.read()?.unwrap()[..3], [0xff, 0x10, 0x04]);
+
+        // 连接关闭之后可以再连接
+        packets.seq = 0;
+        packets.write(&[COM_QUIT])?;
+        packets.flush()?;
+        assert_eq!(packets.read()?, None);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let mut packets = PacketStream::new(Box::new(TcpStream::connect(addr)?));
+        assert_eq!(packets.read()?.unwrap()[0], 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mysql_server_tls() -> Result<()> {
+        let (server_config, client_config) = tls::tests::configs()?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = MysqlServer::new(KVEngine::new(MemoryEngine::new())?).with_tls(server_config);
+        std::thread::spawn(move || server.serve(listener));
+
+        // 发送 SSLRequest，之后的握手在 tls 上进行
+        let connect = |username: &str| -> Result<PacketStream> {
+            let stream = TcpStream::connect(addr)?;
+            let mut packets = PacketStream::new(Box::new(stream.try_clone()?));
+            packets.read()?.unwrap();
+            let mut ssl_request = handshake_response(username, "");
+            ssl_request.truncate(SSL_REQUEST_SIZE);
+            ssl_request[..4].copy_from_slice(&(CLIENT_PROTOCOL_41 | CLIENT_SSL).to_le_bytes());
+            packets.write(&ssl_request)?;
+            packets.flush()?;
+            packets.stream = tls::connect(client_config.clone(), "localhost", stream)?;
+            packets.write(&handshake_response(username, ""))?;
+            packets.flush()?;
+            Ok(packets)
+        };
+        let mut packets = connect("root")?;
+        assert_eq!(packets.read()?.unwrap()[0], 0x00);
+        assert_eq!(command(&mut packets, COM_QUERY, "create table t (a int)")?[0][0], 0x00);
+        assert_eq!(command(&mut packets, COM_QUERY, "select * from t")?[0], vec![1]);
+        assert_eq!(command(&mut packets, COM_QUERY, "create user alice password 'secret'")?[0][0], 0x00);
+
+        // 有用户之后，在 tls 上切换到明文密码验证
+        for (password, ok) in [("secret", true), ("wrong", false)] {
+            let mut packets = connect("alice")?;
+            let switch = packets.read()?.unwrap();
+            assert_eq!(switch, b"\xfemysql_clear_password\0");
+            packets.write(format!("{}\0", password).as_bytes())?;
+            packets.flush()?;
+            assert_eq!(packets.read()?.unwrap()[0], if ok { 0x00 } else { 0xff });
+        }
+
+        // 不使用 tls 的客户端被拒绝
+        let mut packets = PacketStream::new(Box::new(TcpStream::connect(addr)?));
+        packets.read()?.unwrap();
+        packets.write(&handshake_response("root", ""))?;
+        packets.flush()?;
+        assert_eq!(packets.read()?.unwrap()[0], 0xff);
+        Ok(())
+    }
+}
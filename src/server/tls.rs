@@ -0,0 +1,80 @@
+use std::{net::TcpStream, path::Path, sync::Arc};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, ClientConnection, ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::error::{Error, Result};
+
+use super::Stream;
+
+impl From<rustls::Error> for Error {
+    fn from(value: rustls::Error) -> Self {
+        Error::Internal(value.to_string())
+    }
+}
+
+impl From<rustls::pki_types::pem::Error> for Error {
+    fn from(value: rustls::pki_types::pem::Error) -> Self {
+        Error::Config(format!("bad pem file: {}", value))
+    }
+}
+
+// server side tls from a pem certificate chain and a pem private key
+pub fn server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)?.collect::<std::result::Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(Error::Config(format!("no certificate in {}", cert_path.display())));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)?;
+    let config = ServerConfig::builder().with_no_client_auth().with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+// the handshake runs on the first read or write
+pub fn accept(config: Arc<ServerConfig>, stream: TcpStream) -> Result<Box<dyn Stream>> {
+    Ok(Box::new(StreamOwned::new(ServerConnection::new(config)?, stream)))
+}
+
+pub fn connect(config: Arc<ClientConfig>, server_name: &str, stream: TcpStream) -> Result<Box<dyn Stream>> {
+    let server_name = ServerName::try_from(server_name.to_string())
+        .map_err(|err| Error::Config(format!("bad server name: {}", err)))?;
+    Ok(Box::new(StreamOwned::new(ClientConnection::new(config, server_name)?, stream)))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Arc;
+
+    use rustls::{pki_types::CertificateDer, ClientConfig, RootCertStore, ServerConfig};
+
+    use crate::error::Result;
+
+    // 生成自签名证书，服务端使用证书和私钥，客户端只信任这个证书
+    pub fn configs() -> Result<(Arc<ServerConfig>, Arc<ClientConfig>)> {
+        let dir = tempfile::tempdir()?;
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert_path, key_path) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        std::fs::write(&cert_path, cert.cert.pem())?;
+        std::fs::write(&key_path, cert.signing_key.serialize_pem())?;
+        let server = super::server_config(&cert_path, &key_path)?;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(cert.cert.der().to_vec()))?;
+        let client = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        Ok((server, Arc::new(client)))
+    }
+
+    #[test]
+    fn test_server_config() -> Result<()> {
+        configs()?;
+        // 证书文件不存在或者为空
+        let dir = tempfile::tempdir()?;
+        let empty = dir.path().join("empty.pem");
+        std::fs::write(&empty, "")?;
+        assert!(super::server_config(&dir.path().join("nope.pem"), &empty).is_err());
+        assert!(super::server_config(&empty, &empty).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+// name resolution, between parsing and planning
+// a statement is checked against the schema before it is planned, so an unknown table or column
+// fails before any row is read, and every expression gets the type of its values
+// a query reads one table, so a column name can never be ambiguous
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::Transaction,
+        parser::ast::{self, AggregateFunc, Consts, Expression, Function, Operation, OrderDirection, SelectItem},
+        plan::label,
+        schema::Table,
+        types::DataType,
+    },
+};
+
+pub struct Binder<'a, T: Transaction> {
+    txn: &'a T,
+}
+
+impl<'a, T: Transaction> Binder<'a, T> {
+    pub fn new(txn: &'a T) -> Self {
+        Self { txn }
+    }
+
+    // statements without names of columns are left to the planner and executor
+    pub fn bind(&self, stmt: &ast::Statement) -> Result<()> {
+        match stmt {
+            ast::Statement::Select { select, table_name, filter, group_by, order_by, .. } => {
+                self.bind_select(select, table_name, filter.as_ref(), group_by, order_by)?;
+            }
+            ast::Statement::Insert { table_name, columns, .. } => {
+                let table = self.txn.must_get_table(table_name.clone())?;
+                for column in columns.iter().flatten() {
+                    column_type(&table, column)?;
+                }
+            }
+            ast::Statement::Explain { stmt } => self.bind(stmt)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // the types of the select list, with stars expanded
+    pub fn bind_select(
+        &self,
+        select: &[SelectItem],
+        table_name: &str,
+        filter: Option<&Expression>,
+        group_by: &[Expression],
+        order_by: &[(Expression, OrderDirection)],
+    ) -> Result<Vec<Option<DataType>>> {
+        let table = self.txn.must_get_table(table_name.to_string())?;
+        let mut types = Vec::new();
+        for item in select {
+            match item {
+                SelectItem::Wildcard(_) => types.extend(table.columns.iter().map(|c| Some(c.datatype.value_type()))),
+                SelectItem::Expr(expr, _) => types.push(type_of(expr, &table)?),
+            }
+        }
+        if let Some(filter) = filter {
+            check_condition(filter, &table)?;
+        }
+        // a position or AS name stands for a select expression, the planner resolves it
+        for expr in group_by.iter().chain(order_by.iter().map(|(expr, _)| expr)) {
+            let resolved = match expr {
+                Expression::Consts(Consts::Integer(_)) => true,
+                Expression::Field(name) => {
+                    select.iter().any(|item| matches!(item, SelectItem::Expr(_, Some(alias)) if alias == name))
+                }
+                _ => false,
+            };
+            if !resolved {
+                type_of(expr, &table)?;
+            }
+        }
+        Ok(types)
+    }
+}
+
+fn column_type(table: &Table, name: &str) -> Result<DataType> {
+    match table.columns.iter().find(|c| c.name == name) {
+        Some(column) => Ok(column.datatype.value_type()),
+        None => Err(Error::ColumnNotFound { table: table.name.clone(), column: name.to_string(), position: None }),
+    }
+}
+
+// the type of the values of an expression over rows of the table, None if it is always NULL
+// or not a value, like a row
+// integers with floats are floats, like in eval::arithmetic
+// operands are checked like eval checks values, an operand of the wrong type is a TypeMismatch
+pub fn type_of(expr: &Expression, table: &Table) -> Result<Option<DataType>> {
+    Ok(match expr {
+        Expression::Consts(Consts::Null) => None,
+        Expression::Consts(Consts::Boolean(_)) => Some(DataType::Boolean),
+        Expression::Consts(Consts::Integer(_)) => Some(DataType::Integer),
+        Expression::Consts(Consts::Float(_)) => Some(DataType::Float),
+        Expression::Consts(Consts::String(_)) => Some(DataType::String),
+        Expression::Consts(Consts::Vector(v)) => Some(DataType::Vector(v.len())),
+        Expression::Field(name) => Some(column_type(table, name)?),
+        Expression::Row(exprs) => {
+            for expr in exprs {
+                type_of(expr, table)?;
+            }
+            None
+        }
+        Expression::Operation(Operation::Compare(_, l, r)) => {
+            check_compare(l, r, table)?;
+            Some(DataType::Boolean)
+        }
+        Expression::Operation(Operation::In(l, list)) => {
+            for expr in list {
+                check_compare(l, expr, table)?;
+            }
+            type_of(l, table)?;
+            Some(DataType::Boolean)
+        }
+        Expression::Operation(Operation::Distance(l, r)) => {
+            match (type_of(l, table)?, type_of(r, table)?) {
+                (Some(DataType::Vector(n)), Some(got)) if got != DataType::Vector(n) => {
+                    return Err(mismatch(r, DataType::Vector(n), got))
+                }
+                (Some(got), Some(DataType::Vector(n))) if got != DataType::Vector(n) => {
+                    return Err(mismatch(l, DataType::Vector(n), got))
+                }
+                _ => {}
+            }
+            Some(DataType::Float)
+        }
+        Expression::Operation(Operation::Arithmetic(_, l, r)) => match (type_of(l, table)?, type_of(r, table)?) {
+            (Some(got), other) if !is_number(&got) => return Err(mismatch(l, number_like(other), got)),
+            (other, Some(got)) if !is_number(&got) => return Err(mismatch(r, number_like(other), got)),
+            (Some(DataType::Integer), Some(DataType::Integer)) => Some(DataType::Integer),
+            (None, datatype) | (datatype, None) => datatype,
+            _ => Some(DataType::Float),
+        },
+        Expression::Function(func, args) => {
+            let params = match func {
+                Function::Substr => &[DataType::String, DataType::Integer, DataType::Integer][..],
+                Function::Length | Function::Upper | Function::Lower => &[DataType::String][..],
+            };
+            for (arg, param) in args.iter().zip(params) {
+                match type_of(arg, table)? {
+                    Some(got) if &got != param => return Err(mismatch(arg, param.clone(), got)),
+                    _ => {}
+                }
+            }
+            for arg in args.iter().skip(params.len()) {
+                type_of(arg, table)?;
+            }
+            Some(match func {
+                Function::Substr | Function::Upper | Function::Lower => DataType::String,
+                Function::Length => DataType::Integer,
+            })
+        }
+        Expression::Aggregate(aggregate) => {
+            if let Some(filter) = &aggregate.filter {
+                check_condition(filter, table)?;
+            }
+            let arg = match &aggregate.arg {
+                Some(arg) => type_of(arg, table)?,
+                None => None,
+            };
+            match (aggregate.func, arg) {
+                (AggregateFunc::Sum | AggregateFunc::Avg, Some(got)) if !is_number(&got) => {
+                    return Err(mismatch(aggregate.arg.as_deref().unwrap(), DataType::Integer, got))
+                }
+                (AggregateFunc::Count, _) => Some(DataType::Integer),
+                (AggregateFunc::Avg, _) => Some(DataType::Float),
+                (AggregateFunc::Sum | AggregateFunc::Min | AggregateFunc::Max, arg) => arg,
+            }
+        }
+    })
+}
+
+// WHERE and FILTER conditions are booleans, or NULL
+pub fn check_condition(expr: &Expression, table: &Table) -> Result<()> {
+    match type_of(expr, table)? {
+        Some(got) if got != DataType::Boolean => Err(mismatch(expr, DataType::Boolean, got)),
+        _ => Ok(()),
+    }
+}
+
+// numbers compare with numbers, other values only with their own type
+// rows compare value by value, the error names the column side
+fn check_compare(l: &Expression, r: &Expression, table: &Table) -> Result<()> {
+    if let (Expression::Row(ls), Expression::Row(rs)) = (l, r) {
+        if ls.len() == rs.len() {
+            return ls.iter().zip(rs).try_for_each(|(l, r)| check_compare(l, r, table));
+        }
+    }
+    match (type_of(l, table)?, type_of(r, table)?) {
+        (Some(lt), Some(rt)) if lt != rt && !(is_number(&lt) && is_number(&rt)) => match (l, r) {
+            (Expression::Field(_), _) | (_, Expression::Consts(_)) => Err(mismatch(l, lt, rt)),
+            _ => Err(mismatch(r, rt, lt)),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn is_number(datatype: &DataType) -> bool {
+    matches!(datatype, DataType::Integer | DataType::Float)
+}
+
+// the number an arithmetic operand should be, going by the other operand
+fn number_like(other: Option<DataType>) -> DataType {
+    match other {
+        Some(DataType::Float) => DataType::Float,
+        _ => DataType::Integer,
+    }
+}
+
+fn mismatch(expr: &Expression, expected: DataType, got: DataType) -> Error {
+    Error::TypeMismatch { column: label(expr, &None), expected, got }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{Error, Result},
+        sql::{
+            parser::{
+                ast::{SelectItem, Statement},
+                Parser,
+            },
+            schema::{Column, Table},
+            types::DataType,
+        },
+    };
+
+    use super::type_of;
+
+    fn check(sql: &str) -> Result<Option<DataType>> {
+        let table = Table {
+            name: "t".to_string(),
+            columns: vec![
+                Column { name: "a".to_string(), datatype: DataType::Integer, nullable: true, default: None },
+                Column { name: "b".to_string(), datatype: DataType::Float, nullable: true, default: None },
+                Column { name: "s".to_string(), datatype: DataType::String, nullable: true, default: None },
+            ],
+            ttl: None,
+            audit: false,
+        };
+        match Parser::new(&format!("select {} from t;", sql)).parse()? {
+            Statement::Select { select, .. } => match &select[0] {
+                SelectItem::Expr(expr, _) => type_of(expr, &table),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_binder_type_of() -> Result<()> {
+        assert_eq!(check("a + 1")?, Some(DataType::Integer));
+        assert_eq!(check("a * b")?, Some(DataType::Float));
+        assert_eq!(check("a + null")?, Some(DataType::Integer));
+        assert_eq!(check("null")?, None);
+        assert_eq!(check("length(upper(s))")?, Some(DataType::Integer));
+        assert_eq!(check("a in (1, 2)")?, Some(DataType::Boolean));
+        // 聚合的类型
+        assert_eq!(check("count(*)")?, Some(DataType::Integer));
+        assert_eq!(check("max(s)")?, Some(DataType::String));
+        assert_eq!(check("avg(a)")?, Some(DataType::Float));
+        // 表达式里任何位置的未知列
+        assert_eq!(
+            check("sum(a) filter (where c > 1)"),
+            Err(Error::ColumnNotFound { table: "t".to_string(), column: "c".to_string(), position: None })
+        );
+        assert!(check("substr(s, x)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_binder_type_mismatch() -> Result<()> {
+        let mismatch = |column: &str, expected, got| {
+            Err(Error::TypeMismatch { column: column.to_string(), expected, got })
+        };
+        // 比较的两边类型要一致，整数和浮点数可以比较
+        assert_eq!(check("s > 5"), mismatch("s", DataType::String, DataType::Integer));
+        assert_eq!(check("5 < s"), mismatch("s", DataType::String, DataType::Integer));
+        assert_eq!(check("a = s"), mismatch("a", DataType::Integer, DataType::String));
+        assert_eq!(check("upper(s) = true"), mismatch("upper", DataType::String, DataType::Boolean));
+        assert_eq!(check("(a, s) = (1, 2)"), mismatch("s", DataType::String, DataType::Integer));
+        assert_eq!(check("s in ('x', 1)"), mismatch("s", DataType::String, DataType::Integer));
+        assert_eq!(check("a < b"), Ok(Some(DataType::Boolean)));
+        assert_eq!(check("s = null"), Ok(Some(DataType::Boolean)));
+        // 运算和函数的参数
+        assert_eq!(check("s + 1"), mismatch("s", DataType::Integer, DataType::String));
+        assert_eq!(check("b * s"), mismatch("s", DataType::Float, DataType::String));
+        assert_eq!(check("length(a)"), mismatch("a", DataType::String, DataType::Integer));
+        assert_eq!(check("substr(s, b)"), mismatch("b", DataType::Integer, DataType::Float));
+        assert_eq!(check("sum(s)"), mismatch("s", DataType::Integer, DataType::String));
+        assert_eq!(check("max(s)")?, Some(DataType::String));
+        assert_eq!(check("count(*) filter (where a)"), mismatch("a", DataType::Boolean, DataType::Integer));
+        Ok(())
+    }
+}
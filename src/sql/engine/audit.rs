@@ -0,0 +1,155 @@
+// the audit log of the tables created WITH (audit = true), a table named _audit
+// a row inserted into or deleted from one of them is recorded by the transaction writing it,
+// so a rolled back write leaves no record behind and a committed one always has its record
+//   id          the version of the transaction times 2^32, plus the position of the record in it
+//   txn         the version of the transaction
+//   table_name
+//   op          insert or delete, there is no UPDATE statement yet
+//   username    the logged in user, NULL for sessions opened by the application itself
+//   at          unix milliseconds
+//   old_row     the row before the change as a json object of its columns, NULL for an insert
+//   new_row     the row after it, NULL for a delete
+// expired rows deleted by VACUUM are recorded as deletes, DROP TABLE only removes the table
+// the engine creates the log with the first audited table, and again if it was dropped since,
+// it is read with SELECT like any other table, but only the engine writes it
+use serde_json::{Map, Value as Json};
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        schema::{Column, Table},
+        types::{json::to_json, DataType, Row, Value},
+    },
+    storage::mvcc::Version,
+};
+
+pub const AUDIT_TABLE: &str = "_audit";
+
+pub fn audit_table() -> Table {
+    let column = |name: &str, datatype, nullable| Column {
+        name: name.to_string(),
+        datatype,
+        nullable,
+        default: nullable.then_some(Value::Null),
+    };
+    let op = DataType::Enum(["insert", "update", "delete"].map(String::from).to_vec());
+    Table {
+        name: AUDIT_TABLE.to_string(),
+        columns: vec![
+            column("id", DataType::Integer, false),
+            column("txn", DataType::Integer, false),
+            column("table_name", DataType::String, false),
+            column("op", op, false),
+            column("username", DataType::String, true),
+            column("at", DataType::Integer, false),
+            column("old_row", DataType::String, true),
+            column("new_row", DataType::String, true),
+        ],
+        ttl: None,
+        audit: false,
+    }
+}
+
+// the record of a change to a row of the table, the row before an insert and after a delete are None
+pub fn audit_row(txn: Version, n: u64, table: &Table, user: &str, at: u64, old: Option<&Row>, new: Option<&Row>) -> Result<Row> {
+    let id = txn
+        .checked_mul(1 << 32)
+        .filter(|_| n < 1 << 32)
+        .and_then(|id| i64::try_from(id + n).ok())
+        .ok_or_else(|| Error::Internal(format!("Transaction {} has no audit log ids left", txn)))?;
+    let op = match (old, new) {
+        (None, _) => "insert",
+        (Some(_), Some(_)) => "update",
+        (Some(_), None) => "delete",
+    };
+    let json = |row: Option<&Row>| match row {
+        Some(row) => {
+            let columns = table.columns.iter().zip(row).map(|(col, value)| (col.name.clone(), to_json(value)));
+            Value::String(Json::Object(columns.collect::<Map<_, _>>()).to_string())
+        }
+        None => Value::Null,
+    };
+    Ok(vec![
+        Value::Integer(id),
+        Value::Integer(txn as i64),
+        Value::String(table.name.clone()),
+        Value::String(op.to_string()),
+        match user.is_empty() {
+            true => Value::Null,
+            false => Value::String(user.to_string()),
+        },
+        Value::Integer(at as i64),
+        json(old),
+        json(new),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{kv::KVEngine, Engine, Session, Transaction},
+            executor::ResultSet,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_audit() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int, b text) with (audit = true);")?;
+        s.execute("create table u (a int);")?;
+        s.execute("create user alice password 'a';")?;
+        let mut alice = kvengine.user_session("alice")?;
+        let log = |s: &mut Session<_>| -> Result<Vec<Vec<Value>>> {
+            match s.execute("select table_name, op, username, old_row, new_row from _audit order by id;")? {
+                ResultSet::Scan { row, .. } => Ok(row),
+                _ => unreachable!(),
+            }
+        };
+        let record = |op: &str, user: Option<&str>, old: Option<&str>, new: Option<&str>| {
+            let text = |s: Option<&str>| s.map_or(Value::Null, |s| Value::String(s.to_string()));
+            vec![Value::String("t".to_string()), Value::String(op.to_string()), text(user), text(old), text(new)]
+        };
+
+        // 只记录开启了审计的表，和写入在同一个事务里
+        alice.execute("insert into t values (1, 'x'), (2, null);")?;
+        alice.execute("insert into u values (1);")?;
+        let mut txn = kvengine.begin()?;
+        txn.delete_row("t".to_string(), &Value::Integer(1))?;
+        txn.delete_row("t".to_string(), &Value::Integer(9))?;
+        txn.commit()?;
+        let mut txn = kvengine.begin()?;
+        let t = txn.must_get_table("t".to_string())?;
+        txn.create_row(&t, vec![Value::Integer(3), Value::Null])?;
+        txn.rollback()?;
+        assert_eq!(log(&mut s)?, vec![
+            record("insert", Some("alice"), None, Some(r#"{"a":1,"b":"x"}"#)),
+            record("insert", Some("alice"), None, Some(r#"{"a":2,"b":null}"#)),
+            record("delete", None, Some(r#"{"a":1,"b":"x"}"#), None),
+        ]);
+
+        // 审计日志只能由引擎写入，删除之后下一次写入重新创建
+        assert!(alice.execute("insert into _audit values (1, 1, 't', 'insert', null, 0, null, null);").is_err());
+        assert!(alice.execute("create table _audit (a int);").is_err());
+        alice.execute("drop table _audit;")?;
+        alice.execute("insert into t values (3, 'z');")?;
+        assert_eq!(log(&mut s)?, vec![record("insert", Some("alice"), None, Some(r#"{"a":3,"b":"z"}"#))]);
+
+        // VACUUM 删除的过期行也记录下来
+        s.execute("create table e (a int) with (ttl = 0, audit = true);")?;
+        s.execute("insert into e values (1);")?;
+        assert!(matches!(s.execute("vacuum e;")?, ResultSet::Vacuum { count: 1 }));
+        match s.execute("select op, old_row from _audit where table_name = 'e' order by id;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row, vec![
+                vec![Value::String("insert".to_string()), Value::Null],
+                vec![Value::String("delete".to_string()), Value::String(r#"{"a":1}"#.to_string())],
+            ]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+// table schemas of the latest committed catalog, shared by all clones of an engine
+// queries read them by loading an Arc, without a kv get or a lock
+// a transaction that changed tables commits and then publishes the tables after it, one at a time
+// the generation is odd while a change is committed and published, a transaction only uses the tables
+// when the generation is even and the same from before it begins until they are loaded, they are then
+// exactly the tables its snapshot sees
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use arc_swap::ArcSwapOption;
+
+use crate::{error::Result, sql::schema::Table};
+
+pub type Tables = BTreeMap<String, Table>;
+
+#[derive(Clone, Default)]
+pub struct SchemaCache {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // None until the first publish, or after a publish failed to read the tables
+    tables: ArcSwapOption<Tables>,
+    generation: AtomicU64,
+    publish: Mutex<()>,
+}
+
+impl SchemaCache {
+    // begin a transaction and get the tables it sees, None if it must read them from its snapshot
+    pub fn begin<T>(&self, begin: impl FnOnce() -> Result<T>) -> Result<(T, Option<Arc<Tables>>)> {
+        let generation = self.inner.generation.load(Ordering::SeqCst);
+        let txn = begin()?;
+        if generation % 2 == 1 {
+            return Ok((txn, None));
+        }
+        let tables = self.inner.tables.load_full();
+        match self.inner.generation.load(Ordering::SeqCst) == generation {
+            true => Ok((txn, tables)),
+            false => Ok((txn, None)),
+        }
+    }
+
+    // commit a change of tables, then publish the tables update makes from the ones published before
+    // the tables are left out until the next publish if update fails, the commit still succeeded
+    pub fn publish(
+        &self,
+        commit: impl FnOnce() -> Result<()>,
+        update: impl FnOnce(Option<&Tables>) -> Result<Tables>,
+    ) -> Result<()> {
+        let _publish = self.inner.publish.lock()?;
+        self.inner.generation.fetch_add(1, Ordering::SeqCst);
+        let result = commit();
+        if result.is_ok() {
+            let tables = self.inner.tables.load_full();
+            self.inner.tables.store(update(tables.as_deref()).ok().map(Arc::new));
+        }
+        self.inner.generation.fetch_add(1, Ordering::SeqCst);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{Error, Result},
+        sql::schema::Table,
+    };
+
+    use super::{SchemaCache, Tables};
+
+    fn tables(names: &[&str]) -> Tables {
+        names.iter().map(|name| (name.to_string(), Table { name: name.to_string(), columns: vec![], ttl: None, audit: false })).collect()
+    }
+
+    fn snapshot(cache: &SchemaCache) -> Result<Option<std::sync::Arc<Tables>>> {
+        Ok(cache.begin(|| Ok(()))?.1)
+    }
+
+    #[test]
+    fn test_schema_cache() -> Result<()> {
+        let cache = SchemaCache::default();
+        assert!(snapshot(&cache)?.is_none());
+        cache.publish(|| Ok(()), |_| Ok(tables(&["t1"])))?;
+        let first = snapshot(&cache)?.unwrap();
+        assert_eq!(first.keys().collect::<Vec<_>>(), vec!["t1"]);
+
+        // 在已发布的表上更新，提交和发布期间不能使用缓存
+        cache.publish(
+            || {
+                assert!(snapshot(&cache)?.is_none());
+                Ok(())
+            },
+            |published| {
+                let mut tables = published.unwrap().clone();
+                tables.extend(self::tables(&["t2"]));
+                Ok(tables)
+            },
+        )?;
+        assert_eq!(snapshot(&cache)?.unwrap().len(), 2);
+        // 之前取得的快照不变
+        assert_eq!(first.len(), 1);
+
+        // 事务开始后有新的发布，不能使用缓存
+        let (_, tables) = cache.begin(|| cache.publish(|| Ok(()), |_| Ok(self::tables(&["t1", "t2", "t3"]))))?;
+        assert!(tables.is_none());
+        assert_eq!(snapshot(&cache)?.unwrap().len(), 3);
+
+        // 提交失败时缓存不变
+        assert!(cache.publish(|| Err(Error::WriteConflict), |_| Ok(self::tables(&[]))).is_err());
+        assert_eq!(snapshot(&cache)?.unwrap().len(), 3);
+        // 更新失败时缓存失效，直到下次发布
+        cache.publish(|| Ok(()), |_| Err(Error::Internal("x".to_string())))?;
+        assert!(snapshot(&cache)?.is_none());
+        cache.publish(|| Ok(()), |published| {
+            assert!(published.is_none());
+            Ok(self::tables(&["t1"]))
+        })?;
+        assert_eq!(snapshot(&cache)?.unwrap().len(), 1);
+        Ok(())
+    }
+}
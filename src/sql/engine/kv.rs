@@ -1,25 +1,107 @@
 
+use std::{cell::Cell, collections::{BTreeMap, BTreeSet, HashSet}, path::PathBuf, sync::Arc};
+
 use serde::{Deserialize, Serialize};
 
-use crate::{error::{Error, Result}, sql::{schema::Table, types::{Row, Value}}, storage::{self, engine::Engine as StorageEngine}};
+use crate::{error::{Error, Result}, sql::{executor::Warning, procedure::Procedure, schema::{Column, LegacyTable, Table}, types::{row_memory_size, Row, Value}, user::{Grants, Role, User, ADMIN_ROLE}}, storage::{self, engine::Engine as StorageEngine, keycode::{deserialize_key, serialize_key}, mvcc::{MvccStatus, ReadSet, Version}}};
 
-use super::{Engine, Transaction};
+use super::{audit::{audit_row, audit_table, AUDIT_TABLE}, catalog::{SchemaCache, Tables}, row::{decode_legacy_row, decode_row, encode_row, has_all_columns, is_expired, now_millis, written_at}, session::{CancelToken, QueryInfo, SessionInfo, SessionRegistry, SessionStats}, stats::RowCounts, Engine, Transaction};
 
 pub struct KVEngine<E: StorageEngine> {
     pub kv: storage::mvcc::Mvcc<E>,
+    sessions: SessionRegistry,
+    schemas: SchemaCache,
+    row_counts: RowCounts,
 }
 
 impl<E: StorageEngine> Clone for KVEngine<E> {
     fn clone(&self) -> Self {
-        Self { kv: self.kv.clone() }
+        Self {
+            kv: self.kv.clone(),
+            sessions: self.sessions.clone(),
+            schemas: self.schemas.clone(),
+            row_counts: self.row_counts.clone(),
+        }
     }
 }
 
 impl<E: StorageEngine> KVEngine<E> {
-    pub fn new(engine: E) -> Self {
-        Self {
+    pub fn new(engine: E) -> Result<Self> {
+        Self::with_sessions(engine, SessionRegistry::default())
+    }
+
+    fn with_sessions(engine: E, sessions: SessionRegistry) -> Result<Self> {
+        let eng = Self {
             kv: storage::mvcc::Mvcc::new(engine),
+            sessions,
+            schemas: SchemaCache::default(),
+            row_counts: RowCounts::default(),
+        };
+        eng.kv.recover()?;
+        let tables = eng.migrate()?;
+        eng.schemas.publish(|| Ok(()), |_| Ok(tables))?;
+        Ok(eng)
+    }
+
+    // roll back the transactions still open and close the storage engine, for every clone of the engine
+    // statements running in other sessions fail with Error::Closed, so does every later one
+    // returns the versions rolled back
+    pub fn close(&self) -> Result<Vec<Version>> {
+        self.kv.close()
+    }
+
+    // the data format is recorded under Key::Format, older data is rewritten once when the engine opens
+    //   none: keys encoded with bincode, whose byte order doesn't follow value order
+    //   1: keys encoded with keycode, rows stored as a bare bincode Vec<Value>
+    //   2: rows stored in the versioned format of row.rs
+    //   3: roles and grants, users of older data are made admins, as they could do anything before
+    //   4: tables record whether they are audited, older ones are not
+    // returns the tables, nothing else runs yet to change them
+    fn migrate(&self) -> Result<Tables> {
+        let txn = self.kv.begin()?;
+        let format: u32 = match txn.get(Key::Format.encode()?)? {
+            Some(v) => bincode::deserialize(&v)?,
+            None => 0,
+        };
+        if format < 1 {
+            for result in txn.scan_prefix(Vec::new())? {
+                if Key::decode(&result.key).is_ok() {
+                    continue;
+                }
+                let key: Key = bincode::deserialize(&result.key)?;
+                txn.delete(result.key)?;
+                txn.set(key.encode()?, result.value)?;
+            }
         }
+        if format < 2 {
+            // rows of a table with ttl live for another full ttl from now
+            let now = now_millis();
+            for result in txn.scan_prefix(KeyPrefix::Table.encode()?)? {
+                let table: Table = bincode::deserialize::<LegacyTable>(&result.value)?.into();
+                for row in txn.scan_prefix(KeyPrefix::Row(table.name.clone()).encode()?)? {
+                    txn.set(row.key, encode_row(&table, &decode_legacy_row(&row.value)?, now)?)?;
+                }
+            }
+        }
+        if format < 3 {
+            for result in txn.scan_prefix(KeyPrefix::User.encode()?)? {
+                let user: User = bincode::deserialize(&result.value)?;
+                let grants = Grants { roles: [ADMIN_ROLE.to_string()].into(), ..Default::default() };
+                txn.set(Key::Grants(user.name).encode()?, bincode::serialize(&grants)?)?;
+            }
+        }
+        if format < 4 {
+            for result in txn.scan_prefix(KeyPrefix::Table.encode()?)? {
+                let table: Table = bincode::deserialize::<LegacyTable>(&result.value)?.into();
+                txn.set(result.key, bincode::serialize(&table)?)?;
+            }
+        }
+        if format < KEY_FORMAT_VERSION {
+            txn.set(Key::Format.encode()?, bincode::serialize(&KEY_FORMAT_VERSION)?)?;
+        }
+        let tables = scan_tables(&txn)?;
+        txn.commit()?;
+        Ok(tables)
     }
 }
 
@@ -27,112 +109,655 @@ impl<E: StorageEngine> Engine for KVEngine<E> {
     type Transaction = KVTransaction<E>;
 
     fn begin(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.begin()?))
+        KVTransaction::new(self)
+    }
+
+    fn sessions(&self) -> &SessionRegistry {
+        &self.sessions
+    }
+
+    // the attached database shares the sessions, so they show up in SHOW PROCESSLIST once
+    fn attach(&self, path: &str) -> Result<Self> {
+        Self::with_sessions(E::open(PathBuf::from(path))?, self.sessions.clone())
     }
 }
 
 pub struct KVTransaction<E: StorageEngine> {
-    txn: storage::mvcc::MvccTransaction<E>
+    txn: storage::mvcc::MvccTransaction<E>,
+    kv: storage::mvcc::Mvcc<E>,
+    sessions: SessionRegistry,
+    schemas: SchemaCache,
+    // the tables of the snapshot from the schema cache, None to read them from the snapshot
+    tables: Option<Arc<Tables>>,
+    // tables created or dropped, the cache no longer has what it sees
+    tables_changed: BTreeSet<String>,
+    // tables whose rows were written, checked at commit like tables_changed, see read_set
+    rows_written: BTreeSet<String>,
+    row_counts: RowCounts,
+    // rows inserted less rows deleted of each table, added to row_counts at commit
+    row_deltas: BTreeMap<String, i64>,
+    memory_limit: Option<usize>,
+    // charged by scan_table as it reads, so it is behind a cell
+    memory_used: Cell<usize>,
+    // of the statement running in the transaction
+    cancel: CancelToken,
+    warnings: Vec<Warning>,
+    // the logged in user, recorded in the audit log
+    user: String,
+    // records written to the audit log, the next one is numbered by it
+    audited: u64,
 }
 
 impl<E: StorageEngine> KVTransaction<E> {
-    pub fn new(txn: storage::mvcc::MvccTransaction<E>) -> Self {
-        Self {txn}
+    pub fn new(engine: &KVEngine<E>) -> Result<Self> {
+        let (txn, tables) = engine.schemas.begin(|| engine.kv.begin())?;
+        Ok(Self {
+            txn,
+            kv: engine.kv.clone(),
+            sessions: engine.sessions.clone(),
+            schemas: engine.schemas.clone(),
+            tables,
+            tables_changed: BTreeSet::new(),
+            rows_written: BTreeSet::new(),
+            row_counts: engine.row_counts.clone(),
+            row_deltas: BTreeMap::new(),
+            memory_limit: None,
+            memory_used: Cell::new(0),
+            cancel: CancelToken::default(),
+            warnings: Vec::new(),
+            user: String::new(),
+            audited: 0,
+        })
+    }
+
+    // check the rows and encode their keys and values for one storage batch
+    fn prepare_rows(&mut self, table: &Table, rows: Vec<Row>) -> Result<PreparedRows> {
+        self.cancel.check()?;
+        let now = now_millis();
+        // a row key is the key prefix of the table followed by the primary key
+        let prefix = KeyPrefix::Row(table.name.clone()).encode()?;
+        let mut rejected = Vec::new();
+        let mut keys = Vec::with_capacity(rows.len());
+        let mut checked = Vec::with_capacity(rows.len());
+        let mut seen = HashSet::with_capacity(rows.len());
+        // the rounded values of each column, one warning per column
+        let mut rounded: Vec<Option<(usize, Value)>> = vec![None; table.columns.len()];
+        for (i, row) in rows.into_iter().enumerate() {
+            for ((value, col), rounded) in row.iter().zip(&table.columns).zip(rounded.iter_mut()) {
+                if !value.coerces_exactly(&col.datatype) {
+                    rounded.get_or_insert_with(|| (0, value.clone())).0 += 1;
+                }
+            }
+            let row = table.coerce_row(row);
+            if let Err(err) = table.check_row(&row) {
+                rejected.push((i, err));
+                continue;
+            }
+            let mut key = prefix.clone();
+            key.extend(serialize_key(&row[0])?);
+            if !seen.insert(key.clone()) {
+                rejected.push((i, Error::UniqueViolation { table: table.name.clone(), key: row[0].clone() }));
+                continue;
+            }
+            keys.push(key);
+            checked.push((i, row));
+        }
+
+        // the keys are read in this transaction, so a concurrent insert of one is a write conflict
+        // an expired row not vacuumed yet is replaced
+        let existing = self.txn.get_many(keys.clone())?;
+        let mut pairs = Vec::with_capacity(keys.len());
+        let mut written = Vec::new();
+        for ((key, (i, row)), value) in keys.into_iter().zip(checked).zip(existing) {
+            if let Some(value) = value {
+                if !is_expired(table, decode_row(table, &value)?.1, now) {
+                    rejected.push((i, Error::UniqueViolation { table: table.name.clone(), key: row[0].clone() }));
+                    continue;
+                }
+            }
+            pairs.push((key, encode_row(table, &row, now)?));
+            if table.audit {
+                written.push(row);
+            }
+        }
+        self.cancel.check()?;
+        for (col, rounded) in table.columns.iter().zip(rounded) {
+            if let Some((count, value)) = rounded {
+                self.warnings.push(Warning::Rounded { table: table.name.clone(), column: col.name.clone(), count, value });
+            }
+        }
+        rejected.sort_by_key(|(i, _)| *i);
+        Ok(PreparedRows { pairs, rejected, written })
     }
+
+    // record the changes to rows of an audited table in the audit log, see audit.rs
+    // each change is the row before and the row after it
+    fn audit(&mut self, table: &Table, changes: Vec<(Option<Row>, Option<Row>)>) -> Result<()> {
+        if !table.audit || changes.is_empty() {
+            return Ok(());
+        }
+        if self.get_table(AUDIT_TABLE.to_string())?.is_none() {
+            self.create_table(audit_table())?;
+        }
+        let log = self.must_get_table(AUDIT_TABLE.to_string())?;
+        let now = now_millis();
+        let mut rows = Vec::with_capacity(changes.len());
+        for (old, new) in changes {
+            rows.push(audit_row(self.version(), self.audited, table, &self.user, now, old.as_ref(), new.as_ref())?);
+            self.audited += 1;
+        }
+        self.create_rows(&log, rows)
+    }
+
+    // see Transaction::reserve_memory
+    fn charge_memory(&self, bytes: usize) -> Result<()> {
+        self.cancel.check()?;
+        self.memory_used.set(self.memory_used.get().saturating_add(bytes));
+        match self.memory_limit {
+            Some(limit) if self.memory_used.get() > limit => Err(Error::MemoryLimitExceeded { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    fn count_rows(&mut self, table_name: &str, delta: i64) {
+        *self.row_deltas.entry(table_name.to_string()).or_default() += delta;
+    }
+
+    fn cached_tables(&self) -> Option<&Tables> {
+        match self.tables_changed.is_empty() {
+            true => self.tables.as_deref(),
+            false => None,
+        }
+    }
+
+    // ddl is transactional as the rows are, a transaction can not commit rows of a table whose schema
+    // another one changed or is changing, and the change can not commit over rows it did not see:
+    // a table whose rows were written must have no version of its schema this transaction can not see,
+    // a table created or dropped must have no such rows
+    fn read_set(&self) -> Result<ReadSet> {
+        Ok(ReadSet {
+            keys: self
+                .rows_written
+                .difference(&self.tables_changed)
+                .map(|name| Key::Table(name.clone()).encode())
+                .collect::<Result<_>>()?,
+            prefixes: self.tables_changed.iter().map(|name| KeyPrefix::Row(name.clone()).encode()).collect::<Result<_>>()?,
+        })
+    }
+
+    fn read_table(&self, table_name: String) -> Result<Option<Table>> {
+        let key = Key::Table(table_name);
+        Ok(self
+            .txn
+            .get(key.encode()?)?
+            .map(|v| bincode::deserialize(&v))
+            .transpose()?)
+    }
+}
+
+// rows checked and encoded for one storage batch
+struct PreparedRows {
+    // keys and values to write
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    // rows left out by index, in order
+    rejected: Vec<(usize, Error)>,
+    // the rows to write, only kept for the audit log of an audited table
+    written: Vec<Row>,
+}
+
+// the tables of the latest committed catalog
+fn load_tables<E: StorageEngine>(kv: &storage::mvcc::Mvcc<E>) -> Result<Tables> {
+    let txn = kv.begin()?;
+    let tables = scan_tables(&txn);
+    txn.rollback()?;
+    tables
+}
+
+fn scan_tables<E: StorageEngine>(txn: &storage::mvcc::MvccTransaction<E>) -> Result<Tables> {
+    txn.scan_prefix(KeyPrefix::Table.encode()?)?
+        .into_iter()
+        .map(|result| {
+            let table: Table = bincode::deserialize(&result.value)?;
+            Ok((table.name.clone(), table))
+        })
+        .collect()
 }
 
 impl<E: StorageEngine> Transaction for KVTransaction<E> {
+    fn version(&self) -> Version {
+        self.txn.version()
+    }
+
     fn commit(&self) -> Result<()> {
-        Ok(())
+        let reads = self.read_set()?;
+        let commit = || self.txn.commit_validated(&reads);
+        if self.tables_changed.is_empty() {
+            commit()?;
+            return self.row_counts.apply(&self.row_deltas, &self.tables_changed);
+        }
+        // the other changes are already in the cache, ddl on the same table conflicts
+        let changes = self
+            .tables_changed
+            .iter()
+            .map(|name| Ok((name.clone(), self.read_table(name.clone())?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.schemas.publish(
+            commit,
+            |tables| {
+                let mut tables = match tables {
+                    Some(tables) => tables.clone(),
+                    None => return load_tables(&self.kv),
+                };
+                for (name, table) in changes {
+                    match table {
+                        Some(table) => tables.insert(name, table),
+                        None => tables.remove(&name),
+                    };
+                }
+                Ok(tables)
+            },
+        )?;
+        self.row_counts.apply(&self.row_deltas, &self.tables_changed)
     }
 
     fn rollback(&self) -> Result<()> {
-        Ok(())
+        self.txn.rollback()
     }
 
-    fn create_row(&mut self, table_name: String, row: Row) -> Result<()> {
-        // check row type validation
-        let table = self.must_get_table(table_name.clone())?;
-        for (i, col) in table.columns.iter().enumerate() {
-            match row[i].datatype() {
-                None if col.nullable => {},
-                None =>  return Err(Error::Internal(format!("column {} cannot be null", col.name))),
-                Some(dt) if dt != col.datatype => return Err(Error::Internal(format!("column {} data type mismatch", col.name))),
-                _ => {},
-            }
-        }
-        // store data in memeory store engine
-        // temporarily use row[0] (the first column) as primary key  (to be continue)
-        let id = Key::Row(table_name.clone(), row[0].clone());
-        let key = bincode::serialize(&id)?;
-        let value = bincode::serialize(&row)?;
-        self.txn.set(key, value)?;
+    fn create_rows(&mut self, table: &Table, rows: Vec<Row>) -> Result<()> {
+        self.rows_written.insert(table.name.clone());
+        let rows = self.prepare_rows(table, rows)?;
+        if let Some((_, err)) = rows.rejected.into_iter().next() {
+            return Err(err);
+        }
+        self.count_rows(&table.name, rows.pairs.len() as i64);
+        self.txn.set_many(rows.pairs)?;
+        self.audit(table, rows.written.into_iter().map(|row| (None, Some(row))).collect())
+    }
 
-        Ok(())
+    fn bulk_insert(&mut self, table: &Table, rows: Vec<Row>) -> Result<Vec<(usize, Error)>> {
+        self.rows_written.insert(table.name.clone());
+        let rows = self.prepare_rows(table, rows)?;
+        self.count_rows(&table.name, rows.pairs.len() as i64);
+        self.txn.set_many(rows.pairs)?;
+        self.audit(table, rows.written.into_iter().map(|row| (None, Some(row))).collect())?;
+        Ok(rows.rejected)
+    }
+
+    fn delete_row(&mut self, table_name: String, id: &Value) -> Result<()> {
+        self.cancel.check()?;
+        let key = Key::Row(table_name.clone(), id.clone()).encode()?;
+        // deleting a row that does not exist changes nothing, the estimate included
+        let Some(value) = self.txn.get(key.clone())? else {
+            return Ok(());
+        };
+        self.rows_written.insert(table_name.clone());
+        self.count_rows(&table_name, -1);
+        if let Some(table) = self.get_table(table_name)?.filter(|table| table.audit) {
+            let row = decode_row(&table, &value)?.0;
+            self.audit(&table, vec![(Some(row), None)])?;
+        }
+        self.txn.delete(key)
     }
 
     fn scan_table(&self, table_name: String) -> Result<Vec<Row>> {
         // 在 Key 枚举中，Row 类型的键是由 Key::Row(table_name, row) 表示的，包含了表名和行的具体数据。
         // 因此，KeyPrefix::Row(table_name) 作为前缀，可以用来定位所有以给定表名开头的行数据。
+        let table = self.must_get_table(table_name.clone())?;
         let prefix = KeyPrefix::Row(table_name.clone());
-        let results = self.txn.scan_prefix(bincode::serialize(&prefix)?)?;
+        let now = now_millis();
         let mut rows  = Vec::new();
-        for result in results {
-            let row: Row = bincode::deserialize(&result.value)?;
-            rows.push(row);
+        // each row is charged as it is read, a table larger than the limit fails before it is all in memory
+        self.txn.scan_prefix_with(prefix.encode()?, |_, value| {
+            self.cancel.check()?;
+            let (row, written_at) = decode_row(&table, value)?;
+            if !is_expired(&table, written_at, now) {
+                self.charge_memory(row_memory_size(&row))?;
+                rows.push(row);
+            }
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    fn count_table(&self, table_name: String) -> Result<u64> {
+        let table = self.must_get_table(table_name.clone())?;
+        let now = now_millis();
+        self.txn.count_prefix(KeyPrefix::Row(table_name).encode()?, &mut |value| {
+            self.cancel.check()?;
+            Ok(table.ttl.is_none() || !is_expired(&table, written_at(&table, value)?, now))
+        })
+    }
+
+    fn scan_ids(&self, table_name: String, after: Option<Value>, limit: usize) -> Result<Vec<Value>> {
+        self.must_get_table(table_name.clone())?;
+        let after = after.map(|id| Key::Row(table_name.clone(), id).encode()).transpose()?;
+        self.txn
+            .scan_prefix_after(KeyPrefix::Row(table_name).encode()?, after, limit)?
+            .into_iter()
+            .map(|result| match Key::decode(&result.key)? {
+                Key::Row(_, id) => Ok(id),
+                key => Err(Error::Internal(format!("Unexpected key {:?}", key))),
+            })
+            .collect()
+    }
+
+    fn delete_expired(&mut self, table_name: String) -> Result<usize> {
+        let table = self.must_get_table(table_name.clone())?;
+        if table.ttl.is_none() {
+            return Ok(0);
+        }
+        let now = now_millis();
+        let mut count = 0;
+        let mut deleted = Vec::new();
+        for result in self.txn.scan_prefix(KeyPrefix::Row(table_name.clone()).encode()?)? {
+            self.cancel.check()?;
+            let (row, written_at) = decode_row(&table, &result.value)?;
+            if is_expired(&table, written_at, now) {
+                self.txn.delete(result.key)?;
+                count += 1;
+                if table.audit {
+                    deleted.push((Some(row), None));
+                }
+            }
+        }
+        self.count_rows(&table_name, -(count as i64));
+        self.audit(&table, deleted)?;
+        Ok(count)
+    }
+
+    fn estimate_rows(&self, table_name: String) -> Result<u64> {
+        self.must_get_table(table_name.clone())?;
+        let delta = self.row_deltas.get(&table_name).copied().unwrap_or(0);
+        if !self.tables_changed.contains(&table_name) {
+            if let Some(rows) = self.row_counts.get(&table_name)? {
+                return Ok(rows.saturating_add_signed(delta));
+            }
+        }
+        // the scan sees the writes of this transaction, only a count of committed rows is kept
+        let rows = self.txn.count_prefix(KeyPrefix::Row(table_name.clone()).encode()?, &mut |_| Ok(true))?;
+        if !self.rows_written.contains(&table_name) && !self.tables_changed.contains(&table_name) {
+            self.row_counts.set(&table_name, rows)?;
         }
         Ok(rows)
     }
 
-    fn create_table(&mut self, table: Table) -> Result<()> {
+    fn table_names(&self) -> Result<Vec<String>> {
+        if let Some(tables) = self.cached_tables() {
+            return Ok(tables.keys().cloned().collect());
+        }
+        self.txn
+            .scan_prefix(KeyPrefix::Table.encode()?)?
+            .into_iter()
+            .map(|result| match Key::decode(&result.key)? {
+                Key::Table(name) => Ok(name),
+                key => Err(Error::Internal(format!("Unexpected key {:?}", key))),
+            })
+            .collect()
+    }
+
+    fn create_table(&mut self, mut table: Table) -> Result<()> {
         // check if the table exists
         if self.get_table(table.name.clone())?.is_some() {
-            return Err(Error::Internal(format!("Table {} already exist.", table.name)));
+            return Err(Error::DuplicateTable(table.name));
         }
-        // check validation
-        if table.columns.is_empty() {
-            return Err(Error::Internal(format!("Table {} has no columns.", table.name)));
-        }
-        let key = bincode::serialize(&Key::Table(table.name.clone()))?;
+        table.normalize_defaults();
+        table.validate()?;
+        let key = Key::Table(table.name.clone()).encode()?;
         let value = bincode::serialize(&table)?;
+        self.tables_changed.insert(table.name.clone());
         self.txn.set(key, value)?;
+        // the log is there before the first write to record
+        if table.audit && self.get_table(AUDIT_TABLE.to_string())?.is_none() {
+            self.create_table(audit_table())?;
+        }
         Ok(())
     }
 
     fn get_table(&self, table_name: String) -> Result<Option<Table>> {
-        let key = Key::Table(table_name);
+        if let Some(tables) = self.cached_tables() {
+            return Ok(tables.get(&table_name).cloned());
+        }
+        self.read_table(table_name)
+    }
+
+    fn drop_table(&mut self, table_name: String) -> Result<()> {
+        self.must_get_table(table_name.clone())?;
+        self.tables_changed.insert(table_name.clone());
+        let prefix = KeyPrefix::Row(table_name.clone());
+        for result in self.txn.scan_prefix(prefix.encode()?)? {
+            self.txn.delete(result.key)?;
+        }
+        self.txn.delete(Key::Table(table_name).encode()?)
+    }
+
+    fn add_column(&mut self, table_name: String, column: Column) -> Result<()> {
+        let mut table = self.must_get_table(table_name.clone())?;
+        // stored rows read a missing column as its default, a NOT NULL one must have one
+        if column.default.is_none() {
+            return Err(Error::NotNullViolation { column: column.name });
+        }
+        table.columns.push(column);
+        table.normalize_defaults();
+        table.validate()?;
+        self.tables_changed.insert(table_name.clone());
+        self.txn.set(Key::Table(table_name).encode()?, bincode::serialize(&table)?)
+    }
+
+    fn fill_defaults(&mut self, table_name: String, ids: &[Value]) -> Result<usize> {
+        self.cancel.check()?;
+        let table = self.must_get_table(table_name.clone())?;
+        self.rows_written.insert(table_name.clone());
+        let keys = ids.iter().map(|id| Key::Row(table_name.clone(), id.clone()).encode()).collect::<Result<Vec<_>>>()?;
+        let mut pairs = Vec::new();
+        for (key, value) in keys.clone().into_iter().zip(self.txn.get_many(keys)?) {
+            let Some(value) = value else {
+                continue;
+            };
+            if has_all_columns(&table, &value)? {
+                continue;
+            }
+            // the row keeps when it was written, so it expires as it would have
+            let (row, written_at) = decode_row(&table, &value)?;
+            pairs.push((key, encode_row(&table, &row, written_at.unwrap_or_else(now_millis))?));
+        }
+        let count = pairs.len();
+        self.txn.set_many(pairs)?;
+        Ok(count)
+    }
+
+    fn status(&self) -> Result<MvccStatus> {
+        self.txn.status()
+    }
+
+    fn get_user(&self, name: String) -> Result<Option<User>> {
+        let key = Key::User(name);
+        Ok(self
+            .txn
+            .get(key.encode()?)?
+            .map(|v| bincode::deserialize(&v))
+            .transpose()?)
+    }
+
+    fn set_user(&mut self, user: User) -> Result<()> {
+        let key = Key::User(user.name.clone()).encode()?;
+        self.txn.set(key, bincode::serialize(&user)?)
+    }
+
+    fn has_users(&self) -> Result<bool> {
+        Ok(!self.txn.scan_prefix(KeyPrefix::User.encode()?)?.is_empty())
+    }
+
+    fn get_role(&self, name: String) -> Result<Option<Role>> {
+        let key = Key::Role(name);
+        Ok(self
+            .txn
+            .get(key.encode()?)?
+            .map(|v| bincode::deserialize(&v))
+            .transpose()?)
+    }
+
+    fn set_role(&mut self, role: Role) -> Result<()> {
+        let key = Key::Role(role.name.clone()).encode()?;
+        self.txn.set(key, bincode::serialize(&role)?)
+    }
+
+    fn delete_role(&mut self, name: String) -> Result<()> {
+        self.txn.delete(Key::Grants(name.clone()).encode()?)?;
+        self.txn.delete(Key::Role(name).encode()?)
+    }
+
+    fn get_grants(&self, name: String) -> Result<Grants> {
+        let key = Key::Grants(name);
+        Ok(self
+            .txn
+            .get(key.encode()?)?
+            .map(|v| bincode::deserialize(&v))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    fn set_grants(&mut self, name: String, grants: Grants) -> Result<()> {
+        let key = Key::Grants(name).encode()?;
+        if grants == Grants::default() {
+            return self.txn.delete(key);
+        }
+        self.txn.set(key, bincode::serialize(&grants)?)
+    }
+
+    fn list_grants(&self) -> Result<Vec<(String, Grants)>> {
+        let mut grants = Vec::new();
+        for result in self.txn.scan_prefix(KeyPrefix::Grants.encode()?)? {
+            match Key::decode(&result.key)? {
+                Key::Grants(name) => grants.push((name, bincode::deserialize(&result.value)?)),
+                key => return Err(Error::Internal(format!("Unexpected key {:?} among grants", key))),
+            }
+        }
+        Ok(grants)
+    }
+
+    fn get_procedure(&self, name: String) -> Result<Option<Procedure>> {
+        let key = Key::Procedure(name);
         Ok(self
             .txn
-            .get(bincode::serialize(&key)?)?
+            .get(key.encode()?)?
             .map(|v| bincode::deserialize(&v))
             .transpose()?)
     }
+
+    fn set_procedure(&mut self, procedure: Procedure) -> Result<()> {
+        let key = Key::Procedure(procedure.name.clone()).encode()?;
+        self.txn.set(key, bincode::serialize(&procedure)?)
+    }
+
+    fn delete_procedure(&mut self, name: String) -> Result<()> {
+        self.txn.delete(Key::Procedure(name).encode()?)
+    }
+
+    fn sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.sessions.list()
+    }
+
+    fn session_stats(&self) -> Result<SessionStats> {
+        self.sessions.stats()
+    }
+
+    fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    fn queries(&self) -> Result<Vec<QueryInfo>> {
+        self.sessions.queries()
+    }
+
+    fn kill_query(&self, id: u64) -> Result<()> {
+        self.sessions.kill(id)
+    }
+
+    fn set_cancel(&mut self, cancel: CancelToken) {
+        self.cancel = cancel;
+    }
+
+    fn set_session_user(&mut self, user: &str) {
+        self.user = user.to_string();
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // operators reserve memory for every row they hold, so a killed query stops within a row
+    // a plain wrapper of charge_memory, which scan_table also calls for the rows it reads
+    fn reserve_memory(&mut self, bytes: usize) -> Result<()> {
+        self.charge_memory(bytes)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// version of the data format, stored under Key::Format, see KVEngine::migrate
+const KEY_FORMAT_VERSION: u32 = 4;
+
+// keys are encoded with keycode, so rows of a table are sorted by primary key value
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 enum Key {
     Table(String),// table name
     Row(String, Value), // table name, value
+    Format, // key format version
+    User(String), // user name, kept apart from tables and rows
+    Procedure(String), // procedure name
+    Role(String), // role name
+    Grants(String), // name of the user or role granted to
+}
+
+impl Key {
+    fn encode(&self) -> Result<Vec<u8>> {
+        serialize_key(self)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        deserialize_key(data)
+    }
 }
 
 // KeyPrefix::Table 是为了与Key::Table对齐。在序列化后的字节中：
-// 	•	Table 会以 0x01 开头。
-// 	•	Row(String) 会以 0x02 开头。
+// 	•	Table 会以 0x00 开头。
+// 	•	Row(String) 会以 0x01 开头。
 #[derive(Debug, Serialize, Deserialize)]
 enum KeyPrefix {
     Table, // align
     Row(String), // table name
+    Format, // align
+    User, // align
+    Procedure, // align
+    Role, // align
+    Grants, // align
+}
+
+impl KeyPrefix {
+    fn encode(&self) -> Result<Vec<u8>> {
+        serialize_key(self)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{error::Result, sql::engine::Engine, storage::memory::MemoryEngine};
+    use std::time::Duration;
+
+    use crate::{
+        error::{Error, Result},
+        sql::{engine::{Engine, Session, Transaction}, executor::{ResultSet, Warning}, parser::Position, schema::{Column, Table}, types::{DataType, Value}},
+        storage::memory::MemoryEngine,
+    };
+    #[cfg(feature = "disk")]
+    use crate::{
+        sql::{schema::LegacyTable, user::{User, ADMIN_ROLE}},
+        storage::{disk::DiskEngine, mvcc::Mvcc},
+    };
 
-    use super::KVEngine;
+    use super::{Key, KVEngine};
 
     #[test]
     fn test_create_table() -> Result<()> {
-        let kvengine = KVEngine::new(MemoryEngine::new());
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
         let mut s = kvengine.session()?;
 
         s.execute("create table t1 (a int, b text default 'vv', c integer default 100);")?;
@@ -144,4 +769,1232 @@ mod tests {
         println!("{:?}", v);
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_insert_coerce() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int, b float, c float default 2);")?;
+        // 整数可以写入浮点列，默认值也一样
+        s.execute("insert into t1 values (1, 1);")?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { row, .. } => {
+                assert!(matches!(row[0][..], [Value::Integer(1), Value::Float(b), Value::Float(c)] if b == 1.0 && c == 2.0))
+            }
+            _ => unreachable!(),
+        }
+        // 默认值在建表时就转换成列的类型
+        let txn = kvengine.begin()?;
+        let table = txn.must_get_table("t1".to_string())?;
+        txn.rollback()?;
+        assert!(matches!(table.columns[2].default, Some(Value::Float(c)) if c == 2.0));
+        // 浮点数和字符串不会隐式转换成整数
+        assert!(s.execute("insert into t1 values (1.5, 1);").is_err());
+        assert!(s.execute("insert into t1 values ('2', 1);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_errors() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int not null, b text);")?;
+        assert_eq!(s.execute("create table t1 (a int);").unwrap_err(), Error::DuplicateTable("t1".to_string()));
+        assert_eq!(s.execute("select * from t2;").unwrap_err(), Error::TableNotFound("t2".to_string()));
+        assert_eq!(
+            s.execute("create table t2 (a int, a text);").unwrap_err(),
+            Error::DuplicateColumn { table: "t2".to_string(), column: "a".to_string() }
+        );
+        assert!(s.execute("create table t2 (a int default 'x');").is_err());
+        assert_eq!(
+            s.execute("insert into t1 (a, c) values (1, 2);").unwrap_err(),
+            Error::ColumnNotFound { table: "t1".to_string(), column: "c".to_string(), position: Some(Position { line: 1, column: 20 }) }
+        );
+        // 查询里未知的列在执行前就报错，并指出位置
+        assert_eq!(
+            s.execute("select a,\n b + c from t1 where a > 1;").unwrap_err(),
+            Error::ColumnNotFound { table: "t1".to_string(), column: "c".to_string(), position: Some(Position { line: 2, column: 6 }) }
+        );
+        for sql in ["select * from t1 where c = 1;", "select * from t1 order by c;", "select a from t1 group by c;", "select count(c) from t1;"] {
+            assert!(matches!(s.execute(sql), Err(Error::ColumnNotFound { position: Some(_), .. })), "{}", sql);
+        }
+        assert_eq!(s.execute("select c from t2;").unwrap_err(), Error::TableNotFound("t2".to_string()));
+        // 别名和位置不是列
+        assert!(s.execute("select a as c from t1 order by c, 1;").is_ok());
+        assert!(s.execute("select a as x from t1 where x = 1;").is_err());
+        assert_eq!(
+            s.execute("insert into t1 values ('x', 'y');").unwrap_err(),
+            Error::TypeMismatch { column: "a".to_string(), expected: DataType::Integer, got: DataType::String }
+        );
+        // 查询的类型在执行前检查，即使表里没有行
+        let err = s.execute("select * from t1 where b > 5;").unwrap_err();
+        assert_eq!(err, Error::TypeMismatch { column: "b".to_string(), expected: DataType::String, got: DataType::Integer });
+        assert_eq!(err.to_string(), "expected STRING for column b, but got INTEGER");
+        assert!(matches!(s.execute("select a + b from t1;"), Err(Error::TypeMismatch { .. })));
+        assert!(matches!(s.execute("select a from t1 where a;"), Err(Error::TypeMismatch { .. })));
+        assert_eq!(s.execute("insert into t1 values (null, 'y');").unwrap_err(), Error::NotNullViolation { column: "a".to_string() });
+        assert_eq!(s.execute("insert into t1 (b) values ('y');").unwrap_err(), Error::NotNullViolation { column: "a".to_string() });
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_value_count() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int, b text);")?;
+        let mismatch = |expected, got| Error::ValueCountMismatch { table: "t1".to_string(), expected, got };
+        // 值多于表的列数
+        assert_eq!(s.execute("insert into t1 values (1, 'a', 2);").unwrap_err(), mismatch(2, 3));
+        // 值的个数和给出的列数不同
+        assert_eq!(s.execute("insert into t1 (a, b) values (1);").unwrap_err(), mismatch(2, 1));
+        assert_eq!(s.execute("insert into t1 (a) values (1, 'a');").unwrap_err(), mismatch(1, 2));
+        // 同一列出现两次
+        assert!(s.execute("insert into t1 (a, a) values (1, 2);").is_err());
+        s.execute("insert into t1 (b) values ('a');")?;
+        s.execute("insert into t1 values (1);")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_primary_key_unique() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int, b text);")?;
+        s.execute("insert into t1 values (1, 'a');")?;
+        let duplicate = Error::UniqueViolation { table: "t1".to_string(), key: Value::Integer(1) };
+        assert_eq!(s.execute("insert into t1 values (1, 'b');").unwrap_err(), duplicate);
+        // 同一条语句中的重复主键，整条语句都不会写入
+        assert_eq!(s.execute("insert into t1 values (2, 'b'), (2, 'c');").unwrap_err().to_string(), "duplicate key 2 in table t1");
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row, vec![vec![Value::Integer(1), Value::String("a".to_string())]]),
+            _ => unreachable!(),
+        }
+
+        // 并发插入同一个主键，后写入的事务冲突
+        let mut t1 = kvengine.begin()?;
+        let mut t2 = kvengine.begin()?;
+        let table = t1.must_get_table("t1".to_string())?;
+        t1.create_row(&table, vec![Value::Integer(3), Value::Null])?;
+        assert_eq!(t2.create_row(&table, vec![Value::Integer(3), Value::Null]), Err(Error::WriteConflict));
+        t1.commit()?;
+        t2.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_insert() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        kvengine.session()?.execute("create table t1 (a int, b text not null);")?;
+        kvengine.session()?.execute("insert into t1 values (1, 'a');")?;
+
+        let mut txn = kvengine.begin()?;
+        let table = txn.must_get_table("t1".to_string())?;
+        let row = |a: i64, b: &str| vec![Value::Integer(a), Value::String(b.to_string())];
+        let rejected = txn.bulk_insert(&table, vec![
+            row(2, "b"),
+            // 已存在的主键
+            row(1, "c"),
+            vec![Value::Integer(3), Value::Null],
+            // 批内重复的主键，保留第一行
+            row(2, "d"),
+            // 类型不符
+            vec![Value::Float(4.0), Value::String("e".to_string())],
+            row(4, "e"),
+        ])?;
+        assert_eq!(rejected.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert_eq!(rejected[0].1, Error::UniqueViolation { table: "t1".to_string(), key: Value::Integer(1) });
+        assert_eq!(rejected[2].1, Error::UniqueViolation { table: "t1".to_string(), key: Value::Integer(2) });
+        txn.commit()?;
+
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.scan_table("t1".to_string())?, vec![row(1, "a"), row(2, "b"), row(4, "e")]);
+        txn.rollback()?;
+
+        // create_rows 有一行失败时什么都不写入
+        let mut txn = kvengine.begin()?;
+        let duplicate = Error::UniqueViolation { table: "t1".to_string(), key: Value::Integer(1) };
+        assert_eq!(txn.create_rows(&table, vec![row(7, "x"), row(1, "y"), vec![Value::Null]]), Err(duplicate));
+        assert_eq!(txn.scan_table("t1".to_string())?.len(), 3);
+        txn.rollback()?;
+
+        // 并发写入同一个主键时整批冲突
+        let mut t1 = kvengine.begin()?;
+        let mut t2 = kvengine.begin()?;
+        t1.create_row(&table, row(5, "f"))?;
+        assert_eq!(t2.bulk_insert(&table, vec![row(6, "g"), row(5, "h")]), Err(Error::WriteConflict));
+        t1.commit()?;
+        t2.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_ttl() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int) with (ttl = 0);")?;
+        s.execute("create table t2 (a int) with (ttl = 3600);")?;
+        s.execute("insert into t1 values (1);")?;
+        s.execute("insert into t2 values (1);")?;
+        // ttl 为 0 的表，行写入之后立即过期
+        for (table, count) in [("t1", 0), ("t2", 1)] {
+            match s.execute(&format!("select * from {};", table))? {
+                ResultSet::Scan { row, .. } => assert_eq!(row.len(), count),
+                _ => unreachable!(),
+            }
+        }
+
+        // VACUUM 物理删除过期的行，没有 ttl 或未过期的行不受影响
+        s.execute("create table t3 (a int);")?;
+        s.execute("insert into t3 values (1);")?;
+        s.execute("insert into t1 values (2), (3);")?;
+        assert!(matches!(s.execute("vacuum t2;")?, ResultSet::Vacuum { count: 0 }));
+        assert!(matches!(s.execute("vacuum;")?, ResultSet::Vacuum { count: 3 }));
+        assert!(matches!(s.execute("vacuum;")?, ResultSet::Vacuum { count: 0 }));
+        assert!(s.execute("vacuum missing;").is_err());
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.scan_table("t3".to_string())?.len(), 1);
+        assert_eq!(txn.scan_table("t2".to_string())?.len(), 1);
+        txn.rollback()?;
+
+        // 过期的主键可以重新插入
+        s.execute("insert into t1 values (1);")?;
+        assert_eq!(kvengine.vacuum()?, 1);
+        assert!(s.execute("insert into t2 values (1);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_procedure() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table users (id int not null, name text, score float);")?;
+        s.execute(
+            "create procedure add_user (id int, name text, score float) as begin
+                insert into users values (id, name, score);
+                select * from users;
+            end;",
+        )?;
+        // 整数参数转换成浮点数，结果是最后一条语句的结果
+        match s.execute("call add_user(1, 'a', 2);")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row, vec![vec![Value::Integer(1), Value::String("a".to_string()), Value::Float(2.0)]]),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("create procedure add_user as begin select * from users; end;").is_err());
+
+        // 参数个数和类型不对
+        assert!(s.execute("call add_user(2, 'b');").is_err());
+        assert!(matches!(s.execute("call add_user('2', 'b', 1);"), Err(Error::TypeMismatch { .. })));
+        assert!(s.execute("call missing();").is_err());
+
+        // 过程中的语句在一个事务里，后面的失败会撤销前面的
+        s.execute(
+            "create procedure add_twice (id int) as begin
+                insert into users values (id, 'x', null);
+                insert into users values (id, 'y', null);
+            end;",
+        )?;
+        assert!(matches!(s.execute("call add_twice(5);"), Err(Error::UniqueViolation { .. })));
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.scan_table("users".to_string())?.len(), 1);
+        txn.rollback()?;
+
+        // 过程体里的未知标识符在调用时报错
+        s.execute("create procedure bad (id int) as begin insert into users values (idx, 'x', null); end;")?;
+        assert!(s.execute("call bad(1);").is_err());
+
+        s.execute("drop procedure add_twice;")?;
+        assert!(s.execute("call add_twice(5);").is_err());
+        assert!(s.execute("drop procedure add_twice;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_roles() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int);")?;
+        // 第一个用户是管理员
+        s.execute("create user alice password 'a';")?;
+        s.execute("create user bob password 'b';")?;
+        let mut alice = kvengine.user_session("alice")?;
+        let mut bob = kvengine.user_session("bob")?;
+        alice.execute("insert into t values (1);")?;
+        let denied = |result: Result<ResultSet>| match result {
+            Err(Error::PermissionDenied { privilege, .. }) => privilege,
+            result => panic!("expected permission denied, got {:?}", result),
+        };
+        assert_eq!(denied(bob.execute("select * from t;")), "SELECT ON t");
+        assert_eq!(denied(bob.execute("create role r;")), "ADMIN");
+        // 自己可以改自己的密码
+        bob.execute("alter user bob password 'c';")?;
+        assert_eq!(denied(bob.execute("alter user alice password 'c';")), "ADMIN");
+
+        // 权限通过角色继承：bob -> writer -> reader
+        alice.execute("create role reader;")?;
+        alice.execute("create role writer;")?;
+        alice.execute("grant select on t to reader;")?;
+        alice.execute("grant insert on * to writer;")?;
+        alice.execute("grant reader to writer;")?;
+        alice.execute("grant writer to bob;")?;
+        bob.execute("insert into t values (2);")?;
+        bob.execute("select * from t order by a limit 1;")?;
+        assert_eq!(denied(bob.execute("drop table t;")), "DROP ON t");
+        // 角色不能成环，名字不能和用户重复
+        assert!(alice.execute("grant writer to reader;").is_err());
+        assert!(alice.execute("grant reader to reader;").is_err());
+        assert!(alice.execute("create role bob;").is_err());
+        assert!(alice.execute("create user reader password 'x';").is_err());
+        assert!(alice.execute("grant missing to bob;").is_err());
+        assert!(alice.execute("grant select on t to nobody;").is_err());
+        assert!(alice.execute("grant select on t to admin;").is_err());
+
+        // 调用过程需要过程里每条语句的权限
+        alice.execute("create procedure add (a int) as begin insert into t values (a); select * from t; end;")?;
+        bob.execute("call add(3);")?;
+        alice.execute("revoke select on t from reader;")?;
+        assert_eq!(denied(bob.execute("call add(4);")), "SELECT ON t");
+
+        // 删除角色后，被授予的用户也失去它
+        alice.execute("grant select on * to reader;")?;
+        bob.execute("select * from t;")?;
+        alice.execute("drop role writer;")?;
+        assert_eq!(denied(bob.execute("select * from t;")), "SELECT ON t");
+        assert!(alice.execute("drop role writer;").is_err());
+        assert!(alice.execute("drop role admin;").is_err());
+
+        // 授予管理员角色，之后什么都能做
+        alice.execute("grant admin to bob;")?;
+        bob.execute("drop table t;")?;
+        bob.execute("revoke admin from bob;")?;
+        assert_eq!(denied(bob.execute("create table t (a int);")), "CREATE ON t");
+        // 不存在的用户，应用自己的会话不受限制
+        assert!(matches!(kvengine.user_session("carol")?.execute("show status;"), Err(Error::AccessDenied(_))));
+        s.execute("create table t (a int);")?;
+        Ok(())
+    }
+
+    #[cfg(feature = "disk")]
+    #[test]
+    fn test_migrate_users_admin() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        // 有角色之前创建的用户
+        let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+        let txn = mvcc.begin()?;
+        txn.set(Key::Format.encode()?, bincode::serialize(&2u32)?)?;
+        for name in ["alice", "bob"] {
+            txn.set(Key::User(name.to_string()).encode()?, bincode::serialize(&User::new(name.to_string(), "x"))?)?;
+        }
+        txn.commit()?;
+        drop(txn);
+        drop(mvcc);
+
+        // 重新打开时他们都成为管理员，和以前一样什么都能做
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+        let txn = kvengine.begin()?;
+        for name in ["alice", "bob"] {
+            assert!(txn.get_grants(name.to_string())?.roles.contains(ADMIN_ROLE));
+        }
+        txn.rollback()?;
+        kvengine.user_session("bob")?.execute("create table t (a int);")?;
+        drop(kvengine);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_limit() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int, b text);")?;
+        for i in 0..10 {
+            s.execute(&format!("insert into t values ({}, 'row {}');", i, i))?;
+        }
+        let row = crate::sql::types::row_memory_size(&[Value::Integer(0), Value::String("row 0".to_string())]);
+
+        // 新会话使用引擎的限制，每个查询单独计算
+        kvengine.sessions().set_query_memory_limit(Some(row * 10))?;
+        let mut s = kvengine.session()?;
+        s.execute("select * from t;")?;
+        s.execute("select * from t;")?;
+        // 排序键也占内存
+        assert_eq!(s.execute("select * from t order by b;").err(), Some(Error::MemoryLimitExceeded { limit: row * 10 }));
+        s.execute("insert into t values (10, 'row 10');")?;
+        assert!(matches!(s.execute("select * from t;"), Err(Error::MemoryLimitExceeded { .. })));
+        // 扫描在读的过程中计算内存，游标和直接扫描表也一样
+        assert!(matches!(s.execute("declare c cursor for select * from t;"), Err(Error::MemoryLimitExceeded { .. })));
+        let mut txn = kvengine.begin()?;
+        txn.set_memory_limit(Some(row * 10));
+        assert!(matches!(txn.scan_table("t".to_string()), Err(Error::MemoryLimitExceeded { .. })));
+        // 停在第一行超过限制的行
+        assert!(txn.memory_used.get() < row * 12);
+        txn.rollback()?;
+
+        // 会话可以单独修改
+        s.set_memory_limit(None);
+        s.execute("select * from t order by b;")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_result_rows() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int);")?;
+        s.execute("insert into t values (1), (2), (3), (4), (5);")?;
+        let rows = |result: ResultSet| match result {
+            ResultSet::Scan { row, .. } => row.len(),
+            _ => unreachable!(),
+        };
+
+        // 超出的行被丢掉，并留下警告
+        assert!(matches!(s.execute("set max_result_rows = 3;")?, ResultSet::Set { name } if name == "max_result_rows"));
+        assert_eq!(rows(s.execute("select * from t order by a desc;")?), 3);
+        assert_eq!(s.warnings(), [Warning::Truncated { max: 3, dropped: 2 }]);
+        // 没有截断时没有警告，每次执行都会清空
+        assert_eq!(rows(s.execute("select * from t limit 3;")?), 3);
+        assert!(s.warnings().is_empty());
+        // 游标不受限制
+        s.execute("declare c cursor for select * from t;")?;
+        assert_eq!(rows(s.execute("fetch 10 from c;")?), 5);
+
+        s.execute("set max_result_rows = default;")?;
+        assert_eq!(rows(s.execute("select * from t;")?), 5);
+        s.set_max_result_rows(Some(1));
+        assert_eq!(rows(s.execute("select * from t;")?), 1);
+        // 其他会话不受影响
+        assert_eq!(rows(kvengine.session()?.execute("select * from t;")?), 5);
+
+        assert!(s.execute("set max_result_rows = -1;").is_err());
+        assert!(s.execute("set max_result_rows = 'a';").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "disk")]
+    #[test]
+    fn test_estimate_rows() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int) with (ttl = 3600);")?;
+        s.execute("create table u (a int);")?;
+        s.execute("insert into t values (1), (2), (3);")?;
+        let estimate = |name: &str| -> Result<u64> {
+            let txn = kvengine.begin()?;
+            let rows = txn.estimate_rows(name.to_string())?;
+            txn.rollback()?;
+            Ok(rows)
+        };
+        assert_eq!(estimate("t")?, 3);
+        assert_eq!(estimate("u")?, 0);
+        assert!(estimate("missing").is_err());
+
+        // 提交之后计数增减，没有提交的只有自己能看到
+        let mut txn = kvengine.begin()?;
+        let t = txn.must_get_table("t".to_string())?;
+        txn.create_rows(&t, vec![vec![Value::Integer(4)], vec![Value::Integer(5)]])?;
+        txn.delete_row("t".to_string(), &Value::Integer(1))?;
+        assert_eq!(txn.estimate_rows("t".to_string())?, 4);
+        assert_eq!(estimate("t")?, 3);
+        txn.commit()?;
+        drop(txn);
+        assert_eq!(estimate("t")?, 4);
+        let mut txn = kvengine.begin()?;
+        txn.create_rows(&t, vec![vec![Value::Integer(6)]])?;
+        txn.rollback()?;
+        drop(txn);
+        assert_eq!(estimate("t")?, 4);
+        // 删除不存在的行不改变计数
+        let mut txn = kvengine.begin()?;
+        txn.delete_row("t".to_string(), &Value::Integer(1))?;
+        txn.delete_row("t".to_string(), &Value::Integer(100))?;
+        assert_eq!(txn.estimate_rows("t".to_string())?, 4);
+        txn.commit()?;
+        drop(txn);
+        assert_eq!(estimate("t")?, 4);
+
+        // 删表重建之后重新计数
+        s.execute("drop table u;")?;
+        s.execute("create table u (a int, b int);")?;
+        s.execute("insert into u values (1, 1);")?;
+        assert_eq!(estimate("u")?, 1);
+        match s.execute("show tables;")? {
+            ResultSet::Scan { columns, row } => {
+                assert_eq!(columns, vec!["name", "rows"]);
+                assert_eq!(row, vec![
+                    vec![Value::String("t".to_string()), Value::Integer(4)],
+                    vec![Value::String("u".to_string()), Value::Integer(1)],
+                ]);
+            }
+            _ => unreachable!(),
+        }
+
+        // 重新打开之后第一次估计时扫描一遍
+        drop(s);
+        drop(kvengine);
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.estimate_rows("t".to_string())?, 4);
+        txn.rollback()?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_warnings() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int, f float, g float);")?;
+        assert!(s.warnings().is_empty());
+
+        // 浮点数放不下的整数被舍入，每列一条警告
+        let big = (1_i64 << 53) + 1;
+        s.execute(&format!("insert into t values (1, {}, 1), (2, {}, 2), (3, 3, {});", big, big + 2, i64::MAX))?;
+        assert_eq!(
+            s.warnings(),
+            [
+                Warning::Rounded { table: "t".to_string(), column: "f".to_string(), count: 2, value: Value::Integer(big) },
+                Warning::Rounded { table: "t".to_string(), column: "g".to_string(), count: 1, value: Value::Integer(i64::MAX) },
+            ]
+        );
+        assert_eq!(s.warnings()[0].to_string(), format!("2 integers written to t.f were rounded to float, the first is {}", big));
+        assert_eq!(s.warnings()[1].to_string(), format!("Integer {} written to t.g was rounded to float", i64::MAX));
+        s.execute("insert into t values (4, 4, 4.5);")?;
+        assert!(s.warnings().is_empty());
+
+        // 不认识的变量被忽略，批量执行的警告都会留下
+        s.execute("set autocommit = 1;")?;
+        assert_eq!(s.warnings(), [Warning::UnknownVariable { name: "autocommit".to_string() }]);
+        s.execute_batch(&["insert into t values (5, 9007199254740993, 0);", "insert into t values (6, 0, 9007199254740995);"])?;
+        assert_eq!(s.warnings().len(), 2);
+        // 失败的语句没有警告
+        assert!(s.execute("insert into t values (7, 9007199254740993, 'x');").is_err());
+        assert!(s.warnings().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_your_writes() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        kvengine.session()?.execute("create table t (a int, b text);")?;
+        kvengine.session()?.execute("insert into t values (1, 'x');")?;
+
+        // 同一个事务里建的表、插入和删除的行，之后的读取都能看到
+        let mut txn = kvengine.begin()?;
+        let other = kvengine.begin()?;
+        let t = txn.must_get_table("t".to_string())?;
+        txn.create_row(&t, vec![Value::Integer(2), Value::String("y".to_string())])?;
+        txn.delete_row("t".to_string(), &Value::Integer(1))?;
+        assert_eq!(txn.scan_table("t".to_string())?, vec![vec![Value::Integer(2), Value::String("y".to_string())]]);
+        txn.create_table(Table {
+            name: "u".to_string(),
+            columns: vec![Column { name: "a".to_string(), datatype: DataType::Integer, nullable: true, default: None }],
+            ttl: None,
+            audit: false,
+        })?;
+        let u = txn.must_get_table("u".to_string())?;
+        txn.create_row(&u, vec![Value::Integer(1)])?;
+        assert_eq!(txn.table_names()?, vec!["t".to_string(), "u".to_string()]);
+        assert_eq!(txn.scan_table("u".to_string())?.len(), 1);
+        // 主键冲突也看自己的写入
+        assert!(txn.create_row(&t, vec![Value::Integer(2), Value::Null]).is_err());
+        txn.create_row(&t, vec![Value::Integer(1), Value::Null])?;
+
+        assert_eq!(other.scan_table("t".to_string())?, vec![vec![Value::Integer(1), Value::String("x".to_string())]]);
+        assert!(other.get_table("u".to_string())?.is_none());
+        other.rollback()?;
+        txn.commit()?;
+
+        // 一批语句里，后面的查询看到前面的写入
+        let results = kvengine.session()?.execute_batch(&[
+            "create table v (a int);",
+            "insert into v values (1), (2);",
+            "select count(*) from v;",
+        ])?;
+        match &results[2] {
+            ResultSet::Scan { row, .. } => assert_eq!(row, &vec![vec![Value::Integer(2)]]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_cache() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let table = |name: &str| Table {
+            name: name.to_string(),
+            columns: vec![Column { name: "a".to_string(), datatype: DataType::Integer, nullable: true, default: None }],
+            ttl: None,
+            audit: false,
+        };
+        kvengine.session()?.execute("create table t1 (a int);")?;
+
+        // 建表提交之前开始的事务看不到新表
+        let before = kvengine.begin()?;
+        let mut txn = kvengine.begin()?;
+        txn.create_table(table("t2"))?;
+        txn.commit()?;
+        assert!(before.get_table("t2".to_string())?.is_none());
+        assert_eq!(before.table_names()?, vec!["t1".to_string()]);
+        before.rollback()?;
+        let after = kvengine.begin()?;
+        assert_eq!(after.table_names()?, vec!["t1".to_string(), "t2".to_string()]);
+        after.rollback()?;
+
+        // 回滚的建表不会发布
+        let mut txn = kvengine.begin()?;
+        txn.create_table(table("t3"))?;
+        txn.rollback()?;
+        assert!(kvengine.begin()?.get_table("t3".to_string())?.is_none());
+
+        // 删表后其他会话看不到，交错的建表和删表都保留
+        let mut create = kvengine.begin()?;
+        let mut s = kvengine.session()?;
+        s.execute("drop table t1;")?;
+        create.create_table(table("t4"))?;
+        create.commit()?;
+        assert!(s.execute("select * from t1;").is_err());
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.table_names()?, vec!["t2".to_string(), "t4".to_string()]);
+        txn.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_column() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int, b text);")?;
+        s.execute("insert into t values (1, 'x');")?;
+
+        // 已有的行读取新列的默认值，之后插入的行可以给出它
+        assert!(matches!(s.execute("alter table t add column c float default 1;")?, ResultSet::AlterTable { table_name } if table_name == "t"));
+        s.execute("alter table t add d int;")?;
+        s.execute("insert into t values (2, 'y', 2.5, 7);")?;
+        s.execute("insert into t (a) values (3);")?;
+        match s.execute("select * from t;")? {
+            ResultSet::Scan { columns, row } => {
+                assert_eq!(columns, vec!["a", "b", "c", "d"]);
+                assert_eq!(row, vec![
+                    vec![Value::Integer(1), Value::String("x".to_string()), Value::Float(1.0), Value::Null],
+                    vec![Value::Integer(2), Value::String("y".to_string()), Value::Float(2.5), Value::Integer(7)],
+                    vec![Value::Integer(3), Value::Null, Value::Float(1.0), Value::Null],
+                ]);
+            }
+            _ => unreachable!(),
+        }
+
+        // 没有默认值的 NOT NULL 列、重名的列和类型不符的默认值
+        assert_eq!(s.execute("alter table t add e int not null;").unwrap_err(), Error::NotNullViolation { column: "e".to_string() });
+        assert!(matches!(s.execute("alter table t add b int;"), Err(Error::DuplicateColumn { .. })));
+        assert!(matches!(s.execute("alter table t add e int default 'x';"), Err(Error::TypeMismatch { .. })));
+        assert!(matches!(s.execute("alter table missing add e int;"), Err(Error::TableNotFound(_))));
+
+        // 有未提交的写入时，加列冲突
+        let mut dml = kvengine.begin()?;
+        let table = dml.must_get_table("t".to_string())?;
+        dml.create_row(&table, vec![Value::Integer(4), Value::Null, Value::Null, Value::Null])?;
+        let mut ddl = kvengine.begin()?;
+        ddl.add_column("t".to_string(), Column { name: "e".to_string(), datatype: DataType::Integer, nullable: true, default: Some(Value::Null) })?;
+        assert_eq!(ddl.commit(), Err(Error::WriteConflict));
+        dml.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_transactional_ddl() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        kvengine.session()?.execute("create table t (a int, b text);")?;
+        kvengine.session()?.execute("insert into t values (1, 'x');")?;
+        let row = |a: i64| vec![Value::Integer(a), Value::Null];
+        let rows = |kvengine: &KVEngine<MemoryEngine>| -> Result<Vec<_>> {
+            let txn = kvengine.begin()?;
+            let rows = txn.scan_table("t".to_string());
+            txn.rollback()?;
+            rows
+        };
+
+        // 删表回滚后表和数据都还在
+        let mut txn = kvengine.begin()?;
+        txn.drop_table("t".to_string())?;
+        txn.rollback()?;
+        assert_eq!(rows(&kvengine)?.len(), 1);
+
+        // 有未提交的写入时，删表冲突
+        let mut dml = kvengine.begin()?;
+        let table = dml.must_get_table("t".to_string())?;
+        dml.create_row(&table, row(2))?;
+        let mut ddl = kvengine.begin()?;
+        ddl.drop_table("t".to_string())?;
+        assert_eq!(ddl.commit(), Err(Error::WriteConflict));
+        dml.rollback()?;
+
+        // 读到表之后，表被其他事务删除，写入行的事务不能提交
+        let mut dml = kvengine.begin()?;
+        let table = dml.must_get_table("t".to_string())?;
+        let mut ddl = kvengine.begin()?;
+        ddl.drop_table("t".to_string())?;
+        ddl.commit()?;
+        dml.create_row(&table, row(2))?;
+        assert_eq!(dml.commit(), Err(Error::WriteConflict));
+        // 重建的表里没有残留的行
+        kvengine.session()?.execute("create table t (a int, b text);")?;
+        assert!(rows(&kvengine)?.is_empty());
+
+        // 删表还没有提交时，写入行的事务不能提交
+        let mut ddl = kvengine.begin()?;
+        ddl.drop_table("t".to_string())?;
+        let mut dml = kvengine.begin()?;
+        dml.create_row(&table, row(3))?;
+        assert_eq!(dml.commit(), Err(Error::WriteConflict));
+        ddl.rollback()?;
+
+        // 删表的事务看不到之后提交的行，删表冲突
+        let mut ddl = kvengine.begin()?;
+        let mut dml = kvengine.begin()?;
+        dml.create_row(&table, row(4))?;
+        dml.commit()?;
+        ddl.drop_table("t".to_string())?;
+        assert_eq!(ddl.commit(), Err(Error::WriteConflict));
+        assert_eq!(rows(&kvengine)?, vec![row(4)]);
+
+        // 同一张表上并发写入行不冲突
+        let mut t1 = kvengine.begin()?;
+        let mut t2 = kvengine.begin()?;
+        t1.create_row(&table, row(5))?;
+        t2.create_row(&table, row(6))?;
+        t2.delete_row("t".to_string(), &Value::Integer(4))?;
+        t1.commit()?;
+        t2.commit()?;
+        assert_eq!(rows(&kvengine)?, vec![row(5), row(6)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_queries_kill() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int);")?;
+        s.execute("insert into t values (1), (2);")?;
+        // 正在执行的语句就是 show queries 自己
+        match s.execute("show queries;")? {
+            ResultSet::Scan { columns, row } => {
+                assert_eq!(columns, vec!["id", "session", "user", "time_ms", "query", "plan"]);
+                assert_eq!(row.len(), 1);
+                assert_eq!(row[0][1], Value::Integer(s.id() as i64));
+                assert_eq!(row[0][4], Value::String("show queries;".to_string()));
+                assert_eq!(row[0][5], Value::String("ShowQueries".to_string()));
+            }
+            _ => unreachable!(),
+        }
+        // 语句结束后不再列出
+        let other = kvengine.session()?;
+        let running = other.handle.begin_statement("select * from t;", "Scan".to_string())?;
+        match s.execute("show queries;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row.len(), 2),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("kill 100;").is_err());
+
+        // kill 之后，语句在读写下一行时失败
+        s.execute(&format!("kill {};", running.id()))?;
+        let mut txn = kvengine.begin()?;
+        txn.set_cancel(running.cancel_token());
+        assert_eq!(txn.scan_table("t".to_string()), Err(Error::QueryCancelled { id: running.id() }));
+        let table = txn.must_get_table("t".to_string())?;
+        assert_eq!(txn.create_row(&table, vec![Value::Integer(3)]), Err(Error::QueryCancelled { id: running.id() }));
+        txn.rollback()?;
+        drop(running);
+        assert!(s.execute("kill 1;").is_err());
+
+        // 只有管理员可以 kill 别人的语句
+        s.execute("create user alice password 'a';")?;
+        s.execute("create user bob password 'b';")?;
+        s.execute("create user carol password 'c';")?;
+        let bob = kvengine.user_session("bob")?;
+        let running = bob.handle.begin_statement("select * from t;", "Scan".to_string())?;
+        let kill = format!("kill query {};", running.id());
+        assert!(matches!(kvengine.user_session("carol")?.execute(&kill), Err(Error::PermissionDenied { .. })));
+        assert!(!running.cancel_token().is_cancelled());
+        kvengine.user_session("bob")?.execute(&kill)?;
+        kvengine.user_session("alice")?.execute(&kill)?;
+        assert!(running.cancel_token().is_cancelled());
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_batch() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        let results = s.execute_batch(&[
+            "create table t (a int, b text);",
+            "insert into t values (1, 'a'), (2, 'b');",
+            "select * from t order by a desc;",
+        ])?;
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[1], ResultSet::Insert { count: 2 }));
+        match &results[2] {
+            ResultSet::Scan { row, .. } => assert_eq!(row[0], vec![Value::Integer(2), Value::String("b".to_string())]),
+            _ => unreachable!(),
+        }
+
+        // 一条失败，整批都回滚
+        let result = s.execute_batch(&[
+            "create table t2 (a int);",
+            "insert into t values (3, 'c');",
+            "insert into t values (1, 'x');",
+        ]);
+        assert!(matches!(result, Err(Error::UniqueViolation { .. })));
+        let txn = kvengine.begin()?;
+        assert!(txn.get_table("t2".to_string())?.is_none());
+        assert_eq!(txn.scan_table("t".to_string())?.len(), 2);
+        txn.rollback()?;
+
+        // 解析失败时什么都不执行，ATTACH 不能放在批里
+        assert!(s.execute_batch(&["insert into t values (3, 'c');", "select from;"]).is_err());
+        assert!(s.execute_batch(&["insert into t values (3, 'c');", "detach other;"]).is_err());
+        // 每个字符串只能是一条语句
+        assert!(s.execute_batch(&["insert into t values (3, 'c'); insert into t values (4, 'd');"]).is_err());
+        assert!(s.execute_batch(&[])?.is_empty());
+        match s.execute("select * from t;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row.len(), 2),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cursor() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int);")?;
+        s.execute("insert into t values (1), (2), (3), (4), (5);")?;
+        let fetch = |s: &mut Session<_>, sql: &str| -> Result<Vec<Value>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { row, .. } => Ok(row.into_iter().map(|r| r[0].clone()).collect()),
+                _ => unreachable!(),
+            }
+        };
+        s.execute("declare c cursor for select * from t order by a desc;")?;
+        assert_eq!(fetch(&mut s, "fetch 2 from c;")?, vec![Value::Integer(5), Value::Integer(4)]);
+        // 声明之后写入的行看不到，从上次的位置继续
+        s.execute("insert into t values (6);")?;
+        assert_eq!(fetch(&mut s, "fetch c;")?, vec![Value::Integer(3)]);
+        assert_eq!(fetch(&mut s, "fetch 100 from c;")?, vec![Value::Integer(2), Value::Integer(1)]);
+        assert!(fetch(&mut s, "fetch 100 from c;")?.is_empty());
+
+        // 游标属于会话，名字不能重复
+        assert!(s.execute("declare c cursor for select * from t;").is_err());
+        assert!(kvengine.session()?.execute("fetch c;").is_err());
+        assert!(s.execute_batch(&["declare d cursor for select * from t;", "fetch d;"]).is_err());
+        s.execute("close c;")?;
+        assert!(s.execute("fetch c;").is_err());
+        assert!(s.execute("close c;").is_err());
+        assert!(s.execute("declare c cursor for select * from missing;").is_err());
+        s.execute("declare c cursor for select * from t;")?;
+        assert_eq!(fetch(&mut s, "fetch 10 c;")?.len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (id int not null, price float, qty int, status text);")?;
+        s.execute("insert into t values (1, 2.5, 2, 'ok'), (2, 1.0, 3, 'failed'), (3, 4.0, null, 'ok'), (4, null, 5, null);")?;
+        let query = |s: &mut Session<_>, sql: &str| -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+            match s.execute(sql)? {
+                ResultSet::Scan { columns, row } => Ok((columns, row)),
+                _ => unreachable!(),
+            }
+        };
+        let (columns, rows) = query(
+            &mut s,
+            "select sum(price * qty) as revenue, count(*), count(*) filter (where status = 'ok'), \
+             count(qty), min(price), max(status), avg(qty) from t;",
+        )?;
+        assert_eq!(columns, vec!["revenue", "count", "count", "count", "min", "max", "avg"]);
+        assert_eq!(rows, vec![vec![
+            Value::Float(8.0),
+            Value::Integer(4),
+            Value::Integer(2),
+            Value::Integer(3),
+            Value::Float(1.0),
+            Value::String("ok".to_string()),
+            Value::Float(10.0 / 3.0),
+        ]]);
+        // 同一个聚合只计算一次，可以参与运算
+        let (_, rows) = query(&mut s, "select sum(qty) filter (where id > 1) * 2 + count(*), sum(qty) from t where id != 4;")?;
+        assert_eq!(rows, vec![vec![Value::Integer(9), Value::Integer(5)]]);
+        // 没有行时 COUNT 是 0，其他是 NULL
+        let (_, rows) = query(&mut s, "select count(*), sum(qty), avg(price) from t where id > 10;")?;
+        assert_eq!(rows, vec![vec![Value::Integer(0), Value::Null, Value::Null]]);
+        let (columns, rows) = query(&mut s, "select id, price * qty, qty / 2 as half from t order by id desc limit 2;")?;
+        assert_eq!(columns, vec!["id", "?column?", "half"]);
+        assert_eq!(rows, vec![vec![Value::Integer(4), Value::Null, Value::Integer(2)], vec![Value::Integer(3), Value::Null, Value::Null]]);
+
+        assert!(s.execute("select id, count(*) from t;").is_err());
+        assert!(s.execute("select * from t where count(*) > 1;").is_err());
+        assert!(s.execute("select sum(count(*)) from t;").is_err());
+        assert!(s.execute("select sum(status) from t;").is_err());
+        assert!(s.execute("select * from t order by count(*);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_scan() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int);")?;
+        s.execute("create table e (a int) with (ttl = 0);")?;
+        s.execute("insert into t values (1), (2), (3);")?;
+        s.execute("insert into e values (1), (2);")?;
+        let count = |s: &mut Session<_>, sql: &str| -> Result<Vec<Vec<Value>>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { row, .. } => Ok(row),
+                _ => unreachable!(),
+            }
+        };
+        // 只有单独的 COUNT(*) 才不读行，带别名和运算也可以
+        let plan = |s: &mut Session<_>, sql: &str| -> Result<Value> { Ok(count(s, sql)?.remove(0).remove(0)) };
+        assert_eq!(plan(&mut s, "explain select count(*) as n from t;")?, Value::String("Projection: #0 AS n".to_string()));
+        assert_eq!(count(&mut s, "explain select count(*) + 1 from t;")?[1], vec![Value::String("  CountScan: t".to_string())]);
+        assert_eq!(count(&mut s, "explain select count(*) from t where a > 1;")?[1], vec![Value::String("  Aggregate: count(*)".to_string())]);
+        assert_eq!(count(&mut s, "explain select count(a) from t;")?[1], vec![Value::String("  Aggregate: count(a)".to_string())]);
+        assert_eq!(count(&mut s, "select count(*), count(*) + 1 from t;")?, vec![vec![Value::Integer(3), Value::Integer(4)]]);
+        // 过期的行不计数
+        assert_eq!(count(&mut s, "select count(*) from e;")?, vec![vec![Value::Integer(0)]]);
+
+        // 事务自己删除和写入的行计入，其他事务未提交的不计入
+        let mut txn = kvengine.begin()?;
+        let t = txn.must_get_table("t".to_string())?;
+        txn.delete_row("t".to_string(), &Value::Integer(1))?;
+        txn.create_rows(&t, vec![vec![Value::Integer(4)], vec![Value::Integer(5)]])?;
+        assert_eq!(txn.count_table("t".to_string())?, 4);
+        assert_eq!(count(&mut s, "select count(*) from t;")?, vec![vec![Value::Integer(3)]]);
+        txn.commit()?;
+        assert_eq!(count(&mut s, "select count(*) from t;")?, vec![vec![Value::Integer(4)]]);
+        s.execute("drop table t;")?;
+        assert!(s.execute("select count(*) from t;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (id int not null, name text, dept text, salary int);")?;
+        s.execute(
+            "insert into t values (1, 'alice', 'eng', 10), (2, 'amy', 'eng', 20), (3, 'bob', 'ops', 5), \
+             (4, 'bea', null, 7), (5, 'carl', null, 1);",
+        )?;
+        let query = |s: &mut Session<_>, sql: &str| -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+            match s.execute(sql)? {
+                ResultSet::Scan { columns, row } => Ok((columns, row)),
+                _ => unreachable!(),
+            }
+        };
+        let string = |s: &str| Value::String(s.to_string());
+        // 按表达式分组，别名和位置都指向选择列表
+        let (columns, rows) = query(
+            &mut s,
+            "select substr(name, 1, 1) as initial, count(*), sum(salary) from t group by initial order by 2 desc, 1;",
+        )?;
+        assert_eq!(columns, vec!["initial", "count", "sum"]);
+        assert_eq!(rows, vec![
+            vec![string("a"), Value::Integer(2), Value::Integer(30)],
+            vec![string("b"), Value::Integer(2), Value::Integer(12)],
+            vec![string("c"), Value::Integer(1), Value::Integer(1)],
+        ]);
+        // NULL 是一组，排在最后，聚合可以参与运算
+        let (_, rows) = query(&mut s, "select dept, max(salary) - min(salary) from t group by 1;")?;
+        assert_eq!(rows, vec![
+            vec![string("eng"), Value::Integer(10)],
+            vec![string("ops"), Value::Integer(0)],
+            vec![Value::Null, Value::Integer(6)],
+        ]);
+        let (_, rows) = query(&mut s, "select upper(dept) from t where salary > 6 group by upper(dept) order by upper(dept) desc;")?;
+        assert_eq!(rows, vec![vec![Value::Null], vec![string("ENG")]]);
+        // 没有行时没有分组
+        let (_, rows) = query(&mut s, "select dept, count(*) from t where id > 10 group by dept;")?;
+        assert!(rows.is_empty());
+        // 不分组时按位置和别名排序
+        let (_, rows) = query(&mut s, "select id, salary * 2 as doubled from t order by doubled desc limit 2;")?;
+        assert_eq!(rows, vec![vec![Value::Integer(2), Value::Integer(40)], vec![Value::Integer(1), Value::Integer(20)]]);
+
+        assert!(s.execute("select name, count(*) from t group by dept;").is_err());
+        assert!(s.execute("select * from t group by dept;").is_err());
+        assert!(s.execute("select dept from t group by 3;").is_err());
+        assert!(s.execute("select dept from t group by 0;").is_err());
+        assert!(s.execute("select count(*) from t group by 1;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_wildcard() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        let query = |s: &mut Session<_>, sql: &str| -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+            match s.execute(sql)? {
+                ResultSet::Scan { columns, row } => Ok((columns, row)),
+                _ => unreachable!(),
+            }
+        };
+        // 同一批里先建表，后面的语句展开 * 时能看到它
+        s.execute_batch(&["create table t (a int not null, b text);", "insert into t values (1, 'x'), (2, 'y');"])?;
+        let (columns, rows) = query(&mut s, "select t.*, 1 as flag from t where a = 2;")?;
+        assert_eq!(columns, vec!["a", "b", "flag"]);
+        assert_eq!(rows, vec![vec![Value::Integer(2), Value::String("y".to_string()), Value::Integer(1)]]);
+        let (columns, rows) = query(&mut s, "select a * 10, * from t order by 1 desc limit 1;")?;
+        assert_eq!(columns, vec!["?column?", "a", "b"]);
+        assert_eq!(rows, vec![vec![Value::Integer(20), Value::Integer(2), Value::String("y".to_string())]]);
+
+        assert!(s.execute("select u.* from t;").is_err());
+        assert!(s.execute("select t.*, 1 from missing;").is_err());
+        assert!(s.execute("select t.*, count(*) from t;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_where_row_compare() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (id int not null, a int, b int);")?;
+        s.execute("insert into t values (1, 1, 1), (2, 1, 2), (3, 2, 1), (4, 2, 2), (5, null, 1);")?;
+        let ids = |s: &mut Session<_>, sql: &str| -> Result<Vec<Value>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { row, .. } => Ok(row.into_iter().map(|r| r[0].clone()).collect()),
+                _ => unreachable!(),
+            }
+        };
+        // 按 (a, b) 翻页，从上一页最后一行之后开始
+        let page = "select * from t where (a, b) > (1, 2) order by a, b limit 2;";
+        assert_eq!(ids(&mut s, page)?, vec![Value::Integer(3), Value::Integer(4)]);
+        assert_eq!(ids(&mut s, "select * from t where (a, b) in ((1, 2), (2, 1));")?, vec![Value::Integer(2), Value::Integer(3)]);
+        assert_eq!(ids(&mut s, "select * from t where a = 2 order by b desc;")?, vec![Value::Integer(4), Value::Integer(3)]);
+        // a 是 NULL 的行条件是 NULL，不会出现
+        assert_eq!(ids(&mut s, "select * from t where a != 1;")?, vec![Value::Integer(3), Value::Integer(4)]);
+        assert!(s.execute("select * from t where (a, b) = (1);").is_err());
+        assert!(s.execute("select * from t where a;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_vector_search() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table docs (id int, embedding vector(2));")?;
+        s.execute("insert into docs values (1, [0, 0]), (2, [1, 1]), (3, [3, 4]), (4, null), (5, [-1, 0.5]);")?;
+        // 按距离从近到远取前 k 个，NULL 排在最后
+        let ids = |result: ResultSet| match result {
+            ResultSet::Scan { row, .. } => row.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            ids(s.execute("select * from docs order by embedding <-> [1, 0.9] limit 3;")?),
+            vec![Value::Integer(2), Value::Integer(1), Value::Integer(5)]
+        );
+        assert_eq!(
+            ids(s.execute("select * from docs order by embedding <-> [3, 4] desc limit 2;")?),
+            vec![Value::Integer(4), Value::Integer(5)]
+        );
+        assert_eq!(ids(s.execute("select * from docs order by id desc limit 0;")?), vec![]);
+
+        // 维度不对的向量不能写入，也不能比较距离
+        assert_eq!(
+            s.execute("insert into docs values (6, [1, 2, 3]);").unwrap_err(),
+            Error::TypeMismatch { column: "embedding".to_string(), expected: DataType::Vector(2), got: DataType::Vector(3) }
+        );
+        assert!(s.execute("select * from docs order by embedding <-> [1];").is_err());
+        assert!(s.execute("select * from docs order by missing;").is_err());
+        // 向量列不能作为主键
+        assert!(s.execute("create table bad (e vector(2), id int);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_status() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int);")?;
+        s.execute("insert into t1 values(1);")?;
+        let s2 = kvengine.session()?;
+        drop(s2);
+        let _s3 = kvengine.session()?;
+        assert!(s.execute("select * from t2;").is_err());
+        match s.execute("show status;")? {
+            ResultSet::ShowStatus { status, sessions } => {
+                // 迁移、建表、插入、失败的查询，以及 show status 自己
+                assert_eq!(status.versions, 5);
+                assert_eq!(status.active_txns, 1);
+                assert_eq!(status.storage.name, "memory");
+                // show status 自己还没有结束，不计入查询数
+                assert_eq!((sessions.active, sessions.total, sessions.max_sessions), (2, 3, None));
+                assert_eq!((sessions.queries, sessions.failed_queries, sessions.slow_queries), (3, 1, 0));
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // 收集日志输出
+    #[derive(Clone, Default)]
+    struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_query_log() -> Result<()> {
+        let buf = LogBuffer::default();
+        let writer = buf.clone();
+        let subscriber = tracing_subscriber::fmt().with_ansi(false).with_writer(move || writer.clone()).finish();
+        tracing::subscriber::with_default(subscriber, || -> Result<()> {
+            let kvengine = KVEngine::new(MemoryEngine::new())?;
+            let mut s = kvengine.session()?;
+            s.execute("create table t1 (a int);")?;
+            s.execute("insert into t1 values (1), (2);")?;
+            assert!(s.execute("select * from t2;").is_err());
+            // 阈值为 0，所有查询都是慢查询
+            kvengine.sessions().set_slow_query_threshold(Some(Duration::ZERO))?;
+            s.execute("select * from t1;")?;
+            Ok(())
+        })?;
+
+        let log = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].contains(r#"sql="insert into t1 values (1), (2);""#) && lines[1].contains("rows=2"));
+        assert!(lines[1].contains("version=Some(3)"));
+        assert!(lines[2].contains("WARN") && lines[2].contains("query failed") && lines[2].contains("does not exist"));
+        assert!(lines[3].contains("sharkdb::query") && lines[3].contains("rows=2"));
+        assert!(lines[4].contains("sharkdb::slow_query") && lines[4].contains("plan=\"Scan"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_order() -> Result<()> {
+        // 行按照主键的值排序，而不是按照编码之后的字节
+        let keys = [-10_i64, -1, 0, 1, 256]
+            .iter()
+            .map(|v| Key::Row("t".to_string(), Value::Integer(*v)).encode())
+            .collect::<Result<Vec<_>>>()?;
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+
+        let key = Key::Row("t".to_string(), Value::String("a".to_string()));
+        assert_eq!(Key::decode(&key.encode()?)?, key);
+        Ok(())
+    }
+
+    #[cfg(feature = "disk")]
+    #[test]
+    fn test_migrate_bincode_keys() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        // 使用旧的 bincode 格式写入数据
+        let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+        let txn = mvcc.begin()?;
+        let table = LegacyTable {
+            name: "t1".to_string(),
+            columns: vec![Column {
+                name: "a".to_string(),
+                datatype: DataType::Integer,
+                nullable: false,
+                default: None,
+            }],
+            ttl: None,
+        };
+        txn.set(bincode::serialize(&Key::Table("t1".to_string()))?, bincode::serialize(&table)?)?;
+        for v in [2_i64, 1] {
+            txn.set(
+                bincode::serialize(&Key::Row("t1".to_string(), Value::Integer(v)))?,
+                bincode::serialize(&vec![Value::Integer(v)])?,
+            )?;
+        }
+        txn.commit()?;
+        drop(txn);
+        drop(mvcc);
+
+        // 重新打开时迁移为 keycode 格式
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+        let mut s = kvengine.session()?;
+        s.execute("insert into t1 values (3);")?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(
+                row,
+                vec![vec![Value::Integer(1)], vec![Value::Integer(2)], vec![Value::Integer(3)]]
+            ),
+            _ => unreachable!(),
+        }
+        drop(s);
+        drop(kvengine);
+
+        // 再次打开不会重复迁移
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+        let mut s = kvengine.session()?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { row, .. } => assert_eq!(row.len(), 3),
+            _ => unreachable!(),
+        }
+        drop(s);
+        drop(kvengine);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[cfg(feature = "disk")]
+    #[test]
+    fn test_attach() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let other = p.with_file_name("other-log");
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?)?;
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int);")?;
+        s.execute("insert into t values (1);")?;
+        s.execute(&format!("attach '{}' as archive;", other.display()))?;
+        s.execute("create table archive.t (a int, b text);")?;
+        s.execute("insert into archive.t values (2, 'x'), (3, 'y');")?;
+        // 同名的表分别属于各自的库
+        let count = |s: &mut Session<_>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { row, .. }) => Ok(row.len()),
+            Ok(_) => unreachable!(),
+            Err(err) => Err(err),
+        };
+        assert_eq!(count(&mut s, "select * from t;")?, 1);
+        assert_eq!(count(&mut s, "select * from main.t;")?, 1);
+        assert_eq!(count(&mut s, "select * from archive.t order by a desc limit 1;")?, 1);
+        assert_eq!(count(&mut s, "select * from archive.t;")?, 2);
+        // 别名不能重复，也不能是 main
+        assert!(s.execute(&format!("attach '{}' as archive;", other.display())).is_err());
+        assert!(s.execute("attach 'x' as main;").is_err());
+        assert!(count(&mut s, "select * from nope.t;").is_err());
+
+        // 其他会话看不到这个会话附加的库
+        let mut s2 = kvengine.session()?;
+        assert!(count(&mut s2, "select * from archive.t;").is_err());
+        drop(s2);
+
+        // 分离之后文件被释放，可以再次附加，数据还在
+        s.execute("detach archive;")?;
+        assert!(count(&mut s, "select * from archive.t;").is_err());
+        assert!(s.execute("detach archive;").is_err());
+        s.execute(&format!("attach database '{}' as old;", other.display()))?;
+        assert_eq!(count(&mut s, "select * from old.t;")?, 2);
+
+        // 内存引擎不能附加其他库
+        let mut m = KVEngine::new(MemoryEngine::new())?.session()?;
+        assert!(m.execute(&format!("attach '{}' as archive;", other.display())).is_err());
+
+        drop(s);
+        drop(kvengine);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+}
@@ -1,8 +1,19 @@
-use crate::error::{Error, Result};
+use std::collections::HashMap;
 
-use super::{executor::ResultSet, parser::Parser, plan::Plan, schema::Table, types::Row};
+use web_time::Instant;
 
-mod kv;
+use session::{CancelToken, QueryInfo, SessionHandle, SessionInfo, SessionRegistry, SessionStats};
+
+use crate::{error::{Error, Result}, storage::mvcc::{MvccStatus, Version}};
+
+use super::{executor::{ResultSet, Warning}, parser::{self, ast, Parser}, plan::Plan, procedure::Procedure, schema::{Column, Table}, types::{Row, Value}, user::{self, Grants, Role, User}};
+
+pub mod audit;
+mod catalog;
+pub mod kv;
+mod row;
+pub mod session;
+mod stats;
 pub trait Engine: Clone {
     // 这个关联类型 Transaction 表示：
 	// •	每个实现 Engine 的类型都必须提供一个具体的类型作为 Transaction。
@@ -11,53 +22,462 @@ pub trait Engine: Clone {
 
     fn begin(&self) -> Result<Self::Transaction>;
 
+    // sessions opened on this engine and its clones
+    fn sessions(&self) -> &SessionRegistry;
+
     fn session(&self) -> Result<Session<Self>> {
+        self.user_session("")
+    }
+
+    // a session of a logged in user, fails once the engine has the max number of sessions
+    fn user_session(&self, user: &str) -> Result<Session<Self>> {
         Ok(Session {
             engine: self.clone(),
+            handle: self.sessions().register(user)?,
+            user: user.to_string(),
+            memory_limit: self.sessions().query_memory_limit()?,
+            max_result_rows: None,
+            warnings: Vec::new(),
+            attached: HashMap::new(),
+            cursors: HashMap::new(),
         })
     }
+
+    // open another database of the same kind at path, for ATTACH
+    // default: not supported
+    fn attach(&self, _path: &str) -> Result<Self> {
+        Err(Error::Internal("The engine can not attach databases".to_string()))
+    }
+
+    // delete the expired rows of all tables with ttl, returns how many
+    // runs VACUUM in a session of its own, for a background job
+    fn vacuum(&self) -> Result<usize> {
+        match self.session()?.execute("vacuum;")? {
+            ResultSet::Vacuum { count } => Ok(count),
+            _ => Err(Error::Internal("Unexpected result of vacuum".to_string())),
+        }
+    }
+
+    // check the password of a user when a client connects
+    // anyone is let in until the first user is created
+    fn authenticate(&self, username: &str, password: &str) -> Result<()> {
+        let txn = self.begin()?;
+        let result = match txn.get_user(username.to_string())? {
+            Some(user) if user.verify(password) => Ok(()),
+            None if !txn.has_users()? => Ok(()),
+            _ => Err(Error::AccessDenied(username.to_string())),
+        };
+        txn.rollback()?;
+        result
+    }
 }
 
 pub trait Transaction {
+    fn version(&self) -> Version;
     fn commit(&self) -> Result<()>;
     fn rollback(&self) -> Result<()>;
-    fn create_row(&mut self, table_name: String, row: Row) -> Result<()>;
+    // insert rows of a table read before in one storage batch
+    // nothing is written if any row fails, the error is the one of the first such row
+    fn create_rows(&mut self, table: &Table, rows: Vec<Row>) -> Result<()>;
+    // the fast path of bulk loads, like create_rows but the rows failing the checks or with a key that
+    // exists are left out, returned by index with the error
+    fn bulk_insert(&mut self, table: &Table, rows: Vec<Row>) -> Result<Vec<(usize, Error)>>;
+    fn create_row(&mut self, table: &Table, row: Row) -> Result<()> {
+        self.create_rows(table, vec![row])
+    }
+    // delete the row with the primary key, nothing happens if it does not exist
+    fn delete_row(&mut self, table_name: String, id: &Value) -> Result<()>;
+    // rows of a table with ttl are left out once they expire
+    // the rows count against the memory limit as they are read, see reserve_memory
     fn scan_table(&self, table_name: String) -> Result<Vec<Row>>;
+    // how many rows scan_table would return, without decoding them
+    fn count_table(&self, table_name: String) -> Result<u64>;
+    // at most limit primary keys of the table in key order, starting after the key after, without
+    // reading the rows into memory, for walking a large table in batches
+    fn scan_ids(&self, table_name: String, after: Option<Value>, limit: usize) -> Result<Vec<Value>>;
+    // delete the expired rows of a table with ttl, returns how many
+    fn delete_expired(&mut self, table_name: String) -> Result<usize>;
+    // about how many rows the table has, kept up to date by the commits without a scan, see stats.rs
+    fn estimate_rows(&self, table_name: String) -> Result<u64>;
+    // names of all tables, in order
+    fn table_names(&self) -> Result<Vec<String>>;
     fn create_table(&mut self, table: Table) -> Result<()>;
     fn get_table(&self, table_name: String) -> Result<Option<Table>>;
+    // delete the table and all of its rows
+    fn drop_table(&mut self, table_name: String) -> Result<()>;
+    // append a column to the table, the rows already stored read it as its default, see row.rs
+    fn add_column(&mut self, table_name: String, column: Column) -> Result<()>;
+    // write the defaults of columns added after them into the stored rows with the primary keys,
+    // the rows that already have every column or do not exist are left as they are, returns how many were written
+    fn fill_defaults(&mut self, table_name: String, ids: &[Value]) -> Result<usize>;
+    // statistics of the transaction layer and the storage under it
+    fn status(&self) -> Result<MvccStatus>;
+    fn get_user(&self, name: String) -> Result<Option<User>>;
+    // create the user, or replace it if it exists
+    fn set_user(&mut self, user: User) -> Result<()>;
+    fn has_users(&self) -> Result<bool>;
+    fn get_role(&self, name: String) -> Result<Option<Role>>;
+    fn set_role(&mut self, role: Role) -> Result<()>;
+    // delete the role and what was granted to it
+    fn delete_role(&mut self, name: String) -> Result<()>;
+    // what was granted to a user or a role, empty if nothing was
+    fn get_grants(&self, name: String) -> Result<Grants>;
+    fn set_grants(&mut self, name: String, grants: Grants) -> Result<()>;
+    // every user and role that was granted something
+    fn list_grants(&self) -> Result<Vec<(String, Grants)>>;
+    fn get_procedure(&self, name: String) -> Result<Option<Procedure>>;
+    // create the procedure, or replace it if it exists
+    fn set_procedure(&mut self, procedure: Procedure) -> Result<()>;
+    fn delete_procedure(&mut self, name: String) -> Result<()>;
+    // sessions alive on the engine, for SHOW PROCESSLIST
+    fn sessions(&self) -> Result<Vec<SessionInfo>>;
+    // session counters of the engine, for SHOW STATUS
+    fn session_stats(&self) -> Result<SessionStats>;
+    // statements running on the engine, for SHOW QUERIES
+    fn queries(&self) -> Result<Vec<QueryInfo>>;
+    // cancel a running statement of any session, for KILL
+    fn kill_query(&self, id: u64) -> Result<()>;
+    // the statement fails once the token is cancelled
+    fn set_cancel(&mut self, cancel: CancelToken);
+    // the logged in user running the statements, for the audit log, empty for the application itself
+    fn set_session_user(&mut self, user: &str);
+    // the bytes of rows the query may hold in memory, None for no limit
+    fn set_memory_limit(&mut self, limit: Option<usize>);
+    // count bytes an operator holds until the query ends, fails once they exceed the limit
+    // every statement of a CALL counts against one limit
+    fn reserve_memory(&mut self, bytes: usize) -> Result<()>;
+    // what the statements since the last call warned about, see Session::warnings
+    fn take_warnings(&mut self) -> Vec<Warning>;
     // must get table info, otherwise return error (such as table not exist)
     fn must_get_table(&self, table_name: String) -> Result<Table> {
         self.get_table(table_name.clone())?
-            .ok_or(Error::Internal(format!(
-                "Table {} does not exist",
-                table_name
-            )))
+            .ok_or(Error::TableNotFound(table_name))
     }
 }
 
 pub struct Session<E: Engine> {
     engine: E,
+    handle: SessionHandle,
+    // the logged in user, empty for sessions opened by the application itself
+    user: String,
+    // bytes of rows a query may hold, starts at the limit of the engine
+    memory_limit: Option<usize>,
+    // rows a select returns at most, the rest are dropped with a warning, None for no limit
+    // set by SET max_result_rows, cursors are not limited, they are the way to read past it
+    max_result_rows: Option<usize>,
+    // of the last execute, see Session::warnings
+    warnings: Vec<Warning>,
+    // databases attached by ATTACH, by alias, closed with the session
+    attached: HashMap<String, E>,
+    // cursors declared by DECLARE, by name
+    cursors: HashMap<String, Cursor>,
 }
 
+// the rows of the select are taken when the cursor is declared, so every FETCH sees the same snapshot
+// and goes on where the last one stopped, without running the query again
+struct Cursor {
+    columns: Vec<String>,
+    rows: std::vec::IntoIter<Row>,
+}
+
+// the alias of the database of the session itself
+pub const MAIN_DATABASE: &str = "main";
+
 impl<E: Engine> Session<E> {
+    pub fn id(&self) -> u64 {
+        self.handle.id()
+    }
+
+    // the memory limit of the queries of this session only
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    // the max rows of the results of this session, like SET max_result_rows
+    pub fn set_max_result_rows(&mut self, max: Option<usize>) {
+        self.max_result_rows = max;
+    }
+
+    // what the last execute did not fail on but the caller should know, such as rows left out of a result
+    // a failed execute has none
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
     // Session -> execute -> Parser -> AST -> PLAN
     pub fn execute(&mut self, sql: &str) -> Result<ResultSet> {
-        // get statement by parser
-        match Parser::new(sql).parse()? {
-            stmt => {
-                let mut txn = self.engine.begin()?;
-                // build plan, execute sql
-                match Plan::build(stmt).execute(&mut txn) {
-                    Ok(result) => {
-                        txn.commit()?;
-                        Ok(result)
-                    },
-                    Err(err) => {
-                        txn.rollback()?;
-                        Err(err)
+        let mut results = self.execute_logged(&[sql])?;
+        results.pop().ok_or_else(|| Error::Internal("Statement returned no result".to_string()))
+    }
+
+    // run the statements in one transaction, one statement per sql, and return their results in order
+    // the first error rolls back all of them, for migrations and seed scripts
+    // they all use tables of one database, and count against one memory limit
+    pub fn execute_batch(&mut self, sqls: &[&str]) -> Result<Vec<ResultSet>> {
+        self.execute_logged(sqls)
+    }
+
+    fn execute_logged(&mut self, sqls: &[&str]) -> Result<Vec<ResultSet>> {
+        let sql = sqls.join(" ");
+        let sql = sql.as_str();
+        self.warnings.clear();
+        self.handle.begin_query(sql);
+        let slow_threshold = self.engine.sessions().slow_query_threshold()?;
+        let start = Instant::now();
+        let mut trace = QueryTrace { version: None, plan: None };
+        let result = self.execute_query(sqls, slow_threshold.is_some(), &mut trace);
+        let elapsed = start.elapsed();
+        let slow = slow_threshold.is_some_and(|threshold| elapsed >= threshold);
+        self.handle.end_query(result.is_err(), slow);
+
+        let duration_us = elapsed.as_micros() as u64;
+        let rows: usize = match &result {
+            Ok(results) => results
+                .iter()
+                .map(|result| match result {
+                    ResultSet::Insert { count } => *count,
+                    ResultSet::Scan { row, .. } => row.len(),
+                    _ => 0,
+                })
+                .sum(),
+            Err(_) => 0,
+        };
+        match &result {
+            Ok(_) => tracing::info!(
+                target: "sharkdb::query", session = self.id(), sql, duration_us, rows, version = ?trace.version, "query"
+            ),
+            Err(err) => tracing::warn!(
+                target: "sharkdb::query", session = self.id(), sql, duration_us, version = ?trace.version, error = %err, "query failed"
+            ),
+        }
+        if slow {
+            tracing::warn!(
+                target: "sharkdb::slow_query", session = self.id(), sql, duration_us, rows, plan = trace.plan, "slow query"
+            );
+        }
+        result
+    }
+
+    fn execute_query(&mut self, sqls: &[&str], keep_plan: bool, trace: &mut QueryTrace) -> Result<Vec<ResultSet>> {
+        // get statements by parser
+        let mut stmts = sqls.iter().map(|sql| Parser::new(sql).parse()).collect::<Result<Vec<_>>>()?;
+        // ATTACH, DETACH and cursors change the session rather than a database
+        let is_session = |stmt: &ast::Statement| {
+            matches!(
+                stmt,
+                ast::Statement::Attach { .. }
+                    | ast::Statement::Detach { .. }
+                    | ast::Statement::Declare { .. }
+                    | ast::Statement::Fetch { .. }
+                    | ast::Statement::Close { .. }
+                    | ast::Statement::Set { .. }
+            )
+        };
+        if stmts.iter().any(is_session) {
+            return match stmts.pop() {
+                Some(stmt) if stmts.is_empty() => Ok(vec![self.execute_session(stmt, sqls[0], keep_plan, trace)?]),
+                _ => Err(Error::Internal("ATTACH, DETACH, SET and cursors can not run in a batch".to_string())),
+            };
+        }
+        let mut results = self.execute_statements(stmts.into_iter().zip(sqls.iter().copied()).collect(), keep_plan, trace)?;
+        if let Some(max) = self.max_result_rows {
+            for result in &mut results {
+                let dropped = result.truncate(max);
+                if dropped > 0 {
+                    self.warnings.push(Warning::Truncated { max, dropped });
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn execute_session(
+        &mut self,
+        stmt: ast::Statement,
+        sql: &str,
+        keep_plan: bool,
+        trace: &mut QueryTrace,
+    ) -> Result<ResultSet> {
+        match stmt {
+            ast::Statement::Attach { path, alias } => self.attach(path, alias),
+            ast::Statement::Detach { alias } => self.detach(alias),
+            ast::Statement::Declare { name, query } => {
+                if self.cursors.contains_key(&name) {
+                    return Err(Error::Internal(format!("Cursor {} already exists", name)));
+                }
+                match self.execute_statements(vec![(*query, sql)], keep_plan, trace)?.pop() {
+                    Some(ResultSet::Scan { columns, row }) => {
+                        self.cursors.insert(name.clone(), Cursor { columns, rows: row.into_iter() });
+                        Ok(ResultSet::Declare { name })
                     }
+                    _ => Err(Error::Internal("Unexpected result set".to_string())),
                 }
+            }
+            ast::Statement::Fetch { name, count } => match self.cursors.get_mut(&name) {
+                Some(cursor) => Ok(ResultSet::Scan {
+                    columns: cursor.columns.clone(),
+                    row: cursor.rows.by_ref().take(count as usize).collect(),
+                }),
+                None => Err(Error::Internal(format!("Cursor {} does not exist", name))),
+            },
+            ast::Statement::Close { name } => match self.cursors.remove(&name) {
+                Some(_) => Ok(ResultSet::Close { name }),
+                None => Err(Error::Internal(format!("Cursor {} does not exist", name))),
+            },
+            ast::Statement::Set { name, value } => self.set(name, value),
+            stmt => Err(Error::Internal(format!("Unexpected session statement {:?}", stmt))),
+        }
+    }
+
+    // the statements in one transaction, each with its sql
+    fn execute_statements(
+        &mut self,
+        mut stmts: Vec<(ast::Statement, &str)>,
+        keep_plan: bool,
+        trace: &mut QueryTrace,
+    ) -> Result<Vec<ResultSet>> {
+        let mut databases = stmts.iter_mut().map(|(stmt, _)| self.route(stmt)).collect::<Result<Vec<_>>>()?;
+        databases.dedup();
+        if databases.len() > 1 {
+            return Err(Error::Internal("A batch can not use tables of more than one database".to_string()));
+        }
+        let attached = match databases.pop().flatten() {
+            Some(alias) => Some(self.attached[&alias].clone()),
+            None => None,
+        };
+        let mut txn = attached.as_ref().unwrap_or(&self.engine).begin()?;
+        txn.set_memory_limit(self.memory_limit);
+        txn.set_session_user(&self.user);
+        self.handle.set_txn(txn.version());
+        trace.version = Some(txn.version());
+        // plan each statement after the ones before it ran, so it sees the tables they created
+        // check privileges, then execute sql
+        let mut plans = Vec::new();
+        let mut warnings = Vec::new();
+        let result = stmts.into_iter().try_fold(Vec::new(), |mut results, (stmt, sql)| {
+            let plan = Plan::build(stmt, &txn).map_err(|err| locate(err, sql))?;
+            let text = format!("{:?}", plan.0);
+            if keep_plan {
+                plans.push(text.clone());
+            }
+            self.authorize(&plan, &txn, attached.is_some())?;
+            // the statement shows up in SHOW QUERIES while it runs, and KILL stops it
+            let statement = self.handle.begin_statement(sql, text)?;
+            txn.set_cancel(statement.cancel_token());
+            results.push(plan.execute(&mut txn)?);
+            warnings.extend(txn.take_warnings());
+            Ok(results)
+        });
+        if keep_plan {
+            trace.plan = Some(plans.join("; "));
+        }
+        match result {
+            Ok(results) => {
+                txn.commit()?;
+                self.warnings.extend(warnings);
+                Ok(results)
             },
+            Err(err) => {
+                txn.rollback()?;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<E: Engine> Session<E> {
+    fn attach(&mut self, path: String, alias: String) -> Result<ResultSet> {
+        // it opens any file the server can read, so only admins may
+        if !self.user.is_empty() {
+            let txn = self.engine.begin()?;
+            let authorized = user::authorize(&txn, &self.user, None);
+            txn.rollback()?;
+            authorized?;
+        }
+        if alias == MAIN_DATABASE || self.attached.contains_key(&alias) {
+            return Err(Error::Internal(format!("Database {} is already in use", alias)));
+        }
+        let engine = self.engine.attach(&path)?;
+        self.attached.insert(alias.clone(), engine);
+        Ok(ResultSet::Attach { alias })
+    }
+
+    // the variables of the session, DEFAULT sets one back to what a new session starts with
+    fn set(&mut self, name: String, value: Option<ast::Expression>) -> Result<ResultSet> {
+        let value = match value {
+            None => Value::Null,
+            Some(ast::Expression::Consts(consts)) => Value::from(consts),
+            Some(expr) => return Err(Error::Internal(format!("Variable {} must be set to a constant, got {}", name, expr))),
+        };
+        match name.as_str() {
+            "max_result_rows" => {
+                self.max_result_rows = match value {
+                    Value::Null => None,
+                    Value::Integer(max) if max >= 0 => Some(max as usize),
+                    value => return Err(Error::Internal(format!("Invalid max_result_rows {}", value))),
+                }
+            }
+            // drivers set variables of other databases, they are let through
+            _ => self.warnings.push(Warning::UnknownVariable { name: name.clone() }),
+        }
+        Ok(ResultSet::Set { name })
+    }
+
+    fn detach(&mut self, alias: String) -> Result<ResultSet> {
+        match self.attached.remove(&alias) {
+            Some(_) => Ok(ResultSet::Detach { alias }),
+            None => Err(Error::Internal(format!("Database {} is not attached", alias))),
+        }
+    }
+
+    // the alias of the attached database a statement works on, None for the main one
+    // the alias is taken off its table names
+    // a statement works on tables of one database, in its own transaction there
+    fn route(&self, stmt: &mut ast::Statement) -> Result<Option<String>> {
+        let mut database: Option<String> = None;
+        for name in stmt.table_names_mut() {
+            let (alias, table) = match name.split_once('.') {
+                Some((alias, table)) => (alias.to_string(), table.to_string()),
+                None => (MAIN_DATABASE.to_string(), name.clone()),
+            };
+            if *database.get_or_insert_with(|| alias.clone()) != alias {
+                return Err(Error::Internal("A statement can not use tables of more than one database".to_string()));
+            }
+            *name = table;
+        }
+        match database.as_deref() {
+            None | Some(MAIN_DATABASE) => Ok(None),
+            Some(alias) if self.attached.contains_key(alias) => Ok(Some(alias.to_string())),
+            Some(alias) => Err(Error::Internal(format!("Database {} is not attached", alias))),
         }
     }
+
+    // privileges are granted in the main database, also on the tables of attached ones
+    fn authorize(&self, plan: &Plan, txn: &E::Transaction, attached: bool) -> Result<()> {
+        if self.user.is_empty() || !attached {
+            return plan.authorize(txn, &self.user);
+        }
+        let main = self.engine.begin()?;
+        let authorized = plan.authorize(&main, &self.user);
+        main.rollback()?;
+        authorized
+    }
+}
+
+// point an unknown column at where the sql names it
+fn locate(err: Error, sql: &str) -> Error {
+    match err {
+        Error::ColumnNotFound { table, column, position: None } => {
+            let position = parser::locate(sql, &column);
+            Error::ColumnNotFound { table, column, position }
+        }
+        err => err,
+    }
+}
+
+// what the query log needs from inside a query
+struct QueryTrace {
+    version: Option<Version>,
+    // only kept when the slow query log is on
+    plan: Option<String>,
 }
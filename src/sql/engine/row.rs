@@ -0,0 +1,197 @@
+// storage format of rows: a format version byte, then the values tagged with their column ids
+// a column id is the position of the column in the table, columns are only ever appended,
+// so an id keeps its meaning as the table changes:
+//   a column missing from a stored row, added after it was written, reads as its default or NULL
+//   an id the table no longer has is skipped
+// version 2 puts the unix millis the row was written at before the values, for tables with ttl
+// a value of an ENUM column is stored as the position of its label, an Integer
+// rows written before the format was versioned are a bare bincode Vec<Value>,
+// they are rewritten when the engine opens, see KVEngine::new
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        schema::Table,
+        types::{DataType, Row, Value},
+    },
+};
+
+pub const ROW_FORMAT_VERSION: u8 = 2;
+
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+pub fn encode_row(table: &Table, row: &Row, written_at: u64) -> Result<Vec<u8>> {
+    let tagged: Vec<(u32, Value)> = row
+        .iter()
+        .zip(table.columns.iter().map(Some).chain(std::iter::repeat(None)))
+        .enumerate()
+        .map(|(id, (value, col))| match (col.map(|c| &c.datatype), value) {
+            (Some(DataType::Enum(labels)), Value::String(s)) => match labels.iter().position(|l| l == s) {
+                Some(i) => Ok((id as u32, Value::Integer(i as i64))),
+                None => Err(Error::CheckViolation { column: col.map(|c| c.name.clone()).unwrap_or_default(), value: value.clone() }),
+            },
+            _ => Ok((id as u32, value.clone())),
+        })
+        .collect::<Result<_>>()?;
+    let mut buf = vec![ROW_FORMAT_VERSION];
+    buf.extend(written_at.to_le_bytes());
+    bincode::serialize_into(&mut buf, &tagged)?;
+    Ok(buf)
+}
+
+// the row and when it was written, None for rows of version 1
+pub fn decode_row(table: &Table, data: &[u8]) -> Result<(Row, Option<u64>)> {
+    let (written_at, tagged) = decode_tagged(table, data)?;
+    let mut row: Vec<Option<Value>> = vec![None; table.columns.len()];
+    for (id, value) in tagged {
+        if let (Some(slot), Some(col)) = (row.get_mut(id as usize), table.columns.get(id as usize)) {
+            *slot = Some(match (&col.datatype, value) {
+                (DataType::Enum(labels), Value::Integer(i)) => match labels.get(i as usize) {
+                    Some(label) => Value::String(label.clone()),
+                    None => {
+                        return Err(Error::Internal(format!("Enum column {} has no label {}", col.name, i)))
+                    }
+                },
+                (_, value) => value,
+            });
+        }
+    }
+    let row = row
+        .into_iter()
+        .zip(&table.columns)
+        .map(|(value, col)| value.or_else(|| col.default.clone()).unwrap_or(Value::Null))
+        .collect();
+    Ok((row, written_at))
+}
+
+// false if the row was written before some columns of the table were added
+pub fn has_all_columns(table: &Table, data: &[u8]) -> Result<bool> {
+    let mut stored = vec![false; table.columns.len()];
+    for (id, _) in decode_tagged(table, data)?.1 {
+        if let Some(slot) = stored.get_mut(id as usize) {
+            *slot = true;
+        }
+    }
+    Ok(stored.into_iter().all(|stored| stored))
+}
+
+// the values of a stored row with their column ids
+type Tagged = Vec<(u32, Value)>;
+
+fn decode_tagged(table: &Table, data: &[u8]) -> Result<(Option<u64>, Tagged)> {
+    let (written_at, body) = decode_header(table, data)?;
+    Ok((written_at, bincode::deserialize(body)?))
+}
+
+// when the row was written, from its header without decoding the values, see decode_row
+pub fn written_at(table: &Table, data: &[u8]) -> Result<Option<u64>> {
+    Ok(decode_header(table, data)?.0)
+}
+
+fn decode_header<'a>(table: &Table, data: &'a [u8]) -> Result<(Option<u64>, &'a [u8])> {
+    Ok(match data.split_first() {
+        Some((1, body)) => (None, body),
+        Some((2, body)) if body.len() >= 8 => (Some(u64::from_le_bytes(body[..8].try_into()?)), &body[8..]),
+        Some((version, _)) => {
+            return Err(Error::Internal(format!(
+                "Row of table {} has unknown format {}, newer than {}",
+                table.name, version, ROW_FORMAT_VERSION
+            )))
+        }
+        None => return Err(Error::Internal(format!("Empty row in table {}", table.name))),
+    })
+}
+
+// rows expire ttl seconds after they are written, rows of version 1 were given a storage ttl instead
+pub fn is_expired(table: &Table, written_at: Option<u64>, now: u64) -> bool {
+    match (table.ttl, written_at) {
+        (Some(ttl), Some(written_at)) => now >= written_at.saturating_add(ttl.saturating_mul(1000)),
+        _ => false,
+    }
+}
+
+// a row written before the format was versioned
+pub fn decode_legacy_row(data: &[u8]) -> Result<Row> {
+    Ok(bincode::deserialize(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Result,
+        sql::{
+            schema::{Column, Table},
+            types::{DataType, Value},
+        },
+    };
+
+    use super::{decode_legacy_row, decode_row, encode_row, is_expired};
+
+    fn column(name: &str, default: Option<Value>) -> Column {
+        Column { name: name.to_string(), datatype: DataType::Integer, nullable: true, default }
+    }
+
+    #[test]
+    fn test_row_format() -> Result<()> {
+        let table = Table { name: "t".to_string(), columns: vec![column("a", None), column("b", None)], ttl: None, audit: false };
+        let row = vec![Value::Integer(1), Value::Null];
+        let data = encode_row(&table, &row, 1000)?;
+        assert_eq!(data[0], 2);
+        assert_eq!(decode_row(&table, &data)?, (row.clone(), Some(1000)));
+
+        // 之后新增的列读取为默认值或 NULL
+        let mut wider = Table { name: "t".to_string(), columns: vec![column("a", None), column("b", None)], ttl: None, audit: false };
+        wider.columns.push(column("c", Some(Value::Integer(7))));
+        wider.columns.push(column("d", None));
+        assert_eq!(decode_row(&wider, &data)?.0, vec![Value::Integer(1), Value::Null, Value::Integer(7), Value::Null]);
+        // 表中已不存在的列被跳过
+        let narrow = Table { name: "t".to_string(), columns: vec![column("a", None)], ttl: None, audit: false };
+        assert_eq!(decode_row(&narrow, &data)?.0, vec![Value::Integer(1)]);
+
+        // 版本 1 没有写入时间
+        let mut v1 = vec![1];
+        v1.extend(bincode::serialize(&vec![(0u32, Value::Integer(1)), (1, Value::Null)])?);
+        assert_eq!(decode_row(&table, &v1)?, (row.clone(), None));
+
+        // 未知的格式版本和空数据
+        let mut newer = data.clone();
+        newer[0] = 3;
+        assert!(decode_row(&table, &newer).is_err());
+        assert!(decode_row(&table, &[]).is_err());
+        assert!(decode_row(&table, &[2, 0]).is_err());
+
+        let legacy = bincode::serialize(&row)?;
+        assert_eq!(decode_legacy_row(&legacy)?, row);
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_expired() {
+        let mut table = Table { name: "t".to_string(), columns: vec![column("a", None)], ttl: None, audit: false };
+        assert!(!is_expired(&table, Some(0), u64::MAX));
+        table.ttl = Some(10);
+        assert!(!is_expired(&table, Some(1000), 10_999));
+        assert!(is_expired(&table, Some(1000), 11_000));
+        assert!(!is_expired(&table, None, u64::MAX));
+        table.ttl = Some(u64::MAX);
+        assert!(!is_expired(&table, Some(1000), u64::MAX - 1));
+    }
+
+    #[test]
+    fn test_row_enum() -> Result<()> {
+        let mut status = column("status", None);
+        status.datatype = DataType::Enum(vec!["new".to_string(), "done".to_string()]);
+        let table = Table { name: "t".to_string(), columns: vec![column("a", None), status], ttl: None, audit: false };
+        let row = vec![Value::Integer(1), Value::String("done".to_string())];
+        let data = encode_row(&table, &row, 0)?;
+        // 存储的是标签的位置
+        let stored: Vec<(u32, Value)> = bincode::deserialize(&data[9..])?;
+        assert_eq!(stored[1], (1, Value::Integer(1)));
+        assert_eq!(decode_row(&table, &data)?.0, row);
+        assert!(encode_row(&table, &vec![Value::Integer(1), Value::String("lost".to_string())], 0).is_err());
+        Ok(())
+    }
+}
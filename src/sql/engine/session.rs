@@ -0,0 +1,329 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use web_time::SystemTime;
+
+use crate::{
+    error::{Error, Result},
+    storage::mvcc::Version,
+};
+
+// sessions alive on an engine and settings shared by them, shared by all clones of the engine
+#[derive(Clone, Default)]
+pub struct SessionRegistry {
+    inner: Arc<Mutex<Registry>>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    // None means no limit
+    max_sessions: Option<usize>,
+    // queries taking longer are logged with their plan, None turns the slow query log off
+    slow_query_threshold: Option<Duration>,
+    // bytes of rows a query may hold, the default of new sessions, None means no limit
+    query_memory_limit: Option<usize>,
+    sessions: BTreeMap<u64, SessionInfo>,
+    next_query_id: u64,
+    // statements running now, by query id
+    running: BTreeMap<u64, (QueryInfo, CancelToken)>,
+    queries: u64,
+    failed_queries: u64,
+    slow_queries: u64,
+}
+
+// session counters since the engine was opened, for SHOW STATUS
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionStats {
+    // sessions alive now, and the limit of them
+    pub active: usize,
+    pub max_sessions: Option<usize>,
+    // sessions ever opened
+    pub total: u64,
+    pub queries: u64,
+    pub failed_queries: u64,
+    pub slow_queries: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub user: String,
+    pub connected_at: SystemTime,
+    // when the running query started, or when the last query finished
+    pub state_since: SystemTime,
+    // the running query, None if idle
+    pub query: Option<String>,
+    // version of the running transaction
+    pub txn: Option<Version>,
+}
+
+// a statement running in a session, for SHOW QUERIES
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryInfo {
+    pub id: u64,
+    pub session: u64,
+    pub user: String,
+    pub started_at: SystemTime,
+    pub sql: String,
+    // the plan being executed, as its debug text
+    pub plan: String,
+}
+
+// set by KILL, the transaction of the statement checks it for every row it reads or writes
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(Error::QueryCancelled { id: self.id }),
+            false => Ok(()),
+        }
+    }
+}
+
+impl SessionRegistry {
+    pub fn set_max_sessions(&self, max: Option<usize>) -> Result<()> {
+        self.inner.lock()?.max_sessions = max;
+        Ok(())
+    }
+
+    pub fn set_slow_query_threshold(&self, threshold: Option<Duration>) -> Result<()> {
+        self.inner.lock()?.slow_query_threshold = threshold;
+        Ok(())
+    }
+
+    pub fn slow_query_threshold(&self) -> Result<Option<Duration>> {
+        Ok(self.inner.lock()?.slow_query_threshold)
+    }
+
+    pub fn set_query_memory_limit(&self, limit: Option<usize>) -> Result<()> {
+        self.inner.lock()?.query_memory_limit = limit;
+        Ok(())
+    }
+
+    pub fn query_memory_limit(&self) -> Result<Option<usize>> {
+        Ok(self.inner.lock()?.query_memory_limit)
+    }
+
+    pub fn register(&self, user: &str) -> Result<SessionHandle> {
+        let mut inner = self.inner.lock()?;
+        if let Some(max) = inner.max_sessions {
+            if inner.sessions.len() >= max {
+                return Err(Error::TooManySessions { max });
+            }
+        }
+        inner.next_id += 1;
+        let id = inner.next_id;
+        let now = SystemTime::now();
+        inner.sessions.insert(id, SessionInfo {
+            id,
+            user: user.to_string(),
+            connected_at: now,
+            state_since: now,
+            query: None,
+            txn: None,
+        });
+        Ok(SessionHandle { id, registry: self.clone() })
+    }
+
+    // sessions ordered by id
+    pub fn list(&self) -> Result<Vec<SessionInfo>> {
+        Ok(self.inner.lock()?.sessions.values().cloned().collect())
+    }
+
+    // running statements ordered by query id
+    pub fn queries(&self) -> Result<Vec<QueryInfo>> {
+        Ok(self.inner.lock()?.running.values().map(|(info, _)| info.clone()).collect())
+    }
+
+    // the statement fails with QueryCancelled at the next row it reads or writes
+    pub fn kill(&self, id: u64) -> Result<()> {
+        match self.inner.lock()?.running.get(&id) {
+            Some((_, cancel)) => {
+                cancel.cancel();
+                Ok(())
+            }
+            None => Err(Error::Internal(format!("Query {} is not running", id))),
+        }
+    }
+
+    pub fn stats(&self) -> Result<SessionStats> {
+        let inner = self.inner.lock()?;
+        Ok(SessionStats {
+            active: inner.sessions.len(),
+            max_sessions: inner.max_sessions,
+            total: inner.next_id,
+            queries: inner.queries,
+            failed_queries: inner.failed_queries,
+            slow_queries: inner.slow_queries,
+        })
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut SessionInfo)) {
+        // a poisoned registry only loses the processlist, never fail the query for it
+        if let Ok(mut inner) = self.inner.lock() {
+            if let Some(info) = inner.sessions.get_mut(&id) {
+                f(info);
+            }
+        }
+    }
+}
+
+// the entry of a session in the registry, removed on drop
+pub struct SessionHandle {
+    id: u64,
+    registry: SessionRegistry,
+}
+
+impl SessionHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn begin_query(&self, sql: &str) {
+        self.registry.update(self.id, |info| {
+            info.query = Some(sql.to_string());
+            info.state_since = SystemTime::now();
+        });
+    }
+
+    pub fn set_txn(&self, version: Version) {
+        self.registry.update(self.id, |info| info.txn = Some(version));
+    }
+
+    // every statement of a query gets an id of its own, it is dropped once the statement ends
+    pub fn begin_statement(&self, sql: &str, plan: String) -> Result<StatementHandle> {
+        let mut inner = self.registry.inner.lock()?;
+        inner.next_query_id += 1;
+        let id = inner.next_query_id;
+        let user = inner.sessions.get(&self.id).map(|info| info.user.clone()).unwrap_or_default();
+        let cancel = CancelToken { id, cancelled: Arc::default() };
+        let info = QueryInfo { id, session: self.id, user, started_at: SystemTime::now(), sql: sql.to_string(), plan };
+        inner.running.insert(id, (info, cancel.clone()));
+        Ok(StatementHandle { id, cancel, registry: self.registry.clone() })
+    }
+
+    pub fn end_query(&self, failed: bool, slow: bool) {
+        self.registry.update(self.id, |info| {
+            info.query = None;
+            info.txn = None;
+            info.state_since = SystemTime::now();
+        });
+        if let Ok(mut inner) = self.registry.inner.lock() {
+            inner.queries += 1;
+            inner.failed_queries += failed as u64;
+            inner.slow_queries += slow as u64;
+        }
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.registry.inner.lock() {
+            inner.sessions.remove(&self.id);
+        }
+    }
+}
+
+// the entry of a running statement in the registry, removed on drop
+pub struct StatementHandle {
+    id: u64,
+    cancel: CancelToken,
+    registry: SessionRegistry,
+}
+
+impl StatementHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
+
+impl Drop for StatementHandle {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.registry.inner.lock() {
+            inner.running.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionRegistry;
+    use crate::error::{Error, Result};
+
+    #[test]
+    fn test_session_registry() -> Result<()> {
+        let registry = SessionRegistry::default();
+        registry.set_max_sessions(Some(2))?;
+        let s1 = registry.register("alice")?;
+        let s2 = registry.register("bob")?;
+        // 超过上限
+        assert!(matches!(registry.register("carol"), Err(Error::TooManySessions { max: 2 })));
+
+        s2.begin_query("select * from t;");
+        s2.set_txn(7);
+        let list = registry.list()?;
+        assert_eq!(list.len(), 2);
+        assert_eq!((list[0].id, list[0].query.clone(), list[0].txn), (s1.id(), None, None));
+        assert_eq!(list[1].query.as_deref(), Some("select * from t;"));
+        assert_eq!(list[1].txn, Some(7));
+        s2.end_query(false, false);
+        assert_eq!(registry.list()?[1].query, None);
+
+        // session 结束之后从列表中移除，可以再连接
+        drop(s1);
+        assert_eq!(registry.list()?.len(), 1);
+        let s3 = registry.register("carol")?;
+        assert_eq!(s3.id(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_registry_queries() -> Result<()> {
+        let registry = SessionRegistry::default();
+        let s1 = registry.register("alice")?;
+        let q1 = s1.begin_statement("select * from t;", "Scan".to_string())?;
+        let q2 = s1.begin_statement("select 1;", "Projection".to_string())?;
+        let queries = registry.queries()?;
+        assert_eq!(queries.iter().map(|q| q.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!((queries[0].session, queries[0].user.as_str(), queries[0].plan.as_str()), (s1.id(), "alice", "Scan"));
+
+        // kill 只标记，语句在读写下一行时失败
+        let cancel = q1.cancel_token();
+        assert!(cancel.check().is_ok());
+        registry.kill(q1.id())?;
+        assert!(cancel.is_cancelled());
+        assert_eq!(cancel.check(), Err(Error::QueryCancelled { id: 1 }));
+        assert!(q2.cancel_token().check().is_ok());
+
+        // 结束的语句从列表中移除
+        drop(q1);
+        assert_eq!(registry.queries()?.len(), 1);
+        assert!(registry.kill(1).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+// approximate live rows of each table, shared by all clones of an engine
+// a transaction adds the rows it inserted and takes off the ones it deleted when it commits,
+// a table is counted with a scan the first time it is estimated after the engine opens, or after ddl on it
+// rows expired but not vacuumed yet are counted, and commits racing with the first scan may be missed
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use crate::error::Result;
+
+#[derive(Clone, Default)]
+pub struct RowCounts {
+    inner: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl RowCounts {
+    // None if the table is not counted yet
+    pub fn get(&self, table_name: &str) -> Result<Option<u64>> {
+        Ok(self.inner.lock()?.get(table_name).copied())
+    }
+
+    // the count of a scan of a committed snapshot
+    pub fn set(&self, table_name: &str, rows: u64) -> Result<()> {
+        self.inner.lock()?.insert(table_name.to_string(), rows);
+        Ok(())
+    }
+
+    // the rows a committed transaction added to each table, tables it created or dropped are counted again
+    pub fn apply(&self, deltas: &BTreeMap<String, i64>, changed: &BTreeSet<String>) -> Result<()> {
+        let mut counts = self.inner.lock()?;
+        for name in changed {
+            counts.remove(name);
+        }
+        for (name, delta) in deltas {
+            if let Some(rows) = counts.get_mut(name) {
+                *rows = rows.saturating_add_signed(*delta);
+            }
+        }
+        Ok(())
+    }
+}
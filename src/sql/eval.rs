@@ -0,0 +1,479 @@
+// three-valued logic of sql, shared by every place that evaluates a condition
+// a condition is TRUE, FALSE or UNKNOWN, and UNKNOWN is Value::Null:
+//   NULL = NULL is NULL, NULL AND FALSE is FALSE, NULL OR TRUE is TRUE, NOT NULL is NULL
+// WHERE and JOIN keep a row only if the condition is TRUE,
+// CHECK rejects a row only if the condition is FALSE
+use std::{cmp::Ordering, fmt::Display};
+
+use crate::error::{Error, Result};
+
+use super::{
+    parser::ast::{Expression, Function, Operation},
+    types::{Row, Value},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Display for CompareOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+        })
+    }
+}
+
+impl Display for ArithmeticOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+        })
+    }
+}
+
+impl ArithmeticOp {
+    fn verb(&self) -> &str {
+        match self {
+            Self::Add => "add",
+            Self::Subtract => "subtract",
+            Self::Multiply => "multiply",
+            Self::Divide => "divide",
+        }
+    }
+}
+
+// Some(true), Some(false), or None for UNKNOWN
+pub fn truth(value: &Value) -> Result<Option<bool>> {
+    match value {
+        Value::Boolean(b) => Ok(Some(*b)),
+        Value::Null => Ok(None),
+        v => Err(Error::Internal(format!("Expected a boolean condition, got {}", v))),
+    }
+}
+
+fn from_truth(truth: Option<bool>) -> Value {
+    truth.map_or(Value::Null, Value::Boolean)
+}
+
+pub fn and(left: &Value, right: &Value) -> Result<Value> {
+    Ok(from_truth(match (truth(left)?, truth(right)?) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }))
+}
+
+pub fn or(left: &Value, right: &Value) -> Result<Value> {
+    Ok(from_truth(match (truth(left)?, truth(right)?) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }))
+}
+
+pub fn not(value: &Value) -> Result<Value> {
+    Ok(from_truth(truth(value)?.map(|b| !b)))
+}
+
+// IS NULL is never unknown
+pub fn is_null(value: &Value) -> Value {
+    Value::Boolean(*value == Value::Null)
+}
+
+// NULL if either side is NULL, other kinds only with themselves
+// integers and floats follow the coercion of Value::coerce, but compare exactly without rounding
+pub fn compare(op: CompareOp, left: &Value, right: &Value) -> Result<Value> {
+    let ordering = match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+        (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_))
+        | (Value::String(_), Value::String(_))
+        | (Value::Boolean(_), Value::Boolean(_)) => left.cmp(right),
+        (l, r) => return Err(Error::Internal(format!("Cannot compare {} with {}", l, r))),
+    };
+    Ok(Value::Boolean(match op {
+        CompareOp::Equal => ordering == Ordering::Equal,
+        CompareOp::NotEqual => ordering != Ordering::Equal,
+        CompareOp::Less => ordering == Ordering::Less,
+        CompareOp::LessOrEqual => ordering != Ordering::Greater,
+        CompareOp::Greater => ordering == Ordering::Greater,
+        CompareOp::GreaterOrEqual => ordering != Ordering::Less,
+    }))
+}
+
+// rows of the same length compare value by value:
+//   = is FALSE if any pair is not equal, else NULL if any pair has a NULL, else TRUE
+//   <, > and the others are decided by the first pair that is not equal, NULL if it has a NULL
+pub fn compare_rows(op: CompareOp, left: &[Value], right: &[Value]) -> Result<Value> {
+    if left.len() != right.len() {
+        return Err(Error::Internal(format!("Cannot compare rows of {} and {} values", left.len(), right.len())));
+    }
+    if let CompareOp::Equal | CompareOp::NotEqual = op {
+        let mut result = Value::Boolean(true);
+        for (l, r) in left.iter().zip(right) {
+            result = and(&result, &compare(CompareOp::Equal, l, r)?)?;
+        }
+        return if op == CompareOp::Equal { Ok(result) } else { not(&result) };
+    }
+    for (l, r) in left.iter().zip(right) {
+        match compare(CompareOp::Equal, l, r)? {
+            Value::Boolean(true) => {}
+            Value::Boolean(false) => return compare(op, l, r),
+            _ => return Ok(Value::Null),
+        }
+    }
+    Ok(Value::Boolean(matches!(op, CompareOp::LessOrEqual | CompareOp::GreaterOrEqual)))
+}
+
+// NULL if either side is NULL, integers stay integers and an integer with a float is a float
+// integer division truncates, overflow and division by zero are errors
+pub fn arithmetic(op: ArithmeticOp, left: &Value, right: &Value) -> Result<Value> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_))
+            if op == ArithmeticOp::Divide && as_float(right) == 0.0 =>
+        {
+            Err(Error::Internal(format!("Cannot divide {} by zero", left)))
+        }
+        (Value::Integer(l), Value::Integer(r)) => match op {
+            ArithmeticOp::Add => l.checked_add(*r),
+            ArithmeticOp::Subtract => l.checked_sub(*r),
+            ArithmeticOp::Multiply => l.checked_mul(*r),
+            ArithmeticOp::Divide => l.checked_div(*r),
+        }
+        .map(Value::Integer)
+        .ok_or_else(|| Error::Internal(format!("Integer overflow, cannot {} {} and {}", op.verb(), l, r))),
+        (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_)) => {
+            let (l, r) = (as_float(left), as_float(right));
+            Ok(Value::Float(match op {
+                ArithmeticOp::Add => l + r,
+                ArithmeticOp::Subtract => l - r,
+                ArithmeticOp::Multiply => l * r,
+                ArithmeticOp::Divide => l / r,
+            }))
+        }
+        (l, r) => Err(Error::Internal(format!("Cannot {} {} and {}", op.verb(), l, r))),
+    }
+}
+
+fn as_float(value: &Value) -> f64 {
+    match value {
+        Value::Integer(i) => *i as f64,
+        Value::Float(f) => *f,
+        _ => f64::NAN,
+    }
+}
+
+// NULL if any argument is NULL
+// substr follows postgres, characters before position 1 count toward the length but are not there
+pub fn call(func: Function, args: &[Value]) -> Result<Value> {
+    let arity = match func {
+        Function::Substr => 2..=3,
+        Function::Length | Function::Upper | Function::Lower => 1..=1,
+    };
+    if !arity.contains(&args.len()) {
+        return Err(Error::Internal(format!("Function {} does not take {} arguments", func, args.len())));
+    }
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    Ok(match (func, args) {
+        (Function::Substr, [Value::String(s), Value::Integer(start), rest @ ..]) => {
+            let start = *start;
+            let end = match rest {
+                [] => i64::MAX,
+                [Value::Integer(len)] if *len >= 0 => start.saturating_add(*len),
+                [Value::Integer(len)] => return Err(Error::Internal(format!("Negative substr length {}", len))),
+                [v] => return Err(Error::Internal(format!("Function substr takes an integer length, got {}", v))),
+                _ => unreachable!(),
+            };
+            let skip = start.saturating_sub(1).max(0);
+            let take = end.saturating_sub(start.max(1)).max(0);
+            Value::String(s.chars().skip(skip as usize).take(take as usize).collect())
+        }
+        (Function::Length, [Value::String(s)]) => Value::Integer(s.chars().count() as i64),
+        (Function::Upper, [Value::String(s)]) => Value::String(s.to_uppercase()),
+        (Function::Lower, [Value::String(s)]) => Value::String(s.to_lowercase()),
+        (func, args) => {
+            let args = args.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+            return Err(Error::Internal(format!("Function {} can not take {}", func, args)));
+        }
+    })
+}
+
+// euclidean distance of two vectors of the same dimension, NULL if either is NULL
+pub fn distance(left: &Value, right: &Value) -> Result<Value> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Vector(a), Value::Vector(b)) if a.len() == b.len() => {
+            let sum: f64 = a.iter().zip(b).map(|(x, y)| (*x as f64 - *y as f64).powi(2)).sum();
+            Ok(Value::Float(sum.sqrt()))
+        }
+        (Value::Vector(a), Value::Vector(b)) => {
+            Err(Error::Internal(format!("Cannot compute the distance of vectors with {} and {} dimensions", a.len(), b.len())))
+        }
+        (l, r) => Err(Error::Internal(format!("Cannot compute the distance of {} and {}", l, r))),
+    }
+}
+
+// the value of an expression on a row, columns are the names of the row values
+pub fn evaluate(expr: &Expression, columns: &[String], row: &Row) -> Result<Value> {
+    match expr {
+        Expression::Consts(_) => Value::from_expression(expr.clone()),
+        Expression::Field(name) => match columns.iter().position(|c| c == name) {
+            Some(i) => Ok(row[i].clone()),
+            None => Err(Error::Internal(format!("Unknown column {}", name))),
+        },
+        Expression::Row(_) => Err(Error::Internal("A row can only be compared with another row".to_string())),
+        Expression::Operation(Operation::Distance(l, r)) => {
+            distance(&evaluate(l, columns, row)?, &evaluate(r, columns, row)?)
+        }
+        Expression::Operation(Operation::Compare(op, l, r)) => {
+            compare_rows(*op, &evaluate_row(l, columns, row)?, &evaluate_row(r, columns, row)?)
+        }
+        Expression::Operation(Operation::Arithmetic(op, l, r)) => {
+            arithmetic(*op, &evaluate(l, columns, row)?, &evaluate(r, columns, row)?)
+        }
+        Expression::Function(func, args) => {
+            call(*func, &args.iter().map(|arg| evaluate(arg, columns, row)).collect::<Result<Vec<_>>>()?)
+        }
+        Expression::Aggregate(aggregate) => {
+            Err(Error::Internal(format!("Aggregate {} is only allowed in the select list and ORDER BY", aggregate.func)))
+        }
+        Expression::Operation(Operation::In(l, list)) => {
+            let l = evaluate_row(l, columns, row)?;
+            let mut result = Value::Boolean(false);
+            for item in list {
+                result = or(&result, &compare_rows(CompareOp::Equal, &l, &evaluate_row(item, columns, row)?)?)?;
+            }
+            Ok(result)
+        }
+    }
+}
+
+// the values of a row expression, or the one value of any other expression
+fn evaluate_row(expr: &Expression, columns: &[String], row: &Row) -> Result<Vec<Value>> {
+    match expr {
+        Expression::Row(exprs) => exprs.iter().map(|expr| evaluate(expr, columns, row)).collect(),
+        expr => Ok(vec![evaluate(expr, columns, row)?]),
+    }
+}
+
+// WHERE and JOIN conditions, UNKNOWN drops the row
+pub fn filter(condition: &Value) -> Result<bool> {
+    Ok(truth(condition)? == Some(true))
+}
+
+// CHECK constraints, UNKNOWN lets the row in
+pub fn check(condition: &Value) -> Result<bool> {
+    Ok(truth(condition)? != Some(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Result,
+        sql::{parser::ast::{Consts, Expression, Function, Operation}, types::Value},
+    };
+
+    use super::{
+        and, arithmetic, call, check, compare, compare_rows, distance, evaluate, filter, is_null, not, or, ArithmeticOp, CompareOp,
+    };
+
+    const T: Value = Value::Boolean(true);
+    const F: Value = Value::Boolean(false);
+    const N: Value = Value::Null;
+
+    #[test]
+    fn test_eval_and_or() -> Result<()> {
+        // 完整真值表：(左, 右, AND, OR)
+        let table = [
+            (T, T, T, T),
+            (T, F, F, T),
+            (T, N, N, T),
+            (F, T, F, T),
+            (F, F, F, F),
+            (F, N, F, N),
+            (N, T, N, T),
+            (N, F, F, N),
+            (N, N, N, N),
+        ];
+        for (l, r, expect_and, expect_or) in table {
+            assert_eq!(and(&l, &r)?, expect_and, "{:?} AND {:?}", l, r);
+            assert_eq!(or(&l, &r)?, expect_or, "{:?} OR {:?}", l, r);
+        }
+        assert!(and(&T, &Value::Integer(1)).is_err());
+        assert!(or(&Value::String("t".to_string()), &N).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_not() -> Result<()> {
+        for (v, expect) in [(T, F), (F, T), (N, N)] {
+            assert_eq!(not(&v)?, expect);
+        }
+        assert!(not(&Value::Integer(0)).is_err());
+        for (v, expect) in [(T, F), (F, F), (N, T), (Value::Integer(0), F)] {
+            assert_eq!(is_null(&v), expect);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_compare() -> Result<()> {
+        let ops = [
+            CompareOp::Equal,
+            CompareOp::NotEqual,
+            CompareOp::Less,
+            CompareOp::LessOrEqual,
+            CompareOp::Greater,
+            CompareOp::GreaterOrEqual,
+        ];
+        // 任何一边是 NULL，结果都是 NULL，包括 NULL = NULL
+        for op in ops {
+            for v in [N, T, Value::Integer(1), Value::String("a".to_string())] {
+                assert_eq!(compare(op, &N, &v)?, N);
+                assert_eq!(compare(op, &v, &N)?, N);
+            }
+        }
+        // (左, 右, =, !=, <, <=, >, >=)
+        let table = [
+            (Value::Integer(1), Value::Integer(2), [F, T, T, T, F, F]),
+            (Value::Integer(2), Value::Float(2.0), [T, F, F, T, F, T]),
+            (Value::Float(2.5), Value::Integer(2), [F, T, F, F, T, T]),
+            (Value::String("a".to_string()), Value::String("b".to_string()), [F, T, T, T, F, F]),
+            (F, T, [F, T, T, T, F, F]),
+        ];
+        for (l, r, expects) in table {
+            for (op, expect) in ops.into_iter().zip(expects) {
+                assert_eq!(compare(op, &l, &r)?, expect, "{:?} {:?} {:?}", l, op, r);
+            }
+        }
+        assert!(compare(CompareOp::Equal, &Value::Integer(1), &Value::String("1".to_string())).is_err());
+        assert!(compare(CompareOp::Less, &T, &Value::Integer(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_filter_check() -> Result<()> {
+        // WHERE 只保留 TRUE，CHECK 只拒绝 FALSE
+        assert_eq!((filter(&T)?, filter(&F)?, filter(&N)?), (true, false, false));
+        assert_eq!((check(&T)?, check(&F)?, check(&N)?), (true, false, true));
+        assert!(filter(&Value::Integer(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_distance() -> Result<()> {
+        let v = |x: &[f32]| Value::Vector(x.to_vec());
+        assert_eq!(distance(&v(&[0.0, 0.0]), &v(&[3.0, 4.0]))?, Value::Float(5.0));
+        assert_eq!(distance(&v(&[1.0]), &N)?, N);
+        // 维度不同，或者不是向量
+        assert!(distance(&v(&[1.0]), &v(&[1.0, 2.0])).is_err());
+        assert!(distance(&v(&[1.0]), &Value::Float(1.0)).is_err());
+
+        let columns = vec!["id".to_string(), "v".to_string()];
+        let row = vec![Value::Integer(1), v(&[1.0, 1.0])];
+        let expr = Expression::Operation(Operation::Distance(
+            Box::new(Expression::Field("v".to_string())),
+            Box::new(Expression::Consts(Consts::Vector(vec![1.0, 2.0]))),
+        ));
+        assert_eq!(evaluate(&expr, &columns, &row)?, Value::Float(1.0));
+        assert!(evaluate(&Expression::Field("x".to_string()), &columns, &row).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_arithmetic() -> Result<()> {
+        let (i, f) = (Value::Integer, Value::Float);
+        assert_eq!(arithmetic(ArithmeticOp::Add, &i(2), &i(3))?, i(5));
+        assert_eq!(arithmetic(ArithmeticOp::Subtract, &i(2), &f(0.5))?, f(1.5));
+        assert_eq!(arithmetic(ArithmeticOp::Multiply, &f(1.5), &i(2))?, f(3.0));
+        // 整数除法截断
+        assert_eq!(arithmetic(ArithmeticOp::Divide, &i(7), &i(2))?, i(3));
+        assert_eq!(arithmetic(ArithmeticOp::Divide, &i(7), &f(2.0))?, f(3.5));
+        assert_eq!(arithmetic(ArithmeticOp::Add, &N, &i(1))?, N);
+        assert_eq!(arithmetic(ArithmeticOp::Divide, &N, &i(0))?, N);
+        // 除以零、溢出和非数值都是错误
+        assert!(arithmetic(ArithmeticOp::Divide, &i(1), &i(0)).is_err());
+        assert!(arithmetic(ArithmeticOp::Divide, &f(1.0), &f(0.0)).is_err());
+        assert!(arithmetic(ArithmeticOp::Add, &i(i64::MAX), &i(1)).is_err());
+        assert!(arithmetic(ArithmeticOp::Divide, &i(i64::MIN), &i(-1)).is_err());
+        assert!(arithmetic(ArithmeticOp::Add, &Value::String("a".to_string()), &i(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_call() -> Result<()> {
+        let s = |s: &str| Value::String(s.to_string());
+        let i = Value::Integer;
+        assert_eq!(call(Function::Substr, &[s("héllo"), i(2), i(3)])?, s("éll"));
+        assert_eq!(call(Function::Substr, &[s("hello"), i(3)])?, s("llo"));
+        // 起始位置在 1 之前的字符也算在长度里
+        assert_eq!(call(Function::Substr, &[s("hello"), i(0), i(2)])?, s("h"));
+        assert_eq!(call(Function::Substr, &[s("hello"), i(-5), i(2)])?, s(""));
+        assert_eq!(call(Function::Substr, &[s("hello"), i(9)])?, s(""));
+        assert_eq!(call(Function::Substr, &[s("hello"), N, i(1)])?, N);
+        assert!(call(Function::Substr, &[s("hello"), i(1), i(-1)]).is_err());
+        assert!(call(Function::Substr, &[s("hello")]).is_err());
+        assert!(call(Function::Substr, &[i(1), i(1)]).is_err());
+        assert_eq!(call(Function::Length, &[s("héllo")])?, i(5));
+        assert_eq!(call(Function::Upper, &[s("abc")])?, s("ABC"));
+        assert_eq!(call(Function::Lower, &[s("ABC")])?, s("abc"));
+        assert!(call(Function::Lower, &[s("a"), s("b")]).is_err());
+        assert!(call(Function::Length, &[i(1)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_compare_rows() -> Result<()> {
+        let i = Value::Integer;
+        // 第一个不相等的位置决定大小
+        assert_eq!(compare_rows(CompareOp::Greater, &[i(1), i(3)], &[i(1), i(2)])?, T);
+        assert_eq!(compare_rows(CompareOp::Less, &[i(1), i(3)], &[i(2), i(0)])?, T);
+        assert_eq!(compare_rows(CompareOp::LessOrEqual, &[i(1), i(2)], &[i(1), i(2)])?, T);
+        assert_eq!(compare_rows(CompareOp::Less, &[i(1), i(2)], &[i(1), i(2)])?, F);
+        // NULL：= 有不相等的位置就是 FALSE，< 在决定大小的位置遇到 NULL 才是 NULL
+        assert_eq!(compare_rows(CompareOp::Equal, &[i(1), N], &[i(2), i(1)])?, F);
+        assert_eq!(compare_rows(CompareOp::Equal, &[i(1), N], &[i(1), i(1)])?, N);
+        assert_eq!(compare_rows(CompareOp::NotEqual, &[i(1), N], &[i(2), i(1)])?, T);
+        assert_eq!(compare_rows(CompareOp::Less, &[i(1), N], &[i(2), i(1)])?, T);
+        assert_eq!(compare_rows(CompareOp::Less, &[N, i(1)], &[i(2), i(1)])?, N);
+        assert!(compare_rows(CompareOp::Equal, &[i(1)], &[i(1), i(2)]).is_err());
+
+        let columns = vec!["a".to_string(), "b".to_string()];
+        let row = vec![i(1), i(2)];
+        let ab = Expression::Row(vec![Expression::Field("a".to_string()), Expression::Field("b".to_string())]);
+        let pair = |a, b| Expression::Row(vec![Consts::Integer(a).into(), Consts::Integer(b).into()]);
+        let in_list = |list| Expression::Operation(Operation::In(Box::new(ab.clone()), list));
+        assert_eq!(evaluate(&in_list(vec![pair(3, 4), pair(1, 2)]), &columns, &row)?, T);
+        assert_eq!(evaluate(&in_list(vec![pair(3, 4)]), &columns, &row)?, F);
+        // 行只能和行比较
+        assert!(evaluate(&ab, &columns, &row).is_err());
+        let mixed = Expression::Operation(Operation::Compare(CompareOp::Equal, Box::new(ab.clone()), Box::new(Consts::Integer(1).into())));
+        assert!(evaluate(&mixed, &columns, &row).is_err());
+        Ok(())
+    }
+}
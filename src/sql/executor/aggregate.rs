@@ -0,0 +1,143 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::Transaction,
+        eval::{self, ArithmeticOp, CompareOp},
+        parser::ast::{Aggregate as AggregateExpr, AggregateFunc, Expression},
+        plan::{aggregate_column, Node},
+        types::{row_memory_size, DataType, Value},
+    },
+};
+
+use super::{Executor, ResultSet};
+
+pub struct Aggregate {
+    source: Node,
+    group_by: Vec<Expression>,
+    aggregates: Vec<AggregateExpr>,
+}
+
+impl Aggregate {
+    pub fn new(source: Node, group_by: Vec<Expression>, aggregates: Vec<AggregateExpr>) -> Box<Self> {
+        Box::new(Self { source, group_by, aggregates })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Aggregate {
+    // one pass over the rows, every aggregate skips the rows its filter is not TRUE for
+    // NULL keys are one group, the groups come out in the order of their keys
+    // the keys of every group count against the memory limit
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match <dyn Executor<T>>::build(self.source).execute(txn)? {
+            ResultSet::Scan { columns, row } => {
+                let new_group = || self.aggregates.iter().map(|a| Accumulator::new(a.func)).collect::<Vec<_>>();
+                let mut groups = BTreeMap::new();
+                if self.group_by.is_empty() {
+                    groups.insert(Vec::new(), new_group());
+                }
+                for row in row {
+                    let keys = self
+                        .group_by
+                        .iter()
+                        .map(|expr| eval::evaluate(expr, &columns, &row))
+                        .collect::<Result<Vec<_>>>()?;
+                    let accumulators = match groups.entry(keys) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => {
+                            txn.reserve_memory(row_memory_size(entry.key()))?;
+                            entry.insert(new_group())
+                        }
+                    };
+                    for (aggregate, accumulator) in self.aggregates.iter().zip(accumulators) {
+                        if let Some(filter) = &aggregate.filter {
+                            if !eval::filter(&eval::evaluate(filter, &columns, &row)?)? {
+                                continue;
+                            }
+                        }
+                        let value = match &aggregate.arg {
+                            Some(arg) => eval::evaluate(arg, &columns, &row)?,
+                            // COUNT(*) counts every row
+                            None => Value::Boolean(true),
+                        };
+                        accumulator.add(value)?;
+                    }
+                }
+                let rows = groups
+                    .into_iter()
+                    .map(|(mut keys, accumulators)| {
+                        for accumulator in accumulators {
+                            keys.push(accumulator.finish()?);
+                        }
+                        Ok(keys)
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(ResultSet::Scan {
+                    columns: (0..self.group_by.len() + self.aggregates.len()).map(aggregate_column).collect(),
+                    row: rows,
+                })
+            }
+            _ => Err(Error::Internal("Unexpected result set".to_string())),
+        }
+    }
+}
+
+// NULL values are skipped, an aggregate over no values is NULL, except COUNT which is 0
+enum Accumulator {
+    Count(i64),
+    Sum(Value),
+    Min(Value),
+    Max(Value),
+    Avg(Value, i64),
+}
+
+impl Accumulator {
+    fn new(func: AggregateFunc) -> Self {
+        match func {
+            AggregateFunc::Count => Self::Count(0),
+            AggregateFunc::Sum => Self::Sum(Value::Null),
+            AggregateFunc::Min => Self::Min(Value::Null),
+            AggregateFunc::Max => Self::Max(Value::Null),
+            AggregateFunc::Avg => Self::Avg(Value::Null, 0),
+        }
+    }
+
+    fn add(&mut self, value: Value) -> Result<()> {
+        if value == Value::Null {
+            return Ok(());
+        }
+        match self {
+            Self::Count(count) => *count += 1,
+            Self::Sum(sum) | Self::Avg(sum, _) => {
+                let total = if *sum == Value::Null { &Value::Integer(0) } else { &*sum };
+                *sum = eval::arithmetic(ArithmeticOp::Add, total, &value)?;
+                if let Self::Avg(_, count) = self {
+                    *count += 1;
+                }
+            }
+            Self::Min(min) => {
+                if *min == Value::Null || eval::compare(CompareOp::Less, &value, min)? == Value::Boolean(true) {
+                    *min = value;
+                }
+            }
+            Self::Max(max) => {
+                if *max == Value::Null || eval::compare(CompareOp::Greater, &value, max)? == Value::Boolean(true) {
+                    *max = value;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value> {
+        Ok(match self {
+            Self::Count(count) => Value::Integer(count),
+            Self::Sum(value) | Self::Min(value) | Self::Max(value) => value,
+            Self::Avg(_, 0) => Value::Null,
+            Self::Avg(sum, count) => {
+                eval::arithmetic(ArithmeticOp::Divide, &sum.coerce(&DataType::Float), &Value::Float(count as f64))?
+            }
+        })
+    }
+}
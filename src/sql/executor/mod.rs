@@ -1,14 +1,24 @@
-use mutation::Insert;
-use query::Scan;
-use schema::CreateTable;
+use aggregate::Aggregate;
+use mutation::{Insert, Vacuum};
+use procedure::{Call, CreateProcedure, DropProcedure};
+use query::{CountScan, Explain, Filter, Kill, Limit, Order, Projection, Scan, ShowProcesslist, ShowQueries, ShowStatus, ShowTables};
+use schema::{AddColumn, CreateTable, DropTable};
+use user::{AlterUser, CreateRole, CreateUser, DropRole, Grant, Revoke};
 
-use crate::error::Result;
+use serde::{Deserialize, Serialize};
 
-use super::{engine::Transaction, plan::Node, types::Row};
+use crate::{error::Result, storage::mvcc::MvccStatus};
+
+use super::{engine::{session::SessionStats, Transaction}, plan::Node, types::{Row, Value}};
 
 mod schema;
 mod mutation;
+
+pub use mutation::{make_row, pad_row};
 mod query;
+mod aggregate;
+mod user;
+mod procedure;
 pub trait Executor<T: Transaction> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet>;
 }
@@ -24,22 +34,144 @@ impl<T: Transaction> dyn Executor<T> {
     pub fn build(node: Node) -> Box<dyn Executor<T>> {
         match node {
             Node::CreateTable { schema } => CreateTable::new(schema),
+            Node::DropTable { table_name } => DropTable::new(table_name),
+            Node::AddColumn { table_name, column } => AddColumn::new(table_name, column),
             Node::Insert { table_name, columns, values } => Insert::new(table_name, columns, values),
             Node::Scan { table_name } => Scan::new(table_name),
+            Node::CountScan { table_name } => CountScan::new(table_name),
+            Node::Filter { source, predicate } => Filter::new(*source, predicate),
+            Node::Aggregate { source, group_by, aggregates } => Aggregate::new(*source, group_by, aggregates),
+            Node::Projection { source, exprs } => Projection::new(*source, exprs),
+            Node::Order { source, order_by } => Order::new(*source, order_by),
+            Node::Limit { source, limit } => Limit::new(*source, limit),
+            Node::Vacuum { table_name } => Vacuum::new(table_name),
+            Node::ShowStatus => ShowStatus::new(),
+            Node::ShowProcesslist => ShowProcesslist::new(),
+            Node::ShowQueries => ShowQueries::new(),
+            Node::ShowTables => ShowTables::new(),
+            Node::Kill { id } => Kill::new(id),
+            Node::CreateUser { name, password } => CreateUser::new(name, password),
+            Node::AlterUser { name, password } => AlterUser::new(name, password),
+            Node::CreateRole { name } => CreateRole::new(name),
+            Node::DropRole { name } => DropRole::new(name),
+            Node::Grant { privileges, roles, grantee } => Grant::new(privileges, roles, grantee),
+            Node::Revoke { privileges, roles, grantee } => Revoke::new(privileges, roles, grantee),
+            Node::CreateProcedure { procedure } => CreateProcedure::new(procedure),
+            Node::DropProcedure { name } => DropProcedure::new(name),
+            Node::Call { name, args } => Call::new(name, args),
+            Node::Explain { source } => Explain::new(*source),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum ResultSet {
     CreateTable {
         table_name: String,
     },
+    DropTable {
+        table_name: String,
+    },
+    AlterTable {
+        table_name: String,
+    },
     Insert {
         count: usize,
     },
     Scan {
         columns: Vec<String>,
         row: Vec<Row>,
+    },
+    Vacuum {
+        count: usize,
+    },
+    ShowStatus {
+        status: MvccStatus,
+        sessions: SessionStats,
+    },
+    CreateUser {
+        name: String,
+    },
+    AlterUser {
+        name: String,
+    },
+    CreateRole {
+        name: String,
+    },
+    DropRole {
+        name: String,
+    },
+    Grant {
+        grantee: String,
+    },
+    Revoke {
+        grantee: String,
+    },
+    CreateProcedure {
+        name: String,
+    },
+    DropProcedure {
+        name: String,
+    },
+    Attach {
+        alias: String,
+    },
+    Detach {
+        alias: String,
+    },
+    Declare {
+        name: String,
+    },
+    Close {
+        name: String,
+    },
+    Kill {
+        id: u64,
+    },
+    Set {
+        name: String,
+    },
+}
+
+// what a statement did not fail on but the caller should know, returned next to the results
+// by Session::warnings, unlike an error the statement still runs and commits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Warning {
+    // rows of a result left out by max_result_rows
+    Truncated { max: usize, dropped: usize },
+    // SET of a variable the session does not have, nothing is set
+    UnknownVariable { name: String },
+    // integers written to a float column that do not fit exactly, how many and the first one
+    Rounded { table: String, column: String, count: usize, value: Value },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::Truncated { max, dropped } => {
+                write!(f, "Result truncated to {} rows by max_result_rows, {} more rows were left out", max, dropped)
+            }
+            Warning::UnknownVariable { name } => write!(f, "Unknown variable {} was ignored", name),
+            Warning::Rounded { table, column, count: 1, value } => {
+                write!(f, "Integer {} written to {}.{} was rounded to float", value, table, column)
+            }
+            Warning::Rounded { table, column, count, value } => {
+                write!(f, "{} integers written to {}.{} were rounded to float, the first is {}", count, table, column, value)
+            }
+        }
+    }
+}
+
+impl ResultSet {
+    // keep the first max rows of a scan, returns how many were dropped
+    pub fn truncate(&mut self, max: usize) -> usize {
+        match self {
+            ResultSet::Scan { row, .. } if row.len() > max => {
+                let dropped = row.len() - max;
+                row.truncate(max);
+                dropped
+            }
+            _ => 0,
+        }
     }
 }
\ No newline at end of file
@@ -21,14 +21,13 @@ impl Insert {
 // insert into tbl values(1, 2, 3);
 // a          b           c           d
 // 1          2           3       default value
-fn pad_row(table: &Table, row: &Row) -> Result<Row> {
+pub fn pad_row(table: &Table, row: &Row) -> Result<Row> {
+    if row.len() > table.columns.len() {
+        return Err(Error::ValueCountMismatch { table: table.name.clone(), expected: table.columns.len(), got: row.len() });
+    }
     let mut results = row.clone();
     for column in table.columns.iter().skip(row.len()) {
-        if let Some(default) = &column.default {
-            results.push(default.clone());
-        } else {
-            return Err(Error::Internal(format!("No default value for column {}", column.name)));
-        }
+        results.push(column.missing_value()?);
     }
     Ok(results)
 }
@@ -36,10 +35,18 @@ fn pad_row(table: &Table, row: &Row) -> Result<Row> {
 // insert into tbl(d, c) values(1, 2);
 // a          b           c           d
 // default   default      2           1
-fn make_row(table: &Table, columns: &Vec<String>, values: &Row) -> Result<Row> {
+pub fn make_row(table: &Table, columns: &[String], values: &Row) -> Result<Row> {
     // check if value number equals columns number
-    if columns.len() != columns.len() {
-        return Err(Error::Internal(format!("columns and values number mismatch")));
+    if columns.len() != values.len() {
+        return Err(Error::ValueCountMismatch { table: table.name.clone(), expected: columns.len(), got: values.len() });
+    }
+    for (i, name) in columns.iter().enumerate() {
+        if table.columns.iter().all(|c| &c.name != name) {
+            return Err(Error::ColumnNotFound { table: table.name.clone(), column: name.clone(), position: None });
+        }
+        if columns[..i].contains(name) {
+            return Err(Error::Internal(format!("Column {} is given more than once", name)));
+        }
     }
     // build hash map
     let mut inputs = HashMap::new();
@@ -54,11 +61,8 @@ fn make_row(table: &Table, columns: &Vec<String>, values: &Row) -> Result<Row> {
         // •	避免悬垂引用：借用的引用会在它们原本的作用域结束时失效，而通过 clone() 获得的值可以独立于原始数据的生命周期存在，避免了悬垂引用的问题
         if let Some(value) = inputs.get(&col.name) {
             results.push(value.clone());
-        } else if let Some(default) = &col.default {
-            results.push(default.clone());
         } else {
-            // Err不会转移所有权
-            return Err(Error::Internal(format!("No value given for the column {}", col.name)));
+            results.push(col.missing_value()?);
         }
     }
     Ok(results)
@@ -68,12 +72,12 @@ impl<T:Transaction> Executor<T> for Insert {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         // get information of table
         let table = txn.must_get_table(self.table_name.clone())?;
-        // pub type Row = Vec<Value>; need to convert Expression to Value so we can use create_row func
-        let mut count = 0;
+        // pub type Row = Vec<Value>; need to convert Expression to Value so we can use create_rows func
+        let mut rows = Vec::with_capacity(self.values.len());
         for exprs in self.values {
             let row = exprs.into_iter()
-                                       .map(|e| Value::from_expression(e))
-                                       .collect::<Vec<_>>();
+                                       .map(Value::from_expression)
+                                       .collect::<Result<Vec<_>>>()?;
             let insert_row = if self.columns.is_empty() {
                 // if we don't know which column we need to insert
                 pad_row(&table, &row)?
@@ -81,9 +85,35 @@ impl<T:Transaction> Executor<T> for Insert {
                 // if we know which column we need to insert
                 make_row(&table, &self.columns, &row)?
             };
-            txn.create_row(self.table_name.clone(), insert_row)?;
-            count += 1;
+            rows.push(insert_row);
         }
+        // the table is read once for all rows, they are written in one batch
+        let count = rows.len();
+        txn.create_rows(&table, rows)?;
         Ok(ResultSet::Insert { count })
     }
-}
\ No newline at end of file
+}
+
+pub struct Vacuum {
+    table_name: Option<String>,
+}
+
+impl Vacuum {
+    pub fn new(table_name: Option<String>) -> Box<Self> {
+        Box::new(Self { table_name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Vacuum {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let tables = match self.table_name {
+            Some(name) => vec![name],
+            None => txn.table_names()?,
+        };
+        let mut count = 0;
+        for name in tables {
+            count += txn.delete_expired(name)?;
+        }
+        Ok(ResultSet::Vacuum { count })
+    }
+}
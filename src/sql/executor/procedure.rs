@@ -0,0 +1,81 @@
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::Transaction,
+        executor::ResultSet,
+        parser::ast::Expression,
+        plan::Plan,
+        procedure::Procedure,
+        types::Value,
+    },
+};
+
+use super::Executor;
+
+pub struct CreateProcedure {
+    procedure: Procedure,
+}
+
+impl CreateProcedure {
+    pub fn new(procedure: Procedure) -> Box<Self> {
+        Box::new(Self { procedure })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateProcedure {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = self.procedure.name.clone();
+        if txn.get_procedure(name.clone())?.is_some() {
+            return Err(Error::Internal(format!("Procedure {} already exist", name)));
+        }
+        txn.set_procedure(self.procedure)?;
+        Ok(ResultSet::CreateProcedure { name })
+    }
+}
+
+pub struct DropProcedure {
+    name: String,
+}
+
+impl DropProcedure {
+    pub fn new(name: String) -> Box<Self> {
+        Box::new(Self { name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropProcedure {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if txn.get_procedure(self.name.clone())?.is_none() {
+            return Err(Error::Internal(format!("Procedure {} does not exist", self.name)));
+        }
+        txn.delete_procedure(self.name.clone())?;
+        Ok(ResultSet::DropProcedure { name: self.name })
+    }
+}
+
+pub struct Call {
+    name: String,
+    args: Vec<Expression>,
+}
+
+impl Call {
+    pub fn new(name: String, args: Vec<Expression>) -> Box<Self> {
+        Box::new(Self { name, args })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Call {
+    // the statements run in the transaction of the CALL, so a failing one undoes them all
+    // the result is the result of the last statement
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let procedure = txn
+            .get_procedure(self.name.clone())?
+            .ok_or_else(|| Error::Internal(format!("Procedure {} does not exist", self.name)))?;
+        let args = self.args.into_iter().map(Value::from_expression).collect::<Result<_>>()?;
+        let mut result = None;
+        for stmt in procedure.statements(args)? {
+            result = Some(Plan::build(stmt, txn)?.execute(txn)?);
+        }
+        result.ok_or_else(|| Error::Internal(format!("Procedure {} has no statements", self.name)))
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{error::Result, sql::engine::Transaction};
+use web_time::SystemTime;
+
+use crate::{error::{Error, Result}, sql::{engine::Transaction, eval, parser::ast::{Expression, OrderDirection}, plan::{aggregate_column, Node}, types::{row_memory_size, Value}}};
 
 use super::{Executor, ResultSet};
 
@@ -22,3 +24,289 @@ impl<T: Transaction> Executor<T> for Scan {
         })
     }
 }
+
+pub struct CountScan {
+    table_name: String,
+}
+
+impl CountScan {
+    pub fn new(table_name: String) -> Box<Self> {
+        Box::new(Self { table_name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CountScan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let count = txn.count_table(self.table_name)?;
+        Ok(ResultSet::Scan {
+            columns: vec![aggregate_column(0)],
+            row: vec![vec![Value::Integer(count as i64)]],
+        })
+    }
+}
+
+pub struct Filter {
+    source: Node,
+    predicate: Expression,
+}
+
+impl Filter {
+    pub fn new(source: Node, predicate: Expression) -> Box<Self> {
+        Box::new(Self { source, predicate })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Filter {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match <dyn Executor<T>>::build(self.source).execute(txn)? {
+            ResultSet::Scan { columns, row } => {
+                let mut rows = Vec::new();
+                for row in row {
+                    if eval::filter(&eval::evaluate(&self.predicate, &columns, &row)?)? {
+                        rows.push(row);
+                    }
+                }
+                Ok(ResultSet::Scan { columns, row: rows })
+            }
+            _ => Err(Error::Internal("Unexpected result set".to_string())),
+        }
+    }
+}
+
+pub struct Projection {
+    source: Node,
+    exprs: Vec<(Expression, String)>,
+}
+
+impl Projection {
+    pub fn new(source: Node, exprs: Vec<(Expression, String)>) -> Box<Self> {
+        Box::new(Self { source, exprs })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Projection {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match <dyn Executor<T>>::build(self.source).execute(txn)? {
+            ResultSet::Scan { columns, row } => {
+                let rows = row
+                    .into_iter()
+                    .map(|row| self.exprs.iter().map(|(expr, _)| eval::evaluate(expr, &columns, &row)).collect())
+                    .collect::<Result<_>>()?;
+                Ok(ResultSet::Scan { columns: self.exprs.into_iter().map(|(_, label)| label).collect(), row: rows })
+            }
+            _ => Err(Error::Internal("Unexpected result set".to_string())),
+        }
+    }
+}
+
+pub struct Order {
+    source: Node,
+    order_by: Vec<(Expression, OrderDirection)>,
+}
+
+impl Order {
+    pub fn new(source: Node, order_by: Vec<(Expression, OrderDirection)>) -> Box<Self> {
+        Box::new(Self { source, order_by })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Order {
+    // the sort keys of every row are evaluated once, then the rows are sorted by them
+    // nulls sort last, and first in DESC
+    // the rows are already counted by the source, the keys count against the memory limit
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match <dyn Executor<T>>::build(self.source).execute(txn)? {
+            ResultSet::Scan { columns, row } => {
+                let mut keyed = row
+                    .into_iter()
+                    .map(|row| {
+                        let keys = self
+                            .order_by
+                            .iter()
+                            .map(|(expr, _)| eval::evaluate(expr, &columns, &row))
+                            .collect::<Result<Vec<_>>>()?;
+                        txn.reserve_memory(row_memory_size(&keys))?;
+                        Ok((keys, row))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                keyed.sort_by(|(a, _), (b, _)| {
+                    a.iter()
+                        .zip(b)
+                        .zip(&self.order_by)
+                        .map(|((a, b), (_, direction))| match direction {
+                            OrderDirection::Asc => a.cmp(b),
+                            OrderDirection::Desc => b.cmp(a),
+                        })
+                        .find(|o| o.is_ne())
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+                Ok(ResultSet::Scan { columns, row: keyed.into_iter().map(|(_, row)| row).collect() })
+            }
+            _ => Err(Error::Internal("Unexpected result set".to_string())),
+        }
+    }
+}
+
+pub struct Limit {
+    source: Node,
+    limit: usize,
+}
+
+impl Limit {
+    pub fn new(source: Node, limit: usize) -> Box<Self> {
+        Box::new(Self { source, limit })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Limit {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match <dyn Executor<T>>::build(self.source).execute(txn)? {
+            ResultSet::Scan { columns, mut row } => {
+                row.truncate(self.limit);
+                Ok(ResultSet::Scan { columns, row })
+            }
+            _ => Err(Error::Internal("Unexpected result set".to_string())),
+        }
+    }
+}
+
+pub struct ShowStatus;
+
+impl ShowStatus {
+    pub fn new() -> Box<Self> {
+        Box::new(Self)
+    }
+}
+
+impl<T: Transaction> Executor<T> for ShowStatus {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        Ok(ResultSet::ShowStatus { status: txn.status()?, sessions: txn.session_stats()? })
+    }
+}
+
+pub struct ShowProcesslist;
+
+impl ShowProcesslist {
+    pub fn new() -> Box<Self> {
+        Box::new(Self)
+    }
+}
+
+impl<T: Transaction> Executor<T> for ShowProcesslist {
+    // one row per session, time is the seconds spent in the current state
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let now = SystemTime::now();
+        let row = txn
+            .sessions()?
+            .into_iter()
+            .map(|s| {
+                let time = now.duration_since(s.state_since).unwrap_or_default().as_secs();
+                vec![
+                    Value::Integer(s.id as i64),
+                    Value::String(s.user),
+                    Value::String(if s.query.is_some() { "query" } else { "sleep" }.to_string()),
+                    Value::Integer(time as i64),
+                    s.txn.map_or(Value::Null, |v| Value::Integer(v as i64)),
+                    s.query.map_or(Value::Null, Value::String),
+                ]
+            })
+            .collect();
+        Ok(ResultSet::Scan {
+            columns: ["id", "user", "state", "time", "txn", "query"].map(String::from).to_vec(),
+            row,
+        })
+    }
+}
+
+pub struct ShowQueries;
+
+impl ShowQueries {
+    pub fn new() -> Box<Self> {
+        Box::new(Self)
+    }
+}
+
+impl<T: Transaction> Executor<T> for ShowQueries {
+    // one row per running statement, time is the milliseconds it has run
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let now = SystemTime::now();
+        let row = txn
+            .queries()?
+            .into_iter()
+            .map(|q| {
+                let time = now.duration_since(q.started_at).unwrap_or_default().as_millis();
+                vec![
+                    Value::Integer(q.id as i64),
+                    Value::Integer(q.session as i64),
+                    Value::String(q.user),
+                    Value::Integer(time as i64),
+                    Value::String(q.sql),
+                    Value::String(q.plan),
+                ]
+            })
+            .collect();
+        Ok(ResultSet::Scan {
+            columns: ["id", "session", "user", "time_ms", "query", "plan"].map(String::from).to_vec(),
+            row,
+        })
+    }
+}
+
+pub struct ShowTables;
+
+impl ShowTables {
+    pub fn new() -> Box<Self> {
+        Box::new(Self)
+    }
+}
+
+impl<T: Transaction> Executor<T> for ShowTables {
+    // one row per table, rows is an estimate, see Transaction::estimate_rows
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let row = txn
+            .table_names()?
+            .into_iter()
+            .map(|name| {
+                let rows = txn.estimate_rows(name.clone())?;
+                Ok(vec![Value::String(name), Value::Integer(rows as i64)])
+            })
+            .collect::<Result<_>>()?;
+        Ok(ResultSet::Scan { columns: ["name", "rows"].map(String::from).to_vec(), row })
+    }
+}
+
+pub struct Explain {
+    source: Node,
+}
+
+impl Explain {
+    pub fn new(source: Node) -> Box<Self> {
+        Box::new(Self { source })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Explain {
+    fn execute(self: Box<Self>, _txn: &mut T) -> Result<ResultSet> {
+        Ok(ResultSet::Scan {
+            columns: vec!["plan".to_string()],
+            row: self.source.to_string().lines().map(|line| vec![Value::String(line.to_string())]).collect(),
+        })
+    }
+}
+
+pub struct Kill {
+    id: u64,
+}
+
+impl Kill {
+    pub fn new(id: u64) -> Box<Self> {
+        Box::new(Self { id })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Kill {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.kill_query(self.id)?;
+        Ok(ResultSet::Kill { id: self.id })
+    }
+}
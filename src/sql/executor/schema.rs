@@ -1,4 +1,4 @@
-use crate::{error::Result, sql::{engine::Transaction, executor::ResultSet, schema::Table}};
+use crate::{error::Result, sql::{engine::Transaction, executor::ResultSet, schema::{Column, Table}}};
 
 use super::Executor;
 
@@ -18,4 +18,39 @@ impl<T: Transaction> Executor<T> for CreateTable {
         txn.create_table(self.schema)?; // move
         Ok(ResultSet::CreateTable { table_name })
     }
-}
\ No newline at end of file
+}
+
+pub struct DropTable {
+    table_name: String,
+}
+
+impl DropTable {
+    pub fn new(table_name: String) -> Box<Self> {
+        Box::new(Self { table_name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropTable {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.drop_table(self.table_name.clone())?;
+        Ok(ResultSet::DropTable { table_name: self.table_name })
+    }
+}
+
+pub struct AddColumn {
+    table_name: String,
+    column: Column,
+}
+
+impl AddColumn {
+    pub fn new(table_name: String, column: Column) -> Box<Self> {
+        Box::new(Self { table_name, column })
+    }
+}
+
+impl<T: Transaction> Executor<T> for AddColumn {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.add_column(self.table_name.clone(), self.column)?;
+        Ok(ResultSet::AlterTable { table_name: self.table_name })
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::Transaction,
+        executor::ResultSet,
+        user::{self, inherited_roles, Grants, Role, User, ADMIN_ROLE},
+    },
+};
+
+use super::Executor;
+
+pub struct CreateUser {
+    name: String,
+    password: String,
+}
+
+impl CreateUser {
+    pub fn new(name: String, password: String) -> Box<Self> {
+        Box::new(Self { name, password })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateUser {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if txn.get_user(self.name.clone())?.is_some() {
+            return Err(Error::Internal(format!("User {} already exist.", self.name)));
+        }
+        // users and roles are granted to by name, so they share names
+        if is_role(txn, &self.name)? {
+            return Err(Error::Internal(format!("Role {} already exist.", self.name)));
+        }
+        // anyone is let in until the first user is created, so the first user becomes an admin
+        if !txn.has_users()? {
+            let grants = Grants { roles: [ADMIN_ROLE.to_string()].into(), ..Default::default() };
+            txn.set_grants(self.name.clone(), grants)?;
+        }
+        txn.set_user(User::new(self.name.clone(), &self.password))?;
+        Ok(ResultSet::CreateUser { name: self.name })
+    }
+}
+
+pub struct AlterUser {
+    name: String,
+    password: String,
+}
+
+impl AlterUser {
+    pub fn new(name: String, password: String) -> Box<Self> {
+        Box::new(Self { name, password })
+    }
+}
+
+impl<T: Transaction> Executor<T> for AlterUser {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if txn.get_user(self.name.clone())?.is_none() {
+            return Err(Error::Internal(format!("User {} does not exist", self.name)));
+        }
+        // a new salt comes with the new password
+        txn.set_user(User::new(self.name.clone(), &self.password))?;
+        Ok(ResultSet::AlterUser { name: self.name })
+    }
+}
+
+pub struct CreateRole {
+    name: String,
+}
+
+impl CreateRole {
+    pub fn new(name: String) -> Box<Self> {
+        Box::new(Self { name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateRole {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if is_role(txn, &self.name)? {
+            return Err(Error::Internal(format!("Role {} already exist.", self.name)));
+        }
+        if txn.get_user(self.name.clone())?.is_some() {
+            return Err(Error::Internal(format!("User {} already exist.", self.name)));
+        }
+        txn.set_role(Role { name: self.name.clone() })?;
+        Ok(ResultSet::CreateRole { name: self.name })
+    }
+}
+
+pub struct DropRole {
+    name: String,
+}
+
+impl DropRole {
+    pub fn new(name: String) -> Box<Self> {
+        Box::new(Self { name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropRole {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if self.name == ADMIN_ROLE {
+            return Err(Error::Internal(format!("Role {} is built in", ADMIN_ROLE)));
+        }
+        if txn.get_role(self.name.clone())?.is_none() {
+            return Err(Error::Internal(format!("Role {} does not exist", self.name)));
+        }
+        // the users and roles it was granted to lose it
+        for (grantee, mut grants) in txn.list_grants()? {
+            if grants.roles.remove(&self.name) {
+                txn.set_grants(grantee, grants)?;
+            }
+        }
+        txn.delete_role(self.name.clone())?;
+        Ok(ResultSet::DropRole { name: self.name })
+    }
+}
+
+pub struct Grant {
+    privileges: Vec<user::Grant>,
+    roles: Vec<String>,
+    grantee: String,
+}
+
+impl Grant {
+    pub fn new(privileges: Vec<user::Grant>, roles: Vec<String>, grantee: String) -> Box<Self> {
+        Box::new(Self { privileges, roles, grantee })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Grant {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut grants = grants_of(txn, &self.grantee)?;
+        for role in self.roles {
+            if !is_role(txn, &role)? {
+                return Err(Error::Internal(format!("Role {} does not exist", role)));
+            }
+            // a role inheriting itself would never end
+            if role == self.grantee || inherited_roles(txn, &role)?.contains(&self.grantee) {
+                return Err(Error::Internal(format!("Granting role {} to {} makes a cycle", role, self.grantee)));
+            }
+            grants.roles.insert(role);
+        }
+        grants.privileges.extend(self.privileges);
+        txn.set_grants(self.grantee.clone(), grants)?;
+        Ok(ResultSet::Grant { grantee: self.grantee })
+    }
+}
+
+pub struct Revoke {
+    privileges: Vec<user::Grant>,
+    roles: Vec<String>,
+    grantee: String,
+}
+
+impl Revoke {
+    pub fn new(privileges: Vec<user::Grant>, roles: Vec<String>, grantee: String) -> Box<Self> {
+        Box::new(Self { privileges, roles, grantee })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Revoke {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        // only what was granted exactly is revoked, SELECT ON * stays after REVOKE SELECT ON t
+        let mut grants = grants_of(txn, &self.grantee)?;
+        for role in &self.roles {
+            grants.roles.remove(role);
+        }
+        for privilege in &self.privileges {
+            grants.privileges.remove(privilege);
+        }
+        txn.set_grants(self.grantee.clone(), grants)?;
+        Ok(ResultSet::Revoke { grantee: self.grantee })
+    }
+}
+
+fn is_role<T: Transaction>(txn: &T, name: &str) -> Result<bool> {
+    Ok(name == ADMIN_ROLE || txn.get_role(name.to_string())?.is_some())
+}
+
+// what was granted to an existing user or role, the admin role can not be changed
+fn grants_of<T: Transaction>(txn: &T, grantee: &str) -> Result<Grants> {
+    if grantee == ADMIN_ROLE {
+        return Err(Error::Internal(format!("Role {} already has every privilege", ADMIN_ROLE)));
+    }
+    if txn.get_user(grantee.to_string())?.is_none() && txn.get_role(grantee.to_string())?.is_none() {
+        return Err(Error::Internal(format!("User or role {} does not exist", grantee)));
+    }
+    txn.get_grants(grantee.to_string())
+}
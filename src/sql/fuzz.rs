@@ -0,0 +1,203 @@
+// fuzzing of the parser and the session, run by cargo fuzz from fuzz/ and by the seeded test below
+// only built for tests and with the fuzz feature, which the crate in fuzz/ turns on
+// the first byte of the input picks how the rest becomes sql
+//   raw text, decoded lossily
+//   a stream of tokens of the grammar, one byte picks one token
+//   a valid statement, mutated by dropping, repeating, swapping and replacing its tokens
+// the sql is parsed and run in a session over a small in-memory database, neither may panic,
+// and sql that does not parse must fail with Error::Parse
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{kv::KVEngine, Engine},
+        parser::Parser,
+    },
+    storage::memory::MemoryEngine,
+};
+
+// inputs are cut to this many bytes, longer ones only make each run slower
+pub const MAX_INPUT: usize = 4096;
+
+const SETUP: &[&str] = &[
+    "create table t (a int not null, b text default 'x', c float, d bool, v vector(3));",
+    "insert into t values (1, 'one', 1.5, true, [1, 0, 0]), (2, null, -2.0, false, [0, 1, 0]), (3, 'three', null, null, null);",
+    "create table u (id text, n int) with (ttl = 3600);",
+    "insert into u values ('a', 1), ('b', 2);",
+    "create procedure p (x int, y text) as begin insert into u values (y, x); end;",
+    "create role reader;",
+    "create role writer;",
+    "declare c cursor for select * from t order by a;",
+];
+
+// valid statements over the tables of SETUP, mutated into invalid ones
+pub const SEEDS: &[&str] = &[
+    "select * from t;",
+    "select a, b from t where (a, c) > (1, 2.0) order by c desc, a limit 2;",
+    "select b, count(*), sum(c), min(a), max(a), avg(c) from t group by b;",
+    "select count(*) filter (where d = true) as n from t;",
+    "select a, v <-> [1, 0, 0] as dist from t order by dist limit 1;",
+    "select a + 1, a * 2 - c / 3, lower(b), length(b), substr(b, 1, 2) from t where b != 'x';",
+    "select * from u where id in ('a', 'b');",
+    "explain select b, count(*) from t where a > 1 group by b order by 2 desc limit 1;",
+    "insert into t (a, b) values (4, 'four');",
+    "insert into t values (5, 'five', 5.0, true, [0.5, -0.5, 1000]);",
+    "insert into u values ('c', 3), ('d', null);",
+    "create table w (x int not null, y varchar null, z double not null default 0.0, f boolean);",
+    "create table e (k int) with (ttl = '7 days');",
+    "create table s (k enum('a', 'b'), m string check (m in ('x', 'y')) default 'x');",
+    "alter table t add column e int not null default 0;",
+    "drop table u;",
+    "vacuum;",
+    "vacuum u;",
+    "show status;",
+    "show processlist;",
+    "show queries;",
+    "show tables;",
+    "kill query 1;",
+    "call p(7, 'seven');",
+    "drop procedure p;",
+    "declare c2 cursor for select b, c from t where a > 1;",
+    "fetch 2 from c;",
+    "close c;",
+    "set max_result_rows = 2;",
+    "detach other;",
+    "create user bob password 'secret';",
+    "alter user alice with password 'new';",
+    "grant select, insert on t to reader;",
+    "grant all privileges on * to writer;",
+    "grant reader to writer;",
+    "revoke reader from writer;",
+    "drop role reader;",
+];
+
+const TOKENS: &[&str] = &[
+    "create", "table", "drop", "alter", "add", "column", "select", "from", "where", "insert", "into", "values", "order", "by", "asc",
+    "desc", "limit", "group", "filter", "as", "in", "not", "null", "true", "false", "default",
+    "primary", "key", "with", "ttl", "show", "status", "queries", "tables", "processlist", "kill", "query", "vacuum", "call",
+    "procedure", "begin", "end", "explain", "set", "declare", "cursor", "for", "fetch", "close", "attach", "detach", "database",
+    "user", "role", "password", "grant", "revoke", "all", "privileges", "on", "to", "writer", "int", "integer", "float",
+    "double", "text", "varchar", "string", "bool", "boolean", "vector", "enum", "check", "count", "sum", "min", "max", "avg", "lower", "substr", "length", "t",
+    "u", "w", "p", "c", "a", "b", "d", "v", "x", "id", "n", "reader", "alice", "0", "1", "-1", "3", "2.5", "1e309",
+    "-0.0", "9223372036854775807", "9223372036854775808", "18446744073709551616", "65536", "'a'", "''", "'7 days'",
+    "'x;y'", "'it''s'", "(", ")", "[", "]", ",", ";", ".", "*", "+", "-", "/", "=", "!=", "<>", "<", "<=", ">", ">=",
+    "<->", "'", "\"", "`", "#", "\n",
+];
+
+// one run of the harness, panics on a bug
+pub fn fuzz(data: &[u8]) {
+    let data = &data[..data.len().min(MAX_INPUT)];
+    let Some((mode, rest)) = data.split_first() else {
+        return;
+    };
+    let sql = match mode % 3 {
+        0 => String::from_utf8_lossy(rest).into_owned(),
+        1 => rest.iter().map(|b| TOKENS[*b as usize % TOKENS.len()]).collect::<Vec<_>>().join(" "),
+        _ => mutate(rest),
+    };
+    check(&sql).expect("setup of the fuzz database failed");
+}
+
+// parse and run sql, the errors of both are checked, only a failed setup is returned
+pub fn check(sql: &str) -> Result<()> {
+    let parsed = Parser::new(sql).parse_all();
+    if let Err(err) = &parsed {
+        assert!(matches!(err, Error::Parse(_)), "parse of {:?} failed with {:?}", sql, err);
+    }
+
+    let engine = KVEngine::new(MemoryEngine::new())?;
+    let mut session = engine.session()?;
+    for stmt in SETUP {
+        session.execute(stmt)?;
+    }
+    let result = session.execute(sql);
+    if parsed.is_err() {
+        assert!(result.is_err(), "{:?} does not parse, but it ran", sql);
+    }
+    Ok(())
+}
+
+// a seed picked and mutated by the bytes, two bytes for each mutation
+fn mutate(data: &[u8]) -> String {
+    let Some((seed, ops)) = data.split_first() else {
+        return SEEDS[0].to_string();
+    };
+    let mut tokens = tokenize(SEEDS[*seed as usize % SEEDS.len()]);
+    for op in ops.chunks(2) {
+        let at = *op.get(1).unwrap_or(&0) as usize;
+        let len = tokens.len();
+        let i = at % len.max(1);
+        match (op[0] % 5, tokens.is_empty()) {
+            (_, true) => tokens.push(TOKENS[at % TOKENS.len()].to_string()),
+            (0, _) => {
+                tokens.remove(i);
+            }
+            (1, _) => tokens.insert(i, tokens[i].clone()),
+            (2, _) => tokens.swap(i, (i + 1) % len),
+            (3, _) => tokens[i] = TOKENS[(op[0] / 5) as usize % TOKENS.len()].to_string(),
+            _ => tokens.insert(i, TOKENS[(op[0] / 5) as usize % TOKENS.len()].to_string()),
+        }
+    }
+    tokens.join(" ")
+}
+
+// split at spaces and around punctuation, strings with spaces in them are split too, which is fine here
+fn tokenize(sql: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in sql.split_whitespace() {
+        let mut token = String::new();
+        for ch in word.chars() {
+            if "(),;[]".contains(ch) {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                tokens.push(ch.to_string());
+            } else {
+                token.push(ch);
+            }
+        }
+        if !token.is_empty() {
+            tokens.push(token);
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Result;
+
+    use super::{check, fuzz, mutate, SEEDS};
+
+    // splitmix64, the same seed always gives the same inputs
+    fn inputs(seed: u64, n: usize) -> Vec<Vec<u8>> {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+        (0..n).map(|_| (0..1 + next() % 64).map(|_| next() as u8).collect()).collect()
+    }
+
+    #[test]
+    fn test_fuzz_seeds() -> Result<()> {
+        // 种子语句都能解析，没有变异时原样执行
+        for seed in SEEDS {
+            crate::sql::parser::Parser::new(seed).parse()?;
+            check(seed)?;
+        }
+        assert_eq!(mutate(&[1]), "select a , b from t where ( a , c ) > ( 1 , 2.0 ) order by c desc , a limit 2 ;");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fuzz() {
+        // 随机的字节、记号流和变异的语句都不会 panic
+        for input in inputs(476, 3000) {
+            fuzz(&input);
+        }
+        fuzz(&[]);
+    }
+}
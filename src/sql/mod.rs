@@ -1,6 +1,12 @@
 pub mod parser;
 pub mod types;
+pub mod eval;
+pub mod binder;
 pub mod plan;
 pub mod schema;
 pub mod executor;
-pub mod engine;
\ No newline at end of file
+pub mod engine;
+pub mod user;
+pub mod procedure;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
@@ -1,10 +1,27 @@
-use crate::sql::types::DataType;
+use std::fmt::Display;
+
+use crate::{
+    error::Result,
+    sql::{eval::{ArithmeticOp, CompareOp}, types::{DataType, Value}, user::Grant},
+};
 
 #[derive(Debug, PartialEq)]
 pub enum Statement {
     CreateTable {
         name: String,
         columns: Vec<Column>,
+        // rows expire this many seconds after they are written
+        ttl: Option<u64>,
+        // writes of its rows are recorded in the audit log
+        audit: bool,
+    },
+    DropTable {
+        name: String,
+    },
+    // ALTER TABLE ADD COLUMN
+    AddColumn {
+        table_name: String,
+        column: Column,
     },
     Insert {
         table_name: String,
@@ -12,10 +29,291 @@ pub enum Statement {
         values: Vec<Vec<Expression>>,
     },
     Select {
+        select: Vec<SelectItem>,
         table_name: String,
+        // WHERE, only rows it is TRUE for are kept
+        filter: Option<Expression>,
+        // GROUP BY and ORDER BY may also name a select expression by its position or AS name
+        group_by: Vec<Expression>,
+        order_by: Vec<(Expression, OrderDirection)>,
+        limit: Option<Expression>,
+    },
+    Vacuum {
+        table_name: Option<String>,
+    },
+    ShowStatus,
+    ShowProcesslist,
+    ShowQueries,
+    ShowTables,
+    // stop a running statement, by the id SHOW QUERIES gives it
+    Kill {
+        id: u64,
+    },
+    CreateUser {
+        name: String,
+        password: String,
+    },
+    AlterUser {
+        name: String,
+        password: String,
+    },
+    CreateRole {
+        name: String,
+    },
+    DropRole {
+        name: String,
+    },
+    // either privileges or roles are granted
+    Grant {
+        privileges: Vec<Grant>,
+        roles: Vec<String>,
+        grantee: String,
+    },
+    Revoke {
+        privileges: Vec<Grant>,
+        roles: Vec<String>,
+        grantee: String,
+    },
+    CreateProcedure {
+        name: String,
+        params: Vec<(String, DataType)>,
+        // the statements between BEGIN and END, as sql text
+        body: String,
+    },
+    DropProcedure {
+        name: String,
+    },
+    Call {
+        name: String,
+        args: Vec<Expression>,
+    },
+    Attach {
+        path: String,
+        alias: String,
+    },
+    Detach {
+        alias: String,
+    },
+    // a cursor of the session over the rows of a select
+    Declare {
+        name: String,
+        query: Box<Statement>,
+    },
+    Fetch {
+        name: String,
+        count: u64,
+    },
+    Close {
+        name: String,
+    },
+    // a variable of the session, None for DEFAULT
+    Set {
+        name: String,
+        value: Option<Expression>,
+    },
+    // the plan of the statement, which is not run
+    Explain {
+        stmt: Box<Statement>,
     },
 }
 
+impl Statement {
+    // names of the tables the statement works on, a table of an attached database is alias.table
+    pub fn table_names_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Statement::CreateTable { name, .. } | Statement::DropTable { name } => vec![name],
+            Statement::AddColumn { table_name, .. } => vec![table_name],
+            Statement::Insert { table_name, .. } | Statement::Select { table_name, .. } => vec![table_name],
+            Statement::Vacuum { table_name } => table_name.iter_mut().collect(),
+            Statement::Declare { query, .. } | Statement::Explain { stmt: query } => query.table_names_mut(),
+            _ => vec![],
+        }
+    }
+
+    // the expressions of the statement in the order of the sql, those of a statement in it included
+    // an expression in another is not listed, see Expression::children
+    pub fn expressions(&self) -> Vec<&Expression> {
+        match self {
+            Statement::CreateTable { columns, .. } => columns.iter().filter_map(|c| c.default.as_ref()).collect(),
+            Statement::AddColumn { column, .. } => column.default.iter().collect(),
+            Statement::Insert { values, .. } => values.iter().flatten().collect(),
+            Statement::Select { select, filter, group_by, order_by, limit, .. } => select
+                .iter()
+                .filter_map(|item| match item {
+                    SelectItem::Expr(expr, _) => Some(expr),
+                    SelectItem::Wildcard(_) => None,
+                })
+                .chain(filter)
+                .chain(group_by)
+                .chain(order_by.iter().map(|(expr, _)| expr))
+                .chain(limit)
+                .collect(),
+            Statement::Call { args, .. } => args.iter().collect(),
+            Statement::Set { value, .. } => value.iter().collect(),
+            Statement::Declare { query, .. } | Statement::Explain { stmt: query } => query.expressions(),
+            _ => vec![],
+        }
+    }
+
+    pub fn expressions_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Statement::CreateTable { columns, .. } => columns.iter_mut().filter_map(|c| c.default.as_mut()).collect(),
+            Statement::AddColumn { column, .. } => column.default.iter_mut().collect(),
+            Statement::Insert { values, .. } => values.iter_mut().flatten().collect(),
+            Statement::Select { select, filter, group_by, order_by, limit, .. } => select
+                .iter_mut()
+                .filter_map(|item| match item {
+                    SelectItem::Expr(expr, _) => Some(expr),
+                    SelectItem::Wildcard(_) => None,
+                })
+                .chain(filter)
+                .chain(group_by)
+                .chain(order_by.iter_mut().map(|(expr, _)| expr))
+                .chain(limit)
+                .collect(),
+            Statement::Call { args, .. } => args.iter_mut().collect(),
+            Statement::Set { value, .. } => value.iter_mut().collect(),
+            Statement::Declare { query, .. } | Statement::Explain { stmt: query } => query.expressions_mut(),
+            _ => vec![],
+        }
+    }
+
+    // rewrite every expression of the statement with Expression::transform
+    pub fn transform(&mut self, f: &mut impl FnMut(Expression) -> Result<Expression>) -> Result<()> {
+        for expr in self.expressions_mut() {
+            let taken = std::mem::replace(expr, Expression::Consts(Consts::Null));
+            *expr = taken.transform(f)?;
+        }
+        Ok(())
+    }
+
+    // call f on every expression of the statement and the expressions in them, see Expression::walk
+    pub fn walk(&self, f: &mut impl FnMut(&Expression)) {
+        for expr in self.expressions() {
+            expr.walk(f);
+        }
+    }
+}
+
+// canonical sql text of the statement: keywords in upper case, names as the lexer gives them,
+// one space between tokens, expressions as Expression displays them, optional words left out
+// it parses back to the same statement, so equal statements have the same text
+//   SELECT a, count(*) AS n FROM t WHERE a > 1 GROUP BY a ORDER BY n DESC LIMIT 10
+// the text of a procedure body is kept as it was given
+impl Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn list(items: impl Iterator<Item = String>) -> String {
+            items.collect::<Vec<_>>().join(", ")
+        }
+        let exprs = |exprs: &[Expression]| list(exprs.iter().map(Expression::to_string));
+        let string = |s: &str| Value::String(s.to_string()).to_string();
+        match self {
+            Statement::CreateTable { name, columns, ttl, audit } => {
+                write!(f, "CREATE TABLE {} ({})", name, list(columns.iter().map(Column::to_string)))?;
+                let options = ttl.iter().map(|ttl| format!("ttl = {}", ttl)).chain(audit.then(|| "audit = TRUE".to_string()));
+                let options = list(options);
+                match options.is_empty() {
+                    true => Ok(()),
+                    false => write!(f, " WITH ({})", options),
+                }
+            }
+            Statement::DropTable { name } => write!(f, "DROP TABLE {}", name),
+            Statement::AddColumn { table_name, column } => write!(f, "ALTER TABLE {} ADD COLUMN {}", table_name, column),
+            Statement::Insert { table_name, columns, values } => {
+                write!(f, "INSERT INTO {}", table_name)?;
+                if let Some(columns) = columns {
+                    write!(f, " ({})", columns.join(", "))?;
+                }
+                write!(f, " VALUES {}", list(values.iter().map(|row| format!("({})", exprs(row)))))
+            }
+            Statement::Select { select, table_name, filter, group_by, order_by, limit } => {
+                let items = select.iter().map(|item| match item {
+                    SelectItem::Wildcard(None) => "*".to_string(),
+                    SelectItem::Wildcard(Some(table)) => format!("{}.*", table),
+                    SelectItem::Expr(expr, None) => expr.to_string(),
+                    SelectItem::Expr(expr, Some(alias)) => format!("{} AS {}", expr, alias),
+                });
+                write!(f, "SELECT {} FROM {}", list(items), table_name)?;
+                if let Some(filter) = filter {
+                    write!(f, " WHERE {}", filter)?;
+                }
+                if !group_by.is_empty() {
+                    write!(f, " GROUP BY {}", exprs(group_by))?;
+                }
+                if !order_by.is_empty() {
+                    let items = order_by.iter().map(|(expr, direction)| match direction {
+                        OrderDirection::Asc => expr.to_string(),
+                        OrderDirection::Desc => format!("{} DESC", expr),
+                    });
+                    write!(f, " ORDER BY {}", list(items))?;
+                }
+                match limit {
+                    Some(limit) => write!(f, " LIMIT {}", limit),
+                    None => Ok(()),
+                }
+            }
+            Statement::Vacuum { table_name: Some(table_name) } => write!(f, "VACUUM {}", table_name),
+            Statement::Vacuum { table_name: None } => f.write_str("VACUUM"),
+            Statement::ShowStatus => f.write_str("SHOW STATUS"),
+            Statement::ShowProcesslist => f.write_str("SHOW PROCESSLIST"),
+            Statement::ShowQueries => f.write_str("SHOW QUERIES"),
+            Statement::ShowTables => f.write_str("SHOW TABLES"),
+            Statement::Kill { id } => write!(f, "KILL QUERY {}", id),
+            Statement::CreateUser { name, password } => write!(f, "CREATE USER {} PASSWORD {}", name, string(password)),
+            Statement::AlterUser { name, password } => write!(f, "ALTER USER {} PASSWORD {}", name, string(password)),
+            Statement::CreateRole { name } => write!(f, "CREATE ROLE {}", name),
+            Statement::DropRole { name } => write!(f, "DROP ROLE {}", name),
+            // the parser gives the grants of one statement the same table
+            Statement::Grant { privileges, roles, grantee } | Statement::Revoke { privileges, roles, grantee } => {
+                let revoke = matches!(self, Statement::Revoke { .. });
+                f.write_str(if revoke { "REVOKE " } else { "GRANT " })?;
+                match privileges.first() {
+                    Some(grant) => write!(
+                        f,
+                        "{} ON {}",
+                        list(privileges.iter().map(|g| g.privilege.to_string())),
+                        grant.table.as_deref().unwrap_or("*")
+                    )?,
+                    None => f.write_str(&roles.join(", "))?,
+                }
+                write!(f, " {} {}", if revoke { "FROM" } else { "TO" }, grantee)
+            }
+            Statement::CreateProcedure { name, params, body } => {
+                write!(f, "CREATE PROCEDURE {}", name)?;
+                if !params.is_empty() {
+                    write!(f, "({})", list(params.iter().map(|(p, datatype)| format!("{} {}", p, datatype))))?;
+                }
+                write!(f, " AS BEGIN {} END", body)
+            }
+            Statement::DropProcedure { name } => write!(f, "DROP PROCEDURE {}", name),
+            Statement::Call { name, args } => write!(f, "CALL {}({})", name, exprs(args)),
+            Statement::Attach { path, alias } => write!(f, "ATTACH DATABASE {} AS {}", string(path), alias),
+            Statement::Detach { alias } => write!(f, "DETACH DATABASE {}", alias),
+            Statement::Declare { name, query } => write!(f, "DECLARE {} CURSOR FOR {}", name, query),
+            Statement::Fetch { name, count } => write!(f, "FETCH {} FROM {}", count, name),
+            Statement::Close { name } => write!(f, "CLOSE {}", name),
+            Statement::Set { name, value: Some(value) } => write!(f, "SET {} = {}", name, value),
+            Statement::Set { name, value: None } => write!(f, "SET {} = DEFAULT", name),
+            Statement::Explain { stmt } => write!(f, "EXPLAIN {}", stmt),
+        }
+    }
+}
+
+impl Statement {
+    // the canonical text with the ";" that ends it, see Display above
+    pub fn to_sql(&self) -> String {
+        format!("{};", self)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SelectItem {
+    // * or table.*, expanded to the columns of the table when planned
+    Wildcard(Option<String>),
+    // an expression and its AS name
+    Expr(Expression, Option<String>),
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Column {
     pub name: String,
@@ -24,9 +322,214 @@ pub struct Column {
     pub default: Option<Expression>,
 }
 
-#[derive(Debug, PartialEq)]
+// name TYPE [NULL | NOT NULL] [DEFAULT expr]
+impl Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.datatype)?;
+        match self.nullable {
+            Some(true) => f.write_str(" NULL")?,
+            Some(false) => f.write_str(" NOT NULL")?,
+            None => {}
+        }
+        match &self.default {
+            Some(default) => write!(f, " DEFAULT {}", default),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     Consts(Consts),
+    // a column of the table
+    Field(String),
+    // a row value, (a, b), only compared with another row of the same length
+    Row(Vec<Expression>),
+    Operation(Operation),
+    // a scalar function, func(arg, ...)
+    Function(Function, Vec<Expression>),
+    // only in the select list and ORDER BY, see Planner
+    Aggregate(Aggregate),
+}
+
+impl Expression {
+    // the expressions directly in this one, in the order of the sql, the arguments and filter of aggregates included
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Consts(_) | Expression::Field(_) => vec![],
+            Expression::Row(exprs) | Expression::Function(_, exprs) => exprs.iter().collect(),
+            Expression::Operation(
+                Operation::Distance(l, r) | Operation::Compare(_, l, r) | Operation::Arithmetic(_, l, r),
+            ) => vec![l, r],
+            Expression::Operation(Operation::In(l, list)) => std::iter::once(&**l).chain(list).collect(),
+            Expression::Aggregate(aggregate) => aggregate.arg.iter().chain(&aggregate.filter).map(|e| &**e).collect(),
+        }
+    }
+
+    // whether the expression or one in it, down to the arguments of aggregates, matches
+    pub fn contains(&self, pred: &impl Fn(&Expression) -> bool) -> bool {
+        pred(self) || self.children().into_iter().any(|e| e.contains(pred))
+    }
+
+    // call f on the expression, then on the expressions in it, top down like transform
+    pub fn walk(&self, f: &mut impl FnMut(&Expression)) {
+        f(self);
+        for expr in self.children() {
+            expr.walk(f);
+        }
+    }
+
+    // replace the expression by what f returns, then the expressions in that, top down
+    pub fn transform(self, f: &mut impl FnMut(Expression) -> Result<Expression>) -> Result<Expression> {
+        fn boxed(expr: Expression, f: &mut impl FnMut(Expression) -> Result<Expression>) -> Result<Box<Expression>> {
+            Ok(Box::new(expr.transform(f)?))
+        }
+        Ok(match f(self)? {
+            Expression::Row(exprs) => Expression::Row(exprs.into_iter().map(|e| e.transform(f)).collect::<Result<_>>()?),
+            Expression::Function(func, args) => {
+                Expression::Function(func, args.into_iter().map(|e| e.transform(f)).collect::<Result<_>>()?)
+            }
+            Expression::Operation(op) => Expression::Operation(match op {
+                Operation::Distance(l, r) => Operation::Distance(boxed(*l, f)?, boxed(*r, f)?),
+                Operation::Compare(op, l, r) => Operation::Compare(op, boxed(*l, f)?, boxed(*r, f)?),
+                Operation::Arithmetic(op, l, r) => Operation::Arithmetic(op, boxed(*l, f)?, boxed(*r, f)?),
+                Operation::In(l, list) => {
+                    Operation::In(boxed(*l, f)?, list.into_iter().map(|e| e.transform(f)).collect::<Result<_>>()?)
+                }
+            }),
+            Expression::Aggregate(Aggregate { func, arg, filter }) => Expression::Aggregate(Aggregate {
+                func,
+                arg: arg.map(|e| boxed(*e, f)).transpose()?,
+                filter: filter.map(|e| boxed(*e, f)).transpose()?,
+            }),
+            expr => expr,
+        })
+    }
+}
+
+// sql text of the expression, an operand that is an operation itself is in parentheses
+//   (a + 1) * 2 > b, count(*) FILTER (WHERE a = 1)
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operand = |expr: &Expression| match expr {
+            Expression::Operation(_) => format!("({})", expr),
+            expr => expr.to_string(),
+        };
+        let list = |exprs: &[Expression]| exprs.iter().map(Expression::to_string).collect::<Vec<_>>().join(", ");
+        match self {
+            Expression::Consts(consts) => write!(f, "{}", Value::from(consts.clone())),
+            Expression::Field(name) => f.write_str(name),
+            Expression::Row(exprs) => write!(f, "({})", list(exprs)),
+            Expression::Operation(Operation::Distance(l, r)) => write!(f, "{} <-> {}", operand(l), operand(r)),
+            Expression::Operation(Operation::Compare(op, l, r)) => write!(f, "{} {} {}", operand(l), op, operand(r)),
+            Expression::Operation(Operation::Arithmetic(op, l, r)) => write!(f, "{} {} {}", operand(l), op, operand(r)),
+            Expression::Operation(Operation::In(l, exprs)) => write!(f, "{} IN ({})", operand(l), list(exprs)),
+            Expression::Function(func, args) => write!(f, "{}({})", func, list(args)),
+            Expression::Aggregate(Aggregate { func, arg, filter }) => {
+                match arg {
+                    Some(arg) => write!(f, "{}({})", func, arg)?,
+                    None => write!(f, "{}(*)", func)?,
+                }
+                match filter {
+                    Some(filter) => write!(f, " FILTER (WHERE {})", filter),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    // euclidean distance of two vectors, a <-> b
+    Distance(Box<Expression>, Box<Expression>),
+    // a = b, a < b, ..., of two values or two rows
+    Compare(CompareOp, Box<Expression>, Box<Expression>),
+    // a IN (b, c) is a = b OR a = c
+    In(Box<Expression>, Vec<Expression>),
+    // a + b, a - b, a * b, a / b
+    Arithmetic(ArithmeticOp, Box<Expression>, Box<Expression>),
+}
+
+// func(arg) [FILTER (WHERE filter)], over the rows the filter is TRUE for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub func: AggregateFunc,
+    // None for COUNT(*)
+    pub arg: Option<Box<Expression>>,
+    pub filter: Option<Box<Expression>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    // substr(s, start [, length]), start counts characters from 1
+    Substr,
+    Length,
+    Upper,
+    Lower,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "substr" | "substring" => Self::Substr,
+            "length" => Self::Length,
+            "upper" => Self::Upper,
+            "lower" => Self::Lower,
+            _ => return None,
+        })
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Substr => "substr",
+            Self::Length => "length",
+            Self::Upper => "upper",
+            Self::Lower => "lower",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunc {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl AggregateFunc {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "count" => Self::Count,
+            "sum" => Self::Sum,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "avg" => Self::Avg,
+            _ => return None,
+        })
+    }
+}
+
+impl Display for AggregateFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Count => "count",
+            Self::Sum => "sum",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Avg => "avg",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderDirection {
+    Asc,
+    Desc,
 }
 
 // Use const.into() converse Consts to Expression
@@ -36,11 +539,12 @@ impl From<Consts> for Expression {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Consts {
     Null,
     Boolean(bool),
     Integer(i64),
     Float(f64),
     String(String),
+    Vector(Vec<f32>),
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
 use std::{fmt::Display, iter::Peekable, str::Chars};
 use crate::error::{Error, Result};
 
+use super::Position;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Keyword {
     Create,
@@ -26,6 +28,17 @@ pub enum Keyword {
     Null,
     Primary,
     Key,
+    Show,
+    With,
+    Alter,
+    Drop,
+    Vector,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Limit,
+    Where,
 }
 
 impl Keyword {
@@ -54,6 +67,17 @@ impl Keyword {
             "NULL" => Keyword::Null,
             "PRIMARY" => Keyword::Primary,
             "KEY" => Keyword::Key,
+            "SHOW" => Keyword::Show,
+            "WITH" => Keyword::With,
+            "ALTER" => Keyword::Alter,
+            "DROP" => Keyword::Drop,
+            "VECTOR" => Keyword::Vector,
+            "ORDER" => Keyword::Order,
+            "BY" => Keyword::By,
+            "ASC" => Keyword::Asc,
+            "DESC" => Keyword::Desc,
+            "LIMIT" => Keyword::Limit,
+            "WHERE" => Keyword::Where,
             _ => return None,
         })
     }
@@ -82,7 +106,18 @@ impl Keyword {
             Keyword::Null => "NULL",
             Keyword::Primary => "PRIMARY",
             Keyword::Key => "KEY",
+            Keyword::Show => "SHOW",
+            Keyword::With => "WITH",
+            Keyword::Alter => "ALTER",
+            Keyword::Drop => "DROP",
             Keyword::Bool => "Bool",
+            Keyword::Vector => "VECTOR",
+            Keyword::Order => "ORDER",
+            Keyword::By => "BY",
+            Keyword::Asc => "ASC",
+            Keyword::Desc => "DESC",
+            Keyword::Limit => "LIMIT",
+            Keyword::Where => "WHERE",
         }
     }
 }
@@ -107,6 +142,16 @@ pub enum Token {
     Plus,               //  +
     Minus,              //  -
     Slash,              //  /
+    Equal,              //  =
+    OpenBracket,        //  [
+    CloseBracket,       //  ]
+    Distance,           //  <->
+    NotEqual,           //  != or <>
+    Less,               //  <
+    LessOrEqual,        //  <=
+    Greater,            //  >
+    GreaterOrEqual,     //  >=
+    Period,             //  .
 }
 
 impl Display for Token {
@@ -124,6 +169,16 @@ impl Display for Token {
             Token::Plus => "+",
             Token::Minus => "-",
             Token::Slash => "/",
+            Token::Equal => "=",
+            Token::OpenBracket => "[",
+            Token::CloseBracket => "]",
+            Token::Distance => "<->",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessOrEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterOrEqual => ">=",
+            Token::Period => ".",
         })
     }
 }
@@ -181,7 +236,8 @@ impl<'a> Lexer<'a> {
         match self.iter.peek() {
             Some('\'') => self.scan_string(),
             Some(c) if c.is_ascii_digit() => Ok(self.scan_number()),
-            Some(c) if c.is_alphabetic() => Ok(self.scan_ident()),
+            Some(c) if c.is_alphabetic() || *c == '_' => Ok(self.scan_ident()),
+            Some('<' | '>' | '!') => self.scan_operator(),
             Some(_) => Ok(self.scan_symbol()),
             None => Ok(None),
         }
@@ -195,9 +251,11 @@ impl<'a> Lexer<'a> {
         let mut val = String::new();
         loop {
             match self.iter.next() {
+                // '' is a quote in the string, 'it''s'
+                Some('\'') if self.next_if(|c| c == '\'').is_some() => val.push('\''),
                 Some('\'') => break,
                 Some(c) => val.push(c),
-                None => return Err(Error::Parse(format!("[Lexer] Unexpected end of String"))),
+                None => return Err(Error::Parse("[Lexer] Unexpected end of String".to_string())),
             }
         }
         Ok(Some(Token::String(val)))
@@ -218,7 +276,7 @@ impl<'a> Lexer<'a> {
 
     // scan table/column name, true/false, Keyword
     fn scan_ident(&mut self) -> Option<Token> {
-        let mut value = self.next_if(|c| c.is_alphabetic())?.to_string();
+        let mut value = self.next_if(|c| c.is_alphabetic() || c == '_')?.to_string();
         while let Some(c) = self.next_if(|c| c.is_alphanumeric() || c == '_') {
             value.push(c);
         }
@@ -228,6 +286,27 @@ impl<'a> Lexer<'a> {
         ))
     }
 
+    // <->, the only operator of more than one character
+    // operators starting with <, > or !
+    // <- is only taken as <-> if > comes next, so a<-1 is a less than -1
+    fn scan_operator(&mut self) -> Result<Option<Token>> {
+        let first = self.iter.next().unwrap_or_default();
+        let mut ahead = self.iter.clone();
+        let (token, rest) = match (first, ahead.next(), ahead.next()) {
+            ('<', Some('-'), Some('>')) => (Token::Distance, 2),
+            ('<', Some('='), _) => (Token::LessOrEqual, 1),
+            ('<', Some('>'), _) | ('!', Some('='), _) => (Token::NotEqual, 1),
+            ('>', Some('='), _) => (Token::GreaterOrEqual, 1),
+            ('<', _, _) => (Token::Less, 0),
+            ('>', _, _) => (Token::Greater, 0),
+            (c, _, _) => return Err(Error::Parse(format!("[Lexer] Unexpected operator {}", c))),
+        };
+        for _ in 0..rest {
+            self.iter.next();
+        }
+        Ok(Some(token))
+    }
+
     fn scan_symbol(&mut self) -> Option<Token> {
         self.next_if_token(|c| match c {
             '*' => Some(Token::Asterisk),
@@ -238,16 +317,41 @@ impl<'a> Lexer<'a> {
             '+' => Some(Token::Plus),
             '-' => Some(Token::Minus),
             '/' => Some(Token::Slash),
+            '=' => Some(Token::Equal),
+            '[' => Some(Token::OpenBracket),
+            ']' => Some(Token::CloseBracket),
+            '.' => Some(Token::Period),
             _ => None,
         })
     }
 
 }
 
+// where an identifier first appears in the sql, to point at it in an error
+// the sql is lexed again, tokens do not keep their positions
+pub fn locate(sql: &str, ident: &str) -> Option<Position> {
+    let mut lexer = Lexer::new(sql);
+    loop {
+        lexer.erase_whitespace();
+        let offset = sql.chars().count() - lexer.iter.clone().count();
+        match Lexer::scan(&mut lexer) {
+            Ok(Some(Token::Ident(name))) if name == ident => {
+                let before = sql.chars().take(offset).collect::<String>();
+                return Some(Position {
+                    line: before.matches('\n').count() + 1,
+                    column: before.chars().rev().take_while(|c| *c != '\n').count() + 1,
+                });
+            }
+            Ok(Some(_)) => {}
+            _ => return None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{error::Result, sql::parser::lexer::{Keyword, Token}};
-    use super::Lexer;
+    use crate::{error::Result, sql::parser::{lexer::{Keyword, Token}, Position}};
+    use super::{locate, Lexer};
 
     #[test]
     fn test_lexer_create_table() -> Result<()> {
@@ -301,7 +405,7 @@ mod tests {
         let tokens2: Vec<Token> = Lexer::new(sql_text)
             .peekable()
             .collect::<Result<Vec<_>>>()?;
-        assert!(tokens2.len() > 0);
+        assert!(!tokens2.is_empty());
         // println!("{:?}", tokens2);
         Ok(())
     }
@@ -390,4 +494,57 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_lexer_distance() -> Result<()> {
+        let tokens = Lexer::new("order by v<->[1, -0.5]").collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Keyword(Keyword::Order),
+                Token::Keyword(Keyword::By),
+                Token::Ident("v".to_string()),
+                Token::Distance,
+                Token::OpenBracket,
+                Token::Number("1".to_string()),
+                Token::Comma,
+                Token::Minus,
+                Token::Number("0.5".to_string()),
+                Token::CloseBracket,
+            ]
+        );
+        // 比较运算符，<- 后面不是 > 时是小于一个负数
+        let tokens = Lexer::new("a<b <= c<>d != e>f >= g<-1").collect::<Result<Vec<_>>>()?;
+        let ops = tokens.into_iter().filter(|t| !matches!(t, Token::Ident(_) | Token::Number(_))).collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            vec![
+                Token::Less,
+                Token::LessOrEqual,
+                Token::NotEqual,
+                Token::NotEqual,
+                Token::Greater,
+                Token::GreaterOrEqual,
+                Token::Less,
+                Token::Minus,
+            ]
+        );
+        assert!(Lexer::new("a ! b").collect::<Result<Vec<_>>>().is_err());
+        // 字符串里的 '' 是一个引号
+        assert_eq!(
+            Lexer::new("'it''s' ''").collect::<Result<Vec<_>>>()?,
+            vec![Token::String("it's".to_string()), Token::String("".to_string())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexer_locate() {
+        let sql = "select a,\n  'b', b from t;";
+        assert_eq!(locate(sql, "a"), Some(Position { line: 1, column: 8 }));
+        // 字符串里的不算
+        assert_eq!(locate(sql, "b"), Some(Position { line: 2, column: 8 }));
+        assert_eq!(locate(sql, "c"), None);
+        assert_eq!(locate("select 'x", "x"), None);
+    }
+}
@@ -1,24 +1,52 @@
-use std::iter::Peekable;
+use std::{collections::HashMap, fmt::Display, iter::Peekable};
+
+use serde::{Deserialize, Serialize};
 
 use ast::Column;
 use lexer::{Keyword, Lexer, Token};
 
 use crate::error::{Error, Result};
 
-use super::types::DataType;
+use super::{
+    eval::{ArithmeticOp, CompareOp},
+    types::{DataType, MAX_VECTOR_DIMENSIONS},
+    user::{Grant, Privilege},
+};
 
 mod lexer;
 
 pub mod ast;
 
+pub use lexer::locate;
+
+// where a token starts in the sql, both count from 1
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
 pub struct Parser<'a> {
     lexer: Peekable<Lexer<'a>>,
+    // procedure parameters, an identifier where a value is expected is replaced by its value
+    params: HashMap<String, ast::Consts>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(input: &'a str) -> Self {
+        Self::with_params(input, HashMap::new())
+    }
+
+    pub fn with_params(input: &'a str, params: HashMap<String, ast::Consts>) -> Self {
         Parser {
             lexer: Lexer::new(input).peekable(),
+            params,
         }
     }
     // parse and get ast tree
@@ -33,14 +61,34 @@ impl<'a> Parser<'a> {
         Ok(stmt)
     }
 
+    // parse a script of statements, each one ended by ";"
+    pub fn parse_all(&mut self) -> Result<Vec<ast::Statement>> {
+        let mut stmts = Vec::new();
+        while self.peek()?.is_some() {
+            stmts.push(self.parse_statement()?);
+            self.next_expect(Token::Semicolon)?;
+        }
+        Ok(stmts)
+    }
+
     fn parse_statement(&mut self) -> Result<ast::Statement> {
         // check first token
         match self.peek()? {
-            Some(Token::Keyword(Keyword::Create)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Create)) | Some(Token::Keyword(Keyword::Alter))
+            | Some(Token::Keyword(Keyword::Drop)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Select)) => self.parse_select(),
             Some(Token::Keyword(Keyword::Insert)) => self.parse_insert(),
+            Some(Token::Keyword(Keyword::Show)) => self.parse_show(),
+            Some(Token::Ident(ident)) if ident == "vacuum" => self.parse_vacuum(),
+            Some(Token::Ident(ident)) if ident == "call" => self.parse_call(),
+            Some(Token::Ident(ident)) if ident == "kill" => self.parse_kill(),
+            Some(Token::Ident(ident)) if ident == "explain" => self.parse_explain(),
+            Some(Token::Ident(ident)) if ident == "attach" || ident == "detach" => self.parse_attach(),
+            Some(Token::Ident(ident)) if ident == "grant" || ident == "revoke" => self.parse_grant(),
+            Some(Token::Ident(ident)) if ident == "declare" || ident == "fetch" || ident == "close" => self.parse_cursor(),
+            Some(Token::Ident(ident)) if ident == "set" => self.parse_set(),
             Some(t) => Err(Error::Parse(format!("[Parser] Unexpected token {}", t))),
-            None => Err(Error::Parse(format!("[Parser] Unexpected end of input"))),
+            None => Err(Error::Parse("[Parser] Unexpected end of input".to_string())),
         }
     }
 
@@ -55,7 +103,7 @@ impl<'a> Parser<'a> {
         self.next_expect(Token::Keyword(Keyword::Insert))?;
         self.next_expect(Token::Keyword(Keyword::Into))?;
         // check table name
-        let table_name = self.next_indent()?;
+        let table_name = self.parse_table_name()?;
         // check "(" so we know if we have column name here, and get column info
         let columns = if self.next_if_token(Token::OpenParen).is_some() {
             let mut cols = Vec::new();
@@ -95,14 +143,283 @@ impl<'a> Parser<'a> {
         Ok(ast::Statement::Insert { table_name, columns, values})
     }
 
+    // SELECT * | table.* | expr [AS name], ... FROM table_name
+    // [WHERE expr]
+    // [GROUP BY expr, ...]
+    // [ORDER BY expr [ASC | DESC], ...]
+    // [LIMIT n]
     fn parse_select(&mut self) -> Result<ast::Statement> {
-        // check 'select * from'
         self.next_expect(Token::Keyword(Keyword::Select))?;
-        self.next_expect(Token::Asterisk)?;
+        let mut select = Vec::new();
+        loop {
+            if self.next_if_token(Token::Asterisk).is_some() {
+                select.push(ast::SelectItem::Wildcard(None));
+            } else {
+                let expr = self.parse_expression()?;
+                match expr {
+                    // table.*, the table name was taken as a column
+                    ast::Expression::Field(table) if self.next_if_token(Token::Period).is_some() => {
+                        self.next_expect(Token::Asterisk)?;
+                        select.push(ast::SelectItem::Wildcard(Some(table)));
+                    }
+                    expr => {
+                        // as is not a keyword
+                        let alias = match self.next_if(|t| matches!(t, Token::Ident(ident) if ident == "as")) {
+                            Some(_) => Some(self.next_indent()?),
+                            None => None,
+                        };
+                        select.push(ast::SelectItem::Expr(expr, alias));
+                    }
+                }
+            }
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
         self.next_expect(Token::Keyword(Keyword::From))?;
         // check table name
-        let table_name = self.next_indent()?;
-        Ok(ast::Statement::Select { table_name })
+        let table_name = self.parse_table_name()?;
+        let filter = match self.next_if_token(Token::Keyword(Keyword::Where)) {
+            Some(_) => Some(self.parse_expression()?),
+            None => None,
+        };
+        // group is not a keyword
+        let mut group_by = Vec::new();
+        if self.next_if(|t| matches!(t, Token::Ident(ident) if ident == "group")).is_some() {
+            self.next_expect(Token::Keyword(Keyword::By))?;
+            loop {
+                group_by.push(self.parse_expression()?);
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+        }
+        let mut order_by = Vec::new();
+        if self.next_if_token(Token::Keyword(Keyword::Order)).is_some() {
+            self.next_expect(Token::Keyword(Keyword::By))?;
+            loop {
+                let expr = self.parse_expression()?;
+                let direction = match self.next_if(|t| matches!(t, Token::Keyword(Keyword::Asc | Keyword::Desc))) {
+                    Some(Token::Keyword(Keyword::Desc)) => ast::OrderDirection::Desc,
+                    _ => ast::OrderDirection::Asc,
+                };
+                order_by.push((expr, direction));
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+        }
+        let limit = match self.next_if_token(Token::Keyword(Keyword::Limit)) {
+            Some(_) => Some(self.parse_expression()?),
+            None => None,
+        };
+        Ok(ast::Statement::Select { select, table_name, filter, group_by, order_by, limit })
+    }
+
+    // VACUUM [table_name], deletes expired rows of one or all tables with ttl
+    // vacuum is not a keyword, so it can still be used as a table name
+    fn parse_vacuum(&mut self) -> Result<ast::Statement> {
+        self.next_indent()?;
+        let table_name = match self.peek()? {
+            Some(Token::Ident(_)) => Some(self.parse_table_name()?),
+            _ => None,
+        };
+        Ok(ast::Statement::Vacuum { table_name })
+    }
+
+    // KILL [QUERY] id
+    // kill and query are not keywords
+    fn parse_kill(&mut self) -> Result<ast::Statement> {
+        self.next_indent()?;
+        self.next_if(|t| matches!(t, Token::Ident(ident) if ident == "query"));
+        match self.next()? {
+            Token::Number(n) => match n.parse::<u64>() {
+                Ok(id) => Ok(ast::Statement::Kill { id }),
+                Err(_) => Err(Error::Parse(format!("[Parser] Invalid query id {}", n))),
+            },
+            token => Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+        }
+    }
+
+    // EXPLAIN statement
+    // explain is not a keyword, statements of the session have no plan to explain
+    fn parse_explain(&mut self) -> Result<ast::Statement> {
+        self.next_indent()?;
+        match self.parse_statement()? {
+            ast::Statement::Explain { .. }
+            | ast::Statement::Attach { .. }
+            | ast::Statement::Detach { .. }
+            | ast::Statement::Declare { .. }
+            | ast::Statement::Fetch { .. }
+            | ast::Statement::Close { .. }
+            | ast::Statement::Set { .. } => Err(Error::Parse("[Parser] The statement can not be explained".to_string())),
+            stmt => Ok(ast::Statement::Explain { stmt: Box::new(stmt) }),
+        }
+    }
+
+    // ATTACH [DATABASE] 'path' AS alias
+    // DETACH [DATABASE] alias
+    fn parse_attach(&mut self) -> Result<ast::Statement> {
+        let attach = self.next_indent()? == "attach";
+        self.next_if(|t| matches!(t, Token::Ident(ident) if ident == "database"));
+        if !attach {
+            return Ok(ast::Statement::Detach { alias: self.next_indent()? });
+        }
+        let path = match self.next()? {
+            Token::String(path) => path,
+            token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+        };
+        match self.next_indent()?.as_str() {
+            "as" => {}
+            ident => return Err(Error::Parse(format!("[Parser] Expect as, got {}", ident))),
+        }
+        Ok(ast::Statement::Attach { path, alias: self.next_indent()? })
+    }
+
+    // SET name = value, SET name = DEFAULT
+    // SET NAMES charset [COLLATE collation], sent by mysql drivers on connect, sets the variable names
+    // set, names and collate are not keywords
+    fn parse_set(&mut self) -> Result<ast::Statement> {
+        self.next_indent()?;
+        let name = self.next_indent()?;
+        if name == "names" && self.peek()? != Some(Token::Equal) {
+            let mut charset = || match self.next()? {
+                Token::Ident(s) | Token::String(s) => Ok(s),
+                token => Err(Error::Parse(format!("[Parser] Expect character set, got token {}", token))),
+            };
+            let value = charset()?;
+            if self.next_if(|t| matches!(t, Token::Ident(ident) if ident == "collate")).is_some() {
+                self.next()?;
+            }
+            return Ok(ast::Statement::Set { name, value: Some(ast::Expression::Consts(ast::Consts::String(value))) });
+        }
+        self.next_expect(Token::Equal)?;
+        let value = match self.next_if_token(Token::Keyword(Keyword::Default)) {
+            Some(_) => None,
+            None => Some(self.parse_expression()?),
+        };
+        Ok(ast::Statement::Set { name, value })
+    }
+
+    // DECLARE name CURSOR FOR SELECT ...
+    // FETCH [count] [FROM] name, one row if count is not given
+    // CLOSE name
+    // declare, cursor, for, fetch and close are not keywords
+    fn parse_cursor(&mut self) -> Result<ast::Statement> {
+        match self.next_indent()?.as_str() {
+            "fetch" => {
+                let count = match self.next_if(|t| matches!(t, Token::Number(_))) {
+                    Some(Token::Number(n)) => n.parse::<u64>().map_err(|_| Error::Parse(format!("[Parser] Invalid fetch count {}", n)))?,
+                    _ => 1,
+                };
+                self.next_if_token(Token::Keyword(Keyword::From));
+                Ok(ast::Statement::Fetch { name: self.next_indent()?, count })
+            }
+            "close" => Ok(ast::Statement::Close { name: self.next_indent()? }),
+            _ => {
+                let name = self.next_indent()?;
+                for word in ["cursor", "for"] {
+                    match self.next_indent()?.as_str() {
+                        w if w == word => {}
+                        ident => return Err(Error::Parse(format!("[Parser] Expect {}, got {}", word, ident))),
+                    }
+                }
+                match self.peek()? {
+                    Some(Token::Keyword(Keyword::Select)) => {}
+                    _ => return Err(Error::Parse(format!("[Parser] Cursor {} must be declared for a select", name))),
+                }
+                Ok(ast::Statement::Declare { name, query: Box::new(self.parse_select()?) })
+            }
+        }
+    }
+
+    // GRANT privilege, ... ON table|* TO name
+    // GRANT role, ... TO name
+    // REVOKE privilege, ... ON table|* FROM name
+    // REVOKE role, ... FROM name
+    // a privilege is SELECT, INSERT, CREATE, DROP, or ALL [PRIVILEGES] for all of them
+    // grant, revoke, on, to and all are not keywords, so they can still be used as column names
+    fn parse_grant(&mut self) -> Result<ast::Statement> {
+        let revoke = self.next_indent()? == "revoke";
+        let mut privileges = Vec::new();
+        let mut roles = Vec::new();
+        loop {
+            match self.next()? {
+                Token::Keyword(Keyword::Select) => privileges.push(Privilege::Select),
+                Token::Keyword(Keyword::Insert) => privileges.push(Privilege::Insert),
+                Token::Keyword(Keyword::Create) => privileges.push(Privilege::Create),
+                Token::Keyword(Keyword::Drop) => privileges.push(Privilege::Drop),
+                Token::Ident(ident) if ident == "all" => {
+                    self.next_if(|t| matches!(t, Token::Ident(ident) if ident == "privileges"));
+                    privileges.extend(Privilege::ALL);
+                }
+                Token::Ident(role) => roles.push(role),
+                token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+            }
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        if !privileges.is_empty() && !roles.is_empty() {
+            return Err(Error::Parse("[Parser] Privileges and roles can not be granted together".to_string()));
+        }
+        let mut grants = Vec::new();
+        if !privileges.is_empty() {
+            match self.next_indent()?.as_str() {
+                "on" => {}
+                ident => return Err(Error::Parse(format!("[Parser] Expect on, got {}", ident))),
+            }
+            let table = match self.next_if_token(Token::Asterisk) {
+                Some(_) => None,
+                None => Some(self.next_indent()?),
+            };
+            grants = privileges.into_iter().map(|privilege| Grant { privilege, table: table.clone() }).collect();
+        }
+        if revoke {
+            self.next_expect(Token::Keyword(Keyword::From))?;
+        } else {
+            match self.next_indent()?.as_str() {
+                "to" => {}
+                ident => return Err(Error::Parse(format!("[Parser] Expect to, got {}", ident))),
+            }
+        }
+        let grantee = self.next_indent()?;
+        Ok(match revoke {
+            true => ast::Statement::Revoke { privileges: grants, roles, grantee },
+            false => ast::Statement::Grant { privileges: grants, roles, grantee },
+        })
+    }
+
+    // CALL name(arg, ...)
+    fn parse_call(&mut self) -> Result<ast::Statement> {
+        self.next_indent()?;
+        let name = self.next_indent()?;
+        self.next_expect(Token::OpenParen)?;
+        let mut args = Vec::new();
+        if self.next_if_token(Token::CloseParen).is_none() {
+            loop {
+                args.push(self.parse_expression()?);
+                match self.next()? {
+                    Token::CloseParen => break,
+                    Token::Comma => {}
+                    token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+                }
+            }
+        }
+        Ok(ast::Statement::Call { name, args })
+    }
+
+    // SHOW STATUS, SHOW PROCESSLIST, SHOW QUERIES
+    // status, processlist and queries are not keywords, so it can still be used as a column name
+    fn parse_show(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Show))?;
+        match self.next_indent()?.as_str() {
+            "status" => Ok(ast::Statement::ShowStatus),
+            "processlist" => Ok(ast::Statement::ShowProcesslist),
+            "queries" => Ok(ast::Statement::ShowQueries),
+            "tables" => Ok(ast::Statement::ShowTables),
+            name => Err(Error::Parse(format!("[Parser] Unexpected show target {}", name))),
+        }
     }
 
     // parse ddl type，create xxx, drop xxx
@@ -111,19 +428,113 @@ impl<'a> Parser<'a> {
         match self.next()? {
             Token::Keyword(Keyword::Create) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(),
+                Token::Ident(ident) if ident == "user" => {
+                    let (name, password) = self.parse_ddl_user()?;
+                    Ok(ast::Statement::CreateUser { name, password })
+                }
+                Token::Ident(ident) if ident == "procedure" => self.parse_ddl_create_procedure(),
+                Token::Ident(ident) if ident == "role" => Ok(ast::Statement::CreateRole { name: self.next_indent()? }),
+                token => Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+            },
+            Token::Keyword(Keyword::Alter) => match self.next()? {
+                Token::Keyword(Keyword::Table) => self.parse_ddl_alter_table(),
+                Token::Ident(ident) if ident == "user" => {
+                    let (name, password) = self.parse_ddl_user()?;
+                    Ok(ast::Statement::AlterUser { name, password })
+                }
+                token => Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+            },
+            Token::Keyword(Keyword::Drop) => match self.next()? {
+                Token::Keyword(Keyword::Table) => Ok(ast::Statement::DropTable { name: self.parse_table_name()? }),
+                Token::Ident(ident) if ident == "procedure" => {
+                    Ok(ast::Statement::DropProcedure { name: self.next_indent()? })
+                }
+                Token::Ident(ident) if ident == "role" => Ok(ast::Statement::DropRole { name: self.next_indent()? }),
                 token => Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
             },
             token => Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
         }
     }
 
+    // CREATE USER name [WITH] PASSWORD 'secret'
+    // ALTER USER name [WITH] PASSWORD 'secret'
+    // user and password are not keywords, so they can still be used as column names
+    fn parse_ddl_user(&mut self) -> Result<(String, String)> {
+        let name = self.next_indent()?;
+        self.next_if_token(Token::Keyword(Keyword::With));
+        match self.next_indent()?.as_str() {
+            "password" => {}
+            ident => return Err(Error::Parse(format!("[Parser] Expect password, got {}", ident))),
+        }
+        match self.next()? {
+            Token::String(password) => Ok((name, password)),
+            token => Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+        }
+    }
+
+    // CREATE PROCEDURE name [(param type, ...)] AS BEGIN statement; ... END
+    // procedure, as, begin and end are not keywords, END is only taken as the end of the body
+    // where a statement could start, so a column named end still works
+    fn parse_ddl_create_procedure(&mut self) -> Result<ast::Statement> {
+        let name = self.next_indent()?;
+        let mut params: Vec<(String, DataType)> = Vec::new();
+        if self.next_if_token(Token::OpenParen).is_some() && self.next_if_token(Token::CloseParen).is_none() {
+            loop {
+                let param = self.next_indent()?;
+                if params.iter().any(|(p, _)| *p == param) {
+                    return Err(Error::Parse(format!("[Parser] Duplicate parameter {}", param)));
+                }
+                params.push((param, self.parse_datatype()?));
+                match self.next()? {
+                    Token::CloseParen => break,
+                    Token::Comma => {}
+                    token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+                }
+            }
+        }
+        for word in ["as", "begin"] {
+            match self.next_indent()?.as_str() {
+                w if w == word => {}
+                ident => return Err(Error::Parse(format!("[Parser] Expect {}, got {}", word, ident))),
+            }
+        }
+        let mut tokens = Vec::new();
+        let mut at_start = true;
+        loop {
+            match self.next()? {
+                Token::Ident(ident) if at_start && ident == "end" => break,
+                token => {
+                    at_start = token == Token::Semicolon;
+                    tokens.push(match token {
+                        Token::String(s) => format!("'{}'", s.replace('\'', "''")),
+                        token => token.to_string(),
+                    });
+                }
+            }
+        }
+        let body = tokens.join(" ");
+
+        // check the body once now, with every parameter bound to NULL
+        let nulls = params.iter().map(|(p, _)| (p.clone(), ast::Consts::Null)).collect();
+        let stmts = Parser::with_params(&body, nulls).parse_all()?;
+        if stmts.is_empty() {
+            return Err(Error::Parse(format!("[Parser] Procedure {} has no statements", name)));
+        }
+        for stmt in &stmts {
+            if let ast::Statement::Call { .. } | ast::Statement::CreateProcedure { .. } | ast::Statement::DropProcedure { .. } = stmt {
+                return Err(Error::Parse(format!("[Parser] Procedure {} can not call or define procedures", name)));
+            }
+        }
+        Ok(ast::Statement::CreateProcedure { name, params, body })
+    }
+
     // CREATE TABLE table_name (
     //     id INT NOT NULL DEFAULT 0
     //     ...
-    // );
+    // ) [WITH (ttl = 3600, audit = true)];
     fn parse_ddl_create_table(&mut self) -> Result<ast::Statement> {
         // check table's name, must be indent type
-        let table_name = self.next_indent()?;
+        let table_name = self.parse_table_name()?;
         // check "(" afther table name
         self.next_expect(Token::OpenParen)?;
         // check column after "("
@@ -137,25 +548,75 @@ impl<'a> Parser<'a> {
         }
         // check ")"
         self.next_expect(Token::CloseParen)?;
-        Ok(ast::Statement::CreateTable { name: table_name, columns: columns })
+        let (ttl, audit) = self.parse_ddl_table_options()?;
+        Ok(ast::Statement::CreateTable { name: table_name, columns, ttl, audit })
+    }
+
+    // ALTER TABLE table_name ADD [COLUMN] name type [NOT NULL] [DEFAULT value]
+    // add and column are not keywords
+    fn parse_ddl_alter_table(&mut self) -> Result<ast::Statement> {
+        let table_name = self.parse_table_name()?;
+        match self.next_indent()?.as_str() {
+            "add" => {}
+            ident => return Err(Error::Parse(format!("[Parser] Expect add, got {}", ident))),
+        }
+        // a type follows the name, so a column named column can be added without the word before it
+        let mut name = self.next_indent()?;
+        if name == "column" && matches!(self.peek()?, Some(Token::Ident(_))) {
+            name = self.next_indent()?;
+        }
+        Ok(ast::Statement::AddColumn { table_name, column: self.parse_ddl_column_as(name)? })
+    }
+
+    // WITH (ttl = 3600, audit = true), ttl in seconds, or WITH (ttl = '7 days')
+    // option names are not keywords, so they can still be used as column names
+    fn parse_ddl_table_options(&mut self) -> Result<(Option<u64>, bool)> {
+        let (mut ttl, mut audit) = (None, false);
+        if self.next_if_token(Token::Keyword(Keyword::With)).is_none() {
+            return Ok((ttl, audit));
+        }
+        self.next_expect(Token::OpenParen)?;
+        loop {
+            let name = self.next_indent()?;
+            self.next_expect(Token::Equal)?;
+            match (name.as_str(), self.next()?) {
+                ("ttl", Token::Number(n)) => ttl = Some(n.parse()?),
+                ("ttl", Token::String(s)) => ttl = Some(parse_duration(&s)?),
+                ("audit", Token::Keyword(Keyword::True)) => audit = true,
+                ("audit", Token::Keyword(Keyword::False)) => audit = false,
+                ("ttl" | "audit", token) => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+                (name, _) => return Err(Error::Parse(format!("[Parser] Unexpected table option {}", name))),
+            }
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Token::CloseParen)?;
+        Ok((ttl, audit))
     }
 
     fn parse_ddl_column(&mut self) -> Result<ast::Column> {
+        let name = self.next_indent()?;
+        self.parse_ddl_column_as(name)
+    }
+
+    // the type and constraints of a column after its name
+    fn parse_ddl_column_as(&mut self, name: String) -> Result<ast::Column> {
         let mut column = Column {
-            name: self.next_indent()?,
-            datatype: match self.next()? {
-                Token::Keyword(Keyword::Int) | Token::Keyword(Keyword::Integer) => DataType::Integer,
-                Token::Keyword(Keyword::Boolean) | Token::Keyword(Keyword::Bool) => DataType::Boolean,
-                Token::Keyword(Keyword::Float) | Token::Keyword(Keyword::Double) => DataType::Float,
-                Token::Keyword(Keyword::String) | Token::Keyword(Keyword::Text) 
-                | Token::Keyword(Keyword::Varchar) => DataType::String,
-                token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
-            },
+            name,
+            datatype: self.parse_datatype()?,
             nullable: None,
             default: None,
         };
         // check if this column could have default value, and if it is nullable
-        while let Some(Token::Keyword(keyword)) = self.next_if_keyword() {
+        loop {
+            if self.next_if(|t| matches!(t, Token::Ident(ident) if ident == "check")).is_some() {
+                column.datatype = self.parse_ddl_check(&column)?;
+                continue;
+            }
+            let Some(Token::Keyword(keyword)) = self.next_if_keyword() else {
+                break;
+            };
             match keyword { 
                 Keyword::Null => column.nullable = Some(true),
                 Keyword::Not => {
@@ -169,25 +630,250 @@ impl<'a> Parser<'a> {
         Ok(column)
     }
 
-    fn parse_expression(&mut self) -> Result<ast::Expression> {
+    // CHECK (column IN ('a', 'b')) of a string column, the only check there is, makes it ENUM('a', 'b')
+    fn parse_ddl_check(&mut self, column: &ast::Column) -> Result<DataType> {
+        self.next_expect(Token::OpenParen)?;
+        let name = self.next_indent()?;
+        if name != column.name || column.datatype != DataType::String {
+            return Err(Error::Parse(format!(
+                "[Parser] Only CHECK ({} IN (...)) on a string column is supported",
+                column.name
+            )));
+        }
+        match self.next()? {
+            Token::Ident(ident) if ident == "in" => {}
+            token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+        }
+        let labels = self.parse_enum_labels()?;
+        self.next_expect(Token::CloseParen)?;
+        Ok(DataType::Enum(labels))
+    }
+
+    // ('a', 'b', ...)
+    fn parse_enum_labels(&mut self) -> Result<Vec<String>> {
+        self.next_expect(Token::OpenParen)?;
+        let mut labels = Vec::new();
+        loop {
+            match self.next()? {
+                Token::String(s) => labels.push(s),
+                token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+            }
+            match self.next()? {
+                Token::CloseParen => break,
+                Token::Comma => {}
+                token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+            }
+        }
+        Ok(labels)
+    }
+
+    // table or alias.table, for a table of an attached database
+    fn parse_table_name(&mut self) -> Result<String> {
+        let name = self.next_indent()?;
+        match self.next_if_token(Token::Period) {
+            Some(_) => Ok(format!("{}.{}", name, self.next_indent()?)),
+            None => Ok(name),
+        }
+    }
+
+    fn parse_datatype(&mut self) -> Result<DataType> {
         Ok(match self.next()? {
-            Token::Number(n) => {
-                if n.chars().all(|c| c.is_ascii_digit()) {
-                    // Integer
-                    ast::Consts::Integer(n.parse()?).into()
-                } else {
-                    // Float
-                    ast::Consts::Float(n.parse()?).into()
+            Token::Keyword(Keyword::Int) | Token::Keyword(Keyword::Integer) => DataType::Integer,
+            Token::Keyword(Keyword::Boolean) | Token::Keyword(Keyword::Bool) => DataType::Boolean,
+            Token::Keyword(Keyword::Float) | Token::Keyword(Keyword::Double) => DataType::Float,
+            Token::Keyword(Keyword::String) | Token::Keyword(Keyword::Text) 
+            | Token::Keyword(Keyword::Varchar) => DataType::String,
+            // VECTOR(n)
+            Token::Keyword(Keyword::Vector) => {
+                self.next_expect(Token::OpenParen)?;
+                let n = match self.next()? {
+                    Token::Number(n) => n.parse::<usize>()?,
+                    token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+                };
+                self.next_expect(Token::CloseParen)?;
+                if n == 0 || n > MAX_VECTOR_DIMENSIONS {
+                    return Err(Error::Parse(format!(
+                        "[Parser] Vector dimension must be between 1 and {}, got {}",
+                        MAX_VECTOR_DIMENSIONS, n
+                    )));
                 }
+                DataType::Vector(n)
+            }
+            // ENUM('a', 'b')
+            Token::Ident(ident) if ident == "enum" => DataType::Enum(self.parse_enum_labels()?),
+            token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+        })
+    }
+
+    // a [op b], op is =, !=, <>, <, <=, > or >=, or a IN (b, ...)
+    // comparisons bind looser than <->, <-> looser than + and -, and those looser than * and /
+    // comparisons do not chain, the others group from the left
+    // in is not a keyword, it is only taken as one after an operand
+    fn parse_expression(&mut self) -> Result<ast::Expression> {
+        let expr = self.parse_operand()?;
+        let op = match self.peek()? {
+            Some(Token::Equal) => CompareOp::Equal,
+            Some(Token::NotEqual) => CompareOp::NotEqual,
+            Some(Token::Less) => CompareOp::Less,
+            Some(Token::LessOrEqual) => CompareOp::LessOrEqual,
+            Some(Token::Greater) => CompareOp::Greater,
+            Some(Token::GreaterOrEqual) => CompareOp::GreaterOrEqual,
+            Some(Token::Ident(ident)) if ident == "in" => {
+                self.next()?;
+                self.next_expect(Token::OpenParen)?;
+                let mut list = Vec::new();
+                loop {
+                    list.push(self.parse_operand()?);
+                    match self.next()? {
+                        Token::CloseParen => break,
+                        Token::Comma => {}
+                        token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+                    }
+                }
+                return Ok(ast::Expression::Operation(ast::Operation::In(Box::new(expr), list)));
+            }
+            _ => return Ok(expr),
+        };
+        self.next()?;
+        let rhs = self.parse_operand()?;
+        Ok(ast::Expression::Operation(ast::Operation::Compare(op, Box::new(expr), Box::new(rhs))))
+    }
+
+    // a <-> b <-> ..., looser than arithmetic
+    fn parse_operand(&mut self) -> Result<ast::Expression> {
+        let mut expr = self.parse_sum()?;
+        while self.next_if_token(Token::Distance).is_some() {
+            let rhs = self.parse_sum()?;
+            expr = ast::Expression::Operation(ast::Operation::Distance(Box::new(expr), Box::new(rhs)));
+        }
+        Ok(expr)
+    }
+
+    // a + b - c ..., from the left
+    fn parse_sum(&mut self) -> Result<ast::Expression> {
+        let mut expr = self.parse_product()?;
+        while let Some(token) = self.next_if(|t| matches!(t, Token::Plus | Token::Minus)) {
+            let op = if token == Token::Plus { ArithmeticOp::Add } else { ArithmeticOp::Subtract };
+            let rhs = self.parse_product()?;
+            expr = ast::Expression::Operation(ast::Operation::Arithmetic(op, Box::new(expr), Box::new(rhs)));
+        }
+        Ok(expr)
+    }
+
+    // a * b / c ..., from the left
+    fn parse_product(&mut self) -> Result<ast::Expression> {
+        let mut expr = self.parse_expression_atom()?;
+        while let Some(token) = self.next_if(|t| matches!(t, Token::Asterisk | Token::Slash)) {
+            let op = if token == Token::Asterisk { ArithmeticOp::Multiply } else { ArithmeticOp::Divide };
+            let rhs = self.parse_expression_atom()?;
+            expr = ast::Expression::Operation(ast::Operation::Arithmetic(op, Box::new(expr), Box::new(rhs)));
+        }
+        Ok(expr)
+    }
+
+    // func(expr, ...), the number of arguments is checked when evaluated
+    fn parse_function(&mut self, func: ast::Function) -> Result<ast::Expression> {
+        self.next_expect(Token::OpenParen)?;
+        let mut args = Vec::new();
+        if self.next_if_token(Token::CloseParen).is_none() {
+            loop {
+                args.push(self.parse_expression()?);
+                match self.next()? {
+                    Token::CloseParen => break,
+                    Token::Comma => {}
+                    token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+                }
+            }
+        }
+        Ok(ast::Expression::Function(func, args))
+    }
+
+    // COUNT(*), func(expr), then an optional FILTER (WHERE expr)
+    // the function names and filter are not keywords
+    fn parse_aggregate(&mut self, func: ast::AggregateFunc) -> Result<ast::Expression> {
+        self.next_expect(Token::OpenParen)?;
+        let arg = match func {
+            ast::AggregateFunc::Count if self.next_if_token(Token::Asterisk).is_some() => None,
+            _ => Some(Box::new(self.parse_expression()?)),
+        };
+        self.next_expect(Token::CloseParen)?;
+        let filter = match self.next_if(|t| matches!(t, Token::Ident(ident) if ident == "filter")) {
+            Some(_) => {
+                self.next_expect(Token::OpenParen)?;
+                self.next_expect(Token::Keyword(Keyword::Where))?;
+                let filter = self.parse_expression()?;
+                self.next_expect(Token::CloseParen)?;
+                Some(Box::new(filter))
+            }
+            None => None,
+        };
+        Ok(ast::Expression::Aggregate(ast::Aggregate { func, arg, filter }))
+    }
+
+    fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
+        Ok(match self.next()? {
+            Token::Number(n) => self.parse_number(&n)?.into(),
+            // negative numbers
+            Token::Minus => match self.next()? {
+                Token::Number(n) => self.parse_number(&format!("-{}", n))?.into(),
+                token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
             },
+            // vector literal, [1, 2.5, -3]
+            Token::OpenBracket => {
+                let mut v = Vec::new();
+                if self.next_if_token(Token::CloseBracket).is_none() {
+                    loop {
+                        let negative = self.next_if_token(Token::Minus).is_some();
+                        match self.next()? {
+                            Token::Number(n) => v.push(if negative { -n.parse::<f32>()? } else { n.parse()? }),
+                            token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+                        }
+                        match self.next()? {
+                            Token::CloseBracket => break,
+                            Token::Comma => {}
+                            token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+                        }
+                    }
+                }
+                ast::Consts::Vector(v).into()
+            }
+            // (a) is a, (a, b) is a row
+            Token::OpenParen => {
+                let mut exprs = vec![self.parse_expression()?];
+                while self.next_if_token(Token::Comma).is_some() {
+                    exprs.push(self.parse_expression()?);
+                }
+                self.next_expect(Token::CloseParen)?;
+                match exprs.len() {
+                    1 => exprs.remove(0),
+                    _ => ast::Expression::Row(exprs),
+                }
+            }
             Token::String(s) => ast::Consts::String(s).into(),
             Token::Keyword(Keyword::True) => ast::Consts::Boolean(true).into(),
             Token::Keyword(Keyword::False) => ast::Consts::Boolean(false).into(),
             Token::Keyword(Keyword::Null) => ast::Consts::Null.into(),
+            Token::Ident(name) if self.peek()? == Some(Token::OpenParen) => {
+                match (ast::AggregateFunc::from_name(&name), ast::Function::from_name(&name)) {
+                    (Some(func), _) => self.parse_aggregate(func)?,
+                    (None, Some(func)) => self.parse_function(func)?,
+                    (None, None) => return Err(Error::Parse(format!("[Parser] Unknown function {}", name))),
+                }
+            }
+            Token::Ident(param) if self.params.contains_key(&param) => self.params[&param].clone().into(),
+            Token::Ident(name) => ast::Expression::Field(name),
             t => return Err(Error::Parse(format!("[Parser] Unexpected token {}", t)))
         })
     }
 
+    fn parse_number(&self, n: &str) -> Result<ast::Consts> {
+        Ok(if n.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            ast::Consts::Integer(n.parse()?)
+        } else {
+            ast::Consts::Float(n.parse()?)
+        })
+    }
+
     fn peek(&mut self) -> Result<Option<Token>> {
         // Option<Result<T, E>> -> Result<Option<T>, E>
         self.lexer.peek().cloned().transpose()
@@ -196,7 +882,7 @@ impl<'a> Parser<'a> {
     fn next(&mut self) -> Result<Token> {
         // Some(Token) -> Token -> Ok(Token)
         // None -> Err
-        self.lexer.next().unwrap_or_else(|| Err(Error::Parse(format!("[Parser] Unexpected end of input"))))
+        self.lexer.next().unwrap_or_else(|| Err(Error::Parse("[Parser] Unexpected end of input".to_string())))
     }
 
     fn next_indent(&mut self) -> Result<String> {
@@ -211,7 +897,7 @@ impl<'a> Parser<'a> {
         if token != expect {
             return Err(Error::Parse(format!("[Parser] Expect token {}, got token {}",expect ,token)));
         }
-        return Ok(());
+        Ok(())
     }
 
     fn next_if<F: Fn(&Token) -> bool>(&mut self, predicate: F) -> Option<Token> {
@@ -230,9 +916,42 @@ impl<'a> Parser<'a> {
 }
 
 
+// '7 days', '1 hour 30 minutes', '90s' or '3600', in seconds
+fn parse_duration(text: &str) -> Result<u64> {
+    let invalid = || Error::Parse(format!("[Parser] Invalid duration {}", text));
+    let mut chars = text.trim().chars().peekable();
+    let mut seconds: u64 = 0;
+    while chars.peek().is_some() {
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+            number.push(c);
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut unit = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+            unit.push(c.to_ascii_lowercase());
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let n: u64 = number.parse().map_err(|_| invalid())?;
+        let scale = match unit.as_str() {
+            "" | "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hour" | "hours" => 3600,
+            "d" | "day" | "days" => 86400,
+            "w" | "week" | "weeks" => 7 * 86400,
+            _ => return Err(invalid()),
+        };
+        seconds = n.checked_mul(scale).and_then(|n| seconds.checked_add(n)).ok_or_else(invalid)?;
+    }
+    if text.trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(seconds)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{error::Result, sql::{parser::ast, types::DataType}};
+    use crate::{error::Result, sql::{eval::{ArithmeticOp, CompareOp}, parser::ast, types::DataType, user::{Grant, Privilege}}};
 
     use super::Parser;
 
@@ -275,8 +994,28 @@ mod tests {
                     default: Some(ast::Consts::Boolean(true).into()),
                 },
             ],
+            ttl: None,
+            audit: false,
         };
         assert_eq!(stmt, expected_stmt);
+
+        let stmt = Parser::new("create table tbl2 (a int) with (ttl = 3600);").parse()?;
+        match stmt {
+            ast::Statement::CreateTable { ttl, .. } => assert_eq!(ttl, Some(3600)),
+            _ => unreachable!(),
+        }
+        let stmt = Parser::new("create table tbl2 (a int) with (ttl = '7 days');").parse()?;
+        match stmt {
+            ast::Statement::CreateTable { ttl, .. } => assert_eq!(ttl, Some(7 * 86400)),
+            _ => unreachable!(),
+        }
+        let stmt = Parser::new("create table tbl2 (a int) with (audit = true, ttl = 60);").parse()?;
+        match stmt {
+            ast::Statement::CreateTable { ttl, audit, .. } => assert_eq!((ttl, audit), (Some(60), true)),
+            _ => unreachable!(),
+        }
+        assert!(Parser::new("create table tbl2 (a int) with (size = 1);").parse().is_err());
+        assert!(Parser::new("create table tbl2 (a int) with (audit = 1);").parse().is_err());
         Ok(())
     }
 
@@ -324,6 +1063,142 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parser_where() -> Result<()> {
+        let field = |name: &str| ast::Expression::Field(name.to_string());
+        let int = |i: i64| ast::Expression::from(ast::Consts::Integer(i));
+        let row = |exprs: Vec<ast::Expression>| ast::Expression::Row(exprs);
+        let filter = |sql: &str| match Parser::new(sql).parse() {
+            Ok(ast::Statement::Select { filter, .. }) => Ok(filter),
+            Ok(_) => unreachable!(),
+            Err(err) => Err(err),
+        };
+        assert_eq!(
+            filter("select * from t where (a, b) > (1, 2) order by a;")?,
+            Some(ast::Expression::Operation(ast::Operation::Compare(
+                CompareOp::Greater,
+                Box::new(row(vec![field("a"), field("b")])),
+                Box::new(row(vec![int(1), int(2)])),
+            )))
+        );
+        assert_eq!(
+            filter("select * from t where (a, b) in ((1, 2), (3, 4));")?,
+            Some(ast::Expression::Operation(ast::Operation::In(
+                Box::new(row(vec![field("a"), field("b")])),
+                vec![row(vec![int(1), int(2)]), row(vec![int(3), int(4)])],
+            )))
+        );
+        // 括号里只有一个值时不是行，<-> 比比较运算结合得更紧
+        assert_eq!(
+            filter("select * from t where (a) <> 1;")?,
+            Some(ast::Expression::Operation(ast::Operation::Compare(CompareOp::NotEqual, Box::new(field("a")), Box::new(int(1)))))
+        );
+        assert!(matches!(
+            filter("select * from t where v <-> [1] <= 2;")?,
+            Some(ast::Expression::Operation(ast::Operation::Compare(CompareOp::LessOrEqual, ..)))
+        ));
+        // 比较不能连写，IN 的列表不能为空
+        assert!(filter("select * from t where a < b < c;").is_err());
+        assert!(filter("select * from t where a in ();").is_err());
+        assert!(filter("select * from t where;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_aggregate() -> Result<()> {
+        let field = |name: &str| Box::new(ast::Expression::Field(name.to_string()));
+        let arithmetic = |op, l, r| ast::Expression::Operation(ast::Operation::Arithmetic(op, l, r));
+        let select = |sql: &str| match Parser::new(sql).parse() {
+            Ok(ast::Statement::Select { select, .. }) => Ok(select
+                .into_iter()
+                .map(|item| match item {
+                    ast::SelectItem::Expr(expr, alias) => (expr, alias),
+                    ast::SelectItem::Wildcard(_) => unreachable!(),
+                })
+                .collect::<Vec<_>>()),
+            Ok(_) => unreachable!(),
+            Err(err) => Err(err),
+        };
+        assert_eq!(
+            select("select sum(price * qty) as total, count(*) filter (where status = 'ok') from t;")?,
+            vec![
+                (
+                    ast::Expression::Aggregate(ast::Aggregate {
+                        func: ast::AggregateFunc::Sum,
+                        arg: Some(Box::new(arithmetic(ArithmeticOp::Multiply, field("price"), field("qty")))),
+                        filter: None,
+                    }),
+                    Some("total".to_string())
+                ),
+                (
+                    ast::Expression::Aggregate(ast::Aggregate {
+                        func: ast::AggregateFunc::Count,
+                        arg: None,
+                        filter: Some(Box::new(ast::Expression::Operation(ast::Operation::Compare(
+                            CompareOp::Equal,
+                            field("status"),
+                            Box::new(ast::Consts::String("ok".to_string()).into()),
+                        )))),
+                    }),
+                    None
+                ),
+            ]
+        );
+        // * 和 / 比 + 和 - 结合得更紧，同级从左到右
+        assert_eq!(
+            select("select a - b + c * d / e from t;")?,
+            vec![(
+                arithmetic(
+                    ArithmeticOp::Add,
+                    Box::new(arithmetic(ArithmeticOp::Subtract, field("a"), field("b"))),
+                    Box::new(arithmetic(
+                        ArithmeticOp::Divide,
+                        Box::new(arithmetic(ArithmeticOp::Multiply, field("c"), field("d"))),
+                        field("e")
+                    )),
+                ),
+                None
+            )]
+        );
+        // 分组可以是表达式、位置或别名
+        let stmt = Parser::new("select substr(name, 1, 1) as initial, count(*) from t group by initial, 2, length(name) order by 2 desc;").parse()?;
+        let ast::Statement::Select { group_by, order_by, .. } = stmt else { unreachable!() };
+        assert_eq!(
+            group_by,
+            vec![
+                ast::Expression::Field("initial".to_string()),
+                ast::Consts::Integer(2).into(),
+                ast::Expression::Function(ast::Function::Length, vec![ast::Expression::Field("name".to_string())]),
+            ]
+        );
+        assert_eq!(order_by, vec![(ast::Consts::Integer(2).into(), ast::OrderDirection::Desc)]);
+        assert!(select("select lower() from t;").is_ok());
+        assert!(select("select a from t group by;").is_err());
+        assert!(select("select sum(*) from t;").is_err());
+        assert!(select("select foo(a) from t;").is_err());
+        assert!(select("select count(*) filter (a > 1) from t;").is_err());
+        assert!(select("select a, from t;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_select_wildcard() -> Result<()> {
+        let stmt = Parser::new("select t.*, 1 as flag, *, a from t;").parse()?;
+        let ast::Statement::Select { select, .. } = stmt else { unreachable!() };
+        assert_eq!(
+            select,
+            vec![
+                ast::SelectItem::Wildcard(Some("t".to_string())),
+                ast::SelectItem::Expr(ast::Consts::Integer(1).into(), Some("flag".to_string())),
+                ast::SelectItem::Wildcard(None),
+                ast::SelectItem::Expr(ast::Expression::Field("a".to_string()), None),
+            ]
+        );
+        assert!(Parser::new("select t. from t;").parse().is_err());
+        assert!(Parser::new("select t.* as x from t;").parse().is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_parser_select() -> Result<()> {
         let sql = "select * from tbl1;";
@@ -331,9 +1206,418 @@ mod tests {
         assert_eq!(
             stmt,
             ast::Statement::Select {
-                table_name: "tbl1".to_string()
+                select: vec![ast::SelectItem::Wildcard(None)],
+                table_name: "tbl1".to_string(),
+                filter: None,
+                group_by: vec![],
+                order_by: vec![],
+                limit: None,
+            }
+        );
+
+        let stmt = Parser::new("select * from tbl1 order by v <-> [1, -2.5] desc, a limit 5;").parse()?;
+        assert_eq!(
+            stmt,
+            ast::Statement::Select {
+                select: vec![ast::SelectItem::Wildcard(None)],
+                table_name: "tbl1".to_string(),
+                filter: None,
+                group_by: vec![],
+                order_by: vec![
+                    (
+                        ast::Expression::Operation(ast::Operation::Distance(
+                            Box::new(ast::Expression::Field("v".to_string())),
+                            Box::new(ast::Consts::Vector(vec![1.0, -2.5]).into()),
+                        )),
+                        ast::OrderDirection::Desc,
+                    ),
+                    (ast::Expression::Field("a".to_string()), ast::OrderDirection::Asc),
+                ],
+                limit: Some(ast::Consts::Integer(5).into()),
+            }
+        );
+        assert!(Parser::new("select * from tbl1 order v;").parse().is_err());
+        assert!(Parser::new("select * from tbl1 order by [1, 'a'];").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_vector() -> Result<()> {
+        let stmt = Parser::new("create table t (id int, e vector(3));").parse()?;
+        match stmt {
+            ast::Statement::CreateTable { columns, .. } => assert_eq!(columns[1].datatype, DataType::Vector(3)),
+            _ => unreachable!(),
+        }
+        assert!(Parser::new("create table t (id int, e vector(0));").parse().is_err());
+        assert!(Parser::new("create table t (id int, e vector);").parse().is_err());
+        // 负数和空向量
+        let stmt = Parser::new("insert into t values (-1, [], -0.5);").parse()?;
+        match stmt {
+            ast::Statement::Insert { values, .. } => assert_eq!(
+                values[0],
+                vec![
+                    ast::Consts::Integer(-1).into(),
+                    ast::Consts::Vector(vec![]).into(),
+                    ast::Consts::Float(-0.5).into()
+                ]
+            ),
+            _ => unreachable!(),
+        }
+        assert_eq!(
+            Parser::new("insert into t values (-9223372036854775808);").parse()?,
+            ast::Statement::Insert {
+                table_name: "t".to_string(),
+                columns: None,
+                values: vec![vec![ast::Consts::Integer(i64::MIN).into()]],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_show() -> Result<()> {
+        let stmt = Parser::new("SHOW STATUS;").parse()?;
+        assert_eq!(stmt, ast::Statement::ShowStatus);
+        let stmt = Parser::new("show processlist;").parse()?;
+        assert_eq!(stmt, ast::Statement::ShowProcesslist);
+        assert_eq!(Parser::new("show queries;").parse()?, ast::Statement::ShowQueries);
+        assert_eq!(Parser::new("show tables;").parse()?, ast::Statement::ShowTables);
+        assert!(Parser::new("show columns;").parse().is_err());
+        // kill 和 query 不是关键字
+        assert_eq!(Parser::new("kill 3;").parse()?, ast::Statement::Kill { id: 3 });
+        assert_eq!(Parser::new("KILL QUERY 3;").parse()?, ast::Statement::Kill { id: 3 });
+        assert!(Parser::new("kill -1;").parse().is_err());
+        assert!(Parser::new("kill query;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        for (text, seconds) in [("90", 90), ("90s", 90), ("1 hour 30 minutes", 5400), ("2 Days", 172800), (" 1w ", 604800)] {
+            assert_eq!(super::parse_duration(text)?, seconds);
+        }
+        for text in ["", "days", "1 fortnight", "1.5 hours", "99999999999999999999 days"] {
+            assert!(super::parse_duration(text).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_explain() -> Result<()> {
+        assert_eq!(
+            Parser::new("EXPLAIN drop table t;").parse()?,
+            ast::Statement::Explain { stmt: Box::new(ast::Statement::DropTable { name: "t".to_string() }) }
+        );
+        let mut stmt = Parser::new("explain select * from archive.t;").parse()?;
+        assert_eq!(stmt.table_names_mut(), vec!["archive.t"]);
+        // 会话语句没有计划
+        assert!(Parser::new("explain explain select * from t;").parse().is_err());
+        assert!(Parser::new("explain fetch c;").parse().is_err());
+        assert!(Parser::new("explain;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_set() -> Result<()> {
+        assert_eq!(
+            Parser::new("SET max_result_rows = 100;").parse()?,
+            ast::Statement::Set {
+                name: "max_result_rows".to_string(),
+                value: Some(ast::Expression::Consts(ast::Consts::Integer(100))),
+            }
+        );
+        assert_eq!(
+            Parser::new("set max_result_rows = default;").parse()?,
+            ast::Statement::Set { name: "max_result_rows".to_string(), value: None }
+        );
+        assert!(Parser::new("set max_result_rows 100;").parse().is_err());
+        assert_eq!(
+            Parser::new("SET NAMES utf8mb4 COLLATE utf8mb4_general_ci;").parse()?,
+            ast::Statement::Set {
+                name: "names".to_string(),
+                value: Some(ast::Expression::Consts(ast::Consts::String("utf8mb4".to_string()))),
+            }
+        );
+        assert!(Parser::new("set names;").parse().is_err());
+        assert!(Parser::new("explain set max_result_rows = 1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_vacuum() -> Result<()> {
+        assert_eq!(Parser::new("vacuum;").parse()?, ast::Statement::Vacuum { table_name: None });
+        assert_eq!(Parser::new("VACUUM t1;").parse()?, ast::Statement::Vacuum { table_name: Some("t1".to_string()) });
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_add_column() -> Result<()> {
+        let column = |name: &str| ast::Column {
+            name: name.to_string(),
+            datatype: DataType::Integer,
+            nullable: Some(false),
+            default: Some(ast::Expression::Consts(ast::Consts::Integer(0))),
+        };
+        assert_eq!(
+            Parser::new("alter table t add column n int not null default 0;").parse()?,
+            ast::Statement::AddColumn { table_name: "t".to_string(), column: column("n") }
+        );
+        assert_eq!(
+            Parser::new("ALTER TABLE t ADD n INT NOT NULL DEFAULT 0;").parse()?,
+            ast::Statement::AddColumn { table_name: "t".to_string(), column: column("n") }
+        );
+        // 列名可以是 column
+        assert_eq!(
+            Parser::new("alter table t add column int not null default 0;").parse()?,
+            ast::Statement::AddColumn { table_name: "t".to_string(), column: column("column") }
+        );
+        assert!(Parser::new("alter table t drop column n;").parse().is_err());
+        assert!(Parser::new("alter table t add column n;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_enum() -> Result<()> {
+        let status = |sql: &str| -> Result<ast::Column> {
+            match Parser::new(sql).parse()? {
+                ast::Statement::CreateTable { mut columns, .. } => Ok(columns.remove(1)),
+                stmt => panic!("unexpected statement {:?}", stmt),
+            }
+        };
+        let expect = DataType::Enum(vec!["new".to_string(), "done".to_string()]);
+        assert_eq!(status("create table t (a int, status enum('new', 'done'));")?.datatype, expect);
+        // CHECK (列 IN (...)) 是 ENUM 的另一种写法，可以和其他约束混在一起
+        let column = status("create table t (a int, status string not null check (status in ('new', 'done')) default 'new');")?;
+        assert_eq!(column.datatype, expect);
+        assert_eq!(column.nullable, Some(false));
+        assert!(column.default.is_some());
+
+        assert!(Parser::new("create table t (a int, s enum());").parse().is_err());
+        assert!(Parser::new("create table t (a int, s enum(1, 2));").parse().is_err());
+        assert!(Parser::new("create table t (a int, s int check (s in ('a')));").parse().is_err());
+        assert!(Parser::new("create table t (a int, s string check (a in ('a')));").parse().is_err());
+        assert!(Parser::new("create table t (a int, s string check (s > 'a'));").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_sql() -> Result<()> {
+        let cases = [
+            (
+                "select a, count(*) filter (where d = true) as n from t where (a + 1) * 2 > 3 group by a order by n desc, a asc limit 5;",
+                "SELECT a, count(*) FILTER (WHERE d = TRUE) AS n FROM t WHERE ((a + 1) * 2) > 3 GROUP BY a ORDER BY n DESC, a LIMIT 5;",
+            ),
+            (
+                "create table t (a int not null, s enum('x', 'y') default 'x', v vector(2) null) with (ttl = '1 hour');",
+                "CREATE TABLE t (a INTEGER NOT NULL, s ENUM('x', 'y') DEFAULT 'x', v VECTOR(2) NULL) WITH (ttl = 3600);",
+            ),
+            ("create table _t (a int) with (audit = true);", "CREATE TABLE _t (a INTEGER) WITH (audit = TRUE);"),
+            ("grant all on t to bob;", "GRANT SELECT, INSERT, CREATE, DROP ON t TO bob;"),
+            ("revoke reader, writer from bob;", "REVOKE reader, writer FROM bob;"),
+            ("fetch c;", "FETCH 1 FROM c;"),
+            ("select * from t where lower(b) in ('x', 'it''s');", "SELECT * FROM t WHERE lower(b) IN ('x', 'it''s');"),
+            ("explain insert into t values (1, -2.5, null), (2, [1, 0], true);", "EXPLAIN INSERT INTO t VALUES (1, -2.5, NULL), (2, [1.0, 0.0], TRUE);"),
+        ];
+        for (sql, expect) in cases {
+            let stmt = Parser::new(sql).parse()?;
+            assert_eq!(stmt.to_sql(), expect);
+            assert_eq!(Parser::new(expect).parse()?, stmt);
+        }
+        // 所有种子语句都能原样解析回来
+        for seed in crate::sql::fuzz::SEEDS {
+            let stmt = Parser::new(seed).parse()?;
+            assert_eq!(Parser::new(&stmt.to_sql()).parse()?, stmt, "{}", stmt.to_sql());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_statement_walk() -> Result<()> {
+        let mut stmt = Parser::new("explain select a + 1, count(*) filter (where b in (1, c)) from t where a > 2 order by a limit 10;").parse()?;
+        assert_eq!(
+            stmt.expressions().iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec!["a + 1", "count(*) FILTER (WHERE b IN (1, c))", "a > 2", "a", "10"]
+        );
+        let mut fields = Vec::new();
+        stmt.walk(&mut |expr| {
+            if let ast::Expression::Field(name) = expr {
+                fields.push(name.clone());
+            }
+        });
+        assert_eq!(fields, vec!["a", "b", "c", "a", "a"]);
+
+        // 把列 a 改名为 x
+        stmt.transform(&mut |expr| match expr {
+            ast::Expression::Field(name) if name == "a" => Ok(ast::Expression::Field("x".to_string())),
+            expr => Ok(expr),
+        })?;
+        assert_eq!(
+            stmt.to_sql(),
+            "EXPLAIN SELECT x + 1, count(*) FILTER (WHERE b IN (1, c)) FROM t WHERE x > 2 ORDER BY x LIMIT 10;"
+        );
+        let mut insert = Parser::new("insert into t values (1, 'a'), (2, null);").parse()?;
+        assert_eq!(insert.expressions_mut().len(), 4);
+        assert!(Parser::new("show tables;").parse()?.expressions().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_drop_table() -> Result<()> {
+        let stmt = Parser::new("drop table tbl1;").parse()?;
+        assert_eq!(stmt, ast::Statement::DropTable { name: "tbl1".to_string() });
+        assert!(Parser::new("drop tbl1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_procedure() -> Result<()> {
+        let sql = "create procedure add_user (id int, name text) as begin
+            insert into users values (id, name);
+            insert into logs (end, msg) values (id, 'added');
+        end;";
+        assert_eq!(
+            Parser::new(sql).parse()?,
+            ast::Statement::CreateProcedure {
+                name: "add_user".to_string(),
+                params: vec![("id".to_string(), DataType::Integer), ("name".to_string(), DataType::String)],
+                body: "INSERT INTO users VALUES ( id , name ) ; INSERT INTO logs ( end , msg ) VALUES ( id , 'added' ) ;"
+                    .to_string(),
+            }
+        );
+        // 没有参数的存储过程
+        assert!(Parser::new("create procedure p as begin select * from t; end;").parse().is_ok());
+        // 空的过程体、重复的参数、嵌套调用
+        assert!(Parser::new("create procedure p as begin end;").parse().is_err());
+        assert!(Parser::new("create procedure p (a int, a int) as begin select * from t; end;").parse().is_err());
+        assert!(Parser::new("create procedure p as begin call q(); end;").parse().is_err());
+        assert!(Parser::new("create procedure p as begin select * from t;").parse().is_err());
+
+        assert_eq!(
+            Parser::new("call add_user(1, 'a');").parse()?,
+            ast::Statement::Call {
+                name: "add_user".to_string(),
+                args: vec![ast::Consts::Integer(1).into(), ast::Consts::String("a".to_string()).into()],
+            }
+        );
+        assert_eq!(Parser::new("call p();").parse()?, ast::Statement::Call { name: "p".to_string(), args: vec![] });
+        assert_eq!(Parser::new("drop procedure p;").parse()?, ast::Statement::DropProcedure { name: "p".to_string() });
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_attach() -> Result<()> {
+        assert_eq!(
+            Parser::new("attach database 'other.db' as archive;").parse()?,
+            ast::Statement::Attach { path: "other.db".to_string(), alias: "archive".to_string() }
+        );
+        assert_eq!(
+            Parser::new("ATTACH 'other.db' AS archive;").parse()?,
+            ast::Statement::Attach { path: "other.db".to_string(), alias: "archive".to_string() }
+        );
+        assert_eq!(Parser::new("detach archive;").parse()?, ast::Statement::Detach { alias: "archive".to_string() });
+        assert!(Parser::new("attach archive;").parse().is_err());
+
+        // 带库名的表名
+        let mut stmt = Parser::new("select * from archive.t1;").parse()?;
+        assert_eq!(stmt.table_names_mut(), vec!["archive.t1"]);
+        let mut stmt2 = Parser::new("insert into archive.t1 values (1);").parse()?;
+        assert_eq!(stmt2.table_names_mut(), vec!["archive.t1"]);
+        assert!(Parser::new("select * from archive.;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_cursor() -> Result<()> {
+        let mut stmt = Parser::new("declare c cursor for select * from archive.t order by a;").parse()?;
+        match &stmt {
+            ast::Statement::Declare { name, query } => {
+                assert_eq!(name, "c");
+                assert!(matches!(**query, ast::Statement::Select { .. }));
+            }
+            _ => unreachable!(),
+        }
+        // 游标里的表名也要路由到对应的库
+        assert_eq!(stmt.table_names_mut(), vec!["archive.t"]);
+        assert_eq!(Parser::new("FETCH 100 FROM c;").parse()?, ast::Statement::Fetch { name: "c".to_string(), count: 100 });
+        assert_eq!(Parser::new("fetch c;").parse()?, ast::Statement::Fetch { name: "c".to_string(), count: 1 });
+        assert_eq!(Parser::new("close c;").parse()?, ast::Statement::Close { name: "c".to_string() });
+        // 只能为 select 声明游标
+        assert!(Parser::new("declare c cursor for insert into t values (1);").parse().is_err());
+        assert!(Parser::new("declare c for select * from t;").parse().is_err());
+        assert!(Parser::new("fetch -1 from c;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_parse_all() -> Result<()> {
+        // 分号出现在字符串里不会切断语句
+        let stmts = Parser::new("create table t (a int, b text); insert into t values (1, 'x;y');").parse_all()?;
+        assert_eq!(stmts.len(), 2);
+        assert!(Parser::new("").parse_all()?.is_empty());
+        // 最后一条语句也必须以分号结尾
+        assert!(Parser::new("select * from t; select * from t").parse_all().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_user() -> Result<()> {
+        let stmt = Parser::new("create user alice password 'secret';").parse()?;
+        assert_eq!(
+            stmt,
+            ast::Statement::CreateUser { name: "alice".to_string(), password: "secret".to_string() }
+        );
+        let stmt = Parser::new("ALTER USER alice WITH PASSWORD 'new';").parse()?;
+        assert_eq!(
+            stmt,
+            ast::Statement::AlterUser { name: "alice".to_string(), password: "new".to_string() }
+        );
+        // 密码必须是字符串
+        assert!(Parser::new("create user alice password secret;").parse().is_err());
+        assert!(Parser::new("alter table t1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_role() -> Result<()> {
+        assert_eq!(Parser::new("create role reader;").parse()?, ast::Statement::CreateRole { name: "reader".to_string() });
+        assert_eq!(Parser::new("drop role reader;").parse()?, ast::Statement::DropRole { name: "reader".to_string() });
+
+        let grant = |privilege, table: Option<&str>| Grant { privilege, table: table.map(|t| t.to_string()) };
+        assert_eq!(
+            Parser::new("grant select, insert on t to reader;").parse()?,
+            ast::Statement::Grant {
+                privileges: vec![grant(Privilege::Select, Some("t")), grant(Privilege::Insert, Some("t"))],
+                roles: vec![],
+                grantee: "reader".to_string(),
+            }
+        );
+        // ALL 是全部四种权限，* 是所有的表
+        match Parser::new("GRANT ALL PRIVILEGES ON * TO alice;").parse()? {
+            ast::Statement::Grant { privileges, .. } => {
+                assert_eq!(privileges, Privilege::ALL.map(|p| grant(p, None)).to_vec())
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(
+            Parser::new("grant reader, writer to alice;").parse()?,
+            ast::Statement::Grant {
+                privileges: vec![],
+                roles: vec!["reader".to_string(), "writer".to_string()],
+                grantee: "alice".to_string(),
+            }
+        );
+        assert_eq!(
+            Parser::new("revoke drop on * from reader;").parse()?,
+            ast::Statement::Revoke {
+                privileges: vec![grant(Privilege::Drop, None)],
+                roles: vec![],
+                grantee: "reader".to_string(),
             }
         );
+        // 权限和角色不能一起授予
+        assert!(Parser::new("grant select, reader on t to alice;").parse().is_err());
+        assert!(Parser::new("grant select to alice;").parse().is_err());
+        assert!(Parser::new("revoke reader to alice;").parse().is_err());
         Ok(())
     }
 }
\ No newline at end of file
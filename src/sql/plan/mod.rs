@@ -1,8 +1,20 @@
+use std::fmt::Display;
+
+pub(crate) use planner::label;
 use planner::Planner;
 
 use crate::error::Result;
 
-use super::{engine::Transaction, executor::{Executor, ResultSet}, parser::ast::{self, Expression}, schema::Table};
+use super::{
+    binder::Binder,
+    engine::Transaction,
+    executor::{Executor, ResultSet},
+    parser::ast::{self, Expression, OrderDirection},
+    procedure::Procedure,
+    schema::{Column, Table},
+    types::Value,
+    user::{authorize, Grant, Privilege},
+};
 mod planner;
 // plan node
 #[derive(Debug, PartialEq)]
@@ -10,6 +22,13 @@ pub enum Node {
     CreateTable {
         schema: Table,
     },
+    DropTable {
+        table_name: String,
+    },
+    AddColumn {
+        table_name: String,
+        column: Column,
+    },
     Insert {
         table_name: String,
         columns: Vec<String>,
@@ -18,18 +37,238 @@ pub enum Node {
     Scan {
         table_name: String,
     },
+    // the rows of the table counted without decoding them, in column #0, for SELECT COUNT(*) FROM t alone
+    CountScan {
+        table_name: String,
+    },
+    // the rows of the source the predicate is TRUE for
+    Filter {
+        source: Box<Node>,
+        predicate: Expression,
+    },
+    // a row per group of the source rows, with the group keys then the aggregates in columns #0, #1, ...
+    // without group keys all rows are one group, even when there are none
+    Aggregate {
+        source: Box<Node>,
+        group_by: Vec<Expression>,
+        aggregates: Vec<ast::Aggregate>,
+    },
+    // the select list evaluated on every row of the source, with the column names
+    Projection {
+        source: Box<Node>,
+        exprs: Vec<(Expression, String)>,
+    },
+    // sort the rows of the source, brute force over all of them
+    Order {
+        source: Box<Node>,
+        order_by: Vec<(Expression, OrderDirection)>,
+    },
+    Limit {
+        source: Box<Node>,
+        limit: usize,
+    },
+    Vacuum {
+        table_name: Option<String>,
+    },
+    ShowStatus,
+    ShowProcesslist,
+    ShowQueries,
+    ShowTables,
+    Kill {
+        id: u64,
+    },
+    CreateUser {
+        name: String,
+        password: String,
+    },
+    AlterUser {
+        name: String,
+        password: String,
+    },
+    CreateRole {
+        name: String,
+    },
+    DropRole {
+        name: String,
+    },
+    Grant {
+        privileges: Vec<Grant>,
+        roles: Vec<String>,
+        grantee: String,
+    },
+    Revoke {
+        privileges: Vec<Grant>,
+        roles: Vec<String>,
+        grantee: String,
+    },
+    CreateProcedure {
+        procedure: Procedure,
+    },
+    DropProcedure {
+        name: String,
+    },
+    Call {
+        name: String,
+        args: Vec<Expression>,
+    },
+    // the plan of the source as text, one row per node
+    Explain {
+        source: Box<Node>,
+    },
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Plan(pub Node);
 
+// the i-th column in the output of Node::Aggregate, # is never in a column name
+pub fn aggregate_column(i: usize) -> String {
+    format!("#{}", i)
+}
+
 impl Plan {
-    pub fn build(stmt: ast::Statement) -> Self {
-        Planner::new().build(stmt)
+    // the statement is bound first, see Binder
+    pub fn build<T: Transaction>(stmt: ast::Statement, txn: &T) -> Result<Self> {
+        Binder::new(txn).bind(&stmt)?;
+        Planner::new(txn).build(stmt)
     }
     pub fn execute<T: Transaction>(self, txn: &mut T) -> Result<ResultSet> {
         <dyn Executor<T>>::build(self.0).execute(txn)
     }
+
+    // check the user may run the plan, see user::authorize
+    pub fn authorize<T: Transaction>(&self, txn: &T, user: &str) -> Result<()> {
+        if user.is_empty() {
+            return Ok(());
+        }
+        authorize(txn, user, self.0.required_privileges(txn, user)?)
+    }
+}
+
+impl Node {
+    // the privileges needed to run the node, None if only admins may run it
+    fn required_privileges<T: Transaction>(&self, txn: &T, user: &str) -> Result<Option<Vec<Grant>>> {
+        let on = |privilege, table: &String| Grant { privilege, table: Some(table.clone()) };
+        let all = |privilege| Grant { privilege, table: None };
+        Ok(Some(match self {
+            Node::CreateTable { schema } => vec![on(Privilege::Create, &schema.name)],
+            Node::DropTable { table_name } => vec![on(Privilege::Drop, table_name)],
+            Node::AddColumn { table_name, .. } => vec![on(Privilege::Create, table_name)],
+            Node::Insert { table_name, .. } => vec![on(Privilege::Insert, table_name)],
+            Node::Scan { table_name } | Node::CountScan { table_name } => vec![on(Privilege::Select, table_name)],
+            Node::Filter { source, .. }
+            | Node::Aggregate { source, .. }
+            | Node::Projection { source, .. }
+            | Node::Order { source, .. }
+            | Node::Limit { source, .. }
+            | Node::Explain { source } => {
+                return source.required_privileges(txn, user)
+            }
+            Node::Vacuum { table_name: Some(table_name) } => vec![on(Privilege::Drop, table_name)],
+            Node::Vacuum { table_name: None } => vec![all(Privilege::Drop)],
+            Node::ShowStatus | Node::ShowProcesslist | Node::ShowQueries | Node::ShowTables => vec![],
+            // anyone may kill their own statements, only admins those of others
+            Node::Kill { id } => match txn.queries()?.into_iter().find(|q| q.id == *id) {
+                Some(query) if query.user != user => return Ok(None),
+                _ => vec![],
+            },
+            // anyone may change their own password
+            Node::AlterUser { name, .. } if name == user => vec![],
+            Node::CreateUser { .. }
+            | Node::AlterUser { .. }
+            | Node::CreateRole { .. }
+            | Node::DropRole { .. }
+            | Node::Grant { .. }
+            | Node::Revoke { .. } => return Ok(None),
+            Node::CreateProcedure { .. } => vec![all(Privilege::Create)],
+            Node::DropProcedure { .. } => vec![all(Privilege::Drop)],
+            // a procedure runs with the privileges of its caller, a missing one fails when called
+            Node::Call { name, args } => {
+                let mut required = Vec::new();
+                if let Some(procedure) = txn.get_procedure(name.clone())? {
+                    let args = args.iter().map(|arg| Value::from_expression(arg.clone())).collect::<Result<_>>()?;
+                    // not bound, a statement may use a table the ones before it create
+                    for stmt in procedure.statements(args)? {
+                        match Planner::new(txn).build(stmt)?.0.required_privileges(txn, user)? {
+                            Some(privileges) => required.extend(privileges),
+                            None => return Ok(None),
+                        }
+                    }
+                }
+                required
+            }
+        }))
+    }
+}
+
+// one line per node, the source of a node on the lines after it, indented
+//   Projection: a, #0 AS total
+//     Aggregate: sum(b) GROUP BY a
+//       Scan: t
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |exprs: &[Expression]| exprs.iter().map(Expression::to_string).collect::<Vec<_>>().join(", ");
+        let (line, source) = match self {
+            Node::CreateTable { schema } => (format!("CreateTable: {}", schema.name), None),
+            Node::DropTable { table_name } => (format!("DropTable: {}", table_name), None),
+            Node::AddColumn { table_name, column } => (format!("AddColumn: {}.{}", table_name, column.name), None),
+            Node::Insert { table_name, columns, values } => {
+                let columns = match columns.is_empty() {
+                    true => String::new(),
+                    false => format!(" ({})", columns.join(", ")),
+                };
+                (format!("Insert: {}{}, {} rows", table_name, columns, values.len()), None)
+            }
+            Node::Scan { table_name } => (format!("Scan: {}", table_name), None),
+            Node::CountScan { table_name } => (format!("CountScan: {}", table_name), None),
+            Node::Filter { source, predicate } => (format!("Filter: {}", predicate), Some(source)),
+            Node::Aggregate { source, group_by, aggregates } => {
+                let aggregates = aggregates.iter().map(|a| Expression::Aggregate(a.clone())).collect::<Vec<_>>();
+                let mut line = format!("Aggregate: {}", list(&aggregates));
+                if !group_by.is_empty() {
+                    line.push_str(&format!(" GROUP BY {}", list(group_by)));
+                }
+                (line, Some(source))
+            }
+            Node::Projection { source, exprs } => {
+                let exprs = exprs.iter().map(|(expr, label)| match expr.to_string() {
+                    text if &text == label => text,
+                    text => format!("{} AS {}", text, label),
+                });
+                (format!("Projection: {}", exprs.collect::<Vec<_>>().join(", ")), Some(source))
+            }
+            Node::Order { source, order_by } => {
+                let order_by = order_by.iter().map(|(expr, direction)| match direction {
+                    OrderDirection::Asc => format!("{} ASC", expr),
+                    OrderDirection::Desc => format!("{} DESC", expr),
+                });
+                (format!("Order: {}", order_by.collect::<Vec<_>>().join(", ")), Some(source))
+            }
+            Node::Limit { source, limit } => (format!("Limit: {}", limit), Some(source)),
+            Node::Vacuum { table_name } => (format!("Vacuum: {}", table_name.as_deref().unwrap_or("*")), None),
+            Node::ShowStatus => ("ShowStatus".to_string(), None),
+            Node::ShowProcesslist => ("ShowProcesslist".to_string(), None),
+            Node::ShowQueries => ("ShowQueries".to_string(), None),
+            Node::ShowTables => ("ShowTables".to_string(), None),
+            Node::Kill { id } => (format!("Kill: {}", id), None),
+            Node::CreateUser { name, .. } => (format!("CreateUser: {}", name), None),
+            Node::AlterUser { name, .. } => (format!("AlterUser: {}", name), None),
+            Node::CreateRole { name } => (format!("CreateRole: {}", name), None),
+            Node::DropRole { name } => (format!("DropRole: {}", name), None),
+            Node::Grant { grantee, .. } => (format!("Grant: {}", grantee), None),
+            Node::Revoke { grantee, .. } => (format!("Revoke: {}", grantee), None),
+            Node::CreateProcedure { procedure } => (format!("CreateProcedure: {}", procedure.name), None),
+            Node::DropProcedure { name } => (format!("DropProcedure: {}", name), None),
+            Node::Call { name, args } => (format!("Call: {}({})", name, list(args)), None),
+            Node::Explain { source } => ("Explain".to_string(), Some(source)),
+        };
+        f.write_str(&line)?;
+        if let Some(source) = source {
+            for line in source.to_string().lines() {
+                write!(f, "\n  {}", line)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -37,14 +276,25 @@ mod tests {
     use crate::{
         error::Result,
         sql::{
+            engine::{kv::KVEngine, Engine},
             parser::{
                 ast::{self, Expression},
                 Parser,
             },
             plan::{Node, Plan},
         },
+        storage::memory::MemoryEngine,
     };
 
+    // names are bound before planning, so the tables must exist
+    fn build(sql: &str) -> Result<Plan> {
+        let engine = KVEngine::new(MemoryEngine::new())?;
+        let mut s = engine.session()?;
+        s.execute("create table tbl1 (a int, b int, c int, d text, v vector(2));")?;
+        s.execute("create table tbl2 (c1 int, c2 text, c3 bool);")?;
+        Plan::build(Parser::new(sql).parse()?, &engine.begin()?)
+    }
+
     #[test]
     fn test_plan_create_table() -> Result<()> {
         let sql1 = "
@@ -55,8 +305,7 @@ mod tests {
             d bool default true
         );
         ";
-        let stmt1 = Parser::new(sql1).parse()?;
-        let p1 = Plan::build(stmt1);
+        let p1 = build(sql1)?;
 
         let sql2 = "
         create            table tbl1 (
@@ -66,8 +315,7 @@ mod tests {
             d       bool default        true
         );
         ";
-        let stmt2 = Parser::new(sql2).parse()?;
-        let p2 = Plan::build(stmt2);
+        let p2 = build(sql2)?;
         assert_eq!(p1, p2);
 
         Ok(())
@@ -76,8 +324,7 @@ mod tests {
     #[test]
     fn test_plan_insert() -> Result<()> {
         let sql1 = "insert into tbl1 values (1, 2, 3, 'a', true);";
-        let stmt1 = Parser::new(sql1).parse()?;
-        let p1 = Plan::build(stmt1);
+        let p1 = build(sql1)?;
         assert_eq!(
             p1,
             Plan(Node::Insert {
@@ -94,8 +341,7 @@ mod tests {
         );
 
         let sql2 = "insert into tbl2 (c1, c2, c3) values (3, 'a', true),(4, 'b', false);";
-        let stmt2 = Parser::new(sql2).parse()?;
-        let p2 = Plan::build(stmt2);
+        let p2 = build(sql2)?;
         assert_eq!(
             p2,
             Plan(Node::Insert {
@@ -122,8 +368,7 @@ mod tests {
     #[test]
     fn test_plan_select() -> Result<()> {
         let sql = "select * from tbl1;";
-        let stmt = Parser::new(sql).parse()?;
-        let p = Plan::build(stmt);
+        let p = build(sql)?;
         assert_eq!(
             p,
             Plan(Node::Scan {
@@ -131,6 +376,30 @@ mod tests {
             })
         );
 
+        assert_eq!(
+            build("select * from tbl1 order by v <-> [1, 2] limit 3;")?,
+            Plan(Node::Limit {
+                source: Box::new(Node::Order {
+                    source: Box::new(Node::Scan { table_name: "tbl1".to_string() }),
+                    order_by: vec![(
+                        Expression::Operation(ast::Operation::Distance(
+                            Box::new(Expression::Field("v".to_string())),
+                            Box::new(Expression::Consts(ast::Consts::Vector(vec![1.0, 2.0]))),
+                        )),
+                        ast::OrderDirection::Asc,
+                    )],
+                }),
+                limit: 3,
+            })
+        );
+        assert!(build("select * from tbl1 limit -1;").is_err());
+        assert!(build("select * from tbl1 limit a;").is_err());
+        assert!(build("create table t (a int default b);").is_err());
+        // 表和列都要存在
+        assert!(build("select * from tbl3;").is_err());
+        assert!(build("select tbl1.*, e from tbl1;").is_err());
+        assert!(build("insert into tbl2 (c1, c4) values (1, 2);").is_err());
+
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,46 +1,271 @@
-use crate::sql::{parser::ast, schema::{self, Table}, types::Value};
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{audit::AUDIT_TABLE, Transaction},
+        parser::ast::{self, Expression, OrderDirection, SelectItem},
+        procedure::Procedure,
+        schema::{self, Table},
+        types::Value,
+    },
+};
 
-use super::{Plan, Node};
+use super::{aggregate_column, Node, Plan};
 
-pub struct Planner;
+// the transaction is only read, for the schema of the tables
+pub struct Planner<'a, T: Transaction> {
+    txn: &'a T,
+}
 
-impl Planner {
-    pub fn new() -> Self {
-        Self {}
+impl<'a, T: Transaction> Planner<'a, T> {
+    pub fn new(txn: &'a T) -> Self {
+        Self { txn }
     }
 
-    pub fn build(&mut self, stmt: ast::Statement) -> Plan {
-        Plan(self.build_statement(stmt))
+    pub fn build(&mut self, stmt: ast::Statement) -> Result<Plan> {
+        Ok(Plan(self.build_statement(stmt)?))
     }
 
-    fn build_statement(&self, stmt: ast::Statement) -> Node {
-        match stmt {
-            ast::Statement::CreateTable { name, columns } => Node::CreateTable { 
+    fn build_statement(&self, stmt: ast::Statement) -> Result<Node> {
+        // the audit log can be read and dropped, but only the engine writes it
+        if let ast::Statement::CreateTable { name, .. }
+        | ast::Statement::AddColumn { table_name: name, .. }
+        | ast::Statement::Insert { table_name: name, .. } = &stmt
+        {
+            if name == AUDIT_TABLE {
+                return Err(Error::Internal(format!("Table {} is written by the engine only", AUDIT_TABLE)));
+            }
+        }
+        Ok(match stmt {
+            ast::Statement::CreateTable { name, columns, ttl, audit } => Node::CreateTable { 
                 schema: Table {
                     name,
-                    columns: columns.into_iter().map(|c| {
-                        let nullable = c.nullable.unwrap_or(true);
-                        let default = match c.default {
-                            Some(expr) => Some(Value::from_expression(expr)),
-                            None if nullable => Some(Value::Null),
-                            None => None,
-                        };
-                        schema::Column {
-                            name: c.name,
-                            datatype: c.datatype,
-                            nullable,
-                            default,
-                        }
-                    }).collect()
+                    columns: columns.into_iter().map(build_column).collect::<Result<_>>()?,
+                    ttl,
+                    audit,
                 } 
             },
+            ast::Statement::DropTable { name } => Node::DropTable { table_name: name },
+            ast::Statement::AddColumn { table_name, column } => {
+                Node::AddColumn { table_name, column: build_column(column)? }
+            }
             ast::Statement::Insert { table_name, columns, values } => 
             Node::Insert { 
                 table_name, 
                 columns: columns.unwrap_or_default(), 
                 values,
             },
-            ast::Statement::Select { table_name } => Node::Scan{ table_name },
+            ast::Statement::Select { select, table_name, filter, group_by, order_by, limit } => {
+                self.build_select(select, table_name, filter, group_by, order_by, limit)?
+            }
+            ast::Statement::Vacuum { table_name } => Node::Vacuum { table_name },
+            ast::Statement::ShowStatus => Node::ShowStatus,
+            ast::Statement::ShowProcesslist => Node::ShowProcesslist,
+            ast::Statement::ShowTables => Node::ShowTables,
+            ast::Statement::ShowQueries => Node::ShowQueries,
+            ast::Statement::Kill { id } => Node::Kill { id },
+            ast::Statement::CreateUser { name, password } => Node::CreateUser { name, password },
+            ast::Statement::AlterUser { name, password } => Node::AlterUser { name, password },
+            ast::Statement::CreateRole { name } => Node::CreateRole { name },
+            ast::Statement::DropRole { name } => Node::DropRole { name },
+            ast::Statement::Grant { privileges, roles, grantee } => Node::Grant { privileges, roles, grantee },
+            ast::Statement::Revoke { privileges, roles, grantee } => Node::Revoke { privileges, roles, grantee },
+            ast::Statement::CreateProcedure { name, params, body } => {
+                Node::CreateProcedure { procedure: Procedure { name, params, body } }
+            }
+            ast::Statement::DropProcedure { name } => Node::DropProcedure { name },
+            ast::Statement::Call { name, args } => Node::Call { name, args },
+            ast::Statement::Explain { stmt } => Node::Explain { source: Box::new(self.build_statement(*stmt)?) },
+            // they change the session rather than the database, see Session::execute
+            ast::Statement::Attach { .. } | ast::Statement::Detach { .. } => {
+                return Err(Error::Internal("ATTACH and DETACH can only be run by a session".to_string()))
+            }
+            ast::Statement::Declare { .. } | ast::Statement::Fetch { .. } | ast::Statement::Close { .. } => {
+                return Err(Error::Internal("Cursors can only be used by a session".to_string()))
+            }
+            ast::Statement::Set { .. } => {
+                return Err(Error::Internal("SET can only be run by a session".to_string()))
+            }
+        })
+    }
+
+    // Scan, Filter, Aggregate, Order, Limit, then Projection, each one only when the query needs it
+    fn build_select(
+        &self,
+        select: Vec<SelectItem>,
+        table_name: String,
+        filter: Option<Expression>,
+        group_by: Vec<Expression>,
+        order_by: Vec<(Expression, OrderDirection)>,
+        limit: Option<Expression>,
+    ) -> Result<Node> {
+        // SELECT * alone keeps the rows of the table as they are
+        let select = match select.as_slice() {
+            [SelectItem::Wildcard(None)] => Vec::new(),
+            _ => self.expand_wildcards(select, &table_name)?,
+        };
+        let mut node = Node::Scan { table_name };
+        if let Some(predicate) = filter {
+            if predicate.contains(&is_aggregate) {
+                return Err(Error::Internal("Aggregates are not allowed in WHERE".to_string()));
+            }
+            node = Node::Filter { source: Box::new(node), predicate };
+        }
+
+        // a position or AS name in GROUP BY and ORDER BY stands for that select expression,
+        // an AS name hides a column of the same name
+        let resolve = |expr: Expression| -> Result<Expression> {
+            match expr {
+                Expression::Consts(ast::Consts::Integer(i)) => match i.checked_sub(1).and_then(|i| usize::try_from(i).ok()).and_then(|i| select.get(i)) {
+                    Some((expr, _)) => Ok(expr.clone()),
+                    None => Err(Error::Internal(format!("Position {} is not in the select list", i))),
+                },
+                Expression::Field(name) => match select.iter().find(|(_, alias)| alias.as_ref() == Some(&name)) {
+                    Some((expr, _)) => Ok(expr.clone()),
+                    None => Ok(Expression::Field(name)),
+                },
+                expr => Ok(expr),
+            }
+        };
+        let group_by = group_by.into_iter().map(resolve).collect::<Result<Vec<_>>>()?;
+        let mut order_by = order_by
+            .into_iter()
+            .map(|(expr, direction)| Ok((resolve(expr)?, direction)))
+            .collect::<Result<Vec<_>>>()?;
+        if group_by.iter().any(|expr| expr.contains(&is_aggregate)) {
+            return Err(Error::Internal("Aggregates are not allowed in GROUP BY".to_string()));
+        }
+
+        let labels = select.iter().map(|(expr, alias)| label(expr, alias)).collect::<Vec<_>>();
+        let mut exprs = select.into_iter().map(|(expr, _)| expr).collect::<Vec<_>>();
+        let aggregated = exprs.iter().chain(order_by.iter().map(|(expr, _)| expr)).any(|expr| expr.contains(&is_aggregate));
+        // the groups and aggregates are computed first, the select list and ORDER BY then read them as columns
+        if aggregated || !group_by.is_empty() {
+            if exprs.is_empty() {
+                return Err(Error::Internal("SELECT * can not be used with GROUP BY or aggregates".to_string()));
+            }
+            let mut aggregates = Vec::new();
+            exprs = exprs
+                .into_iter()
+                .map(|expr| extract_grouped(expr, &group_by, &mut aggregates))
+                .collect::<Result<_>>()?;
+            order_by = order_by
+                .into_iter()
+                .map(|(expr, direction)| Ok((extract_grouped(expr, &group_by, &mut aggregates)?, direction)))
+                .collect::<Result<_>>()?;
+            for expr in exprs.iter().chain(order_by.iter().map(|(expr, _)| expr)) {
+                if let Some(name) = find_column(expr) {
+                    return Err(Error::Internal(format!(
+                        "Column {} must appear in GROUP BY or be used in an aggregate",
+                        name
+                    )));
+                }
+            }
+            node = match (node, group_by.is_empty(), aggregates.as_slice()) {
+                // the rows are only counted, the table does not have to be read
+                (Node::Scan { table_name }, true, [ast::Aggregate { func: ast::AggregateFunc::Count, arg: None, filter: None }]) => {
+                    Node::CountScan { table_name }
+                }
+                (node, _, _) => Node::Aggregate { source: Box::new(node), group_by, aggregates },
+            };
+        }
+
+        if !order_by.is_empty() {
+            node = Node::Order { source: Box::new(node), order_by };
         }
+        if let Some(expr) = limit {
+            let limit = match Value::from_expression(expr)? {
+                Value::Integer(n) if n >= 0 => n as usize,
+                v => return Err(Error::Internal(format!("Invalid limit {}", v))),
+            };
+            node = Node::Limit { source: Box::new(node), limit };
+        }
+        if !exprs.is_empty() {
+            node = Node::Projection { source: Box::new(node), exprs: exprs.into_iter().zip(labels).collect() };
+        }
+        Ok(node)
+    }
+
+    // * and table.* become the columns of the table
+    fn expand_wildcards(&self, select: Vec<SelectItem>, table_name: &str) -> Result<Vec<(Expression, Option<String>)>> {
+        let mut exprs = Vec::new();
+        for item in select {
+            match item {
+                SelectItem::Expr(expr, alias) => exprs.push((expr, alias)),
+                SelectItem::Wildcard(Some(table)) if table != table_name => {
+                    return Err(Error::Internal(format!("Table {} is not in FROM", table)))
+                }
+                SelectItem::Wildcard(_) => {
+                    let table = self.txn.must_get_table(table_name.to_string())?;
+                    exprs.extend(table.columns.into_iter().map(|c| (Expression::Field(c.name), None)));
+                }
+            }
+        }
+        Ok(exprs)
+    }
+}
+
+// a nullable column without a default defaults to NULL
+fn build_column(c: ast::Column) -> Result<schema::Column> {
+    let nullable = c.nullable.unwrap_or(true);
+    let default = match c.default {
+        Some(expr) => Some(Value::from_expression(expr)?),
+        None if nullable => Some(Value::Null),
+        None => None,
+    };
+    Ok(schema::Column {
+        name: c.name,
+        datatype: c.datatype,
+        nullable,
+        default,
+    })
+}
+
+fn is_aggregate(expr: &Expression) -> bool {
+    matches!(expr, Expression::Aggregate(_))
+}
+
+// replace the group expressions and aggregates by their columns, equal aggregates are computed once
+fn extract_grouped(expr: Expression, group_by: &[Expression], aggregates: &mut Vec<ast::Aggregate>) -> Result<Expression> {
+    expr.transform(&mut |expr| {
+        if let Some(i) = group_by.iter().position(|group| group == &expr) {
+            return Ok(Expression::Field(aggregate_column(i)));
+        }
+        match expr {
+            Expression::Aggregate(aggregate) => {
+                if aggregate.arg.iter().chain(&aggregate.filter).any(|e| e.contains(&is_aggregate)) {
+                    return Err(Error::Internal(format!("Aggregate {} can not contain an aggregate", aggregate.func)));
+                }
+                let i = aggregates.iter().position(|a| a == &aggregate).unwrap_or_else(|| {
+                    aggregates.push(aggregate);
+                    aggregates.len() - 1
+                });
+                Ok(Expression::Field(aggregate_column(group_by.len() + i)))
+            }
+            expr => Ok(expr),
+        }
+    })
+}
+
+// a column of the table left in an expression over aggregates
+fn find_column(expr: &Expression) -> Option<String> {
+    let mut found = None;
+    expr.walk(&mut |expr| {
+        if let Expression::Field(name) = expr {
+            if !name.starts_with('#') {
+                found.get_or_insert(name.clone());
+            }
+        }
+    });
+    found
+}
+
+// AS name, a column its own name, a function or aggregate its function, like postgres
+pub(crate) fn label(expr: &Expression, alias: &Option<String>) -> String {
+    match (alias, expr) {
+        (Some(alias), _) => alias.clone(),
+        (None, Expression::Field(name)) => name.clone(),
+        (None, Expression::Function(func, _)) => func.to_string(),
+        (None, Expression::Aggregate(aggregate)) => aggregate.func.to_string(),
+        (None, _) => "?column?".to_string(),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,46 @@
+// a stored procedure, CREATE PROCEDURE name (a int, b text) AS BEGIN <statements> END
+// the body is kept as sql text and parsed again by every CALL, with the arguments bound to the parameters
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+use super::{
+    parser::{ast, Parser},
+    types::{DataType, Value},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Procedure {
+    pub name: String,
+    pub params: Vec<(String, DataType)>,
+    pub body: String,
+}
+
+impl Procedure {
+    // the statements of the body, with the arguments in place of the parameters
+    // arguments are coerced to the parameter types like values inserted into a column
+    pub fn statements(&self, args: Vec<Value>) -> Result<Vec<ast::Statement>> {
+        if args.len() != self.params.len() {
+            return Err(Error::Internal(format!(
+                "Procedure {} expects {} arguments, got {}",
+                self.name,
+                self.params.len(),
+                args.len()
+            )));
+        }
+        let mut bindings = HashMap::new();
+        for ((param, datatype), arg) in self.params.iter().zip(args) {
+            let arg = arg.coerce(datatype);
+            match arg.datatype() {
+                Some(dt) if dt != *datatype => {
+                    return Err(Error::TypeMismatch { column: param.clone(), expected: datatype.clone(), got: dt })
+                }
+                _ => {}
+            }
+            bindings.insert(param.clone(), arg.into());
+        }
+        Parser::with_params(&self.body, bindings).parse_all()
+    }
+}
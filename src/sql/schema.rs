@@ -1,17 +1,216 @@
 use serde::{Deserialize, Serialize};
 
-use super::types::{DataType, Value};
+use crate::error::{Error, Result};
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+use super::types::{DataType, Row, Value};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    // seconds a row lives after it is written, None means forever
+    pub ttl: Option<u64>,
+    // the rows written and deleted are recorded in the audit log, see engine::audit
+    pub audit: bool,
+}
+
+// a table as it was stored before audit, read by the migration of older data, see KVEngine::migrate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LegacyTable {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub ttl: Option<u64>,
+}
+
+impl From<LegacyTable> for Table {
+    fn from(table: LegacyTable) -> Self {
+        Self { name: table.name, columns: table.columns, ttl: table.ttl, audit: false }
+    }
+}
+
+// the most columns a table can have
+pub const MAX_COLUMNS: usize = 1024;
+
+impl Table {
+    // store defaults as values of their column type, so rows get them as they are
+    pub fn normalize_defaults(&mut self) {
+        for col in &mut self.columns {
+            col.default = col.default.take().map(|v| v.coerce(&col.datatype));
+        }
+    }
+
+    // check the schema before the table is created
+    // defaults are constants, so they can't refer to other columns or to themselves
+    pub fn validate(&self) -> Result<()> {
+        if self.columns.is_empty() {
+            return Err(Error::Internal(format!("Table {} has no columns.", self.name)));
+        }
+        if self.columns.len() > MAX_COLUMNS {
+            return Err(Error::TooManyColumns { table: self.name.clone(), max: MAX_COLUMNS });
+        }
+        // vectors have no key encoding that keeps their length
+        if let DataType::Vector(_) = self.columns[0].datatype {
+            return Err(Error::Internal(format!("Vector column {} can not be the primary key", self.columns[0].name)));
+        }
+        for (i, col) in self.columns.iter().enumerate() {
+            if self.columns[..i].iter().any(|c| c.name == col.name) {
+                return Err(Error::DuplicateColumn { table: self.name.clone(), column: col.name.clone() });
+            }
+            if let DataType::Enum(labels) = &col.datatype {
+                if labels.is_empty() || labels.iter().enumerate().any(|(j, l)| labels[..j].contains(l)) {
+                    return Err(Error::Internal(format!("Enum column {} needs distinct labels", col.name)));
+                }
+            }
+            // DEFAULT NULL on a NOT NULL column fails too
+            if let Some(default) = &col.default {
+                col.check_value(&default.clone().coerce(&col.datatype))?;
+            }
+        }
+        Ok(())
+    }
+
+    // convert the values to the column types where it is allowed, see Value::coerce
+    pub fn coerce_row(&self, row: Row) -> Row {
+        row.into_iter()
+            .zip(self.columns.iter().map(Some).chain(std::iter::repeat(None)))
+            .map(|(value, col)| match col {
+                Some(col) => value.coerce(&col.datatype),
+                None => value,
+            })
+            .collect()
+    }
+
+    // check a complete row against the columns before it is written,
+    // every write of a row goes through it, so NOT NULL holds for all of them
+    pub fn check_row(&self, row: &Row) -> Result<()> {
+        if row.len() != self.columns.len() {
+            return Err(Error::ValueCountMismatch { table: self.name.clone(), expected: self.columns.len(), got: row.len() });
+        }
+        for (value, col) in row.iter().zip(&self.columns) {
+            col.check_value(value)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
     pub datatype: DataType,
     pub nullable: bool,
     pub default: Option<Value>,
 }
+
+impl Column {
+    // a value the column can hold: of its type, NULL only if nullable, an enum label for an enum
+    pub fn check_value(&self, value: &Value) -> Result<()> {
+        match (value.datatype(), &self.datatype, value) {
+            (None, _, _) if self.nullable => Ok(()),
+            (None, _, _) => Err(Error::NotNullViolation { column: self.name.clone() }),
+            (Some(dt), _, _) if dt != self.datatype.value_type() => {
+                Err(Error::TypeMismatch { column: self.name.clone(), expected: self.datatype.clone(), got: dt })
+            }
+            (_, DataType::Enum(labels), Value::String(s)) if !labels.contains(s) => {
+                Err(Error::CheckViolation { column: self.name.clone(), value: value.clone() })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // the value of the column in a row that gives none: the default, else NULL if the column is nullable
+    pub fn missing_value(&self) -> Result<Value> {
+        match &self.default {
+            Some(default) => Ok(default.clone()),
+            None if self.nullable => Ok(Value::Null),
+            None => Err(Error::NotNullViolation { column: self.name.clone() }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{Error, Result},
+        sql::types::{DataType, Value},
+    };
+
+    use super::{Column, Table};
+
+    fn column(name: &str, nullable: bool, default: Option<Value>) -> Column {
+        Column { name: name.to_string(), datatype: DataType::Integer, nullable, default }
+    }
+
+    #[test]
+    fn test_missing_value() -> Result<()> {
+        assert_eq!(column("a", true, None).missing_value()?, Value::Null);
+        assert_eq!(column("a", false, Some(Value::Integer(1))).missing_value()?, Value::Integer(1));
+        assert_eq!(column("a", false, None).missing_value(), Err(Error::NotNullViolation { column: "a".to_string() }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let table = |columns| Table { name: "t".to_string(), columns, ttl: None, audit: false };
+        table(vec![column("a", false, None), column("b", true, Some(Value::Null))]).validate()?;
+        // 空表和重复的列名
+        assert!(table(vec![]).validate().is_err());
+        assert_eq!(
+            table(vec![column("a", true, None), column("a", true, None)]).validate(),
+            Err(Error::DuplicateColumn { table: "t".to_string(), column: "a".to_string() })
+        );
+        // 默认值的类型要和列一致，整数可以作为浮点列的默认值
+        assert_eq!(
+            table(vec![column("a", true, Some(Value::String("x".to_string())))]).validate(),
+            Err(Error::TypeMismatch { column: "a".to_string(), expected: DataType::Integer, got: DataType::String })
+        );
+        let mut float = column("f", true, Some(Value::Integer(1)));
+        float.datatype = DataType::Float;
+        table(vec![float]).validate()?;
+        // 不能为空的列不能以 NULL 为默认值
+        assert_eq!(
+            table(vec![column("a", false, Some(Value::Null))]).validate(),
+            Err(Error::NotNullViolation { column: "a".to_string() })
+        );
+        let many = (0..=super::MAX_COLUMNS).map(|i| column(&format!("c{}", i), true, None)).collect();
+        assert!(matches!(table(many).validate(), Err(Error::TooManyColumns { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_row_not_null() -> Result<()> {
+        let table = Table { name: "t".to_string(), columns: vec![column("a", false, None), column("b", true, None)], ttl: None, audit: false };
+        table.check_row(&vec![Value::Integer(1), Value::Null])?;
+        assert_eq!(
+            table.check_row(&vec![Value::Null, Value::Integer(1)]),
+            Err(Error::NotNullViolation { column: "a".to_string() })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_row_enum() -> Result<()> {
+        let labels = vec!["new".to_string(), "done".to_string()];
+        let mut status = column("status", true, Some(Value::String("new".to_string())));
+        status.datatype = DataType::Enum(labels.clone());
+        let table = Table { name: "t".to_string(), columns: vec![column("a", false, None), status], ttl: None, audit: false };
+        table.validate()?;
+        table.check_row(&vec![Value::Integer(1), Value::String("done".to_string())])?;
+        table.check_row(&vec![Value::Integer(1), Value::Null])?;
+        // 不在列表里的值，和非字符串的值
+        assert_eq!(
+            table.check_row(&vec![Value::Integer(1), Value::String("lost".to_string())]),
+            Err(Error::CheckViolation { column: "status".to_string(), value: Value::String("lost".to_string()) })
+        );
+        assert_eq!(
+            table.check_row(&vec![Value::Integer(1), Value::Integer(0)]),
+            Err(Error::TypeMismatch { column: "status".to_string(), expected: DataType::Enum(labels), got: DataType::Integer })
+        );
+        // 默认值也要在列表里，标签不能重复
+        let mut bad = table.clone();
+        bad.columns[1].default = Some(Value::String("lost".to_string()));
+        assert!(matches!(bad.validate(), Err(Error::CheckViolation { .. })));
+        bad.columns[1].datatype = DataType::Enum(vec!["new".to_string(), "new".to_string()]);
+        assert!(bad.validate().is_err());
+        Ok(())
+    }
+}
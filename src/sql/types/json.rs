@@ -0,0 +1,153 @@
+// the json encoding of values and results, for clients reading results as json
+// it is versioned, a client checks "version" and a change that breaks it gets a new one
+// version 1, by type:
+//   NULL       null, never anything else, so a missing or NaN value can not look like NULL
+//   BOOLEAN    true, false
+//   INTEGER    a number without fraction or exponent, 42
+//              beyond 2^53 javascript numbers lose digits, those are {"$int": "9007199254740993"}
+//   FLOAT      a number with a fraction or exponent, 42.0, 1e-7
+//              json has no NaN or infinity, they are {"$float": "NaN" | "Infinity" | "-Infinity"}
+//   STRING     a string
+//   VECTOR     an array of numbers
+//   bytes      {"$bytes": "base64"}, reserved, no column type holds bytes yet
+// a select is {"version": 1, "columns": [...], "rows": [[...], ...]}, an insert {"version": 1, "count": n},
+// other results the version and the result set as serde writes it
+use serde_json::{json, Map, Value as Json};
+
+use crate::{
+    error::{Error, Result},
+    sql::executor::ResultSet,
+};
+
+use super::Value;
+
+pub const JSON_VERSION: u64 = 1;
+
+// integers javascript numbers hold exactly
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+pub fn to_json(value: &Value) -> Json {
+    match value {
+        Value::Null => Json::Null,
+        Value::Boolean(b) => Json::from(*b),
+        Value::Integer(i) if (-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(i) => Json::from(*i),
+        Value::Integer(i) => json!({ "$int": i.to_string() }),
+        Value::Float(f) if f.is_nan() => json!({ "$float": "NaN" }),
+        Value::Float(f) if f.is_infinite() => json!({ "$float": if *f > 0.0 { "Infinity" } else { "-Infinity" } }),
+        // serde_json always writes a float with a fraction or exponent
+        Value::Float(f) => Json::from(*f),
+        Value::String(s) => Json::from(s.as_str()),
+        Value::Vector(v) => Json::from(v.as_slice()),
+    }
+}
+
+pub fn from_json(json: &Json) -> Result<Value> {
+    Ok(match json {
+        Json::Null => Value::Null,
+        Json::Bool(b) => Value::Boolean(*b),
+        Json::Number(n) if n.is_f64() => Value::Float(n.as_f64().unwrap_or_default()),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => return Err(Error::Parse(format!("Integer {} is out of range", n))),
+        },
+        Json::String(s) => Value::String(s.clone()),
+        Json::Array(items) => Value::Vector(
+            items
+                .iter()
+                .map(|item| item.as_f64().map(|f| f as f32))
+                .collect::<Option<_>>()
+                .ok_or_else(|| Error::Parse(format!("Vector {} has an item that is not a number", json)))?,
+        ),
+        Json::Object(object) => from_tagged(object)?,
+    })
+}
+
+// {"$int": ...}, {"$float": ...}
+fn from_tagged(object: &Map<String, Json>) -> Result<Value> {
+    let tagged = match object.iter().next() {
+        Some((tag, Json::String(text))) if object.len() == 1 => Some((tag.as_str(), text.as_str())),
+        _ => None,
+    };
+    Ok(match tagged {
+        Some(("$int", text)) => Value::Integer(text.parse()?),
+        Some(("$float", "NaN")) => Value::Float(f64::NAN),
+        Some(("$float", "Infinity")) => Value::Float(f64::INFINITY),
+        Some(("$float", "-Infinity")) => Value::Float(f64::NEG_INFINITY),
+        Some(("$float", text)) => Value::Float(text.parse()?),
+        Some(("$bytes", _)) => return Err(Error::Parse("Bytes values are not supported".to_string())),
+        _ => return Err(Error::Parse(format!("Invalid json value {}", Json::Object(object.clone())))),
+    })
+}
+
+pub fn result_to_json(result: &ResultSet) -> Result<Json> {
+    let mut json = match result {
+        ResultSet::Scan { columns, row } => json!({
+            "columns": columns,
+            "rows": row.iter().map(|row| row.iter().map(to_json).collect::<Vec<_>>()).collect::<Vec<_>>(),
+        }),
+        ResultSet::Insert { count } => json!({ "count": count }),
+        result => json!({ "result": serde_json::to_value(result)? }),
+    };
+    json["version"] = Json::from(JSON_VERSION);
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        error::Result,
+        sql::{executor::ResultSet, types::Value},
+    };
+
+    use super::{from_json, result_to_json, to_json};
+
+    #[test]
+    fn test_json_values() -> Result<()> {
+        let values = [
+            (Value::Null, "null"),
+            (Value::Boolean(true), "true"),
+            (Value::Integer(-42), "-42"),
+            (Value::Integer(1 << 53), "{\"$int\":\"9007199254740992\"}"),
+            (Value::Integer(i64::MIN), "{\"$int\":\"-9223372036854775808\"}"),
+            (Value::Float(42.0), "42.0"),
+            (Value::Float(1e-7), "1e-7"),
+            (Value::Float(f64::NEG_INFINITY), "{\"$float\":\"-Infinity\"}"),
+            (Value::String("{\"$int\":\"1\"}".to_string()), "\"{\\\"$int\\\":\\\"1\\\"}\""),
+            (Value::Vector(vec![1.0, -0.5]), "[1.0,-0.5]"),
+        ];
+        for (value, text) in values {
+            let json = to_json(&value);
+            assert_eq!(json.to_string(), text);
+            // 整数和浮点数、NULL 和字符串都能区分开
+            assert_eq!(from_json(&serde_json::from_str(text)?)?, value);
+        }
+        assert!(matches!(from_json(&json!({ "$float": "NaN" }))?, Value::Float(f) if f.is_nan()));
+
+        assert!(from_json(&json!(u64::MAX)).is_err());
+        assert!(from_json(&json!([1, "a"])).is_err());
+        assert!(from_json(&json!({ "$bytes": "AAE=" })).is_err());
+        assert!(from_json(&json!({ "$int": "1", "$float": "1" })).is_err());
+        assert!(from_json(&json!({ "a": 1 })).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_results() -> Result<()> {
+        let scan = ResultSet::Scan {
+            columns: vec!["a".to_string(), "b".to_string()],
+            row: vec![vec![Value::Integer(1), Value::Null], vec![Value::Integer(2), Value::Float(2.0)]],
+        };
+        assert_eq!(
+            result_to_json(&scan)?.to_string(),
+            "{\"columns\":[\"a\",\"b\"],\"rows\":[[1,null],[2,2.0]],\"version\":1}"
+        );
+        assert_eq!(result_to_json(&ResultSet::Insert { count: 3 })?, json!({ "count": 3, "version": 1 }));
+        assert_eq!(
+            result_to_json(&ResultSet::DropTable { table_name: "t".to_string() })?,
+            json!({ "result": { "DropTable": { "table_name": "t" } }, "version": 1 })
+        );
+        Ok(())
+    }
+}
@@ -1,32 +1,128 @@
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+    hash::{Hash, Hasher},
+};
+
 use serde::{Deserialize, Serialize};
 
+use crate::error::{Error, Result};
+
 use super::parser::ast::{Consts, Expression};
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub mod json;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DataType {
     Boolean,
     Integer,
     Float,
     String,
+    // f32 arrays of the given dimension
+    Vector(usize),
+    // strings from a fixed list, stored as their position in it, see engine::row
+    // they compare and sort as strings, not by position
+    Enum(Vec<String>),
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// the largest VECTOR(n)
+pub const MAX_VECTOR_DIMENSIONS: usize = 16_000;
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Boolean => f.write_str("BOOLEAN"),
+            Self::Integer => f.write_str("INTEGER"),
+            Self::Float => f.write_str("FLOAT"),
+            Self::String => f.write_str("STRING"),
+            Self::Vector(n) => write!(f, "VECTOR({})", n),
+            Self::Enum(labels) => write!(
+                f,
+                "ENUM({})",
+                labels.iter().map(|l| Value::String(l.clone()).to_string()).collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+impl DataType {
+    // the type of the values of a column of the type, an enum holds strings
+    pub fn value_type(&self) -> DataType {
+        match self {
+            Self::Enum(_) => Self::String,
+            datatype => datatype.clone(),
+        }
+    }
+}
+
+// values have a total order, the same as the key encoding except that numbers compare by value:
+//   integers and floats < strings < booleans < vectors < null
+// integers and floats compare exactly, so 1 = 1.0 and 2^53 + 1 > 2^53 as a float
+// -0.0 = 0.0, and NaN is greater than every other number and equal to itself
+// so null sorts last, as in ORDER BY ... ASC NULLS LAST
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Value {
     Integer(i64),
     Float(f64),
     String(String),
     Boolean(bool),
     Null,
+    // can't be a primary key, see Table::validate
+    Vector(Vec<f32>),
 }
 
 impl Value {
-    pub fn from_expression(expr: Expression) -> Self {
-        match expr {
+    // the value of a constant expression, like the values of an insert
+    pub fn from_expression(expr: Expression) -> Result<Self> {
+        Ok(match expr {
             Expression::Consts(Consts::Null) => Self::Null,
             Expression::Consts(Consts::Boolean(b)) => Self::Boolean(b),
             Expression::Consts(Consts::Integer(x)) => Self::Integer(x),
             Expression::Consts(Consts::Float(f)) => Self::Float(f),
             Expression::Consts(Consts::String(s)) => Self::String(s),
+            Expression::Consts(Consts::Vector(v)) => Self::Vector(v),
+            expr => return Err(Error::Internal(format!("Expected a constant, got {:?}", expr))),
+        })
+    }
+
+    // parse text from outside, like a csv field, as a value of the column type
+    pub fn parse_as(text: &str, datatype: &DataType) -> Result<Self> {
+        Ok(match datatype {
+            DataType::Integer => Self::Integer(text.trim().parse()?),
+            DataType::Float => Self::Float(text.trim().parse()?),
+            // the table checks an enum value is one of the labels
+            DataType::String | DataType::Enum(_) => Self::String(text.to_string()),
+            DataType::Boolean => match text.trim().to_lowercase().as_str() {
+                "true" | "t" | "1" => Self::Boolean(true),
+                "false" | "f" | "0" => Self::Boolean(false),
+                _ => return Err(Error::Parse(format!("invalid boolean {}", text))),
+            },
+            // [1, 2, 3] or 1,2,3
+            DataType::Vector(_) => {
+                let text = text.trim();
+                let text = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')).unwrap_or(text);
+                Self::Vector(text.split(',').map(|x| x.trim().parse()).collect::<std::result::Result<_, _>>()?)
+            }
+        })
+    }
+
+    // implicit conversion to the type of a column, when a value is written or compared
+    //   integer -> float: always, 2^53 and above may round
+    //   null: stays null, nullability is checked by the table
+    //   anything else, float -> integer and string -> number included, stays as it is
+    //   and fails the type check, parse_as is for text that should be parsed
+    pub fn coerce(self, datatype: &DataType) -> Self {
+        match (self, datatype) {
+            (Self::Integer(i), DataType::Float) => Self::Float(i as f64),
+            (value, _) => value,
+        }
+    }
+
+    // false if coerce changes the value, an integer a float can not hold exactly is rounded
+    pub fn coerces_exactly(&self, datatype: &DataType) -> bool {
+        match (self, datatype) {
+            (Self::Integer(i), DataType::Float) => *i as f64 as i128 == *i as i128,
+            _ => true,
         }
     }
 
@@ -37,8 +133,358 @@ impl Value {
             Self::Float(_) => Some(DataType::Float),
             Self::String(_) => Some(DataType::String),
             Self::Integer(_) => Some(DataType::Integer),
+            Self::Vector(v) => Some(DataType::Vector(v.len())),
+        }
+    }
+
+    // bytes the value takes in memory, for the memory limit of queries
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + match self {
+                Self::String(s) => s.capacity(),
+                Self::Vector(v) => v.capacity() * std::mem::size_of::<f32>(),
+                _ => 0,
+            }
+    }
+}
+
+// a value back in the ast, to bind procedure arguments
+impl From<Value> for Consts {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Boolean(b) => Self::Boolean(b),
+            Value::Integer(i) => Self::Integer(i),
+            Value::Float(f) => Self::Float(f),
+            Value::String(s) => Self::String(s),
+            Value::Vector(v) => Self::Vector(v),
+        }
+    }
+}
+
+impl From<Consts> for Value {
+    fn from(consts: Consts) -> Self {
+        match consts {
+            Consts::Null => Self::Null,
+            Consts::Boolean(b) => Self::Boolean(b),
+            Consts::Integer(i) => Self::Integer(i),
+            Consts::Float(f) => Self::Float(f),
+            Consts::String(s) => Self::String(s),
+            Consts::Vector(v) => Self::Vector(v),
+        }
+    }
+}
+
+impl Value {
+    // position of the kind of value in the order
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Integer(_) | Self::Float(_) => 0,
+            Self::String(_) => 1,
+            Self::Boolean(_) => 2,
+            Self::Vector(_) => 3,
+            Self::Null => 4,
+        }
+    }
+}
+
+fn cmp_float(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        // never None without NaN, and -0.0 == 0.0
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+// exact, i as f64 may round
+fn cmp_int_float(i: i64, f: f64) -> Ordering {
+    if f.is_nan() || f >= i64::MAX as f64 {
+        // i64::MAX as f64 is 2^63, above every i64
+        return Ordering::Less;
+    }
+    if f < i64::MIN as f64 {
+        return Ordering::Greater;
+    }
+    i.cmp(&(f.trunc() as i64)).then(cmp_float(0.0, f.fract()))
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Integer(a), Self::Integer(b)) => a.cmp(b),
+            (Self::Float(a), Self::Float(b)) => cmp_float(*a, *b),
+            (Self::Integer(a), Self::Float(b)) => cmp_int_float(*a, *b),
+            (Self::Float(a), Self::Integer(b)) => cmp_int_float(*b, *a).reverse(),
+            (Self::String(a), Self::String(b)) => a.cmp(b),
+            (Self::Boolean(a), Self::Boolean(b)) => a.cmp(b),
+            // element by element like floats, then the shorter first
+            (Self::Vector(a), Self::Vector(b)) => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| cmp_float(*x as f64, *y as f64))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (a, b) => a.rank().cmp(&b.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// equal values by the order above, so Null == Null here
+// sql comparisons with null are handled by the expression evaluation
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+// equal values hash the same: integral floats hash as integers, all NaNs alike
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Self::Integer(i) => i.hash(state),
+            Self::Float(f) if f.is_nan() => f64::NAN.to_bits().hash(state),
+            Self::Float(f) if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f < i64::MAX as f64 => {
+                (*f as i64).hash(state)
+            }
+            Self::Float(f) => f.to_bits().hash(state),
+            Self::String(s) => s.hash(state),
+            Self::Boolean(b) => b.hash(state),
+            Self::Vector(v) => {
+                for x in v {
+                    match x {
+                        x if x.is_nan() => f32::NAN.to_bits().hash(state),
+                        x if *x == 0.0 => 0.0f32.to_bits().hash(state),
+                        x => x.to_bits().hash(state),
+                    }
+                }
+            }
+            Self::Null => {}
+        }
+    }
+}
+
+// as a sql literal: 'it''s', NULL, TRUE, 1, 1.0
+// floats keep a decimal point or an exponent, so they read back as floats
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("NULL"),
+            Self::Boolean(true) => f.write_str("TRUE"),
+            Self::Boolean(false) => f.write_str("FALSE"),
+            Self::Integer(i) => write!(f, "{}", i),
+            Self::Float(x) if x.is_nan() => f.write_str("NaN"),
+            Self::Float(x) if x.is_infinite() => f.write_str(if *x > 0.0 { "Infinity" } else { "-Infinity" }),
+            Self::Float(x) => write!(f, "{:?}", x),
+            Self::String(s) => write!(f, "'{}'", s.replace('\'', "''")),
+            Self::Vector(v) => write!(f, "[{}]", v.iter().map(|x| format!("{:?}", x)).collect::<Vec<_>>().join(", ")),
         }
     }
 }
 
-pub type Row = Vec<Value>;
\ No newline at end of file
+pub type Row = Vec<Value>;
+
+pub fn row_memory_size(row: &[Value]) -> usize {
+    std::mem::size_of::<Row>() + row.iter().map(Value::memory_size).sum::<usize>()
+}
+
+// (1, 'a', NULL)
+pub fn format_row(row: &[Value]) -> String {
+    format!("({})", row.iter().map(Value::to_string).collect::<Vec<_>>().join(", "))
+}
+
+// rows as an aligned text table with a header, numbers aligned right
+//  a | b
+// ---+-----
+//  1 | 'x'
+pub fn format_table(columns: &[String], rows: &[Row]) -> String {
+    let cells: Vec<Vec<String>> = rows.iter().map(|row| row.iter().map(Value::to_string).collect()).collect();
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| cells.iter().filter_map(|row| row.get(i)).map(|c| c.chars().count()).fold(columns[i].chars().count(), usize::max))
+        .collect();
+    let mut out = String::new();
+    let header: Vec<String> = columns.iter().zip(&widths).map(|(c, w)| format!(" {:<w$} ", c, w = w)).collect();
+    out.push_str(header.join("|").trim_end());
+    out.push('\n');
+    out.push_str(&widths.iter().map(|w| "-".repeat(w + 2)).collect::<Vec<_>>().join("+"));
+    out.push('\n');
+    for (row, cells) in rows.iter().zip(&cells) {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(row)
+            .zip(&widths)
+            .map(|((c, v), w)| match v {
+                Value::Integer(_) | Value::Float(_) => format!(" {:>w$} ", c, w = w),
+                _ => format!(" {:<w$} ", c, w = w),
+            })
+            .collect();
+        out.push_str(line.join("|").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cmp::Ordering,
+        collections::{hash_map::DefaultHasher, HashSet},
+        hash::{Hash, Hasher},
+    };
+
+    use super::{format_row, format_table, DataType, Value};
+
+    fn hash(v: &Value) -> u64 {
+        let mut h = DefaultHasher::new();
+        v.hash(&mut h);
+        h.finish()
+    }
+
+    #[test]
+    fn test_value_order() {
+        // 数字 < 字符串 < 布尔 < 向量 < NULL
+        let mut values = vec![
+            Value::Null,
+            Value::Boolean(true),
+            Value::String("b".to_string()),
+            Value::Float(f64::NAN),
+            Value::Boolean(false),
+            Value::Float(1.5),
+            Value::String("a".to_string()),
+            Value::Integer(-3),
+            Value::Float(f64::NEG_INFINITY),
+            Value::Integer(2),
+            Value::Float(f64::INFINITY),
+            Value::Vector(vec![2.0]),
+            Value::Vector(vec![1.0, 0.0]),
+            Value::Vector(vec![1.0]),
+        ];
+        values.sort();
+        assert_eq!(
+            values,
+            vec![
+                Value::Float(f64::NEG_INFINITY),
+                Value::Integer(-3),
+                Value::Float(1.5),
+                Value::Integer(2),
+                Value::Float(f64::INFINITY),
+                Value::Float(f64::NAN),
+                Value::String("a".to_string()),
+                Value::String("b".to_string()),
+                Value::Boolean(false),
+                Value::Boolean(true),
+                Value::Vector(vec![1.0]),
+                Value::Vector(vec![1.0, 0.0]),
+                Value::Vector(vec![2.0]),
+                Value::Null,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_value_numeric_compare() {
+        assert_eq!(Value::Integer(1), Value::Float(1.0));
+        assert_eq!(Value::Float(-0.0), Value::Float(0.0));
+        assert_eq!(Value::Integer(0), Value::Float(-0.0));
+        assert_eq!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert_ne!(Value::Integer(1), Value::String("1".to_string()));
+        // 超过 f64 精度的整数也能精确比较
+        let big = 1i64 << 53;
+        assert_eq!(Value::Integer(big + 1).cmp(&Value::Float(big as f64)), Ordering::Greater);
+        assert_eq!(Value::Float(big as f64).cmp(&Value::Integer(big + 1)), Ordering::Less);
+        assert_eq!(Value::Integer(i64::MAX).cmp(&Value::Float(i64::MAX as f64)), Ordering::Less);
+        assert_eq!(Value::Integer(i64::MIN), Value::Float(i64::MIN as f64));
+        assert_eq!(Value::Integer(2).cmp(&Value::Float(2.5)), Ordering::Less);
+        assert_eq!(Value::Integer(-2).cmp(&Value::Float(-2.5)), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_value_coerce() {
+        // (值, 目标类型, 结果)
+        let matrix = [
+            (Value::Integer(1), DataType::Float, Value::Float(1.0)),
+            (Value::Integer(1), DataType::Integer, Value::Integer(1)),
+            (Value::Float(1.5), DataType::Integer, Value::Float(1.5)),
+            (Value::String("1".to_string()), DataType::Integer, Value::String("1".to_string())),
+            (Value::Boolean(true), DataType::Integer, Value::Boolean(true)),
+            (Value::Null, DataType::Float, Value::Null),
+        ];
+        for (value, datatype, expect) in matrix {
+            assert!(value.coerces_exactly(&datatype));
+            let got = value.coerce(&datatype);
+            assert_eq!(got.datatype(), expect.datatype());
+            assert_eq!(got, expect);
+        }
+    }
+
+    #[test]
+    fn test_value_parse_vector() -> crate::error::Result<()> {
+        assert_eq!(Value::parse_as("[1, -0.5]", &DataType::Vector(2))?, Value::Vector(vec![1.0, -0.5]));
+        assert_eq!(Value::parse_as(" 1,2,3 ", &DataType::Vector(3))?, Value::Vector(vec![1.0, 2.0, 3.0]));
+        assert!(Value::parse_as("[1, x]", &DataType::Vector(2)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_display() {
+        let values = [
+            (Value::Null, "NULL"),
+            (Value::Boolean(true), "TRUE"),
+            (Value::Integer(-42), "-42"),
+            (Value::Float(1.0), "1.0"),
+            (Value::Float(0.1), "0.1"),
+            (Value::Float(1e300), "1e300"),
+            (Value::Float(f64::NEG_INFINITY), "-Infinity"),
+            (Value::Float(f64::NAN), "NaN"),
+            (Value::String("it's".to_string()), "'it''s'"),
+            (Value::Vector(vec![0.5, -1.0]), "[0.5, -1.0]"),
+        ];
+        for (value, expect) in values {
+            assert_eq!(value.to_string(), expect);
+        }
+        assert_eq!(DataType::Float.to_string(), "FLOAT");
+        assert_eq!(DataType::Vector(3).to_string(), "VECTOR(3)");
+        assert_eq!(DataType::Enum(vec!["new".to_string(), "it's".to_string()]).to_string(), "ENUM('new', 'it''s')");
+        assert_eq!(format_row(&[Value::Integer(1), Value::String("a".to_string()), Value::Null]), "(1, 'a', NULL)");
+        assert_eq!(format_row(&[]), "()");
+    }
+
+    #[test]
+    fn test_format_table() {
+        let columns = vec!["id".to_string(), "name".to_string()];
+        let rows = vec![
+            vec![Value::Integer(1), Value::String("alice".to_string())],
+            vec![Value::Integer(100), Value::Null],
+        ];
+        let expect = concat!(
+            " id  | name\n",
+            "-----+---------\n",
+            "   1 | 'alice'\n",
+            " 100 | NULL\n",
+        );
+        assert_eq!(format_table(&columns, &rows), expect);
+    }
+
+    #[test]
+    fn test_value_hash() {
+        // 相等的值哈希相同
+        assert_eq!(hash(&Value::Integer(3)), hash(&Value::Float(3.0)));
+        assert_eq!(hash(&Value::Float(0.0)), hash(&Value::Float(-0.0)));
+        assert_eq!(hash(&Value::Float(f64::NAN)), hash(&Value::Float(-f64::NAN)));
+        assert_eq!(hash(&Value::Vector(vec![0.0, 1.0])), hash(&Value::Vector(vec![-0.0, 1.0])));
+        let set: HashSet<Value> = [Value::Integer(1), Value::Float(1.0), Value::Float(1.5), Value::Null, Value::Null]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 3);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,159 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{Error, Result},
+    sql::engine::Transaction,
+};
+
+const SALT_SIZE: usize = 16;
+// rounds of sha256, makes guessing passwords from a stolen hash slower
+const HASH_ROUNDS: u32 = 10_000;
+
+// a user of the server, the password itself is never stored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl User {
+    pub fn new(name: String, password: &str) -> Self {
+        let mut salt = vec![0; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        let hash = Self::hash(&salt, password);
+        Self { name, salt, hash }
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = Self::hash(&self.salt, password);
+        // compare every byte, so the time taken does not tell how many bytes matched
+        hash.len() == self.hash.len() && hash.iter().zip(&self.hash).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+
+    fn hash(salt: &[u8], password: &str) -> Vec<u8> {
+        let mut hash = Sha256::new().chain_update(salt).chain_update(password).finalize();
+        for _ in 1..HASH_ROUNDS {
+            hash = Sha256::new().chain_update(salt).chain_update(hash).finalize();
+        }
+        hash.to_vec()
+    }
+}
+
+// the built-in role that may do anything, including managing users, roles and grants
+// it is never stored, the first user created gets it
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Privilege {
+    Select,
+    Insert,
+    // CREATE TABLE, ALTER TABLE, CREATE PROCEDURE
+    Create,
+    // DROP TABLE, DROP PROCEDURE, VACUUM
+    Drop,
+}
+
+impl Privilege {
+    pub const ALL: [Privilege; 4] = [Privilege::Select, Privilege::Insert, Privilege::Create, Privilege::Drop];
+}
+
+impl Display for Privilege {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Privilege::Select => "SELECT",
+            Privilege::Insert => "INSERT",
+            Privilege::Create => "CREATE",
+            Privilege::Drop => "DROP",
+        })
+    }
+}
+
+// a privilege on one table, or on every table if table is None
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Grant {
+    pub privilege: Privilege,
+    pub table: Option<String>,
+}
+
+impl Grant {
+    // a grant on every table covers the same privilege on one table
+    pub fn covers(&self, other: &Grant) -> bool {
+        self.privilege == other.privilege && (self.table.is_none() || self.table == other.table)
+    }
+}
+
+impl Display for Grant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ON {}", self.privilege, self.table.as_deref().unwrap_or("*"))
+    }
+}
+
+// a named set of privileges and roles, granted to users or to other roles
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Role {
+    pub name: String,
+}
+
+// what was granted to a user or a role
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Grants {
+    pub roles: BTreeSet<String>,
+    pub privileges: BTreeSet<Grant>,
+}
+
+// the roles of a user or a role, its own and those inherited through them
+pub fn inherited_roles<T: Transaction>(txn: &T, name: &str) -> Result<BTreeSet<String>> {
+    let mut roles = BTreeSet::new();
+    let mut pending = vec![name.to_string()];
+    while let Some(name) = pending.pop() {
+        for role in txn.get_grants(name)?.roles {
+            if roles.insert(role.clone()) {
+                pending.push(role);
+            }
+        }
+    }
+    Ok(roles)
+}
+
+// the privileges of a user or a role, its own and those of every role it inherits
+// None if it is an admin, who has every privilege
+pub fn effective_privileges<T: Transaction>(txn: &T, name: &str) -> Result<Option<BTreeSet<Grant>>> {
+    let roles = inherited_roles(txn, name)?;
+    if name == ADMIN_ROLE || roles.contains(ADMIN_ROLE) {
+        return Ok(None);
+    }
+    let mut privileges = txn.get_grants(name.to_string())?.privileges;
+    for role in roles {
+        privileges.extend(txn.get_grants(role)?.privileges);
+    }
+    Ok(Some(privileges))
+}
+
+// check the user has the privileges, or is an admin if they are None
+// privileges come from the user's own grants and those of every role it inherits
+// sessions without a user, and databases without users, may do anything
+pub fn authorize<T: Transaction>(txn: &T, user: &str, required: Option<Vec<Grant>>) -> Result<()> {
+    if user.is_empty() || !txn.has_users()? {
+        return Ok(());
+    }
+    if txn.get_user(user.to_string())?.is_none() {
+        return Err(Error::AccessDenied(user.to_string()));
+    }
+    let granted = match effective_privileges(txn, user)? {
+        Some(granted) => granted,
+        None => return Ok(()),
+    };
+    let denied = |privilege: String| Error::PermissionDenied { user: user.to_string(), privilege };
+    match required {
+        Some(required) => match required.iter().find(|r| !granted.iter().any(|g| g.covers(r))) {
+            Some(missing) => Err(denied(missing.to_string())),
+            None => Ok(()),
+        },
+        None => Err(denied("ADMIN".to_string())),
+    }
+}
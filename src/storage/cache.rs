@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, HashMap};
+
+// charged for each entry besides its value, so empty values fill the cache too
+const ENTRY_OVERHEAD: usize = std::mem::size_of::<u64>();
+
+// LRU cache of log values, keyed by the offset of the value in the log
+// the log is append only, an offset always points to the same value, so there is nothing to invalidate
+// the total size of cached values, with ENTRY_OVERHEAD for each, never exceeds capacity,
+// capacity 0 disables the cache
+pub struct ValueCache {
+    capacity: usize,
+    size: usize,
+    // increase on every access, used as the recency of an entry
+    tick: u64,
+    // offset -> (value, last used tick)
+    entries: HashMap<u64, (Vec<u8>, u64)>,
+    // last used tick -> offset, the first one is the least recently used
+    recency: BTreeMap<u64, u64>,
+}
+
+impl ValueCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // change capacity, evict entries if it shrinks
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn get(&mut self, offset: u64) -> Option<Vec<u8>> {
+        self.tick += 1;
+        let (value, last_used) = self.entries.get_mut(&offset)?;
+        self.recency.remove(last_used);
+        self.recency.insert(self.tick, offset);
+        *last_used = self.tick;
+        Some(value.clone())
+    }
+
+    pub fn insert(&mut self, offset: u64, value: Vec<u8>) {
+        // too large to cache, it would evict everything else
+        if self.capacity == 0 || Self::cost(&value) > self.capacity {
+            return;
+        }
+        self.tick += 1;
+        self.size += Self::cost(&value);
+        if let Some((old, last_used)) = self.entries.insert(offset, (value, self.tick)) {
+            self.size -= Self::cost(&old);
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(self.tick, offset);
+        self.evict();
+    }
+
+    pub fn clear(&mut self) {
+        self.size = 0;
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    // drop least recently used entries until size fits in capacity
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let Some((_, offset)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((value, _)) = self.entries.remove(&offset) {
+                self.size -= Self::cost(&value);
+            }
+        }
+    }
+
+    fn cost(value: &[u8]) -> usize {
+        value.len() + ENTRY_OVERHEAD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ValueCache, ENTRY_OVERHEAD};
+
+    #[test]
+    fn test_value_cache() {
+        // 两个 4 字节的 value 正好放得下
+        let mut cache = ValueCache::new(2 * (4 + ENTRY_OVERHEAD) + 2);
+        cache.insert(1, vec![1; 4]);
+        cache.insert(2, vec![2; 4]);
+        assert_eq!(cache.get(1), Some(vec![1; 4]));
+
+        // 超过容量，淘汰最久没有访问的 2
+        cache.insert(3, vec![3; 4]);
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(1), Some(vec![1; 4]));
+        assert_eq!(cache.get(3), Some(vec![3; 4]));
+
+        // 比容量还大的 value 不缓存
+        cache.insert(4, vec![4; cache.capacity() - ENTRY_OVERHEAD + 1]);
+        assert_eq!(cache.get(4), None);
+        assert_eq!(cache.get(1), Some(vec![1; 4]));
+
+        // 缩小容量，淘汰 3
+        cache.set_capacity(4 + ENTRY_OVERHEAD);
+        assert_eq!(cache.get(3), None);
+        assert_eq!(cache.get(1), Some(vec![1; 4]));
+
+        // 容量为 0 相当于关闭缓存
+        cache.set_capacity(0);
+        cache.insert(5, vec![5]);
+        assert_eq!(cache.get(5), None);
+        assert_eq!(cache.get(1), None);
+
+        cache.insert(5, vec![]);
+        assert_eq!(cache.get(5), None);
+
+        cache.set_capacity(10);
+        cache.insert(6, vec![6; 2]);
+        cache.clear();
+        assert_eq!(cache.get(6), None);
+    }
+
+    #[test]
+    fn test_value_cache_empty_values() {
+        // 空的 value 也占用容量，缓存不会无限增长
+        let mut cache = ValueCache::new(10 * ENTRY_OVERHEAD);
+        for offset in 0..1000 {
+            cache.insert(offset, Vec::new());
+        }
+        assert_eq!(cache.entries.len(), 10);
+        assert_eq!(cache.size, 10 * ENTRY_OVERHEAD);
+        assert_eq!(cache.get(999), Some(Vec::new()));
+        assert_eq!(cache.get(0), None);
+    }
+}
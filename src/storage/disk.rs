@@ -2,26 +2,383 @@ use std::{
     collections::{btree_map, BTreeMap},
     fs::{File, OpenOptions},
     io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    os::unix::fs::FileExt as _,
     path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
 use fs4::FileExt;
+use memmap2::Mmap;
+
+use crate::error::{Error, Result};
 
-use crate::error::Result;
+use super::{cache::ValueCache, engine::{prefix_range, sync_dir, KeyFn, KeyValue, ScanFn, Status}};
 
-pub type KeyDir = BTreeMap<Vec<u8>, (u64, u32)>;
-const LOG_HEADER_SIZE: u32 = 8; //key len (u32=>4) + value len (u32=>4) = 8
+// key -> (offset of value, value len in the log, entry flags)
+pub type KeyDir = BTreeMap<Vec<u8>, (u64, u32, u8)>;
+// entries of a log batch: key, value (None means delete), value flags
+type LogBatch = [(Vec<u8>, Option<Vec<u8>>, u8)];
+const LOG_HEADER_SIZE: u32 = 13; // crc (u32=>4) + flags (u8=>1) + key len (u32=>4) + value len (u32=>4) = 13
+const LOG_FLAG_LZ4: u8 = 0x01; // value is compressed by lz4
+const LOG_FLAG_AES: u8 = 0x02; // key and value are encrypted by AES-256-GCM
+const LOG_FLAG_TTL: u8 = 0x04; // value starts with its expire time (u64=>8, ms since unix epoch)
+const TTL_SIZE: usize = 8;
+const NONCE_SIZE: usize = 12; // AES-GCM nonce, stored in front of each ciphertext
+const ENCRYPT_OVERHEAD: u32 = 28; // nonce (12) + tag (16) added to each encrypted key/value
+const COMPRESS_MIN_SIZE: usize = 64; // smaller values are not worth compressing
+const LOG_TOMBSTONE: i32 = -1; // value len of a deleted key
+const LOG_BATCH_FLAG: i32 = -2; // value len of a batch header
+const LOG_BATCH_KEY_SIZE: u32 = 8; // batch header key holds the batch body len (u64=>8)
+const DEFAULT_CACHE_SIZE: usize = 8 * 1024 * 1024; // bytes of values cached in memory
+const DEFAULT_MAX_KEY_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_VALUE_SIZE: usize = 64 * 1024 * 1024;
+// value len is an i32 in the entry header, and encryption makes the value longer
+const VALUE_SIZE_LIMIT: usize = i32::MAX as usize - ENCRYPT_OVERHEAD as usize;
+const KEY_SIZE_LIMIT: usize = u32::MAX as usize - ENCRYPT_OVERHEAD as usize;
 pub struct DiskEngine {
-    keydir: KeyDir, // memory index:  BTreeMap<Vec<u8>, (u64, u32)>: key->(offset, value len)
+    keydir: KeyDir, // memory index:  BTreeMap<Vec<u8>, (u64, u32, u8)>: key->(offset, value len, flags)
     log: Log,
+    dead_bytes: u64, // bytes of overwritten/deleted entries, tombstones and batch headers
+    compact_policy: Option<CompactPolicy>, // None means never compact automatically
+    durability: Durability,
+    // the background fsync of Durability::EveryNms
+    flusher: Option<Arc<Flusher>>,
+    last_compaction: Option<SystemTime>,
+    max_key_size: usize,
+    max_value_size: usize,
+    // set by close, the file is no longer locked and must not be written
+    closed: bool,
 }
 
-impl DiskEngine {
-    pub fn new(file_path: PathBuf) -> Result<Self> {
-        let mut log = Log::new(file_path)?;
+// when to fsync the log, writes only reach the OS page cache before that
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Durability {
+    // fsync after every write
+    Always,
+    // a background thread fsyncs every N milliseconds if the log was written since the last time,
+    // a crash loses at most the last N ms of writes, 0 is the same as Always
+    EveryNms(u64),
+    // fsync when a transaction commits (Engine::sync)
+    OnCommit,
+    // leave it to the OS
+    Never,
+}
+
+// how values are compressed when written, reading always follows the flags of each entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+// when to compact the log automatically
+// compact if dead bytes exceed `dead_bytes`, or take more than `dead_ratio` of the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactPolicy {
+    pub dead_ratio: f64,
+    pub dead_bytes: u64,
+    // files smaller than this are never compacted, so small files are not rewritten again and again
+    pub min_file_size: u64,
+}
+
+impl Default for CompactPolicy {
+    fn default() -> Self {
+        Self {
+            dead_ratio: 0.5,
+            dead_bytes: 64 * 1024 * 1024,
+            min_file_size: 1024 * 1024,
+        }
+    }
+}
+
+impl CompactPolicy {
+    fn should_compact(&self, dead_bytes: u64, file_size: u64) -> bool {
+        if file_size < self.min_file_size || dead_bytes == 0 {
+            return false;
+        }
+        dead_bytes >= self.dead_bytes || dead_bytes as f64 >= file_size as f64 * self.dead_ratio
+    }
+}
+
+// options to open a DiskEngine
+// eg: DiskEngineConfig::new(path).durability(Durability::Always).cache_capacity(0).open()?
+#[derive(Clone)]
+pub struct DiskEngineConfig {
+    file_path: PathBuf,
+    durability: Durability,
+    compact_policy: Option<CompactPolicy>,
+    cache_capacity: usize,
+    compression: Compression,
+    mmap: bool,
+    encryption_key: Option<[u8; 32]>,
+    max_key_size: usize,
+    max_value_size: usize,
+    read_only: bool,
+}
+
+impl DiskEngineConfig {
+    pub fn new(file_path: PathBuf) -> Self {
+        Self {
+            file_path,
+            durability: Durability::OnCommit,
+            compact_policy: Some(CompactPolicy::default()),
+            cache_capacity: DEFAULT_CACHE_SIZE,
+            compression: Compression::None,
+            mmap: false,
+            encryption_key: None,
+            max_key_size: DEFAULT_MAX_KEY_SIZE,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            read_only: false,
+        }
+    }
+
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    // when to compact the log automatically, None disables it
+    pub fn compact_policy(mut self, policy: Option<CompactPolicy>) -> Self {
+        self.compact_policy = policy;
+        self
+    }
+
+    // bytes of hot values kept in memory, with a few bytes for each, 0 disables the cache
+    pub fn cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = capacity;
+        self
+    }
+
+    // compress values written, entries already in the log are left as they are
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // read values through a memory map of the log instead of seek + read
+    pub fn mmap(mut self, enabled: bool) -> Self {
+        self.mmap = enabled;
+        self
+    }
+
+    // keys and values written are encrypted by the 256 bits key, so is the hint file
+    // the same key must be supplied to open the log again
+    pub fn encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    // writes with a larger key fail with Error::KeyTooLarge
+    pub fn max_key_size(mut self, size: usize) -> Self {
+        self.max_key_size = size;
+        self
+    }
+
+    // writes with a larger value fail with Error::ValueTooLarge
+    pub fn max_value_size(mut self, size: usize) -> Self {
+        self.max_value_size = size;
+        self
+    }
+
+    // open an existing log for reading only, eg: a backup job on a copied data directory
+    // it takes a shared lock, so several readers can attach but no writer can at the same time
+    // writes fail with Error::ReadOnly, a torn tail is skipped rather than truncated
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.file_path.is_dir() {
+            return Err(Error::Config(format!(
+                "log path {} is a directory",
+                self.file_path.display()
+            )));
+        }
+        if self.max_key_size > KEY_SIZE_LIMIT {
+            return Err(Error::Config(format!(
+                "max key size must not exceed {}, got {}",
+                KEY_SIZE_LIMIT, self.max_key_size
+            )));
+        }
+        if self.max_value_size > VALUE_SIZE_LIMIT {
+            return Err(Error::Config(format!(
+                "max value size must not exceed {}, got {}",
+                VALUE_SIZE_LIMIT, self.max_value_size
+            )));
+        }
+        if let Some(policy) = &self.compact_policy {
+            if !(policy.dead_ratio > 0.0 && policy.dead_ratio <= 1.0) {
+                return Err(Error::Config(format!(
+                    "compact dead ratio must be in (0, 1], got {}",
+                    policy.dead_ratio
+                )));
+            }
+            if policy.dead_bytes == 0 {
+                return Err(Error::Config("compact dead bytes must be positive".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn open(self) -> Result<DiskEngine> {
+        self.validate()?;
+        let mut log = match self.read_only {
+            true => Log::open_read_only(self.file_path)?,
+            false => Log::new(self.file_path)?,
+        };
+        // a compaction that crashed before its rename, the log itself is untouched
+        if !log.read_only {
+            log.remove_compact()?;
+        }
+        log.cache.get_mut()?.set_capacity(self.cache_capacity);
+        log.compression = self.compression;
+        log.mmap = self.mmap.then(|| RwLock::new(None));
+        log.cipher = self.encryption_key.map(|key| Aes256Gcm::new(&key.into()));
         // boot, recover keydir
         let keydir = log.build_keydir()?;
-        Ok(Self { keydir, log })
+        let dead_bytes = log.size()? - DiskEngine::live_bytes(&keydir);
+        let flusher = match self.durability {
+            Durability::EveryNms(ms) if ms > 0 && !log.read_only => {
+                Some(Flusher::start(&log.file, Duration::from_millis(ms))?)
+            }
+            _ => None,
+        };
+        Ok(DiskEngine {
+            keydir,
+            log,
+            dead_bytes,
+            compact_policy: self.compact_policy,
+            durability: self.durability,
+            flusher,
+            last_compaction: None,
+            max_key_size: self.max_key_size,
+            max_value_size: self.max_value_size,
+            closed: false,
+        })
+    }
+}
+
+impl DiskEngine {
+    // open with the default config
+    pub fn new(file_path: PathBuf) -> Result<Self> {
+        DiskEngineConfig::new(file_path).open()
+    }
+
+    // reject the write before anything reaches the log
+    fn check_write(&self, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed);
+        }
+        if self.log.read_only {
+            return Err(Error::ReadOnly);
+        }
+        if key.len() > self.max_key_size {
+            return Err(Error::KeyTooLarge { size: key.len(), max: self.max_key_size });
+        }
+        match value {
+            Some(value) if value.len() > self.max_value_size => Err(Error::ValueTooLarge {
+                size: value.len(),
+                max: self.max_value_size,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // append the entry, then point keydir to it
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>, flags: u8) -> Result<()> {
+        // wirte to disk
+        let (offset, size, flags) = self.log.write_entry(&key, Some(&value), flags)?;
+        // update memory index
+        // 这里offset具体用途：当一条记录写入完成后，文件的下一个空闲位置就是 offset + size，这是新记录的写入起点。
+        // eg: offset = 100, size = 50  =>  100---------|----150
+        // value len = 20                              130
+        // key len, value len, key => 100---130   value => 130---150
+        // value len is the len written in the log, smaller than the value if compressed
+        let pos = Self::value_pos(&key, offset, size, flags);
+        self.discard(&key);
+        // insert key | (offset of value, value len, flags) => (130, 20, 0)      这里offset含义：日志记录中 Value 数据的起始位置
+        self.keydir.insert(key, pos);
+        self.sync_write()?;
+        self.maybe_compact()
+    }
+
+    // strip the expire time of a ttl value, None if it has expired
+    fn unexpired(mut value: Vec<u8>, flags: u8) -> Option<Vec<u8>> {
+        if Self::expired(&value, flags)? {
+            return None;
+        }
+        if flags & LOG_FLAG_TTL == 0 {
+            return Some(value);
+        }
+        Some(value.split_off(TTL_SIZE))
+    }
+
+    // whether a value with the expire time in front of it has expired, None if the time is cut off
+    fn expired(value: &[u8], flags: u8) -> Option<bool> {
+        if flags & LOG_FLAG_TTL == 0 {
+            return Some(false);
+        }
+        let expire_at = u64::from_be_bytes(value.get(..TTL_SIZE)?.try_into().ok()?);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Some(expire_at <= now.as_millis() as u64)
+    }
+
+    // fsync after a write if the policy asks for it, or leave it to the flusher
+    fn sync_write(&mut self) -> Result<()> {
+        match self.durability {
+            Durability::Always | Durability::EveryNms(0) => self.log.sync()?,
+            Durability::EveryNms(_) => {
+                if let Some(flusher) = &self.flusher {
+                    flusher.dirty.store(true, Ordering::Relaxed);
+                }
+            }
+            Durability::OnCommit | Durability::Never => {}
+        }
+        Ok(())
+    }
+
+    // bytes of the entries keydir points to
+    fn live_bytes(keydir: &KeyDir) -> u64 {
+        keydir
+            .iter()
+            .map(|(key, (_, value_size, flags))| Self::entry_size(key, *value_size, *flags))
+            .sum()
+    }
+
+    fn entry_size(key: &[u8], value_size: u32, flags: u8) -> u64 {
+        LOG_HEADER_SIZE as u64 + Log::stored_key_size(key, flags) as u64 + value_size as u64
+    }
+
+    // the old entry of key becomes garbage
+    fn discard(&mut self, key: &[u8]) {
+        if let Some((_, value_size, flags)) = self.keydir.remove(key) {
+            self.dead_bytes += Self::entry_size(key, value_size, flags);
+        }
+    }
+
+    // keydir item of the entry written at offset, the value is at the end of the entry
+    fn value_pos(key: &[u8], offset: u64, size: u32, flags: u8) -> (u64, u32, u8) {
+        let value_size = size - LOG_HEADER_SIZE - Log::stored_key_size(key, flags);
+        (offset + size as u64 - value_size as u64, value_size, flags)
+    }
+
+    // compact the log if the garbage exceeds the policy
+    fn maybe_compact(&mut self) -> Result<()> {
+        if let Some(policy) = &self.compact_policy {
+            if policy.should_compact(self.dead_bytes, self.log.size()?) {
+                self.compact()?;
+            }
+        }
+        Ok(())
     }
 
     // rewrite log data to a new tmp file, then set tmp file as formal data file
@@ -34,91 +391,268 @@ impl DiskEngine {
     // when we delete or set new value to a key, we will update keydir and append info to log
     // what we need to do here is to rewrite log by keydir
     fn compact(&mut self) -> Result<()> {
+        self.rewrite(&mut |_| Ok(true))
+    }
+
+    // compaction without the keys keep rejects, see Engine::compact_filtered
+    fn rewrite(&mut self, keep: &mut KeyFn<'_>) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed);
+        }
+        if self.log.read_only {
+            return Err(Error::ReadOnly);
+        }
         // create new file with suffix "compact"
-        let mut new_path = self.log.file_path.clone();
-        new_path.set_extension("compact");
-        let mut new_log = Log::new(new_path)?;
-        let mut new_keydir: BTreeMap<Vec<u8>, (u64, u32)> = KeyDir::new();
-        // rewrite
-        for (key, (offset, value_size)) in self.keydir.iter() {
-            // get value
-            let value = self.log.read_value(*offset, *value_size)?;
-            let (new_offset, new_size) = new_log.write_entry(key, Some(&value))?;
-            new_keydir.insert(
-                key.clone(),
-                (
-                    new_offset + new_size as u64 - *value_size as u64,
-                    *value_size,
-                ),
-            );
+        let mut new_log = Log::new(self.log.compact_path())?;
+        new_log.cache.get_mut()?.set_capacity(self.log.cache.lock()?.capacity());
+        new_log.mmap = self.log.mmap.as_ref().map(|_| RwLock::new(None));
+        new_log.compression = self.log.compression;
+        new_log.cipher = self.log.cipher.clone();
+        // drop leftover of an interrupted compaction
+        new_log.file.set_len(0)?;
+        // stream the live entries into the new file one by one, expired keys are dropped
+        // the file only replaces the log once it is complete, so it needs no batch to be atomic
+        let mut new_keydir = KeyDir::new();
+        let mut writer = BufWriter::new(&new_log.file);
+        let mut buf = Vec::new();
+        let mut offset = 0;
+        for (key, (value_offset, value_size, flags)) in self.keydir.iter() {
+            if !keep(key)? {
+                continue;
+            }
+            let value = self.log.read_value(*value_offset, *value_size, *flags)?;
+            if Self::expired(&value, *flags) != Some(false) {
+                continue;
+            }
+            buf.clear();
+            let (size, flags) = new_log.encode_entry(&mut buf, key, Some(&value), flags & LOG_FLAG_TTL)?;
+            writer.write_all(&buf)?;
+            new_keydir.insert(key.clone(), Self::value_pos(key, offset, size, flags));
+            offset += size as u64;
         }
-        // replace tmp file as formal file
+        writer.flush()?;
+        drop(writer);
+        // the new file must be complete on disk before it replaces the log, or a crash right after
+        // the rename could leave a log whose tail never made it to the disk
+        new_log.sync()?;
+        // old hint points into the old log, remove it before the log is replaced
+        self.log.remove_hint()?;
+        // replace tmp file as formal file, the rename is durable once the directory is synced
+        // a crash before that leaves the old log, or the new one, both complete
         std::fs::rename(&new_log.file_path, &self.log.file_path)?;
+        sync_dir(&self.log.file_path)?;
         new_log.file_path = self.log.file_path.clone();
         self.keydir = new_keydir;
         self.log = new_log;
-        Ok(())
+        if let Some(flusher) = &self.flusher {
+            flusher.replace(Some(&self.log.file))?;
+        }
+        // the new log holds nothing but live entries
+        self.dead_bytes = self.log.size()? - Self::live_bytes(&self.keydir);
+        self.last_compaction = Some(SystemTime::now());
+        self.log.write_hint(&self.keydir)
+    }
+}
+
+// clean shutdown without close, save keydir so that next startup does not need to scan the whole log
+impl Drop for DiskEngine {
+    fn drop(&mut self) {
+        // the flusher lets go of the file, so the lock is gone once the engine is
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.replace(None);
+        }
+        if !self.log.read_only && !self.closed {
+            let _ = self.log.sync();
+            let _ = self.log.write_hint(&self.keydir);
+        }
     }
 }
 
 impl super::engine::Engine for DiskEngine {
     type EngineIterator<'a> = DiskEngineIterator<'a>;
-    // +----------------+------------------+--------------------+---------------------+
-    // | Key Length (4) | Value Length (4) | Key (Variable)     | Value (Variable)    |
-    // +----------------+------------------+--------------------+---------------------+
+
+    fn open(path: PathBuf) -> Result<Self> {
+        Self::new(path)
+    }
+
+    // +-----------+-----------+----------------+------------------+----------------+------------------+
+    // | CRC32 (4) | Flags (1) | Key Length (4) | Value Length (4) | Key (Variable) | Value (Variable) |
+    // +-----------+-----------+----------------+------------------+----------------+------------------+
     // append log to disk, get (offset, value len)
     fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        // wirte to disk
-        let (offset, size) = self.log.write_entry(&key, Some(&value))?;
-        // update memory index
-        // 这里offset具体用途：当一条记录写入完成后，文件的下一个空闲位置就是 offset + size，这是新记录的写入起点。
-        // eg: offset = 100, size = 50  =>  100---------|----150
-        // value len = 20                              130
-        // key len, value len, key => 100---130   value => 130---150
-        let value_size = value.len() as u32;
-        // insert key | (offset of value, value len) => (130, 20)      这里offset含义：日志记录中 Value 数据的起始位置
-        self.keydir
-            .insert(key, (offset + size as u64 - value_size as u64, value_size));
-        Ok(())
+        self.check_write(&key, Some(&value))?;
+        self.put(key, value, 0)
+    }
+
+    // expire time is stored in front of the value, reads and compaction drop the key after it
+    fn set_with_ttl(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        self.check_write(&key, Some(&value))?;
+        let expire_at = (SystemTime::now() + ttl).duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut buf = (expire_at.as_millis() as u64).to_be_bytes().to_vec();
+        buf.extend(value);
+        self.put(key, buf, LOG_FLAG_TTL)
     }
 
     // get data in disk by (offset of value, value len) in keydir
-    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
         match self.keydir.get(&key) {
-            Some((offset, value_size)) => {
-                let val = self.log.read_value(*offset, *value_size)?;
-                Ok(Some(val))
+            Some((offset, value_size, flags)) => {
+                let val = self.log.read_value(*offset, *value_size, *flags)?;
+                Ok(Self::unexpired(val, *flags))
             }
             None => Ok(None),
         }
     }
 
     fn delete(&mut self, key: Vec<u8>) -> Result<()> {
-        self.log.write_entry(&key, None)?;
-        self.keydir.remove(&key);
+        self.check_write(&key, None)?;
+        let (_, size, _) = self.log.write_entry(&key, None, 0)?;
+        // tombstone itself is garbage too
+        self.dead_bytes += size as u64;
+        self.discard(&key);
+        self.sync_write()?;
+        self.maybe_compact()
+    }
+
+    // write the whole batch with one append, then update keydir
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        for (key, value) in batch.iter() {
+            self.check_write(key, value.as_deref())?;
+        }
+        let batch = batch.into_iter().map(|(key, value)| (key, value, 0)).collect::<Vec<_>>();
+        let positions = self.log.write_batch(&batch)?;
+        // batch header is garbage
+        self.dead_bytes += (LOG_HEADER_SIZE + LOG_BATCH_KEY_SIZE) as u64;
+        for ((key, value, _), (offset, size, flags)) in batch.into_iter().zip(positions) {
+            self.discard(&key);
+            match value {
+                Some(_) => {
+                    let pos = Self::value_pos(&key, offset, size, flags);
+                    self.keydir.insert(key, pos);
+                }
+                None => {
+                    self.dead_bytes += size as u64;
+                }
+            }
+        }
+        self.sync_write()?;
+        self.maybe_compact()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        if self.log.read_only || self.closed {
+            return Ok(());
+        }
+        match self.durability {
+            Durability::OnCommit => self.log.sync(),
+            // commit does not force fsync, the flusher syncs within N ms
+            // Always has synced every write already
+            Durability::Always | Durability::EveryNms(_) | Durability::Never => Ok(()),
+        }
+    }
+
+    fn compact_filtered(&mut self, keep: &mut KeyFn<'_>) -> Result<()> {
+        self.rewrite(keep)
+    }
+
+    // fsync whatever the durability policy left in the OS cache, save the hint, then unlock the file
+    // so another process can open it while this engine is still around
+    fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        if let Some(flusher) = self.flusher.take() {
+            flusher.replace(None)?;
+        }
+        if !self.log.read_only {
+            self.log.sync()?;
+            self.log.write_hint(&self.keydir)?;
+        }
+        FileExt::unlock(&self.log.file)?;
+        self.closed = true;
         Ok(())
     }
 
-    fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+    fn status(&mut self) -> Result<Status> {
+        Ok(Status {
+            name: "disk".to_string(),
+            keys: self.keydir.len() as u64,
+            live_bytes: Self::live_bytes(&self.keydir),
+            dead_bytes: self.dead_bytes,
+            file_size: self.log.size()?,
+            last_compaction: self.last_compaction,
+        })
+    }
+
+    fn scan(&self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
         DiskEngineIterator {
             inner: self.keydir.range(range),
-            log: &mut self.log
+            log: &self.log
+        }
+    }
+
+    // the value is read from the log either way, the key is not copied
+    fn scan_with(
+        &self,
+        range: impl std::ops::RangeBounds<Vec<u8>>,
+        f: &mut ScanFn<'_>,
+    ) -> Result<()> {
+        for (key, (offset, value_size, flags)) in self.keydir.range(range) {
+            if let Some(value) = Self::unexpired(self.log.read_value(*offset, *value_size, *flags)?, *flags) {
+                f(key, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // values are read only for the keys f accepts
+    fn find_last(&self, range: impl std::ops::RangeBounds<Vec<u8>>, f: &mut KeyFn<'_>) -> Result<Option<KeyValue>> {
+        for (key, (offset, value_size, flags)) in self.keydir.range(range).rev() {
+            if !f(key)? {
+                continue;
+            }
+            if let Some(value) = Self::unexpired(self.log.read_value(*offset, *value_size, *flags)?, *flags) {
+                return Ok(Some((key.clone(), value)));
+            }
         }
+        Ok(None)
+    }
+
+    // estimated from keydir without reading the log, expired keys are counted until compaction drops them
+    fn count_prefix(&self, prefix: Vec<u8>) -> Result<u64> {
+        Ok(self.keydir.range(prefix_range(prefix)).count() as u64)
+    }
+
+    // sizes as stored, after compression and with the expire time of ttl values
+    fn size_prefix(&self, prefix: Vec<u8>) -> Result<u64> {
+        Ok(self
+            .keydir
+            .range(prefix_range(prefix))
+            .map(|(key, (_, value_size, _))| key.len() as u64 + *value_size as u64)
+            .sum())
     }
 }
 
 pub struct DiskEngineIterator<'a> {
-    inner: btree_map::Range<'a, Vec<u8>, (u64, u32)>,
-    log: &'a mut Log,
+    inner: btree_map::Range<'a, Vec<u8>, (u64, u32, u8)>,
+    // reads don't move the file cursor, so scans and gets can run at the same time
+    log: &'a Log,
 }
 
 impl<'a> DiskEngineIterator<'a> {
-    fn map(&mut self, item: (&Vec<u8>, &(u64, u32))) -> <Self as Iterator>::Item {
-        let (key, (offset, value_size)) = item;
-        let value = self.log.read_value(*offset, *value_size)?;
+    // None if the key has expired
+    fn map(log: &Log, item: (&Vec<u8>, &(u64, u32, u8))) -> Option<<Self as Iterator>::Item> {
+        let (key, (offset, value_size, flags)) = item;
+        let value = match log.read_value(*offset, *value_size, *flags) {
+            Ok(value) => DiskEngine::unexpired(value, *flags)?,
+            Err(err) => return Some(Err(err)),
+        };
         // •	key.clone()：这里调用 clone 是因为 key 是一个引用类型（&Vec<u8>），而我们需要返回一个拥有所有权的 Vec<u8>，所以需要克隆。
         // •	value：因为 read_value 返回的 value 已经是一个拥有所有权的值，因此可以直接返回。
-        Ok((key.clone(), value))
+        Some(Ok((key.clone(), value)))
     }
 }
 
@@ -128,13 +662,69 @@ impl<'a> Iterator for DiskEngineIterator<'a> {
     type Item = Result<(Vec<u8>, Vec<u8>)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|item| self.map(item))
+        // skip expired keys
+        loop {
+            let item = self.inner.next()?;
+            if let Some(result) = Self::map(self.log, item) {
+                return Some(result);
+            }
+        }
     }
 }
 
 impl<'a> DoubleEndedIterator for DiskEngineIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.inner.next_back().map(|item| self.map(item))
+        loop {
+            let item = self.inner.next_back()?;
+            if let Some(result) = Self::map(self.log, item) {
+                return Some(result);
+            }
+        }
+    }
+}
+
+// the thread of Durability::EveryNms holds it weakly, and ends once the engine has dropped it
+struct Flusher {
+    // a handle of the log file, replaced by a compaction, None once the engine is closed
+    file: Mutex<Option<File>>,
+    // the log was written since the last fsync
+    dirty: AtomicBool,
+}
+
+impl Flusher {
+    fn start(file: &File, interval: Duration) -> Result<Arc<Self>> {
+        let flusher = Arc::new(Self { file: Mutex::new(Some(file.try_clone()?)), dirty: AtomicBool::new(false) });
+        let weak = Arc::downgrade(&flusher);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(flusher) = weak.upgrade() else {
+                return;
+            };
+            if let Err(err) = flusher.flush() {
+                tracing::warn!(error = %err, "log fsync failed");
+            }
+        });
+        Ok(flusher)
+    }
+
+    // fsync the log if it was written since the last time, a failed fsync is tried again next time
+    fn flush(&self) -> Result<()> {
+        let file = self.file.lock()?;
+        if let Some(file) = file.as_ref() {
+            if self.dirty.swap(false, Ordering::Relaxed) {
+                if let Err(err) = file.sync_data() {
+                    self.dirty.store(true, Ordering::Relaxed);
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // waits for a running fsync, so the old file is not synced after it is replaced
+    fn replace(&self, file: Option<&File>) -> Result<()> {
+        *self.file.lock()? = file.map(File::try_clone).transpose()?;
+        Ok(())
     }
 }
 
@@ -142,6 +732,13 @@ impl<'a> DoubleEndedIterator for DiskEngineIterator<'a> {
 struct Log {
     file_path: PathBuf,
     file: std::fs::File,
+    cache: Mutex<ValueCache>,
+    // None: mmap read disabled, Some(None): enabled but not mapped yet
+    mmap: Option<RwLock<Option<Mmap>>>,
+    compression: Compression,
+    // None: entries are written in plain text
+    cipher: Option<Aes256Gcm>,
+    read_only: bool,
 }
 
 impl Log {
@@ -149,12 +746,13 @@ impl Log {
         // check dir exist, if not exist, create recursively by using create_dir_all()
         if let Some(dir) = file_path.parent() {
             if !dir.exists() {
-                std::fs::create_dir_all(&dir)?;
+                std::fs::create_dir_all(dir)?;
             }
         }
         // open
         let file = OpenOptions::new()
             .create(true) // create the file if it does not exist
+            .truncate(false)
             .read(true)
             .write(true)
             .open(&file_path)?;
@@ -162,96 +760,475 @@ impl Log {
         // add exclusive lock, ensure only one service use this file
         file.try_lock_exclusive()?;
 
-        Ok(Self { file_path, file })
+        Ok(Self::with_file(file_path, file, false))
+    }
+
+    // the file must exist, it is never modified
+    fn open_read_only(file_path: PathBuf) -> Result<Self> {
+        let file = OpenOptions::new().read(true).open(&file_path)?;
+        // shared with other readers, conflict with a writer
+        FileExt::try_lock_shared(&file)?;
+        Ok(Self::with_file(file_path, file, true))
+    }
+
+    fn with_file(file_path: PathBuf, file: File, read_only: bool) -> Self {
+        Self {
+            file_path,
+            file,
+            cache: Mutex::new(ValueCache::new(DEFAULT_CACHE_SIZE)),
+            mmap: None,
+            compression: Compression::None,
+            cipher: None,
+            read_only,
+        }
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    // flush file data from OS cache to disk
+    fn sync(&self) -> Result<()> {
+        Ok(self.file.sync_data()?)
     }
 }
 
 impl Log {
-    // +----------------+------------------+--------------------+---------------------+
-    // | Key Length (4) | Value Length (4) | Key (Variable)     | Value (Variable)    |
-    // +----------------+------------------+--------------------+---------------------+
-    fn write_entry(&mut self, key: &Vec<u8>, value: Option<&Vec<u8>>) -> Result<(u64, u32)> {
+    // +-----------+-----------+----------------+------------------+----------------+------------------+
+    // | CRC32 (4) | Flags (1) | Key Length (4) | Value Length (4) | Key (Variable) | Value (Variable) |
+    // +-----------+-----------+----------------+------------------+----------------+------------------+
+    // return (offset, size, flags) of the entry
+    // flags: flags of the value itself (eg: ttl), compression and encryption flags are added here
+    fn write_entry(&mut self, key: &[u8], value: Option<&[u8]>, flags: u8) -> Result<(u64, u32, u8)> {
         // move to the tail of the file, and append data
         let offset = self.file.seek(SeekFrom::End(0))?;
-        let key_size = key.len() as u32;
-        let value_size = value.map_or(0, |v: &Vec<u8>| v.len() as u32);
-        let total_size = LOG_HEADER_SIZE + key_size + value_size; // key len + value len + mutable key info size + mutable value info size
-        let mut writer = BufWriter::with_capacity(total_size as usize, &self.file);
-        // write to buffer => key len | value len | key | value
-        writer.write_all(&key_size.to_be_bytes())?;
-        // None -> -1 -> delete
-        writer.write_all(&value.map_or(-1, |v| v.len() as i32).to_be_bytes())?;
-        writer.write_all(&key)?;
-        if let Some(v) = value {
-            writer.write_all(v)?;
-        }
+        let mut buf = Vec::new();
+        let (total_size, flags) = self.encode_entry(&mut buf, key, value, flags)?;
+        let mut writer = BufWriter::new(&self.file);
+        writer.write_all(&buf)?;
         // flush buffer data to disk
         writer.flush()?;
         // data store in    offset ---------- offset + total size
-        Ok((offset, total_size))
+        Ok((offset, total_size, flags))
+    }
+
+    // +-----------+-----------+----------------+------------------+--------------------+---------------------+
+    // | CRC32 (4) | Flags (1) | Key Length (4) | Batch Flag (4)   | Body Length (8)    | Entries (Variable)  |
+    // +-----------+-----------+----------------+------------------+--------------------+---------------------+
+    // append all entries with one write, behind a header holding the body length
+    // build_keydir only applies a batch whose body is completely on disk
+    // return (offset, size, flags) of each entry, same as write_entry
+    fn write_batch(&mut self, batch: &LogBatch) -> Result<Vec<(u64, u32, u8)>> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        let body_offset = offset + (LOG_HEADER_SIZE + LOG_BATCH_KEY_SIZE) as u64;
+        let mut body = Vec::new();
+        let mut positions = Vec::with_capacity(batch.len());
+        for (key, value, flags) in batch {
+            let entry_offset = body_offset + body.len() as u64;
+            let (size, flags) = self.encode_entry(&mut body, key, value.as_deref(), *flags)?;
+            positions.push((entry_offset, size, flags));
+        }
+        let mut buf = Vec::with_capacity((LOG_HEADER_SIZE + LOG_BATCH_KEY_SIZE) as usize + body.len());
+        Self::encode_record(&mut buf, 0, &(body.len() as u64).to_be_bytes(), LOG_BATCH_FLAG, &[]);
+        buf.extend(body);
+        let mut writer = BufWriter::new(&self.file);
+        writer.write_all(&buf)?;
+        writer.flush()?;
+        Ok(positions)
+    }
+
+    // encode one entry => crc | flags | key len | value len | key | value, return (entry size, flags)
+    // the value is compressed if compression is on and it actually gets smaller,
+    // then key and value are encrypted if there is a cipher
+    fn encode_entry(&self, buf: &mut Vec<u8>, key: &[u8], value: Option<&[u8]>, flags: u8) -> Result<(u32, u8)> {
+        let mut flags = flags;
+        let mut value = value.map(|v| v.to_vec());
+        if let Some(v) = &value {
+            if self.compression == Compression::Lz4 && v.len() >= COMPRESS_MIN_SIZE {
+                let compressed = lz4_flex::compress_prepend_size(v);
+                if compressed.len() < v.len() {
+                    value = Some(compressed);
+                    flags |= LOG_FLAG_LZ4;
+                }
+            }
+        }
+        let mut key = key.to_vec();
+        if let Some(cipher) = &self.cipher {
+            key = Self::encrypt(cipher, &key)?;
+            value = value.map(|v| Self::encrypt(cipher, &v)).transpose()?;
+            flags |= LOG_FLAG_AES;
+        }
+        // None -> -1 -> delete
+        let value_len = value.as_ref().map_or(LOG_TOMBSTONE, |v| v.len() as i32);
+        let size = Self::encode_record(buf, flags, &key, value_len, value.as_deref().unwrap_or_default());
+        Ok((size, flags))
+    }
+
+    // nonce | ciphertext | tag
+    fn encrypt(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, data)
+            .map_err(|err| Error::Internal(format!("encrypt failed: {}", err)))?;
+        let mut buf = nonce.to_vec();
+        buf.extend(ciphertext);
+        Ok(buf)
+    }
+
+    fn decrypt(cipher: Option<&Aes256Gcm>, data: &[u8]) -> Result<Vec<u8>> {
+        let Some(cipher) = cipher else {
+            return Err(Error::Internal("log is encrypted, a key is required to open it".to_string()));
+        };
+        if data.len() < NONCE_SIZE {
+            return Err(Error::Internal("encrypted data is too short".to_string()));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| Error::Internal("decrypt failed, wrong key?".to_string()))
+    }
+
+    // len of the key written in the log
+    fn stored_key_size(key: &[u8], flags: u8) -> u32 {
+        match flags & LOG_FLAG_AES {
+            0 => key.len() as u32,
+            _ => key.len() as u32 + ENCRYPT_OVERHEAD,
+        }
     }
 
-    // traverse disk file, and get all the log, build new memory index
+    fn encode_record(buf: &mut Vec<u8>, flags: u8, key: &[u8], value_len: i32, value: &[u8]) -> u32 {
+        let start = buf.len();
+        // placeholder of crc, fill it after the rest is written
+        buf.extend([0; 4]);
+        buf.push(flags);
+        buf.extend((key.len() as u32).to_be_bytes());
+        buf.extend(value_len.to_be_bytes());
+        buf.extend(key);
+        buf.extend(value);
+        // crc covers everything behind itself
+        let crc = crc32fast::hash(&buf[start + 4..]);
+        buf[start..start + 4].copy_from_slice(&crc.to_be_bytes());
+        // crc + flags + key len + value len + mutable key info size + mutable value info size
+        (buf.len() - start) as u32
+    }
+
+    // load keydir from hint file, then replay the log written after it
+    // fall back to scan the whole log if there is no usable hint file
     fn build_keydir(&mut self) -> Result<KeyDir> {
-        let mut keydir = KeyDir::new();
+        let (keydir, offset) = self.load_hint()?.unwrap_or_default();
+        self.replay(keydir, offset)
+    }
+
+    // traverse disk file from offset, and apply the log to memory index
+    // if the process crashed while appending, the tail of the file may hold an incomplete entry,
+    // drop it so that new entries are appended right after the last complete one
+    fn replay(&mut self, mut keydir: KeyDir, mut offset: u64) -> Result<KeyDir> {
+        let cipher = self.cipher.as_ref();
         let mut buf_reader = BufReader::new(&self.file);
         let file_size = self.file.metadata()?.len();
 
-        let mut offset = 0;
-        loop {
-            if offset >= file_size {
-                break;
-            }
-            let (key, value_size) = Self::read_entry(&mut buf_reader, offset)?;
+        while offset < file_size {
+            let (key, value_size, flags) = match Self::read_entry(&mut buf_reader, offset, file_size)? {
+                Some(entry) => entry,
+                None => break,
+            };
             let key_size = key.len() as u32;
-            if value_size == -1 {
-                keydir.remove(&key);
-                // No value, mutable value size = 0;
-                offset += LOG_HEADER_SIZE as u64 + key_size as u64;
+            if value_size == LOG_BATCH_FLAG {
+                // batch header, key holds the length of the entries behind it
+                let body_size = u64::from_be_bytes(key.as_slice().try_into()?);
+                let body_offset = offset + LOG_HEADER_SIZE as u64 + key_size as u64;
+                let body_end = body_offset + body_size;
+                if body_end > file_size {
+                    break;
+                }
+                // the whole body is in the file, so it was written out, an entry of it that is cut off
+                // by the body end or fails its crc is corrupted, not torn
+                let mut entries = Vec::new();
+                let mut entry_offset = body_offset;
+                while entry_offset < body_end {
+                    match Self::read_entry(&mut buf_reader, entry_offset, body_end)? {
+                        Some((key, value_size, flags)) => {
+                            let size = Self::entry_size(key.len() as u32, value_size);
+                            entries.push((entry_offset, key, value_size, flags));
+                            entry_offset += size;
+                        }
+                        None => return Err(Error::Corruption { offset: entry_offset }),
+                    }
+                }
+                for (entry_offset, key, value_size, flags) in entries {
+                    Self::apply_entry(&mut keydir, cipher, entry_offset, key, value_size, flags)?;
+                }
+                offset = body_end;
             } else {
-                keydir.insert(
-                    key,
-                    (
-                        offset + LOG_HEADER_SIZE as u64 + key_size as u64,
-                        value_size as u32,
-                    ),
-                );
-                offset += key_size as u64 + value_size as u64 + LOG_HEADER_SIZE as u64;
+                Self::apply_entry(&mut keydir, cipher, offset, key, value_size, flags)?;
+                offset += Self::entry_size(key_size, value_size);
             }
         }
+        drop(buf_reader);
+        if offset < file_size && !self.read_only {
+            // crashed while writing, drop the incomplete tail
+            self.file.set_len(offset)?;
+        }
         Ok(keydir)
     }
 
-    fn read_value(&mut self, offset: u64, value_size: u32) -> Result<Vec<u8>> {
-        self.file.seek(SeekFrom::Start(offset))?;
-        let mut buf = vec![0; value_size as usize];
-        self.file.read_exact(&mut buf)?;
+    // hint file sits next to the log: <log>.hint
+    fn hint_path(&self) -> PathBuf {
+        let mut path = self.file_path.clone().into_os_string();
+        path.push(".hint");
+        path.into()
+    }
+
+    // compaction writes the new log here, then renames it over the log: <log>.compact
+    fn compact_path(&self) -> PathBuf {
+        let mut path = self.file_path.clone();
+        path.set_extension("compact");
+        path
+    }
+
+    // the file of an interrupted compaction, only call it with the log locked
+    fn remove_compact(&self) -> Result<()> {
+        match std::fs::remove_file(self.compact_path()) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // +-----------+-----------+--------------+----------------+------------------+-----------+------------------+----------------+-----+
+    // | CRC32 (4) | Flags (1) | Log Size (8) | Key Length (4) | Value Length (4) | Flags (1) | Value Offset (8) | Key (Variable) | ... |
+    // +-----------+-----------+--------------+----------------+------------------+-----------+------------------+----------------+-----+
+    // log size is the log length the hint covers, entries after it are replayed from the log
+    // everything behind the first flags is encrypted as a whole if there is a cipher
+    fn write_hint(&self, keydir: &KeyDir) -> Result<()> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.size()?.to_be_bytes());
+        for (key, (offset, value_size, flags)) in keydir {
+            body.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            body.extend_from_slice(&value_size.to_be_bytes());
+            body.push(*flags);
+            body.extend_from_slice(&offset.to_be_bytes());
+            body.extend_from_slice(key);
+        }
+        let mut buf = vec![0; 4];
+        match &self.cipher {
+            Some(cipher) => {
+                buf.push(LOG_FLAG_AES);
+                buf.extend(Self::encrypt(cipher, &body)?);
+            }
+            None => {
+                buf.push(0);
+                buf.extend(body);
+            }
+        }
+        let crc = crc32fast::hash(&buf[4..]);
+        buf[..4].copy_from_slice(&crc.to_be_bytes());
+
+        // write a tmp file then rename, a crash never leaves a half written hint
+        let mut tmp_path = self.hint_path().into_os_string();
+        tmp_path.push(".tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, self.hint_path())?;
+        sync_dir(&self.file_path)
+    }
+
+    fn remove_hint(&self) -> Result<()> {
+        match std::fs::remove_file(self.hint_path()) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // return keydir and the log size it covers, None if hint file is missing or unusable
+    fn load_hint(&self) -> Result<Option<(KeyDir, u64)>> {
+        let buf = match std::fs::read(self.hint_path()) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if buf.len() < 5 || crc32fast::hash(&buf[4..]).to_be_bytes() != buf[..4] {
+            return Ok(None);
+        }
+        let buf = match (buf[4] & LOG_FLAG_AES, &self.cipher) {
+            (0, None) => buf[5..].to_vec(),
+            (0, Some(_)) => return Ok(None),
+            (_, cipher) => match Self::decrypt(cipher.as_ref(), &buf[5..]) {
+                Ok(body) => body,
+                // let the log scan report the error
+                Err(_) => return Ok(None),
+            },
+        };
+        if buf.len() < 8 {
+            return Ok(None);
+        }
+        let log_size = u64::from_be_bytes(buf[..8].try_into()?);
+        // log was truncated after the hint was written, the hint cannot be trusted
+        if log_size > self.size()? {
+            return Ok(None);
+        }
+
+        let mut keydir = KeyDir::new();
+        let mut pos = 8;
+        while pos < buf.len() {
+            if pos + 17 > buf.len() {
+                return Ok(None);
+            }
+            let key_size = u32::from_be_bytes(buf[pos..pos + 4].try_into()?) as usize;
+            let value_size = u32::from_be_bytes(buf[pos + 4..pos + 8].try_into()?);
+            let flags = buf[pos + 8];
+            let offset = u64::from_be_bytes(buf[pos + 9..pos + 17].try_into()?);
+            pos += 17;
+            if pos + key_size > buf.len() {
+                return Ok(None);
+            }
+            keydir.insert(buf[pos..pos + key_size].to_vec(), (offset, value_size, flags));
+            pos += key_size;
+        }
+        Ok(Some((keydir, log_size)))
+    }
+
+    // update keydir by the entry at offset, key is the one written in the log
+    fn apply_entry(
+        keydir: &mut KeyDir,
+        cipher: Option<&Aes256Gcm>,
+        offset: u64,
+        key: Vec<u8>,
+        value_size: i32,
+        flags: u8,
+    ) -> Result<()> {
+        let value_offset = offset + LOG_HEADER_SIZE as u64 + key.len() as u64;
+        let key = match flags & LOG_FLAG_AES {
+            0 => key,
+            _ => Self::decrypt(cipher, &key)?,
+        };
+        if value_size == LOG_TOMBSTONE {
+            keydir.remove(&key);
+        } else {
+            keydir.insert(key, (value_offset, value_size as u32, flags));
+        }
+        Ok(())
+    }
+
+    // size of the whole entry, tombstone has no value
+    fn entry_size(key_size: u32, value_size: i32) -> u64 {
+        LOG_HEADER_SIZE as u64 + key_size as u64 + value_size.max(0) as u64
+    }
+
+    // read the value written at offset, cache keeps the decompressed value
+    // positioned read, it doesn't share the file cursor with writes and other reads
+    fn read_value(&self, offset: u64, value_size: u32, flags: u8) -> Result<Vec<u8>> {
+        if let Some(value) = self.cache.lock()?.get(offset) {
+            return Ok(value);
+        }
+        let mut buf = match &self.mmap {
+            Some(mmap) => self.read_mmap(mmap, offset, value_size)?,
+            None => {
+                let mut buf = vec![0; value_size as usize];
+                self.file.read_exact_at(&mut buf, offset)?;
+                buf
+            }
+        };
+        if flags & LOG_FLAG_AES != 0 {
+            buf = Self::decrypt(self.cipher.as_ref(), &buf)?;
+        }
+        if flags & LOG_FLAG_LZ4 != 0 {
+            buf = lz4_flex::decompress_size_prepended(&buf)
+                .map_err(|err| Error::Internal(format!("decompress value at offset {}: {}", offset, err)))?;
+        }
+        self.cache.lock()?.insert(offset, buf.clone());
         Ok(buf)
     }
 
-    fn read_entry(buf_reader: &mut BufReader<&File>, offset: u64) -> Result<(Vec<u8>, i32)> {
+    // copy the value out of the memory map, remap if the log has grown past the mapped range
+    fn read_mmap(&self, mmap: &RwLock<Option<Mmap>>, offset: u64, value_size: u32) -> Result<Vec<u8>> {
+        let start = offset as usize;
+        let end = start + value_size as usize;
+        if let Some(value) = mmap.read()?.as_ref().and_then(|m| m.get(start..end)) {
+            return Ok(value.to_vec());
+        }
+        let mut mmap = mmap.write()?;
+        if mmap.as_ref().is_none_or(|m| m.len() < end) {
+            // Safety: the file is locked exclusively and only appended while mapped,
+            // mapped bytes never change or go away
+            *mmap = Some(unsafe { Mmap::map(&self.file)? });
+        }
+        match mmap.as_ref().and_then(|m| m.get(start..end)) {
+            Some(value) => Ok(value.to_vec()),
+            None => Err(Error::Internal(format!(
+                "value at offset {} is out of the log file",
+                offset
+            ))),
+        }
+    }
+
+    // read the entry at offset and check its crc, return (key, value len, flags)
+    // the entry must end before limit, return None if it does not, or if it reaches limit and fails its crc,
+    // which is a torn write when limit is the end of the file
+    fn read_entry(
+        buf_reader: &mut BufReader<&File>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Option<(Vec<u8>, i32, u8)>> {
+        if offset + LOG_HEADER_SIZE as u64 > limit {
+            return Ok(None);
+        }
         buf_reader.seek(SeekFrom::Start(offset))?;
         let mut len_buf = [0; 4]; // 4 bytes
-                                  // read key size
+        // read crc
+        buf_reader.read_exact(&mut len_buf)?;
+        let crc = u32::from_be_bytes(len_buf);
+        // read flags
+        let mut flags = [0; 1];
+        buf_reader.read_exact(&mut flags)?;
+        // read key size
         buf_reader.read_exact(&mut len_buf)?;
         let key_size = u32::from_be_bytes(len_buf);
         // reaf value size, reuse buf
         buf_reader.read_exact(&mut len_buf)?;
         let value_size = i32::from_be_bytes(len_buf);
-        // read key info
-        let mut key = vec![0; key_size as usize];
-        buf_reader.read_exact(&mut key)?;
-        Ok((key, value_size))
+        let end = offset + Self::entry_size(key_size, value_size);
+        if end > limit {
+            return Ok(None);
+        }
+        // read key and value info
+        let mut data = vec![0; key_size as usize + value_size.max(0) as usize];
+        buf_reader.read_exact(&mut data)?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&flags);
+        hasher.update(&key_size.to_be_bytes());
+        hasher.update(&value_size.to_be_bytes());
+        hasher.update(&data);
+        if hasher.finalize() != crc {
+            // the last entry may be partially written, others are corrupted
+            if end == limit {
+                return Ok(None);
+            }
+            return Err(Error::Corruption { offset });
+        }
+        data.truncate(key_size as usize);
+        Ok(Some((data, value_size, flags[0])))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        error::Result,
-        storage::{disk::DiskEngine, engine::Engine},
+        error::{Error, Result},
+        storage::{
+            disk::{
+                CompactPolicy, Compression, DiskEngine, DiskEngineConfig, Durability,
+                ENCRYPT_OVERHEAD, LOG_FLAG_TTL, LOG_HEADER_SIZE,
+            },
+            engine::Engine,
+        },
+    };
+    use std::{
+        path::PathBuf,
+        sync::{atomic::Ordering, Arc},
+        time::Duration,
     };
-    use std::path::PathBuf;
 
     #[test]
     fn test_disk_engine_compact() -> Result<()> {
@@ -290,7 +1267,7 @@ mod tests {
         // must drop, otherwise we cannot create eng2 because of exclusive lock
         drop(eng);
 
-        let mut eng2 = DiskEngine::new_compact(PathBuf::from("/Users/zy/Desktop/SharkDB/tmp/SharkDB-log"))?;
+        let eng2 = DiskEngine::new_compact(PathBuf::from("/Users/zy/Desktop/SharkDB/tmp/SharkDB-log"))?;
         let iter2 = eng2.scan(..);
         let v2 = iter2.collect::<Result<Vec<_>>>()?;
         assert_eq!(
@@ -312,4 +1289,735 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_disk_engine_torn_batch() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.write_batch(vec![
+            (b"key1".to_vec(), None),
+            (b"key2".to_vec(), Some(b"value2".to_vec())),
+        ])?;
+        drop(eng);
+
+        // batch 完整写入，重启之后可见
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.get(b"key1".to_vec())?, None);
+        assert_eq!(eng.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+        eng.write_batch(vec![
+            (b"key2".to_vec(), None),
+            (b"key3".to_vec(), Some(b"value3".to_vec())),
+        ])?;
+        drop(eng);
+
+        // 模拟写 batch 的过程中宕机，最后一个 batch 只写了一部分
+        let file = std::fs::OpenOptions::new().write(true).open(&p)?;
+        file.set_len(file.metadata()?.len() - 3)?;
+        drop(file);
+
+        // 不完整的 batch 整体被丢弃
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+        assert_eq!(eng.get(b"key3".to_vec())?, None);
+        // 之后的写入不受影响
+        eng.set(b"key4".to_vec(), b"value4".to_vec())?;
+        drop(eng);
+
+        let eng = DiskEngine::new(p.clone())?;
+        let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            v,
+            vec![
+                (b"key2".to_vec(), b"value2".to_vec()),
+                (b"key4".to_vec(), b"value4".to_vec()),
+            ]
+        );
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_corruption() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        drop(eng);
+
+        // 删掉 hint 文件，强制全量扫描日志
+        std::fs::remove_file(p.with_file_name("sqldb-log.hint"))?;
+        // 修改第一条记录 value 中的一个字节
+        let mut data = std::fs::read(&p)?;
+        let entry_size = data.len() / 2;
+        data[entry_size - 1] ^= 0x01;
+        std::fs::write(&p, &data)?;
+
+        match DiskEngine::new(p.clone()) {
+            Err(err) => assert_eq!(err, Error::Corruption { offset: 0 }),
+            Ok(_) => panic!("corrupted log should not be opened"),
+        }
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_batch_corruption() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        let batch_end = |eng: &DiskEngine| eng.log.file.metadata().map(|m| m.len());
+        eng.write_batch(vec![(b"key2".to_vec(), Some(b"value2".to_vec())), (b"key3".to_vec(), Some(b"value3".to_vec()))])?;
+        let end = batch_end(&eng)? as usize;
+        eng.set(b"key4".to_vec(), b"value4".to_vec())?;
+        drop(eng);
+        std::fs::remove_file(p.with_file_name("sqldb-log.hint"))?;
+        let size = std::fs::metadata(&p)?.len();
+
+        // 批量写入完整地在文件中间，最后一条记录损坏不是写了一半，不能截断它后面提交的记录
+        let mut data = std::fs::read(&p)?;
+        data[end - 1] ^= 0x01;
+        std::fs::write(&p, &data)?;
+        assert!(matches!(DiskEngine::new(p.clone()), Err(Error::Corruption { .. })));
+        assert_eq!(std::fs::metadata(&p)?.len(), size);
+
+        // 记录长度损坏也一样
+        data[end - 1] ^= 0x01;
+        let key3 = end - b"key3value3".len() - LOG_HEADER_SIZE as usize;
+        data[key3 + 8] ^= 0x01;
+        std::fs::write(&p, &data)?;
+        assert!(matches!(DiskEngine::new(p.clone()), Err(Error::Corruption { .. })));
+        assert_eq!(std::fs::metadata(&p)?.len(), size);
+
+        data[key3 + 8] ^= 0x01;
+        std::fs::write(&p, &data)?;
+        let eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.get(b"key4".to_vec())?, Some(b"value4".to_vec()));
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_torn_write() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        drop(eng);
+        let valid_size = std::fs::metadata(&p)?.len();
+
+        // 模拟最后一条记录只写入了一部分：只有 header 和部分 key
+        let mut data = std::fs::read(&p)?;
+        let entry = data[(valid_size / 2) as usize..].to_vec();
+        data.extend(&entry[..14]);
+        std::fs::write(&p, &data)?;
+
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert_eq!(std::fs::metadata(&p)?.len(), valid_size);
+        assert_eq!(eng.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+        eng.set(b"key3".to_vec(), b"value3".to_vec())?;
+        drop(eng);
+
+        // 模拟最后一条记录长度完整，但内容没有落盘（全是 0）
+        let mut data = std::fs::read(&p)?;
+        let entry = data[(valid_size / 2) as usize..valid_size as usize].to_vec();
+        data.extend(&entry[..13]);
+        data.extend(vec![0; entry.len() - 13]);
+        std::fs::write(&p, &data)?;
+
+        let eng = DiskEngine::new(p.clone())?;
+        let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            v,
+            vec![
+                (b"key1".to_vec(), b"value1".to_vec()),
+                (b"key2".to_vec(), b"value2".to_vec()),
+                (b"key3".to_vec(), b"value3".to_vec()),
+            ]
+        );
+        drop(eng);
+
+        // 只写入了 header 的一部分
+        let mut data = std::fs::read(&p)?;
+        data.extend([0, 1, 2]);
+        std::fs::write(&p, &data)?;
+        let eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.scan(..).count(), 3);
+        drop(eng);
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_compact_crash() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let compact = p.with_file_name("sqldb-log.compact");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key1".to_vec(), b"value2".to_vec())?;
+        drop(eng);
+        // 压缩写了一半就崩溃，还没有 rename，旧日志完好
+        std::fs::write(&compact, b"half written")?;
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert!(!compact.exists());
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(b"value2".to_vec()));
+        // 压缩完成后不留下临时文件
+        eng.compact()?;
+        assert!(!compact.exists());
+        drop(eng);
+        let eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(b"value2".to_vec()));
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_auto_compact() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngineConfig::new(p.clone())
+            .compact_policy(Some(CompactPolicy {
+                dead_ratio: 0.5,
+                dead_bytes: u64::MAX,
+                min_file_size: 1024,
+            }))
+            .open()?;
+        // 反复覆盖同一批 key，产生大量垃圾数据
+        for i in 0..200_u32 {
+            eng.set(format!("key{}", i % 10).into_bytes(), i.to_be_bytes().to_vec())?;
+            eng.delete(b"tmp".to_vec())?;
+            let file_size = std::fs::metadata(&p)?.len();
+            // 超过阈值之后会自动压缩，文件不会无限增长
+            assert!(file_size < 2048, "file size {}", file_size);
+            assert!(file_size < 1024 || eng.dead_bytes as f64 <= file_size as f64 * 0.5);
+        }
+        for i in 0..10_u32 {
+            assert_eq!(
+                eng.get(format!("key{}", i).into_bytes())?,
+                Some((190 + i).to_be_bytes().to_vec())
+            );
+        }
+        drop(eng);
+
+        // 重启之后统计的垃圾数据量一致
+        let mut eng = DiskEngineConfig::new(p.clone()).compact_policy(None).open()?;
+        let dead_bytes = eng.dead_bytes;
+        eng.set(b"key0".to_vec(), b"new".to_vec())?;
+        // 旧的 key0 记录：header + key + value
+        assert_eq!(eng.dead_bytes, dead_bytes + 13 + 4 + 4);
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_hint() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let hint = p.with_file_name("sqldb-log.hint");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        eng.delete(b"key1".to_vec())?;
+        assert!(!hint.exists());
+        // 正常关闭时写 hint 文件
+        drop(eng);
+        assert!(hint.exists());
+
+        // 破坏第一条记录的 crc，有 hint 文件时启动不会扫描这部分日志
+        let mut data = std::fs::read(&p)?;
+        data[0] ^= 0x01;
+        std::fs::write(&p, &data)?;
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.get(b"key1".to_vec())?, None);
+        assert_eq!(eng.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+        // hint 之后的写入，模拟没有正常关闭：恢复旧的 hint 文件
+        let old_hint = std::fs::read(&hint)?;
+        eng.set(b"key3".to_vec(), b"value3".to_vec())?;
+        drop(eng);
+        std::fs::write(&hint, old_hint)?;
+
+        // hint 之后的日志会被重放
+        let eng = DiskEngine::new(p.clone())?;
+        let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            v,
+            vec![
+                (b"key2".to_vec(), b"value2".to_vec()),
+                (b"key3".to_vec(), b"value3".to_vec()),
+            ]
+        );
+        drop(eng);
+
+        // 没有 hint 文件时全量扫描，发现损坏
+        std::fs::remove_file(&hint)?;
+        match DiskEngine::new(p.clone()) {
+            Err(err) => assert_eq!(err, Error::Corruption { offset: 0 }),
+            Ok(_) => panic!("corrupted log should not be opened"),
+        }
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_flusher() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngineConfig::new(p.clone()).durability(Durability::EveryNms(20)).open()?;
+        let flusher = eng.flusher.clone().unwrap();
+        let synced = || {
+            (0..200).any(|_| {
+                std::thread::sleep(Duration::from_millis(10));
+                !flusher.dirty.load(Ordering::Relaxed)
+            })
+        };
+        // 写入之后不再有写入，后台线程也会在 N ms 之内同步
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        assert!(flusher.dirty.load(Ordering::Relaxed));
+        assert!(synced());
+
+        // 压缩之后同步新的日志文件
+        eng.compact_filtered(&mut |_| Ok(true))?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        assert!(synced());
+
+        // 引擎关闭之后线程结束，不再持有文件，马上可以重新打开
+        let weak = Arc::downgrade(&flusher);
+        drop(flusher);
+        drop(eng);
+        assert!((0..200).any(|_| {
+            std::thread::sleep(Duration::from_millis(10));
+            weak.upgrade().is_none()
+        }));
+        let eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_durability() -> Result<()> {
+        for durability in [
+            Durability::Always,
+            Durability::EveryNms(0),
+            Durability::EveryNms(60_000),
+            Durability::OnCommit,
+            Durability::Never,
+        ] {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            let mut eng = DiskEngineConfig::new(p.clone()).durability(durability).open()?;
+            eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+            eng.write_batch(vec![
+                (b"key2".to_vec(), Some(b"value2".to_vec())),
+                (b"key3".to_vec(), Some(b"value3".to_vec())),
+            ])?;
+            eng.delete(b"key3".to_vec())?;
+            eng.sync()?;
+            drop(eng);
+
+            let eng = DiskEngine::new(p.clone())?;
+            let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+            assert_eq!(
+                v,
+                vec![
+                    (b"key1".to_vec(), b"value1".to_vec()),
+                    (b"key2".to_vec(), b"value2".to_vec()),
+                ]
+            );
+            drop(eng);
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_cache() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+
+        // 修改文件中的 value，读到的是缓存中的值
+        let mut data = std::fs::read(&p)?;
+        let entry_size = data.len() / 2;
+        data[entry_size - 1] = b'x';
+        data[entry_size * 2 - 1] = b'x';
+        std::fs::write(&p, &data)?;
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+        assert_eq!(eng.get(b"key2".to_vec())?, Some(b"valuex".to_vec()));
+
+        // 覆盖写之后读到新值
+        eng.set(b"key1".to_vec(), b"value3".to_vec())?;
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(b"value3".to_vec()));
+
+        // 关闭缓存之后直接读文件
+        drop(eng);
+        let mut eng = DiskEngineConfig::new(p.clone()).cache_capacity(0).open()?;
+        eng.set(b"key3".to_vec(), b"value4".to_vec())?;
+        let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            v,
+            vec![
+                (b"key1".to_vec(), b"value3".to_vec()),
+                (b"key2".to_vec(), b"valuex".to_vec()),
+                (b"key3".to_vec(), b"value4".to_vec()),
+            ]
+        );
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_mmap() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngineConfig::new(p.clone())
+            .mmap(true)
+            .cache_capacity(0)
+            .open()?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+        // 文件增长之后重新映射
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        eng.set(b"key1".to_vec(), vec![])?;
+        assert_eq!(eng.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(vec![]));
+
+        // 压缩之后依然使用 mmap 读取
+        eng.compact()?;
+        let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            v,
+            vec![
+                (b"key1".to_vec(), vec![]),
+                (b"key2".to_vec(), b"value2".to_vec()),
+            ]
+        );
+        assert!(eng.log.mmap.is_some());
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_compression() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let big = b"value".repeat(200);
+        let mut eng = DiskEngineConfig::new(p.clone())
+            .compression(Compression::Lz4)
+            .open()?;
+        eng.set(b"key1".to_vec(), big.clone())?;
+        // 太小的 value 不压缩
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        eng.write_batch(vec![(b"key3".to_vec(), Some(big.clone()))])?;
+        assert!(std::fs::metadata(&p)?.len() < big.len() as u64);
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(big.clone()));
+        assert_eq!(eng.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+        assert_eq!(eng.get(b"key3".to_vec())?, Some(big.clone()));
+        drop(eng);
+
+        // 关闭压缩之后，已经压缩的数据依然可以读取，不论是否有 hint 文件
+        std::fs::remove_file(p.with_file_name("sqldb-log.hint"))?;
+        for _ in 0..2 {
+            let mut eng = DiskEngineConfig::new(p.clone()).cache_capacity(0).open()?;
+            eng.set(b"key4".to_vec(), big.clone())?;
+            let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+            assert_eq!(
+                v,
+                vec![
+                    (b"key1".to_vec(), big.clone()),
+                    (b"key2".to_vec(), b"value2".to_vec()),
+                    (b"key3".to_vec(), big.clone()),
+                    (b"key4".to_vec(), big.clone()),
+                ]
+            );
+        }
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_encryption() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let hint = p.with_file_name("sqldb-log.hint");
+        let key = [7; 32];
+        let big = b"secret".repeat(100);
+        let mut eng = DiskEngineConfig::new(p.clone())
+            .encryption_key(key)
+            .compression(Compression::Lz4)
+            .open()?;
+        eng.set(b"secret-key1".to_vec(), b"secret-value1".to_vec())?;
+        eng.write_batch(vec![
+            (b"secret-key2".to_vec(), Some(big.clone())),
+            (b"secret-key3".to_vec(), Some(b"secret-value3".to_vec())),
+        ])?;
+        eng.delete(b"secret-key3".to_vec())?;
+        // 覆盖写，统计的垃圾数据量包含加密的开销
+        let dead_bytes = eng.dead_bytes;
+        eng.set(b"secret-key1".to_vec(), b"v".to_vec())?;
+        assert_eq!(
+            eng.dead_bytes,
+            dead_bytes + 13 + 11 + 13 + 2 * ENCRYPT_OVERHEAD as u64
+        );
+        drop(eng);
+
+        // 日志和 hint 文件中都没有明文
+        for file in [&p, &hint] {
+            let data = std::fs::read(file)?;
+            assert!(!data.windows(6).any(|w| w == b"secret"));
+        }
+
+        // 使用 hint 文件启动，以及全量扫描启动
+        for remove_hint in [false, true] {
+            if remove_hint {
+                std::fs::remove_file(&hint)?;
+            }
+            let eng = DiskEngineConfig::new(p.clone())
+                .encryption_key(key)
+                .cache_capacity(0)
+                .open()?;
+            let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+            assert_eq!(
+                v,
+                vec![
+                    (b"secret-key1".to_vec(), b"v".to_vec()),
+                    (b"secret-key2".to_vec(), big.clone()),
+                ]
+            );
+        }
+
+        // 没有 key 或者 key 错误都无法打开
+        std::fs::remove_file(&hint)?;
+        assert!(DiskEngine::new(p.clone()).is_err());
+        assert!(DiskEngineConfig::new(p.clone()).encryption_key([8; 32]).open().is_err());
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_config() -> Result<()> {
+        let dir = tempfile::tempdir()?.into_path();
+        let p = dir.join("sqldb-log");
+        // 非法的配置
+        assert!(matches!(
+            DiskEngineConfig::new(dir.clone()).open(),
+            Err(Error::Config(_))
+        ));
+        for dead_ratio in [0.0, 1.5, f64::NAN] {
+            let config = DiskEngineConfig::new(p.clone()).compact_policy(Some(CompactPolicy {
+                dead_ratio,
+                ..Default::default()
+            }));
+            assert!(matches!(config.validate(), Err(Error::Config(_))));
+        }
+        assert!(!p.exists());
+
+        let mut eng = DiskEngineConfig::new(p.clone())
+            .durability(Durability::Always)
+            .compact_policy(None)
+            .cache_capacity(1024)
+            .compression(Compression::Lz4)
+            .mmap(true)
+            .open()?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+        drop(eng);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_size_limit() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        assert!(matches!(
+            DiskEngineConfig::new(p.clone()).max_value_size(usize::MAX).validate(),
+            Err(Error::Config(_))
+        ));
+        let mut eng = DiskEngineConfig::new(p.clone())
+            .max_key_size(4)
+            .max_value_size(6)
+            .open()?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        assert_eq!(
+            eng.set(b"key10".to_vec(), b"value".to_vec()),
+            Err(Error::KeyTooLarge { size: 5, max: 4 })
+        );
+        assert_eq!(
+            eng.set(b"key2".to_vec(), b"value10".to_vec()),
+            Err(Error::ValueTooLarge { size: 7, max: 6 })
+        );
+        assert_eq!(
+            eng.delete(b"key10".to_vec()),
+            Err(Error::KeyTooLarge { size: 5, max: 4 })
+        );
+        // batch 中有一个超过限制，整个 batch 都不写入
+        let size = std::fs::metadata(&p)?.len();
+        assert_eq!(
+            eng.write_batch(vec![
+                (b"key2".to_vec(), Some(b"value2".to_vec())),
+                (b"key3".to_vec(), Some(b"value30".to_vec())),
+            ]),
+            Err(Error::ValueTooLarge { size: 7, max: 6 })
+        );
+        assert_eq!(std::fs::metadata(&p)?.len(), size);
+        assert_eq!(eng.get(b"key2".to_vec())?, None);
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_status() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngineConfig::new(p.clone()).compact_policy(None).open()?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key1".to_vec(), b"value2".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        let status = eng.status()?;
+        assert_eq!(status.keys, 2);
+        // 每条记录 13 + 4 + 6
+        assert_eq!(status.live_bytes, 46);
+        assert_eq!(status.dead_bytes, 23);
+        assert_eq!(status.file_size, 69);
+        assert_eq!(status.last_compaction, None);
+
+        eng.compact()?;
+        let status = eng.status()?;
+        assert_eq!(status.keys, 2);
+        assert_eq!(status.live_bytes, 46);
+        // 压缩后的文件只有存活的记录
+        assert_eq!(status.dead_bytes, 0);
+        assert_eq!(status.file_size, 46);
+        assert!(status.last_compaction.is_some());
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_read_only() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        // 文件不存在时无法以只读方式打开
+        assert!(DiskEngineConfig::new(p.clone())
+            .read_only(true)
+            .open()
+            .is_err());
+
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        // 有写入者时无法以只读方式打开
+        assert!(DiskEngineConfig::new(p.clone())
+            .read_only(true)
+            .open()
+            .is_err());
+        drop(eng);
+
+        // 末尾有不完整的记录，只读打开时不会截断
+        let mut data = std::fs::read(&p)?;
+        data.extend([0, 1, 2]);
+        std::fs::write(&p, &data)?;
+        let hint = std::fs::read(p.with_file_name("sqldb-log.hint"))?;
+
+        // 多个只读者可以同时打开
+        let mut r1 = DiskEngineConfig::new(p.clone()).read_only(true).open()?;
+        let r2 = DiskEngineConfig::new(p.clone()).read_only(true).open()?;
+        assert!(DiskEngine::new(p.clone()).is_err());
+        assert_eq!(r1.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+        assert_eq!(r2.scan(..).count(), 1);
+        assert_eq!(
+            r1.set(b"key2".to_vec(), b"value2".to_vec()),
+            Err(Error::ReadOnly)
+        );
+        assert_eq!(r1.delete(b"key1".to_vec()), Err(Error::ReadOnly));
+        assert_eq!(
+            r1.write_batch(vec![(b"key2".to_vec(), None)]),
+            Err(Error::ReadOnly)
+        );
+        assert_eq!(r1.compact(), Err(Error::ReadOnly));
+        drop(r1);
+        drop(r2);
+
+        // 文件和 hint 都没有被修改
+        assert_eq!(std::fs::read(&p)?, data);
+        assert_eq!(std::fs::read(p.with_file_name("sqldb-log.hint"))?, hint);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_ttl() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngineConfig::new(p.clone()).compression(Compression::Lz4).open()?;
+        eng.set_with_ttl(b"key1".to_vec(), vec![1; 100], Duration::from_secs(3600))?;
+        eng.set_with_ttl(b"key2".to_vec(), b"value2".to_vec(), Duration::ZERO)?;
+        eng.set(b"key3".to_vec(), b"value3".to_vec())?;
+        drop(eng);
+
+        // 重启之后过期时间仍然有效，分别通过 hint 文件和扫描日志恢复
+        for remove_hint in [false, true] {
+            if remove_hint {
+                std::fs::remove_file(p.with_file_name("sqldb-log.hint"))?;
+            }
+            let eng = DiskEngine::new(p.clone())?;
+            assert_eq!(eng.get(b"key1".to_vec())?, Some(vec![1; 100]));
+            assert_eq!(eng.get(b"key2".to_vec())?, None);
+            assert_eq!(eng.scan(..).count(), 2);
+        }
+
+        // 压缩时丢弃过期的 key，没过期的 key 保留过期时间
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.status()?.keys, 3);
+        // 估计的数量包含还没压缩掉的过期 key
+        assert_eq!(eng.count_prefix(b"key".to_vec())?, 3);
+        eng.compact()?;
+        assert_eq!(eng.status()?.keys, 2);
+        assert_eq!(eng.count_prefix(b"key".to_vec())?, 2);
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(vec![1; 100]));
+        assert_eq!(eng.keydir[&b"key1".to_vec()].2 & LOG_FLAG_TTL, LOG_FLAG_TTL);
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_concurrent_read() -> Result<()> {
+        for mmap in [false, true] {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            let mut eng = DiskEngineConfig::new(p.clone()).mmap(mmap).cache_capacity(0).open()?;
+            for i in 0..100_u8 {
+                eng.set(vec![i], vec![i; 10])?;
+            }
+
+            // 两个扫描交替进行，中间穿插点读
+            let mut iter1 = eng.scan(..);
+            let mut iter2 = eng.scan(..).rev();
+            for i in 0..50_u8 {
+                assert_eq!(iter1.next().transpose()?, Some((vec![i], vec![i; 10])));
+                assert_eq!(eng.get(vec![99 - i])?, Some(vec![99 - i; 10]));
+                assert_eq!(iter2.next().transpose()?, Some((vec![99 - i], vec![99 - i; 10])));
+            }
+
+            // 多个线程同时读
+            let eng = &eng;
+            std::thread::scope(|s| {
+                let handles = (0..4)
+                    .map(|_| {
+                        s.spawn(move || -> Result<()> {
+                            assert_eq!(eng.scan(..).count(), 100);
+                            for i in 0..100_u8 {
+                                assert_eq!(eng.get(vec![i])?, Some(vec![i; 10]));
+                            }
+                            Ok(())
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles.into_iter().try_for_each(|h| h.join().unwrap())
+            })?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
 }
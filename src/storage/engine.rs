@@ -1,47 +1,196 @@
-use std::ops::{Bound, RangeBounds};
+use std::{
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
-use crate::error::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
 
 // abstract defination of engine
 // can connect to different engine(eg: memory kV engine, disk KV engine)
 pub trait Engine {
     type EngineIterator<'a>: EngineIterator where Self: 'a;
+    // open another database of the same kind with the default config, for ATTACH
+    // default: not supported, engines not kept in files have nothing to open
+    fn open(_path: PathBuf) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Err(Error::Internal("the storage engine can not open other databases".to_string()))
+    }
     // set key value
     fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+    // set key value that expires after ttl, reads ignore it afterwards
+    // default: not supported, engines that keep the expire time should override it
+    // mvcc does not build on it: an expired version would uncover the version before it
+    fn set_with_ttl(&mut self, _key: Vec<u8>, _value: Vec<u8>, _ttl: Duration) -> Result<()> {
+        Err(Error::Internal("ttl is not supported by the storage engine".to_string()))
+    }
     // get value by key
-    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
     // delete key, if key not exist, ignore it
     fn delete(&mut self, key: Vec<u8>) -> Result<()>;
+    // apply a group of writes as one unit, value None means delete
+    // either all of them take effect or none of them (eg: crash in the middle)
+    // default: apply one by one, engines that persist data should override it
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        for (key, value) in batch {
+            match value {
+                Some(value) => self.set(key, value)?,
+                None => self.delete(key)?,
+            }
+        }
+        Ok(())
+    }
+    // make the writes so far durable, called when a transaction commits
+    // engines decide by their own durability policy, default: nothing to do
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+    // flush everything and release the files on shutdown, later writes fail with Error::Closed
+    // default: sync, engines holding files or locks should override it
+    fn close(&mut self) -> Result<()> {
+        self.sync()
+    }
+    // drop the keys keep rejects, engines rewriting their files drop them on the way, see Mvcc::compact
+    // default: delete them one by one
+    fn compact_filtered(&mut self, keep: &mut KeyFn<'_>) -> Result<()> {
+        let mut keys = Vec::new();
+        for item in self.scan(..) {
+            let (key, _) = item?;
+            if !keep(&key)? {
+                keys.push(key);
+            }
+        }
+        for key in keys {
+            self.delete(key)?;
+        }
+        Ok(())
+    }
+    // statistics of the engine
+    fn status(&mut self) -> Result<Status>;
     // scan the engine
-    fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_>;
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_>;
     // scan prefix
-    fn scan_prefix(&mut self, prefix: Vec<u8>) -> Self::EngineIterator<'_> {
-        // prefix: aaaa
-        // start: aaaa
-        // end: aaab
-        // [aaaa, aaab) match all prefix aaaa
-        let start = Bound::Included(prefix.clone());
-        let mut bound_prefix = prefix.clone();
-        if let Some(last) = bound_prefix.iter_mut().last() {
+    fn scan_prefix(&self, prefix: Vec<u8>) -> Self::EngineIterator<'_> {
+        self.scan(prefix_range(prefix))
+    }
+    // walk the keys in range backwards, return the first entry whose key f accepts
+    // only its value is read, so a walk over many keys is cheap when f rejects them
+    // default: reads each value out of scan, engines should override it
+    fn find_last(&self, range: impl RangeBounds<Vec<u8>>, f: &mut KeyFn<'_>) -> Result<Option<KeyValue>> {
+        for item in self.scan(range).rev() {
+            let (key, value) = item?;
+            if f(&key)? {
+                return Ok(Some((key, value)));
+            }
+        }
+        Ok(None)
+    }
+    // keys under prefix, exact unless the engine says otherwise, DiskEngine estimates it from its index
+    // default: counts what scan_with visits, engines should override it to skip the values
+    fn count_prefix(&self, prefix: Vec<u8>) -> Result<u64> {
+        let mut count = 0;
+        self.scan_with(prefix_range(prefix), &mut |_, _| {
+            count += 1;
+            Ok(())
+        })?;
+        Ok(count)
+    }
+    // bytes of the keys and values under prefix, exact or estimated like count_prefix
+    fn size_prefix(&self, prefix: Vec<u8>) -> Result<u64> {
+        let mut size = 0;
+        self.scan_with(prefix_range(prefix), &mut |key, value| {
+            size += (key.len() + value.len()) as u64;
+            Ok(())
+        })?;
+        Ok(size)
+    }
+    // scan without copying, f gets each key and value borrowed from the engine, in order
+    // default: copies them out of scan, engines holding the data in memory should override it
+    fn scan_with(&self, range: impl RangeBounds<Vec<u8>>, f: &mut ScanFn<'_>) -> Result<()> {
+        for item in self.scan(range) {
+            let (key, value) = item?;
+            f(&key, &value)?;
+        }
+        Ok(())
+    }
+}
+
+// range of all keys starting with prefix
+// prefix: aaaa
+// start: aaaa
+// end: aaab
+// [aaaa, aaab) match all prefix aaaa
+// prefix: aa\xff
+// end: ab (drop trailing 0xff, then increase the last byte, like a carry)
+// prefix: \xff\xff or empty
+// end: unbounded, no key greater than every key with this prefix
+pub fn prefix_range(prefix: Vec<u8>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let mut end = prefix.clone();
+    while end.last() == Some(&0xff) {
+        end.pop();
+    }
+    let end = match end.last_mut() {
+        Some(last) => {
             *last += 1;
-        };
-        let end = Bound::Excluded(bound_prefix);
-        self.scan((start, end))
+            Bound::Excluded(end)
+        }
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(prefix), end)
+}
+
+// fsync the directory holding path, a rename or a new file in it is only durable after that
+// windows can not open a directory as a file, its renames go through the journal of ntfs
+pub(crate) fn sync_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        std::fs::File::open(dir)?.sync_all()?;
     }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
 }
 
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+// called with each key of Engine::find_last, true to stop at it, and of Engine::compact_filtered, true to keep it
+pub type KeyFn<'a> = dyn FnMut(&[u8]) -> Result<bool> + 'a;
+
+// called with each key and value of Engine::scan_with
+pub type ScanFn<'a> = dyn FnMut(&[u8], &[u8]) -> Result<()> + 'a;
+
 // let iterator support double sides scan
 // item means the return value type of iterator
 pub trait EngineIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {}
 
+// statistics of a storage engine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub name: String,
+    // number of live keys
+    pub keys: u64,
+    // bytes taken by live keys and values
+    pub live_bytes: u64,
+    // bytes taken by overwritten or deleted data, not reclaimed yet
+    pub dead_bytes: u64,
+    // size of the data file, 0 if the engine keeps nothing on disk
+    pub file_size: u64,
+    pub last_compaction: Option<SystemTime>,
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Engine;
+    use super::{prefix_range, Engine};
     use crate::{
         error::Result,
-        storage::{disk::DiskEngine, memory::MemoryEngine},
+        storage::{memory::MemoryEngine, skiplist::SkipListEngine},
     };
-    use std::{ops::Bound, path::PathBuf};
+    use std::{ops::Bound, time::Duration};
 
     // 测试点读的情况
     fn test_point_opt(mut eng: impl Engine) -> Result<()> {
@@ -101,6 +250,32 @@ mod tests {
 
         let (key5, _) = iter2.next_back().expect("no value founded")?;
         assert_eq!(key5, b"meeae".to_vec());
+        drop(iter2);
+
+        // 借用的扫描和复制的扫描结果相同
+        let mut borrowed = Vec::new();
+        eng.scan_with(b"a".to_vec()..b"o".to_vec(), &mut |k, v| {
+            borrowed.push((k.to_vec(), v.to_vec()));
+            Ok(())
+        })?;
+        assert_eq!(borrowed.len(), 4);
+        assert_eq!(borrowed, eng.scan(b"a".to_vec()..b"o".to_vec()).collect::<Result<Vec<_>>>()?);
+
+        // 从后向前找到第一个满足条件的 key
+        let mut seen = Vec::new();
+        let found = eng.find_last(b"a".to_vec()..b"o".to_vec(), &mut |k| {
+            seen.push(k.to_vec());
+            Ok(k.starts_with(b"a"))
+        })?;
+        assert_eq!(found, Some((b"anehe".to_vec(), b"value5".to_vec())));
+        assert_eq!(seen, vec![b"nnaes".to_vec(), b"meeae".to_vec(), b"anehe".to_vec()]);
+        assert_eq!(eng.find_last(b"a".to_vec()..b"o".to_vec(), &mut |k| Ok(k.starts_with(b"z")))?, None);
+
+        // 前缀下的 key 数和字节数
+        assert_eq!(eng.count_prefix(b"a".to_vec())?, 2);
+        assert_eq!(eng.size_prefix(b"a".to_vec())?, 2 * (5 + 6));
+        assert_eq!(eng.count_prefix(Vec::new())?, 5);
+        assert_eq!(eng.count_prefix(b"b".to_vec())?, 0);
 
         Ok(())
     }
@@ -124,16 +299,139 @@ mod tests {
         Ok(())
     }
 
+    // 测试以 0xff 结尾的前缀
+    fn test_scan_prefix_ff(mut eng: impl Engine) -> Result<()> {
+        for key in [
+            vec![0x01],
+            vec![0x01, 0xff],
+            vec![0x01, 0xff, 0x00],
+            vec![0x01, 0xff, 0xff, 0x07],
+            vec![0x02],
+            vec![0xff],
+            vec![0xff, 0xff],
+            vec![0xff, 0xff, 0x01],
+        ] {
+            eng.set(key, vec![])?;
+        }
+        fn scan(eng: &mut impl Engine, prefix: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+            eng.scan_prefix(prefix).map(|r| r.map(|(k, _)| k)).collect()
+        }
+        assert_eq!(
+            scan(&mut eng, vec![0x01, 0xff])?,
+            vec![vec![0x01, 0xff], vec![0x01, 0xff, 0x00], vec![0x01, 0xff, 0xff, 0x07]]
+        );
+        assert_eq!(scan(&mut eng, vec![0x01, 0xff, 0xff])?, vec![vec![0x01, 0xff, 0xff, 0x07]]);
+        assert_eq!(
+            scan(&mut eng, vec![0xff, 0xff])?,
+            vec![vec![0xff, 0xff], vec![0xff, 0xff, 0x01]]
+        );
+        assert_eq!(scan(&mut eng, vec![0xff])?.len(), 3);
+        assert_eq!(scan(&mut eng, vec![])?.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_range() {
+        assert_eq!(
+            prefix_range(b"aa".to_vec()),
+            (Bound::Included(b"aa".to_vec()), Bound::Excluded(b"ab".to_vec()))
+        );
+        // 末尾的 0xff 向前进位
+        assert_eq!(
+            prefix_range(vec![0x01, 0xfe, 0xff, 0xff]),
+            (Bound::Included(vec![0x01, 0xfe, 0xff, 0xff]), Bound::Excluded(vec![0x01, 0xff]))
+        );
+        // 全是 0xff 或者为空时没有上界
+        assert_eq!(prefix_range(vec![0xff, 0xff]).1, Bound::Unbounded);
+        assert_eq!(prefix_range(vec![]), (Bound::Included(vec![]), Bound::Unbounded));
+    }
+
+    // 测试批量写入
+    fn test_write_batch(mut eng: impl Engine) -> Result<()> {
+        eng.set(b"aa".to_vec(), b"value1".to_vec())?;
+        eng.set(b"bb".to_vec(), b"value2".to_vec())?;
+
+        eng.write_batch(vec![
+            (b"aa".to_vec(), None),
+            (b"bb".to_vec(), Some(b"value3".to_vec())),
+            (b"cc".to_vec(), Some(b"value4".to_vec())),
+            (b"cc".to_vec(), Some(b"value5".to_vec())),
+        ])?;
+        assert_eq!(eng.get(b"aa".to_vec())?, None);
+        assert_eq!(eng.get(b"bb".to_vec())?, Some(b"value3".to_vec()));
+        assert_eq!(eng.get(b"cc".to_vec())?, Some(b"value5".to_vec()));
+
+        // 空的 batch
+        eng.write_batch(vec![])?;
+        let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            v,
+            vec![
+                (b"bb".to_vec(), b"value3".to_vec()),
+                (b"cc".to_vec(), b"value5".to_vec()),
+            ]
+        );
+        Ok(())
+    }
+
+    // 测试过期的 key
+    fn test_ttl(mut eng: impl Engine) -> Result<()> {
+        eng.set_with_ttl(b"aa".to_vec(), b"value1".to_vec(), Duration::from_secs(3600))?;
+        eng.set_with_ttl(b"bb".to_vec(), b"value2".to_vec(), Duration::ZERO)?;
+        eng.set(b"cc".to_vec(), b"value3".to_vec())?;
+        assert_eq!(eng.get(b"aa".to_vec())?, Some(b"value1".to_vec()));
+        assert_eq!(eng.get(b"bb".to_vec())?, None);
+
+        // 扫描时跳过过期的 key
+        let v = eng.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            v,
+            vec![
+                (b"aa".to_vec(), b"value1".to_vec()),
+                (b"cc".to_vec(), b"value3".to_vec()),
+            ]
+        );
+        let v = eng.scan(..).rev().collect::<Result<Vec<_>>>()?;
+        assert_eq!(v.len(), 2);
+        let mut keys = Vec::new();
+        eng.scan_with(.., &mut |k, _| {
+            keys.push(k.to_vec());
+            Ok(())
+        })?;
+        assert_eq!(keys, vec![b"aa".to_vec(), b"cc".to_vec()]);
+
+        // 重新写入之后不再过期
+        eng.set(b"bb".to_vec(), b"value4".to_vec())?;
+        assert_eq!(eng.get(b"bb".to_vec())?, Some(b"value4".to_vec()));
+        Ok(())
+    }
+
     #[test]
     fn test_memory() -> Result<()> {
         test_point_opt(MemoryEngine::new())?;
         test_scan(MemoryEngine::new())?;
         test_scan_prefix(MemoryEngine::new())?;
+        test_write_batch(MemoryEngine::new())?;
+        test_scan_prefix_ff(MemoryEngine::new())?;
+        test_ttl(MemoryEngine::new())?;
         Ok(())
     }
 
+    #[test]
+    fn test_skiplist() -> Result<()> {
+        test_point_opt(SkipListEngine::new())?;
+        test_scan(SkipListEngine::new())?;
+        test_scan_prefix(SkipListEngine::new())?;
+        test_write_batch(SkipListEngine::new())?;
+        test_scan_prefix_ff(SkipListEngine::new())?;
+        Ok(())
+    }
+
+    #[cfg(feature = "disk")]
     #[test]
     fn test_disk() -> Result<()> {
+        use crate::storage::disk::DiskEngine;
+        use std::path::PathBuf;
         test_point_opt(DiskEngine::new(PathBuf::from("/tmp/sqldb1/db.log"))?)?;
         std::fs::remove_dir_all(PathBuf::from("/tmp/sqldb1"))?;
 
@@ -142,6 +440,47 @@ mod tests {
 
         test_scan_prefix(DiskEngine::new(PathBuf::from("/tmp/sqldb3/db.log"))?)?;
         std::fs::remove_dir_all(PathBuf::from("/tmp/sqldb3"))?;
+
+        test_write_batch(DiskEngine::new(PathBuf::from("/tmp/sqldb4/db.log"))?)?;
+        std::fs::remove_dir_all(PathBuf::from("/tmp/sqldb4"))?;
+
+        test_ttl(DiskEngine::new(PathBuf::from("/tmp/sqldb5/db.log"))?)?;
+        std::fs::remove_dir_all(PathBuf::from("/tmp/sqldb5"))?;
+
+        test_scan_prefix_ff(DiskEngine::new(PathBuf::from("/tmp/sqldb6/db.log"))?)?;
+        std::fs::remove_dir_all(PathBuf::from("/tmp/sqldb6"))?;
+        Ok(())
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled() -> Result<()> {
+        use crate::storage::sled::SledEngine;
+        let dir = tempfile::tempdir()?;
+        test_point_opt(SledEngine::new(dir.path().join("point"))?)?;
+        test_scan(SledEngine::new(dir.path().join("scan"))?)?;
+        test_scan_prefix(SledEngine::new(dir.path().join("scan_prefix"))?)?;
+        test_write_batch(SledEngine::new(dir.path().join("write_batch"))?)?;
+        test_scan_prefix_ff(SledEngine::new(dir.path().join("scan_prefix_ff"))?)?;
+
+        // status 不扫描数据库，计数随写入更新，重新打开时数一遍
+        let mut eng = SledEngine::new(dir.path().join("status"))?;
+        eng.set(b"a".to_vec(), b"12".to_vec())?;
+        eng.set(b"a".to_vec(), b"123".to_vec())?;
+        eng.set(b"b".to_vec(), b"1".to_vec())?;
+        eng.delete(b"b".to_vec())?;
+        eng.delete(b"c".to_vec())?;
+        eng.write_batch(vec![
+            (b"c".to_vec(), Some(b"1".to_vec())),
+            (b"c".to_vec(), Some(b"12".to_vec())),
+            (b"a".to_vec(), None),
+            (b"d".to_vec(), None),
+        ])?;
+        let status = eng.status()?;
+        assert_eq!((status.keys, status.live_bytes), (1, 3));
+        drop(eng);
+        let status = SledEngine::new(dir.path().join("status"))?.status()?;
+        assert_eq!((status.keys, status.live_bytes), (1, 3));
         Ok(())
     }
 }
\ No newline at end of file
@@ -13,7 +13,41 @@ pub fn serialize_key<T: serde::Serialize>(key: &T) -> Result<Vec<u8>> {
 
 pub fn deserialize_key<'a, T: serde::Deserialize<'a>>(input: &'a [u8]) -> Result<T> {
     let mut der = Deserializer { input };
-    T::deserialize(&mut der)
+    let value = T::deserialize(&mut der)?;
+    // the whole input must be consumed, otherwise it is not a key of this type
+    if !der.input.is_empty() {
+        return Err(Error::Internal(format!(
+            "unexpected trailing bytes {:x?}",
+            der.input
+        )));
+    }
+    Ok(value)
+}
+
+// signed integer: flip the sign bit, then big endian
+// i64::MIN -> 00..00, -1 -> 7f..ff, 0 -> 80..00, i64::MAX -> ff..ff
+// so negative numbers sort before positive numbers
+pub fn encode_i64(v: i64) -> [u8; 8] {
+    (v as u64 ^ (1 << 63)).to_be_bytes()
+}
+
+pub fn decode_i64(bytes: [u8; 8]) -> i64 {
+    (u64::from_be_bytes(bytes) ^ (1 << 63)) as i64
+}
+
+// IEEE-754 total order: positive -> flip the sign bit, negative -> flip all bits
+// -inf < -1.0 < -0.0 < 0.0 < 1.0 < inf < NaN
+// all NaNs are stored as the same positive quiet NaN, so they sort last
+pub fn encode_f64(v: f64) -> [u8; 8] {
+    let bits = if v.is_nan() { f64::NAN.to_bits() } else { v.to_bits() };
+    let bits = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+    bits.to_be_bytes()
+}
+
+pub fn decode_f64(bytes: [u8; 8]) -> f64 {
+    let bits = u64::from_be_bytes(bytes);
+    let bits = if bits >> 63 == 1 { bits ^ (1 << 63) } else { !bits };
+    f64::from_bits(bits)
 }
 
 pub struct Serializer {
@@ -21,7 +55,7 @@ pub struct Serializer {
 }
 
 // customize serializer
-impl<'a> ser::Serializer for &'a mut Serializer {
+impl ser::Serializer for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
@@ -33,45 +67,56 @@ impl<'a> ser::Serializer for &'a mut Serializer {
 
     type SerializeTupleVariant = Self;
 
-    // no need to implement
-    type SerializeTupleStruct = serde::ser::Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleStruct = Self;
 
-    type SerializeMap = serde::ser::Impossible<Self::Ok, Self::Error>;
+    type SerializeStruct = Self;
 
-    type SerializeStruct = serde::ser::Impossible<Self::Ok, Self::Error>;
+    type SerializeStructVariant = Self;
 
-    type SerializeStructVariant = serde::ser::Impossible<Self::Ok, Self::Error>;
+    // no need to implement, map has no meaningful order
+    type SerializeMap = serde::ser::Impossible<Self::Ok, Self::Error>;
 
+    // false -> 0, true -> 1
     fn serialize_bool(self, v: bool) -> Result<()> {
-        todo!()
+        self.output.push(v as u8);
+        Ok(())
     }
 
+    // signed integer: same as encode_i64 with smaller width
+    // -1 -> 0x7f, 0 -> 0x80, 1 -> 0x81
     fn serialize_i8(self, v: i8) -> Result<()> {
-        todo!()
+        self.output.extend((v as u8 ^ (1 << 7)).to_be_bytes());
+        Ok(())
     }
 
     fn serialize_i16(self, v: i16) -> Result<()> {
-        todo!()
+        self.output.extend((v as u16 ^ (1 << 15)).to_be_bytes());
+        Ok(())
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        todo!()
+        self.output.extend((v as u32 ^ (1 << 31)).to_be_bytes());
+        Ok(())
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        todo!()
+        self.output.extend(encode_i64(v));
+        Ok(())
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
-        todo!()
+        self.output.push(v);
+        Ok(())
     }
 
     fn serialize_u16(self, v: u16) -> Result<()> {
-        todo!()
+        self.output.extend(v.to_be_bytes());
+        Ok(())
     }
 
     fn serialize_u32(self, v: u32) -> Result<()> {
-        todo!()
+        self.output.extend(v.to_be_bytes());
+        Ok(())
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
@@ -80,20 +125,27 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         Ok(())
     }
 
+    // float: same as encode_f64 with smaller width
     fn serialize_f32(self, v: f32) -> Result<()> {
-        todo!()
+        let bits = if v.is_nan() { f32::NAN.to_bits() } else { v.to_bits() };
+        let bits = if bits >> 31 == 1 { !bits } else { bits ^ (1 << 31) };
+        self.output.extend(bits.to_be_bytes());
+        Ok(())
     }
 
     fn serialize_f64(self, v: f64) -> Result<()> {
-        todo!()
+        self.output.extend(encode_f64(v));
+        Ok(())
     }
 
+    // char -> unicode scalar value (u32)
     fn serialize_char(self, v: char) -> Result<()> {
-        todo!()
+        self.serialize_u32(v as u32)
     }
 
+    // utf-8 keeps the order of unicode scalar values, encode as bytes
     fn serialize_str(self, v: &str) -> Result<()> {
-        todo!()
+        self.serialize_bytes(v.as_bytes())
     }
 
     // origin          encode
@@ -102,7 +154,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     // 97 98 0 0 99 -> 97 98 0 255 0 255 99 0 0
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
         let mut res = Vec::new();
-        for e in v.into_iter() {
+        for e in v.iter() {
             match e {
                 0 => res.extend([0, 255]),
                 b => res.push(*b),
@@ -114,31 +166,35 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         Ok(())
     }
 
+    // None -> 0, Some(v) -> 1 + v, so None sorts before any Some
     fn serialize_none(self) -> Result<()> {
-        todo!()
+        self.output.push(0);
+        Ok(())
     }
 
     fn serialize_some<T>(self, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        todo!()
+        self.output.push(1);
+        value.serialize(self)
     }
 
+    // unit has only one value, nothing to write
     fn serialize_unit(self) -> Result<()> {
-        todo!()
+        Ok(())
     }
 
-    fn serialize_unit_struct(self, name: &'static str) -> Result<()> {
-        todo!()
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
     }
 
     // eg: MvccKey::NextVersion
     fn serialize_unit_variant(
         self,
-        name: &'static str,       // Name of the enum type (e.g., "Color")
+        _name: &'static str,      // Name of the enum type (e.g., "Color")
         variant_index: u32,       // Index of the variant in the enum (starting from 0)
-        variant: &'static str,    // Name of the variant (e.g., "Red")
+        _variant: &'static str,   // Name of the variant (e.g., "Red")
     ) -> Result<()> {
         // Attempt to convert the variant index from u32 to u8 and add it to the output.
         // This assumes that the total number of variants does not exceed 255.
@@ -146,11 +202,12 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         Ok(())
     }
 
-    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<()>
+    // eg: struct TableName(String), same as the inner value
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
-        todo!()
+        value.serialize(self)
     }
 
     // eg: TxnAcvtive(Version)
@@ -170,20 +227,21 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         value.serialize(self)
     }
 
-    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
         Ok(self)
     }
 
-    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
         Ok(self)
     }
 
+    // eg: struct Point(i64, i64), fields one by one
     fn serialize_tuple_struct(
         self,
-        name: &'static str,
-        len: usize,
+        _name: &'static str,
+        _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        todo!()
+        Ok(self)
     }
 
     // eg: TxnWrite(Version, Vec<u8>)
@@ -199,32 +257,36 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         name: &'static str,
         variant_index: u32,
         variant: &'static str,
-        len: usize,
+        _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
         self.serialize_unit_variant(name, variant_index, variant)?;
         Ok(self)
     }
 
-    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
-        todo!()
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(Error::Internal("map is not supported by keycode".into()))
     }
 
-    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
-        todo!()
+    // eg: struct Key { table: String, id: i64 }
+    // field names are not stored, fields are written in declaration order
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Ok(self)
     }
 
+    // eg: Key::Row { table: String, id: i64 }, same as tuple variant
     fn serialize_struct_variant(
         self,
         name: &'static str,
         variant_index: u32,
         variant: &'static str,
-        len: usize,
+        _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        todo!()
+        self.serialize_unit_variant(name, variant_index, variant)?;
+        Ok(self)
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl ser::SerializeSeq for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
@@ -248,7 +310,7 @@ impl<'a> ser::SerializeSeq for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl ser::SerializeTuple for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
@@ -265,7 +327,24 @@ impl<'a> ser::SerializeTuple for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl ser::SerializeTupleVariant for &mut Serializer {
+    type Ok = ();
+
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
@@ -282,17 +361,57 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
     }
 }
 
+impl ser::SerializeStruct for &mut Serializer {
+    type Ok = ();
+
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut Serializer {
+    type Ok = ();
+
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
 pub struct Deserializer<'de> {
     input: &'de [u8],
 }
 
 impl<'de> Deserializer<'de> {
-    fn take_bytes(&mut self, len: usize) -> &[u8] {
+    fn take_bytes(&mut self, len: usize) -> Result<&[u8]> {
+        if self.input.len() < len {
+            return Err(Error::Internal(format!(
+                "insufficient bytes, expected {} bytes for {:x?}",
+                len, self.input
+            )));
+        }
         // get and consume len bytes
         let bytes = &self.input[..len];
         // cut array
         self.input = &self.input[len..];
-        bytes
+        Ok(bytes)
     }
 
     // - 如果这个 0 之后的值是 255，说明是原始字符串中的 0，则继续解析
@@ -317,70 +436,79 @@ impl<'de> Deserializer<'de> {
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
-    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    // keycode is not self-describing, caller must know the type
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        Err(Error::Internal("deserialize_any is not supported by keycode".into()))
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_bool(match self.take_bytes(1)?[0] {
+            0 => false,
+            1 => true,
+            b => return Err(Error::Internal(format!("invalid boolean value {}", b))),
+        })
     }
 
+    // flip the sign bit back
     fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        let v = u8::from_be_bytes(self.take_bytes(1)?.try_into()?);
+        visitor.visit_i8((v ^ (1 << 7)) as i8)
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        let v = u16::from_be_bytes(self.take_bytes(2)?.try_into()?);
+        visitor.visit_i16((v ^ (1 << 15)) as i16)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        let v = u32::from_be_bytes(self.take_bytes(4)?.try_into()?);
+        visitor.visit_i32((v ^ (1 << 31)) as i32)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_i64(decode_i64(self.take_bytes(8)?.try_into()?))
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_u8(self.take_bytes(1)?[0])
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_u16(u16::from_be_bytes(self.take_bytes(2)?.try_into()?))
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_u32(u32::from_be_bytes(self.take_bytes(4)?.try_into()?))
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
@@ -388,7 +516,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         V: de::Visitor<'de>,
     {
         // u64 -> 8 bytes
-        let bytes = self.take_bytes(8);
+        let bytes = self.take_bytes(8)?;
         // &[u8] -> Vec<u8> -> u64
         let v = u64::from_be_bytes(bytes.try_into()?);
         // 如何将这个基本类型转换为最终用户所期望的类型（V::Value）则由 Visitor 来决定
@@ -396,39 +524,47 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         visitor.visit_u64(v)
     }
 
+    // positive numbers have the sign bit set after encoding
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        let bits = u32::from_be_bytes(self.take_bytes(4)?.try_into()?);
+        let bits = if bits >> 31 == 1 { bits ^ (1 << 31) } else { !bits };
+        visitor.visit_f32(f32::from_bits(bits))
     }
 
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_f64(decode_f64(self.take_bytes(8)?.try_into()?))
     }
 
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        let v = u32::from_be_bytes(self.take_bytes(4)?.try_into()?);
+        match char::from_u32(v) {
+            Some(c) => visitor.visit_char(c),
+            None => Err(Error::Internal(format!("invalid char value {}", v))),
+        }
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_string(visitor)
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        let bytes = self.next_bytes()?;
+        visitor.visit_string(String::from_utf8(bytes).map_err(|e| Error::Internal(e.to_string()))?)
     }
 
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
@@ -449,28 +585,32 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        match self.take_bytes(1)?[0] {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            b => Err(Error::Internal(format!("invalid option marker {}", b))),
+        }
     }
 
     fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_unit()
     }
 
-    fn deserialize_unit_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_unit()
     }
 
-    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_newtype_struct(self)
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
@@ -480,7 +620,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         visitor.visit_seq(self)
     }
 
-    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
@@ -489,39 +629,40 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
 
     fn deserialize_tuple_struct<V>(
         self,
-        name: &'static str,
-        len: usize,
+        _name: &'static str,
+        _len: usize,
         visitor: V,
     ) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_seq(self)
     }
 
-    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        Err(Error::Internal("map is not supported by keycode".into()))
     }
 
+    // fields are stored in declaration order without names, read them as a sequence
     fn deserialize_struct<V>(
         self,
-        name: &'static str,
-        fields: &'static [&'static str],
+        _name: &'static str,
+        _fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_seq(self)
     }
 
     fn deserialize_enum<V>(
         self,
-        name: &'static str,
-        variants: &'static [&'static str],
+        _name: &'static str,
+        _variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
@@ -530,22 +671,23 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         visitor.visit_enum(self)
     }
 
-    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
+    // enum variants are stored as index, see variant_seed
+    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        Err(Error::Internal("deserialize_identifier is not supported by keycode".into()))
     }
 
-    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        Err(Error::Internal("deserialize_ignored_any is not supported by keycode".into()))
     }
 }
 
-impl<'de, 'a> de::SeqAccess<'de> for Deserializer<'de> {
+impl<'de> de::SeqAccess<'de> for Deserializer<'de> {
     type Error = Error;
 
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
@@ -556,7 +698,7 @@ impl<'de, 'a> de::SeqAccess<'de> for Deserializer<'de> {
     }
 }
 
-impl<'de, 'a> de::EnumAccess<'de> for &mut Deserializer<'de> {
+impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
     type Variant = Self;
@@ -565,13 +707,13 @@ impl<'de, 'a> de::EnumAccess<'de> for &mut Deserializer<'de> {
     where
         V: de::DeserializeSeed<'de>,
     {
-        let index = self.take_bytes(1)[0] as u32;
+        let index = self.take_bytes(1)?[0] as u32;
         let varint_index: Result<_> = seed.deserialize(index.into_deserializer());
         Ok((varint_index?, self))
     }
 }
 
-impl<'de, 'a> de::VariantAccess<'de> for &mut Deserializer<'de> {
+impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
@@ -585,26 +727,33 @@ impl<'de, 'a> de::VariantAccess<'de> for &mut Deserializer<'de> {
         seed.deserialize(&mut *self)
     }
 
-    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
         visitor.visit_seq(self)
     }
 
-    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        visitor.visit_seq(self)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::storage::{
-        keycode::{serialize_key, deserialize_key},
-        mvcc::{MvccKey, MvccKeyPrefix},
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        error::Result,
+        storage::{
+            engine::Engine,
+            keycode::{decode_f64, decode_i64, deserialize_key, encode_f64, encode_i64, serialize_key},
+            memory::MemoryEngine,
+            mvcc::{MvccKey, MvccKeyPrefix},
+        },
     };
 
     #[test]
@@ -662,11 +811,185 @@ mod tests {
         );
     }
 
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point(i64, i64);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Name(String);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        flag: bool,
+        small: i8,
+        medium: i16,
+        int: i32,
+        big: i64,
+        byte: u8,
+        short: u16,
+        word: u32,
+        float: f32,
+        double: f64,
+        letter: char,
+        name: Name,
+        point: Point,
+        maybe: Option<i64>,
+        nothing: Option<String>,
+        unit: (),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle { center: Point, radius: f64 },
+    }
+
+    fn roundtrip<T>(v: T)
+    where
+        T: Serialize + for<'de> Deserialize<'de> + PartialEq + std::fmt::Debug,
+    {
+        let bytes = serialize_key(&v).unwrap();
+        let res: T = deserialize_key(&bytes).unwrap();
+        assert_eq!(res, v);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip(true);
+        roundtrip(false);
+        roundtrip(i8::MIN);
+        roundtrip(-1_i16);
+        roundtrip(i32::MAX);
+        roundtrip(i64::MIN);
+        roundtrip(0_i64);
+        roundtrip(u16::MAX);
+        roundtrip(7_u32);
+        roundtrip(-1.5_f32);
+        roundtrip(f64::NEG_INFINITY);
+        roundtrip(3.25_f64);
+        roundtrip('中');
+        roundtrip("a\0b\0\0c".to_string());
+        roundtrip(String::new());
+        roundtrip(Some(-3_i64));
+        roundtrip(None::<i64>);
+        roundtrip(Point(-1, 2));
+        roundtrip(Shape::Empty);
+        roundtrip(Shape::Circle {
+            center: Point(3, -4),
+            radius: 0.5,
+        });
+        roundtrip(Record {
+            flag: true,
+            small: -8,
+            medium: 300,
+            int: -70000,
+            big: i64::MAX,
+            byte: 255,
+            short: 1,
+            word: 0,
+            float: -0.25,
+            double: 1e100,
+            letter: 'x',
+            name: Name("shark".to_string()),
+            point: Point(i64::MIN, 0),
+            maybe: Some(42),
+            nothing: None,
+            unit: (),
+        });
+    }
+
+    // 编码之后的字节序和原始值的顺序一致
+    fn assert_order<T: Serialize>(sorted: Vec<T>) {
+        let encoded = sorted
+            .iter()
+            .map(|v| serialize_key(v).unwrap())
+            .collect::<Vec<_>>();
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        assert_order(vec![false, true]);
+        assert_order(vec![i8::MIN, -1, 0, 1, i8::MAX]);
+        assert_order(vec![i32::MIN, -65536, -1, 0, 255, 256, i32::MAX]);
+        assert_order(vec![i64::MIN, -1000, -1, 0, 1, 1000, i64::MAX]);
+        assert_order(vec![0_u16, 1, 256, u16::MAX]);
+        assert_order(vec![f32::NEG_INFINITY, -1.5, -0.0, 0.0, 1e-10, 2.0, f32::INFINITY]);
+        assert_order(vec![f64::NEG_INFINITY, f64::MIN, -1.0, -f64::MIN_POSITIVE, 0.0, 0.5, f64::MAX, f64::INFINITY]);
+        assert_order(vec!['a', 'b', 'z', '中']);
+        assert_order(vec!["", "a", "a\0", "a\0b", "ab", "b"]);
+        assert_order(vec![None, Some(i64::MIN), Some(0), Some(1)]);
+        assert_order(vec![Point(-1, 5), Point(0, -5), Point(0, 5), Point(1, -5)]);
+    }
+
+    #[test]
+    fn test_numeric_helpers() {
+        for v in [i64::MIN, -1, 0, 1, i64::MAX] {
+            assert_eq!(decode_i64(encode_i64(v)), v);
+            assert_eq!(serialize_key(&v).unwrap(), encode_i64(v).to_vec());
+        }
+        for v in [f64::NEG_INFINITY, -2.5, -0.0, 0.0, 2.5, f64::INFINITY] {
+            assert_eq!(decode_f64(encode_f64(v)).to_bits(), v.to_bits());
+            assert_eq!(serialize_key(&v).unwrap(), encode_f64(v).to_vec());
+        }
+        // NaN 统一编码，并且排在最后
+        assert_eq!(encode_f64(f64::NAN), encode_f64(-f64::NAN));
+        assert!(decode_f64(encode_f64(-f64::NAN)).is_nan());
+        assert!(encode_f64(f64::INFINITY) < encode_f64(f64::NAN));
+        assert!(encode_f64(f64::NEG_INFINITY) < encode_f64(-f64::MAX));
+    }
+
+    // 数值作为 key 时，范围扫描的结果按照数值排序
+    #[test]
+    fn test_numeric_range_scan() -> Result<()> {
+        let mut eng = MemoryEngine::new();
+        for v in [7_i64, -300, 0, i64::MIN, -1, 42, i64::MAX, 1] {
+            eng.set(serialize_key(&("t", v))?, vec![])?;
+        }
+        let from = serialize_key(&("t", -300_i64))?;
+        let to = serialize_key(&("t", 42_i64))?;
+        let keys = eng
+            .scan(from..to)
+            .map(|r| r.and_then(|(k, _)| deserialize_key::<(String, i64)>(&k)))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            keys.into_iter().map(|(_, v)| v).collect::<Vec<_>>(),
+            vec![-300, -1, 0, 1, 7]
+        );
+
+        let mut eng = MemoryEngine::new();
+        for v in [0.5_f64, -0.5, f64::NEG_INFINITY, 1e9, -1e-9, f64::NAN, 0.0] {
+            eng.set(serialize_key(&v)?, vec![])?;
+        }
+        let keys = eng
+            .scan(serialize_key(&-1.0_f64)?..serialize_key(&f64::INFINITY)?)
+            .map(|r| r.and_then(|(k, _)| deserialize_key::<f64>(&k)))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(keys, vec![-0.5, -1e-9, 0.0, 0.5, 1e9]);
+        // 逆序扫描，NaN 在最后
+        let last = eng.scan(..).next_back().transpose()?.unwrap();
+        assert!(deserialize_key::<f64>(&last.0)?.is_nan());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // 输入不完整时返回错误，而不是 panic
+        assert!(deserialize_key::<i64>(&[0, 1]).is_err());
+        assert!(deserialize_key::<MvccKey>(&[]).is_err());
+        assert!(deserialize_key::<bool>(&[2]).is_err());
+        assert!(deserialize_key::<String>(&[97, 0, 1]).is_err());
+        assert!(deserialize_key::<Option<i64>>(&[3]).is_err());
+        // 多余的字节
+        assert!(deserialize_key::<u8>(&[1, 2]).is_err());
+    }
+
     #[test]
     fn test_u8_convert() {
-        let v = [1 as u8, 2, 3];
+        let v = [1_u8, 2, 3];
         let vv = &v;
-        let vvv: Vec<u8> = vv.try_into().unwrap();
+        let vvv: Vec<u8> = vv.into();
         println!("{:?}", vvv); // [1, 2, 3]
     }
 }
@@ -1,14 +1,121 @@
-use std::collections::{btree_map, BTreeMap};
+use std::{
+    collections::{btree_map, BTreeMap, HashMap},
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    time::Duration,
+};
 
-use crate::error::Result;
+use web_time::SystemTime;
+
+use crate::error::{Error, Result};
+
+use super::engine::{prefix_range, sync_dir, KeyFn, KeyValue, ScanFn, Status};
+
+// expired keys are purged once this many keys have an expire time, see MemoryEngine::purge_at
+const PURGE_MIN_KEYS: usize = 1024;
 
 pub struct MemoryEngine {
     data: BTreeMap<Vec<u8>, Vec<u8>>,
+    // expire time of keys set with ttl
+    expire_at: HashMap<Vec<u8>, SystemTime>,
+    // reads skip expired keys, they are removed by a purge once expire_at has grown to this size,
+    // twice its size after the last purge, so the purges cost O(1) per write
+    purge_at: usize,
+    // None: purely volatile, Some: checkpoint file loaded on start and saved on drop
+    checkpoint_path: Option<PathBuf>,
+    // the file next to the checkpoint, locked exclusively while the engine is open,
+    // the checkpoint itself is replaced by each rename so it can not hold the lock
+    _lock: Option<File>,
 }
 
 impl MemoryEngine {
     pub fn new() -> Self {
-        Self {data: BTreeMap::new()}
+        Self {data: BTreeMap::new(), expire_at: HashMap::new(), purge_at: PURGE_MIN_KEYS, checkpoint_path: None, _lock: None}
+    }
+
+    // load the checkpoint file if it exists, and save to it on drop or checkpoint()
+    // writes after the last checkpoint are lost if the process crashes
+    // only one engine can use the checkpoint at a time, like the log of DiskEngine
+    pub fn with_checkpoint(path: PathBuf) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock = File::options().create(true).truncate(false).write(true).open(lock_path)?;
+        lock.try_lock().map_err(std::io::Error::from)?;
+        let (data, expire_at) = match std::fs::read(&path) {
+            Ok(buf) => Self::decode_checkpoint(&buf)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut engine = Self {data, expire_at, purge_at: PURGE_MIN_KEYS, checkpoint_path: Some(path), _lock: Some(lock)};
+        engine.purge_expired();
+        Ok(engine)
+    }
+
+    // +-----------+-------------------------------+
+    // | CRC32 (4) | data and expire time (bincode) |
+    // +-----------+-------------------------------+
+    // write a tmp file then rename, a crash never leaves a half written checkpoint,
+    // the rename is durable once the directory is synced
+    // expired keys are purged first, they are not saved
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let Some(path) = self.checkpoint_path.clone() else {
+            return Err(Error::Internal("memory engine has no checkpoint file".to_string()));
+        };
+        self.purge_expired();
+        let body = bincode::serialize(&(&self.data, &self.expire_at))?;
+        let mut buf = crc32fast::hash(&body).to_be_bytes().to_vec();
+        buf.extend(body);
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+        sync_dir(&path)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn decode_checkpoint(buf: &[u8]) -> Result<(BTreeMap<Vec<u8>, Vec<u8>>, HashMap<Vec<u8>, SystemTime>)> {
+        if buf.len() < 4 || crc32fast::hash(&buf[4..]).to_be_bytes() != buf[..4] {
+            return Err(Error::Corruption { offset: 0 });
+        }
+        Ok(bincode::deserialize(&buf[4..])?)
+    }
+
+    // remove the expired keys from data and expire_at
+    fn purge_expired(&mut self) {
+        let now = SystemTime::now();
+        let data = &mut self.data;
+        self.expire_at.retain(|key, expire_at| {
+            if *expire_at <= now {
+                data.remove(key);
+            }
+            *expire_at > now
+        });
+        self.purge_at = (self.expire_at.len() * 2).max(PURGE_MIN_KEYS);
+    }
+
+    fn expired(expire_at: &HashMap<Vec<u8>, SystemTime>, key: &[u8], now: SystemTime) -> bool {
+        expire_at.get(key).is_some_and(|t| *t <= now)
+    }
+}
+
+impl Default for MemoryEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// clean shutdown, save data to the checkpoint file
+impl Drop for MemoryEngine {
+    fn drop(&mut self) {
+        if self.checkpoint_path.is_some() {
+            let _ = self.checkpoint();
+        }
     }
 }
 
@@ -16,29 +123,98 @@ impl super::engine::Engine for MemoryEngine {
     type EngineIterator<'a> = MemoryEngineIterator<'a>;
 
     fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.expire_at.remove(&key);
         self.data.insert(key, value);
         Ok(())
     }
 
-    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+    fn set_with_ttl(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        self.expire_at.insert(key.clone(), SystemTime::now() + ttl);
+        self.data.insert(key, value);
+        if self.expire_at.len() >= self.purge_at {
+            self.purge_expired();
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        // expired key stays until it is overwritten, deleted or purged
+        if Self::expired(&self.expire_at, &key, SystemTime::now()) {
+            return Ok(None);
+        }
         let value = self.data.get(&key).cloned();
         Ok(value)
     }
 
     fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        self.expire_at.remove(&key);
         self.data.remove(&key);
         Ok(())
     }
 
-    fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+    fn status(&mut self) -> Result<Status> {
+        Ok(Status {
+            name: "memory".to_string(),
+            keys: self.data.len() as u64,
+            live_bytes: self.data.iter().map(|(k, v)| (k.len() + v.len()) as u64).sum(),
+            dead_bytes: 0,
+            file_size: 0,
+            last_compaction: None,
+        })
+    }
+
+    fn scan(&self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
         MemoryEngineIterator {
-            inner: self.data.range(range)
+            inner: self.data.range(range),
+            expire_at: &self.expire_at,
+            now: SystemTime::now(),
+        }
+    }
+
+    fn scan_with(
+        &self,
+        range: impl std::ops::RangeBounds<Vec<u8>>,
+        f: &mut ScanFn<'_>,
+    ) -> Result<()> {
+        let now = SystemTime::now();
+        for (key, value) in self.data.range(range) {
+            if !Self::expired(&self.expire_at, key, now) {
+                f(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn find_last(&self, range: impl std::ops::RangeBounds<Vec<u8>>, f: &mut KeyFn<'_>) -> Result<Option<KeyValue>> {
+        let now = SystemTime::now();
+        for (key, value) in self.data.range(range).rev() {
+            if !Self::expired(&self.expire_at, key, now) && f(key)? {
+                return Ok(Some((key.clone(), value.clone())));
+            }
         }
+        Ok(None)
+    }
+
+    fn count_prefix(&self, prefix: Vec<u8>) -> Result<u64> {
+        let now = SystemTime::now();
+        let range = self.data.range(prefix_range(prefix));
+        Ok(range.filter(|(key, _)| !Self::expired(&self.expire_at, key, now)).count() as u64)
+    }
+
+    fn size_prefix(&self, prefix: Vec<u8>) -> Result<u64> {
+        let now = SystemTime::now();
+        let range = self.data.range(prefix_range(prefix));
+        Ok(range
+            .filter(|(key, _)| !Self::expired(&self.expire_at, key, now))
+            .map(|(key, value)| (key.len() + value.len()) as u64)
+            .sum())
     }
 }
 
 pub struct MemoryEngineIterator<'a> {
     inner: btree_map::Range<'a, Vec<u8>, Vec<u8>>,
+    expire_at: &'a HashMap<Vec<u8>, SystemTime>,
+    now: SystemTime,
 }
 
 impl<'a> super::engine::EngineIterator for MemoryEngineIterator<'a> {
@@ -58,13 +234,82 @@ impl<'a> Iterator for MemoryEngineIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         // use self-defined map method, change Option<(&Vec, &Vec)> to Result<(&Vec, &Vec)>
-        self.inner.next().map(Self::map)
+        // skip expired keys
+        let (expire_at, now) = (self.expire_at, self.now);
+        self.inner.find(|(k, _)| !MemoryEngine::expired(expire_at, k, now)).map(Self::map)
     }
 }
 
 impl<'a> DoubleEndedIterator for MemoryEngineIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
         // same as before
-        self.inner.next_back().map(Self::map)
+        let (expire_at, now) = (self.expire_at, self.now);
+        self.inner.rfind(|(k, _)| !MemoryEngine::expired(expire_at, k, now)).map(Self::map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{MemoryEngine, PURGE_MIN_KEYS};
+    use crate::{
+        error::{Error, Result},
+        storage::engine::Engine,
+    };
+
+    #[test]
+    fn test_memory_engine_checkpoint() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("memory.checkpoint");
+        let mut eng = MemoryEngine::with_checkpoint(p.clone())?;
+        assert_eq!(eng.scan(..).count(), 0);
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set_with_ttl(b"key2".to_vec(), b"value2".to_vec(), Duration::from_secs(3600))?;
+        eng.set_with_ttl(b"key3".to_vec(), b"value3".to_vec(), Duration::ZERO)?;
+        eng.checkpoint()?;
+        // 过期的 key 在 checkpoint 时清除，不会保存
+        assert!(!eng.data.contains_key(b"key3".as_slice()) && !eng.expire_at.contains_key(b"key3".as_slice()));
+        // checkpoint 之后的写入在 drop 时保存
+        eng.set(b"key4".to_vec(), b"value4".to_vec())?;
+        drop(eng);
+
+        let eng = MemoryEngine::with_checkpoint(p.clone())?;
+        let v = eng.scan(..).map(|r| r.map(|(k, _)| k)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(v, vec![b"key1".to_vec(), b"key2".to_vec(), b"key4".to_vec()]);
+        assert_eq!(eng.get(b"key3".to_vec())?, None);
+        // 过期的 key 不算在内
+        assert_eq!(eng.count_prefix(b"key".to_vec())?, 3);
+        assert_eq!(eng.size_prefix(b"key".to_vec())?, 3 * (4 + 6));
+        // 同一个 checkpoint 文件只能被一个引擎打开
+        assert!(MemoryEngine::with_checkpoint(p.clone()).is_err());
+        drop(eng);
+
+        // 文件损坏时报错，不会当作空的数据打开
+        let mut data = std::fs::read(&p)?;
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        std::fs::write(&p, &data)?;
+        assert!(matches!(MemoryEngine::with_checkpoint(p.clone()), Err(Error::Corruption { .. })));
+
+        // 没有 checkpoint 文件的引擎不能保存
+        assert!(MemoryEngine::new().checkpoint().is_err());
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_memory_engine_purge_expired() -> Result<()> {
+        // 过期的 key 在写入时分批清除，不会一直留在内存里
+        let mut eng = MemoryEngine::new();
+        for i in 0..10 * PURGE_MIN_KEYS {
+            eng.set_with_ttl(i.to_be_bytes().to_vec(), vec![], Duration::ZERO)?;
+        }
+        assert!(eng.data.len() < PURGE_MIN_KEYS && eng.expire_at.len() < PURGE_MIN_KEYS);
+        // 未过期的 key 保留
+        for i in 0..2 * PURGE_MIN_KEYS {
+            eng.set_with_ttl(i.to_be_bytes().to_vec(), vec![], Duration::from_secs(3600))?;
+        }
+        assert_eq!(eng.scan(..).count(), 2 * PURGE_MIN_KEYS);
+        Ok(())
+    }
+}
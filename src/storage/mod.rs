@@ -2,4 +2,13 @@ pub mod engine;
 pub mod memory;
 pub mod keycode;
 pub mod mvcc;
+#[cfg(feature = "disk")]
 pub mod disk;
+#[cfg(feature = "disk")]
+pub mod cache;
+pub mod skiplist;
+pub mod twopc;
+#[cfg(test)]
+mod simulation;
+#[cfg(feature = "sled")]
+pub mod sled;
\ No newline at end of file
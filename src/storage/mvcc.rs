@@ -1,7 +1,11 @@
 use std::{
-    collections::{BTreeMap, HashSet},
-    sync::{Arc, Mutex, MutexGuard},
-    u64,
+    collections::{HashSet, VecDeque},
+    ops::Bound,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex, RwLock,
+    },
 };
 
 use serde::{Deserialize, Serialize};
@@ -9,24 +13,44 @@ use serde::{Deserialize, Serialize};
 use crate::error::{Error, Result};
 
 use super::{
-    engine::Engine,
+    engine::{prefix_range, Engine, Status},
     keycode::{deserialize_key, serialize_key},
 };
 
 pub type Version = u64;
 
-// possibly mutithread call, need to add Arc<Mutex> to ensure safe
+// possibly mutithread call, need to add Arc<RwLock> to ensure safe
 // •	Arc 允许多个线程共享对同一数据的所有权。
-// •	Mutex 确保在任意时刻，只有一个线程可以访问或修改共享数据。
-// •	结合使用 Arc<Mutex<T>>，你可以在多线程环境下安全地共享和修改数据
+// •	RwLock 允许多个线程同时读取，写入时只有一个线程可以访问共享数据。
+// •	读只需要引擎的 &self，所以读不会互相等待，写入和提交仍然是独占的
 pub struct Mvcc<E: Engine> {
-    engine: Arc<Mutex<E>>,
+    engine: Arc<RwLock<E>>,
+    subscribers: Subscribers,
+    group_commit: Arc<GroupCommit>,
+    policy: Arc<Mutex<ConflictPolicy>>,
+    // set by close, no transaction begins after it
+    closed: Arc<AtomicBool>,
+}
+
+// which of two transactions writing the same key fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    // the second write fails right away
+    #[default]
+    FirstWriterWins,
+    // writes never fail, the transaction committing second fails at commit and is rolled back
+    // for long transactions that rarely write the same keys
+    FirstCommitterWins,
 }
 
 impl<E: Engine> Clone for Mvcc<E> {
     fn clone(&self) -> Self {
         Self {
             engine: self.engine.clone(),
+            subscribers: self.subscribers.clone(),
+            group_commit: self.group_commit.clone(),
+            policy: self.policy.clone(),
+            closed: self.closed.clone(),
         }
     }
 }
@@ -34,18 +58,321 @@ impl<E: Engine> Clone for Mvcc<E> {
 impl<E: Engine> Mvcc<E> {
     pub fn new(eng: E) -> Self {
         Self {
-            engine: Arc::new(Mutex::new(eng)),
+            engine: Arc::new(RwLock::new(eng)),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            group_commit: Arc::new(GroupCommit::default()),
+            policy: Arc::default(),
+            closed: Arc::default(),
         }
     }
 
+    // transactions keep the policy they began with
+    pub fn set_conflict_policy(&self, policy: ConflictPolicy) -> Result<()> {
+        *self.policy.lock()? = policy;
+        Ok(())
+    }
+
+    pub fn conflict_policy(&self) -> Result<ConflictPolicy> {
+        Ok(*self.policy.lock()?)
+    }
+
     // start transaction(MvccTransaction)
     pub fn begin(&self) -> Result<MvccTransaction<E>> {
-        MvccTransaction::begin(self.engine.clone())
+        MvccTransaction::begin(
+            self.engine.clone(),
+            self.subscribers.clone(),
+            self.group_commit.clone(),
+            self.conflict_policy()?,
+            self.closed.clone(),
+        )
+    }
+
+    pub fn status(&self) -> Result<MvccStatus> {
+        let mut engine = self.engine.write()?;
+        MvccTransaction::<E>::status_inner(&mut engine)
+    }
+
+    // transactions prepared for a two phase commit and not finished yet, with their global ids
+    pub fn prepared(&self) -> Result<Vec<(Version, Vec<u8>)>> {
+        let engine = self.engine.read()?;
+        let mut prepared = Vec::new();
+        for item in engine.scan_prefix(MvccKeyPrefix::TxnPrepared.encode()?) {
+            let (key, gtid) = item?;
+            match MvccKey::decode(&key)? {
+                MvccKey::TxnPrepared(version) => prepared.push((version, gtid)),
+                _ => {
+                    return Err(Error::Internal(format!(
+                        "unexpected key: {:?}",
+                        String::from_utf8(key)
+                    )))
+                }
+            }
+        }
+        Ok(prepared)
+    }
+
+    // whether this database holds the commit decision of a global transaction
+    pub fn is_committed(&self, gtid: &[u8]) -> Result<bool> {
+        let engine = self.engine.read()?;
+        Ok(engine.get(MvccKey::TxnCommitted(gtid.to_vec()).encode()?)?.is_some())
+    }
+
+    // drop the commit decision of a global transaction once every participant has finished,
+    // a participant still prepared would be rolled back by recovery without it
+    pub fn forget_decision(&self, gtid: &[u8]) -> Result<()> {
+        self.engine.write()?.delete(MvccKey::TxnCommitted(gtid.to_vec()).encode()?)
+    }
+
+    // take over a prepared transaction left by a crash, to commit or roll it back
+    pub fn resume(&self, version: Version) -> Result<MvccTransaction<E>> {
+        let engine = self.engine.read()?;
+        if engine.get(MvccKey::TxnPrepared(version).encode()?)?.is_none() {
+            return Err(Error::Internal(format!("transaction {} is not prepared", version)));
+        }
+        let active_versions = MvccTransaction::<E>::scan_active(&engine)?;
+        Ok(MvccTransaction {
+            engine: self.engine.clone(),
+            subscribers: self.subscribers.clone(),
+            group_commit: self.group_commit.clone(),
+            policy: self.conflict_policy()?,
+            closed: self.closed.clone(),
+            state: TransactionState { version, active_versions },
+        })
+    }
+
+    // roll back the transactions a crash left active, prepared ones are kept for the coordinator
+    // otherwise their versions stay invisible and conflict with every later write of the keys
+    // only call it on startup, before any transaction begins
+    pub fn recover(&self) -> Result<Vec<Version>> {
+        self.rollback_active()
+    }
+
+    // roll back the transactions still running, then close the storage engine
+    // their owners get Error::Closed from their next write or commit, as does every later begin
+    // returns the versions rolled back, prepared ones are kept like in recover
+    pub fn close(&self) -> Result<Vec<Version>> {
+        if self.closed.swap(true, Ordering::Relaxed) {
+            return Ok(Vec::new());
+        }
+        let versions = self.rollback_active()?;
+        self.engine.write()?.close()?;
+        Ok(versions)
+    }
+
+    fn rollback_active(&self) -> Result<Vec<Version>> {
+        let engine = self.engine.read()?;
+        let mut versions = MvccTransaction::<E>::scan_active(&engine)?.into_iter().collect::<Vec<_>>();
+        drop(engine);
+        let prepared = self.prepared()?;
+        versions.retain(|version| prepared.iter().all(|(v, _)| v != version));
+        versions.sort();
+        for version in &versions {
+            MvccTransaction {
+                engine: self.engine.clone(),
+                subscribers: self.subscribers.clone(),
+                group_commit: self.group_commit.clone(),
+                policy: ConflictPolicy::default(),
+                closed: self.closed.clone(),
+                state: TransactionState { version: *version, active_versions: HashSet::new() },
+            }
+            .rollback()?;
+        }
+        Ok(versions)
+    }
+
+    // versions below it are visible to every transaction running now or begun later, so of the versions
+    // of a key below it only the newest can ever be read
+    pub fn gc_watermark(&self) -> Result<Version> {
+        let engine = self.engine.read()?;
+        Self::gc_watermark_inner(&engine)
+    }
+
+    fn gc_watermark_inner(engine: &E) -> Result<Version> {
+        let mut watermark = match engine.get(MvccKey::NextVersion.encode()?)? {
+            Some(value) => bincode::deserialize(&value)?,
+            None => 1,
+        };
+        for item in engine.scan_prefix(MvccKeyPrefix::TxnActive.encode()?) {
+            let (key, value) = item?;
+            let version = match MvccKey::decode(&key)? {
+                MvccKey::TxnActive(version) => version,
+                key => return Err(Error::Internal(format!("unexpected key: {:?}", key))),
+            };
+            // older data did not record it, the transaction itself is the bound then
+            let oldest = match value.is_empty() {
+                true => version,
+                false => bincode::deserialize(&value)?,
+            };
+            watermark = watermark.min(oldest);
+        }
+        Ok(watermark)
+    }
+
+    // compact the storage engine without the versions below the watermark no transaction can read:
+    // all but the newest version of a key, and that one too if it is a delete
+    // the storage engine drops them while it rewrites its files, returns how many were dropped
+    pub fn compact(&self) -> Result<usize> {
+        let mut engine = self.engine.write()?;
+        let watermark = Self::gc_watermark_inner(&engine)?;
+        let tombstone = bincode::serialize(&None::<Vec<u8>>)?;
+        let mut garbage = HashSet::new();
+        // versions of a key are adjacent and ascending, the last one seen below the watermark
+        // is dropped once a newer one below it shows up, or the key ends with it being a delete
+        let mut newest: Option<(Vec<u8>, Vec<u8>, bool)> = None;
+        let mut prefix = MvccKeyPrefix::Version(Vec::new()).encode()?;
+        prefix.truncate(prefix.len() - 2);
+        for item in engine.scan_prefix(prefix) {
+            let (key, value) = item?;
+            let (raw_key, version) = match MvccKey::decode(&key)? {
+                MvccKey::Version(raw_key, version) => (raw_key, version),
+                key => return Err(Error::Internal(format!("unexpected key: {:?}", key))),
+            };
+            if newest.as_ref().is_some_and(|(prev, _, _)| *prev != raw_key) {
+                if let Some((_, prev_key, true)) = newest.take() {
+                    garbage.insert(prev_key);
+                }
+            }
+            if version < watermark {
+                if let Some((_, prev_key, _)) = newest.replace((raw_key, key, value == tombstone)) {
+                    garbage.insert(prev_key);
+                }
+            }
+        }
+        if let Some((_, prev_key, true)) = newest {
+            garbage.insert(prev_key);
+        }
+        engine.compact_filtered(&mut |key| Ok(!garbage.contains(key)))?;
+        Ok(garbage.len())
+    }
+
+    // subscribe the change feed, receiver gets every change committed after this call
+    // changes arrive in commit order, changes of one transaction are adjacent and sorted by key
+    // a change is sent once its commit is durable, a subscriber never sees a change a crash could undo
+    // drop the receiver to unsubscribe
+    pub fn subscribe(&self) -> Result<Receiver<Change>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock()?.push(tx);
+        Ok(rx)
+    }
+}
+
+// statistics of mvcc and the storage engine under it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MvccStatus {
+    // versions handed out so far, i.e. the number of transactions begun
+    pub versions: u64,
+    // transactions neither committed nor rolled back
+    pub active_txns: u64,
+    pub storage: Status,
+}
+
+// senders of change feed subscribers
+type Subscribers = Arc<Mutex<Vec<Sender<Change>>>>;
+
+// a committed change of one key
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    // version of the transaction which made this change
+    pub version: Version,
+    pub key: Vec<u8>,
+    // None means the key did not exist
+    pub old_value: Option<Vec<u8>>,
+    // None means the key is deleted
+    pub new_value: Option<Vec<u8>>,
+}
+
+// keys and key prefixes a transaction read and depends on, checked when it commits
+// the commit fails if another transaction wrote to one of them and this one can not see it,
+// whether that transaction has committed or not
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadSet {
+    pub keys: Vec<Vec<u8>>,
+    pub prefixes: Vec<Vec<u8>>,
+}
+
+impl ReadSet {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.prefixes.is_empty()
+    }
+}
+
+// concurrent commits share one engine sync
+// a commit writes its batch, then waits until a sync started after its write has finished
+// the first waiter becomes the leader and syncs for everyone written so far,
+// commits arriving during that sync are covered by the next leader's sync
+#[derive(Default)]
+pub struct GroupCommit {
+    state: Mutex<GroupCommitState>,
+    synced: Condvar,
+}
+
+#[derive(Default)]
+struct GroupCommitState {
+    // number of commits written to the engine
+    written: u64,
+    // number of commits made durable
+    synced: u64,
+    // a leader is running engine sync
+    syncing: bool,
+    // changes of written commits not sent to the change feed yet, in commit order
+    unpublished: VecDeque<(u64, Vec<Change>)>,
+}
+
+impl GroupCommit {
+    // called while holding the engine lock right after the commit batch is written
+    // the changes of the commit are held back until it is durable
+    fn written(&self, changes: Vec<Change>) -> Result<u64> {
+        let mut state = self.state.lock()?;
+        state.written += 1;
+        let seq = state.written;
+        if !changes.is_empty() {
+            state.unpublished.push_back((seq, changes));
+        }
+        Ok(seq)
+    }
+
+    // take the changes of the commits made durable so far, in commit order
+    fn take_synced(&self) -> Result<Vec<Change>> {
+        let mut state = self.state.lock()?;
+        let mut changes = Vec::new();
+        while state.unpublished.front().is_some_and(|(seq, _)| *seq <= state.synced) {
+            changes.extend(state.unpublished.pop_front().unwrap().1);
+        }
+        Ok(changes)
+    }
+
+    // wait until commit seq is durable, call it without holding the engine lock
+    fn wait_synced<E: Engine>(&self, seq: u64, engine: &RwLock<E>) -> Result<()> {
+        let mut state = self.state.lock()?;
+        while state.synced < seq {
+            if state.syncing {
+                state = self.synced.wait(state)?;
+                continue;
+            }
+            state.syncing = true;
+            drop(state);
+            // lock order: engine first, then state
+            let result = engine.write().map_err(Error::from).and_then(|mut engine| {
+                let target = self.state.lock()?.written;
+                engine.sync()?;
+                Ok(target)
+            });
+            state = self.state.lock()?;
+            state.syncing = false;
+            self.synced.notify_all();
+            state.synced = state.synced.max(result?);
+        }
+        Ok(())
     }
 }
 
 pub struct MvccTransaction<E: Engine> {
-    engine: Arc<Mutex<E>>,
+    engine: Arc<RwLock<E>>,
+    subscribers: Subscribers,
+    group_commit: Arc<GroupCommit>,
+    policy: ConflictPolicy,
+    // of the Mvcc, writes and commits fail once it is closed, rollback still works
+    closed: Arc<AtomicBool>,
     state: TransactionState,
 }
 
@@ -57,11 +384,15 @@ pub struct TransactionState {
 }
 
 impl TransactionState {
+    // a transaction always sees its own writes, committed or not, in get and scan
+    // versions of transactions active when it began, or begun after it, are not visible
     fn is_visible(&self, version: Version) -> bool {
-        if self.active_versions.contains(&version) {
-            return false;
+        if version == self.version {
+            true
+        } else if self.active_versions.contains(&version) {
+            false
         } else {
-            return version <= self.version;
+            version < self.version
         }
     }
 }
@@ -77,6 +408,11 @@ pub enum MvccKey {
     TxnActive(Version),
     TxnWrite(Version, #[serde(with = "serde_bytes")] Vec<u8>),
     Version(#[serde(with = "serde_bytes")] Vec<u8>, Version),
+    // value is the global id of the two phase commit
+    TxnPrepared(Version),
+    // the global transaction committed, kept on the database which decided it
+    // until every participant has finished, see Mvcc::forget_decision
+    TxnCommitted(#[serde(with = "serde_bytes")] Vec<u8>),
 }
 
 
@@ -85,8 +421,8 @@ impl MvccKey {
         serialize_key(&self)
     }
 
-    pub fn decode(data: Vec<u8>) -> Result<Self> {
-        deserialize_key(&data)
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        deserialize_key(data)
     }
 }
 
@@ -96,6 +432,7 @@ pub enum MvccKeyPrefix {
     TxnActive,
     TxnWrite(Version),
     Version(#[serde(with = "serde_bytes")] Vec<u8>),
+    TxnPrepared,
 }
 
 impl MvccKeyPrefix {
@@ -106,9 +443,18 @@ impl MvccKeyPrefix {
 
 impl<E: Engine> MvccTransaction<E> {
     // start a transction
-    pub fn begin(eng: Arc<Mutex<E>>) -> Result<Self> {
+    pub fn begin(
+        eng: Arc<RwLock<E>>,
+        subscribers: Subscribers,
+        group_commit: Arc<GroupCommit>,
+        policy: ConflictPolicy,
+        closed: Arc<AtomicBool>,
+    ) -> Result<Self> {
         // get the current transaction number
-        let mut engine = eng.lock()?;
+        let mut engine = eng.write()?;
+        if closed.load(Ordering::Relaxed) {
+            return Err(Error::Closed);
+        }
         let new_version = match engine.get(MvccKey::NextVersion.encode()?)? {
             Some(value) => bincode::deserialize(&value)?,
             None => 1, // the first trasaction
@@ -119,11 +465,17 @@ impl<E: Engine> MvccTransaction<E> {
             bincode::serialize(&(new_version + 1))?,
         )?;
         // get active transaction list
-        let active_versions = Self::scan_active(&mut engine)?;
+        let active_versions = Self::scan_active(&engine)?;
         // set current to active, note that current active list(get before) doesn't contain current version
-        engine.set(MvccKey::TxnActive(new_version).encode()?, vec![])?;
+        // its value is the oldest version the transaction may read, for the watermark of Mvcc::compact
+        let oldest = active_versions.iter().min().copied().unwrap_or(new_version);
+        engine.set(MvccKey::TxnActive(new_version).encode()?, bincode::serialize(&oldest)?)?;
         Ok(Self {
             engine: eng.clone(),
+            subscribers,
+            group_commit,
+            policy,
+            closed,
             state: TransactionState {
                 version: new_version,
                 active_versions,
@@ -131,8 +483,60 @@ impl<E: Engine> MvccTransaction<E> {
         })
     }
 
+    pub fn version(&self) -> Version {
+        self.state.version
+    }
+
     pub fn commit(&self) -> Result<()> {
-        let mut engine = self.engine.lock()?;
+        self.commit_inner(None, &ReadSet::default())
+    }
+
+    // commit unless what it read changed, then it is rolled back with a write conflict
+    pub fn commit_validated(&self, reads: &ReadSet) -> Result<()> {
+        self.commit_inner(None, reads)
+    }
+
+    // first phase of a two phase commit, the writes and a prepared mark are made durable
+    // a prepared transaction stays active until commit or rollback, even across restarts
+    // with FirstCommitterWins conflicts are checked here, a prepared transaction must be able to commit
+    pub fn prepare(&self, gtid: &[u8]) -> Result<()> {
+        let mut engine = self.engine.write()?;
+        self.check_open()?;
+        if self.policy == ConflictPolicy::FirstCommitterWins && self.has_commit_conflict(&engine)? {
+            drop(engine);
+            self.rollback()?;
+            return Err(Error::WriteConflict);
+        }
+        engine.set(MvccKey::TxnPrepared(self.state.version).encode()?, gtid.to_vec())?;
+        let seq = self.group_commit.written(Vec::new())?;
+        drop(engine);
+        self.group_commit.wait_synced(seq, &self.engine)
+    }
+
+    // commit a prepared transaction and record the commit decision of gtid in the same batch
+    // the decision is what recovery looks for on the other databases
+    pub fn commit_decision(&self, gtid: &[u8]) -> Result<()> {
+        self.commit_inner(Some(gtid), &ReadSet::default())
+    }
+
+    // see Mvcc::forget_decision, for the participant which recorded the decision
+    pub fn forget_decision(&self, gtid: &[u8]) -> Result<()> {
+        self.engine.write()?.delete(MvccKey::TxnCommitted(gtid.to_vec()).encode()?)
+    }
+
+    fn commit_inner(&self, decision: Option<&[u8]>, reads: &ReadSet) -> Result<()> {
+        let mut engine = self.engine.write()?;
+        self.check_open()?;
+        let prepared_key = MvccKey::TxnPrepared(self.state.version).encode()?;
+        let prepared = engine.get(prepared_key.clone())?.is_some();
+        // checked under the engine lock, so no other commit comes between the check and the batch
+        let conflict = (self.policy == ConflictPolicy::FirstCommitterWins && !prepared && self.has_commit_conflict(&engine)?)
+            || self.has_read_conflict(&engine, reads)?;
+        if conflict {
+            drop(engine);
+            self.rollback()?;
+            return Err(Error::WriteConflict);
+        }
         let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnWrite(self.state.version).encode()?);
         let mut delete_keys = Vec::new();
         while let Some((key, _)) = iter.next().transpose()? {
@@ -140,20 +544,141 @@ impl<E: Engine> MvccTransaction<E> {
         }
         // end the life of engine iterator so that we can use engine later
         drop(iter);
-        // clean txnwrite
-        for key in delete_keys.into_iter() {
-            engine.delete(key)?;
-        }
+        // only collect changes when someone is listening
+        let changes = if self.subscribers.lock()?.is_empty() {
+            Vec::new()
+        } else {
+            self.collect_changes(&engine, &delete_keys)?
+        };
         // detete this trasction in active list
-        engine.delete(MvccKey::TxnActive(self.state.version).encode()?)
+        delete_keys.push(MvccKey::TxnActive(self.state.version).encode()?);
+        if prepared {
+            delete_keys.push(prepared_key);
+        }
+        let mut batch: Vec<_> = delete_keys.into_iter().map(|key| (key, None)).collect();
+        if let Some(gtid) = decision {
+            batch.push((MvccKey::TxnCommitted(gtid.to_vec()).encode()?, Some(vec![])));
+        }
+        // clean txnwrite and active mark in one batch
+        engine.write_batch(batch)?;
+        // numbered while holding the engine lock, so the feed follows commit order
+        let seq = self.group_commit.written(changes)?;
+        drop(engine);
+        // commit point, return after it is durable, the sync is shared with concurrent commits
+        self.group_commit.wait_synced(seq, &self.engine)?;
+        self.publish()
+    }
+
+    // whether a key written by this transaction has a version it can not see, by a transaction that
+    // committed, or prepared and so will commit
+    fn has_commit_conflict(&self, engine: &E) -> Result<bool> {
+        let mut keys = Vec::new();
+        for item in engine.scan_prefix(MvccKeyPrefix::TxnWrite(self.state.version).encode()?) {
+            let (key, _) = item?;
+            match MvccKey::decode(&key)? {
+                MvccKey::TxnWrite(_, key) => keys.push(key),
+                _ => return Err(Error::Internal(format!("unexpected key: {:?}", String::from_utf8(key)))),
+            }
+        }
+        let active = Self::scan_active(engine)?;
+        let mut prepared = HashSet::new();
+        for item in engine.scan_prefix(MvccKeyPrefix::TxnPrepared.encode()?) {
+            if let MvccKey::TxnPrepared(version) = MvccKey::decode(&item?.0)? {
+                prepared.insert(version);
+            }
+        }
+        // versions before the oldest transaction active when this one began are all visible
+        let min = self.state.active_versions.iter().min().copied().unwrap_or(self.state.version + 1);
+        for key in keys {
+            let from = MvccKey::Version(key.clone(), min).encode()?;
+            let to = MvccKey::Version(key, u64::MAX).encode()?;
+            for item in engine.scan(from..=to) {
+                let version = match MvccKey::decode(&item?.0)? {
+                    MvccKey::Version(_, version) => version,
+                    key => return Err(Error::Internal(format!("unexpected key: {:?}", key))),
+                };
+                if !self.state.is_visible(version) && (!active.contains(&version) || prepared.contains(&version)) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // a key or a key under a prefix of reads has a version this transaction can not see
+    fn has_read_conflict(&self, engine: &E, reads: &ReadSet) -> Result<bool> {
+        let mut invisible = |key: &[u8]| match MvccKey::decode(key)? {
+            MvccKey::Version(_, version) => Ok(!self.state.is_visible(version)),
+            key => Err(Error::Internal(format!("unexpected key: {:?}", key))),
+        };
+        // versions before the oldest transaction active when this one began are all visible
+        let min = self.state.active_versions.iter().min().copied().unwrap_or(self.state.version + 1);
+        for key in &reads.keys {
+            let from = MvccKey::Version(key.clone(), min).encode()?;
+            let to = MvccKey::Version(key.clone(), u64::MAX).encode()?;
+            if engine.find_last(from..=to, &mut invisible)?.is_some() {
+                return Ok(true);
+            }
+        }
+        for prefix in &reads.prefixes {
+            let mut enc_prefix = MvccKeyPrefix::Version(prefix.clone()).encode()?;
+            enc_prefix.truncate(enc_prefix.len() - 2);
+            if engine.find_last(prefix_range(enc_prefix), &mut invisible)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // build the change of each key written by this transaction
+    fn collect_changes(&self, engine: &E, txn_write_keys: &[Vec<u8>]) -> Result<Vec<Change>> {
+        let mut changes = Vec::with_capacity(txn_write_keys.len());
+        for txn_write_key in txn_write_keys {
+            let key = match MvccKey::decode(txn_write_key)? {
+                MvccKey::TxnWrite(_, key) => key,
+                _ => {
+                    return Err(Error::Internal(format!(
+                        "unexpected key: {:?}",
+                        String::from_utf8(txn_write_key.clone())
+                    )))
+                }
+            };
+            let new_value = match engine.get(MvccKey::Version(key.clone(), self.state.version).encode()?)? {
+                Some(value) => bincode::deserialize(&value)?,
+                None => None,
+            };
+            // the latest version this transaction sees, writers it can not see conflicted with it or
+            // have not committed
+            let old_value = self.latest_visible(engine, key.clone(), self.state.version)?;
+            changes.push(Change {
+                version: self.state.version,
+                key,
+                old_value,
+                new_value,
+            });
+        }
+        Ok(changes)
+    }
+
+    // send the changes of durable commits to subscribers, drop the ones whose receiver is gone
+    // taken under the subscribers lock so that two committers can not send them out of order,
+    // lock order: subscribers first, then group commit state
+    fn publish(&self) -> Result<()> {
+        let mut subscribers = self.subscribers.lock()?;
+        let changes = self.group_commit.take_synced()?;
+        if changes.is_empty() {
+            return Ok(());
+        }
+        subscribers.retain(|tx| changes.iter().all(|change| tx.send(change.clone()).is_ok()));
+        Ok(())
     }
 
     pub fn rollback(&self) -> Result<()> {
-        let mut engine = self.engine.lock()?;
+        let mut engine = self.engine.write()?;
         let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnWrite(self.state.version).encode()?);
         let mut delete_keys = Vec::new();
         while let Some((key, _)) = iter.next().transpose()? {
-            match MvccKey::decode(key.clone())? {
+            match MvccKey::decode(&key)? {
                 MvccKey::TxnWrite(_, raw_key) => {
                     // version key
                     delete_keys.push(MvccKey::Version(raw_key, self.state.version).encode()?);
@@ -169,17 +694,19 @@ impl<E: Engine> MvccTransaction<E> {
         }
         // end the life of engine iterator so that we can use engine later
         drop(iter);
-        // clean txnwrite and version
-        for key in delete_keys.into_iter() {
-            engine.delete(key)?;
-        }
         // detete this trasction in active list
-        engine.delete(MvccKey::TxnActive(self.state.version).encode()?)
+        delete_keys.push(MvccKey::TxnActive(self.state.version).encode()?);
+        let prepared_key = MvccKey::TxnPrepared(self.state.version).encode()?;
+        if engine.get(prepared_key.clone())?.is_some() {
+            delete_keys.push(prepared_key);
+        }
+        // clean txnwrite, version and active mark in one batch
+        engine.write_batch(delete_keys.into_iter().map(|key| (key, None)).collect())
     }
 
-    // •	self.engine 是一个 Mutex 类型的变量，这意味着它包含一个被锁保护的资源。
-    // •	self.engine.lock() 获取这个锁。通过 ? 操作符，若获取锁失败，会将错误向上返回。
-    // •	成功获取锁后，eng 是 MutexGuard 类型，拥有对 self.engine 内部数据的可变访问权限。由于 MutexGuard 会在作用域结束时自动释放锁，eng 仅在当前代码块内有效。
+    // •	self.engine 是一个 RwLock 类型的变量，这意味着它包含一个被锁保护的资源。
+    // •	写入用 self.engine.write() 获取独占的锁，读取用 self.engine.read() 获取共享的锁。通过 ? 操作符，若获取锁失败，会将错误向上返回。
+    // •	写锁的 guard 拥有对 self.engine 内部数据的可变访问权限，读锁的 guard 只能调用引擎的 &self 方法。guard 会在作用域结束时自动释放锁，仅在当前代码块内有效。
     pub fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
         self.write_inner(key, Some(value))
     }
@@ -189,42 +716,91 @@ impl<E: Engine> MvccTransaction<E> {
     }
 
     pub fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        let mut engine = self.engine.lock()?;
-        // current version: 9
-        // scan version 0 - 8
+        let engine = self.engine.read()?;
+        self.get_inner(&engine, key)
+    }
+
+    // read many keys under one lock, for bulk loads
+    pub fn get_many(&self, keys: Vec<Vec<u8>>) -> Result<Vec<Option<Vec<u8>>>> {
+        let engine = self.engine.read()?;
+        keys.into_iter().map(|key| self.get_inner(&engine, key)).collect()
+    }
+
+    fn get_inner(&self, engine: &E, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        // its own version is visible too
+        self.latest_visible(engine, key, self.state.version + 1)
+    }
+
+    // the value of the latest version of key below the version this transaction sees, None if deleted
+    // walks back from the version with an early exit, the versions skipped are the ones of transactions
+    // active when it began, their values are not read
+    fn latest_visible(&self, engine: &E, key: Vec<u8>, below: Version) -> Result<Option<Vec<u8>>> {
         let from = MvccKey::Version(key.clone(), 0).encode()?;
-        let to = MvccKey::Version(key.clone(), self.state.version).encode()?;
-        let mut iter = engine.scan(from..=to).rev();
-        // use rev to reverse iter
-        // 从最新的版本开始读取，找到一个最新的可见的版本
-        while let Some((key, value)) = iter.next().transpose()? {
-            match MvccKey::decode(key.clone())? {
-                MvccKey::Version(_, version) => {
-                    if self.state.is_visible(version) {
-                        return Ok(bincode::deserialize(&value)?);
-                    }
-                }
-                _ => {
-                    return Err(Error::Internal(format!(
-                        "unexpected key: {:?}",
-                        String::from_utf8(key)
-                    )))
-                }
-            }
+        let to = MvccKey::Version(key, below).encode()?;
+        let found = engine.find_last(from..to, &mut |key| match MvccKey::decode(key)? {
+            MvccKey::Version(_, version) => Ok(self.state.is_visible(version)),
+            key => Err(Error::Internal(format!("unexpected key: {:?}", key))),
+        })?;
+        match found {
+            Some((_, value)) => Ok(bincode::deserialize(&value)?),
+            None => Ok(None),
         }
-        Ok(None)
     }
 
     // modify/delete data
+    // Mvcc::close has rolled this transaction back already
+    fn check_open(&self) -> Result<()> {
+        match self.closed.load(Ordering::Relaxed) {
+            true => Err(Error::Closed),
+            false => Ok(()),
+        }
+    }
+
     fn write_inner(&self, key: Vec<u8>, value: Option<Vec<u8>>) -> Result<()> {
-        let mut engine = self.engine.lock()?;
-        // check conflict
+        let mut engine = self.engine.write()?;
+        self.check_open()?;
+        if self.policy == ConflictPolicy::FirstWriterWins {
+            self.check_write_conflict(&engine, &key)?;
+        }
+        // 记录这个 version 写入了哪些 key，用于回滚事务
+        // 写入实际的 key value 数据
+        // 两者放在同一个 batch 中，避免只写入了其中一个
+        engine.write_batch(vec![
+            (
+                MvccKey::TxnWrite(self.state.version, key.clone()).encode()?,
+                Some(vec![]),
+            ),
+            (
+                MvccKey::Version(key.clone(), self.state.version).encode()?,
+                Some(bincode::serialize(&value)?),
+            ),
+        ])
+    }
+
+    // write many keys in one storage batch under one lock, for bulk loads
+    // nothing is written if any of them conflicts
+    pub fn set_many(&self, pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        let mut engine = self.engine.write()?;
+        let mut batch = Vec::with_capacity(pairs.len() * 2);
+        for (key, value) in pairs {
+            if self.policy == ConflictPolicy::FirstWriterWins {
+                self.check_write_conflict(&engine, &key)?;
+            }
+            batch.push((MvccKey::TxnWrite(self.state.version, key.clone()).encode()?, Some(vec![])));
+            batch.push((MvccKey::Version(key, self.state.version).encode()?, Some(bincode::serialize(&Some(value))?)));
+        }
+        engine.write_batch(batch)
+    }
+
+    // the key has a version this transaction can not see, written by a transaction active when it
+    // began or begun after it
+    fn check_write_conflict(&self, engine: &E, key: &[u8]) -> Result<()> {
         // eg: active list: 3 4 5
         // current version: 6
         // key1-3 key2-4 key3-5
         // scan from 3 -- max version
         let from = MvccKey::Version(
-            key.clone(),
+            key.to_vec(),
             self.state
                 .active_versions
                 .iter()
@@ -233,15 +809,15 @@ impl<E: Engine> MvccTransaction<E> {
                 .unwrap_or(self.state.version + 1), // if no active, start from current version + 1
         )
         .encode()?;
-        let to = MvccKey::Version(key.clone(), u64::MAX).encode()?;
+        let to = MvccKey::Version(key.to_vec(), u64::MAX).encode()?;
         // only need to check last value
         // eg: active list: 3 4 5
         // current version: 6
         // 1. key is sorted, ascending sequence
         // 2. if version 10 modify data and commit, 6 is conflict to modify same data
         // 3. if active version has modified the data, like 4, version 5 cannot modify this key
-        if let Some((k, _)) = engine.scan(from..=to).last().transpose()? {
-            match MvccKey::decode(k.clone())? {
+        if let Some((k, _)) = engine.scan(from..=to).next_back().transpose()? {
+            match MvccKey::decode(&k)? {
                 MvccKey::Version(_, version) => {
                     // check if this version is visible
                     if !self.state.is_visible(version) {
@@ -251,28 +827,28 @@ impl<E: Engine> MvccTransaction<E> {
                 _ => {
                     return Err(Error::Internal(format!(
                         "Unexpected key {:?}",
-                        String::from_utf8(key)
+                        String::from_utf8(key.to_vec())
                     )))
                 }
             }
         }
-        // 记录这个 version 写入了哪些 key，用于回滚事务
-        engine.set(
-            MvccKey::TxnWrite(self.state.version, key.clone()).encode()?,
-            vec![],
-        )?;
-
-        // 写入实际的 key value 数据
-        engine.set(
-            MvccKey::Version(key.clone(), self.state.version).encode()?,
-            bincode::serialize(&value)?,
-        )?;
         Ok(())
     }
 
     // check data start by table name as prefix
     pub fn scan_prefix(&self, prefix: Vec<u8>) -> Result<Vec<ScanResult>> {
-        let mut eng = self.engine.lock()?;
+        let mut results = Vec::new();
+        self.scan_prefix_with(prefix, |key, value| {
+            results.push(ScanResult { key, value: value.to_vec() });
+            Ok(())
+        })?;
+        Ok(results)
+    }
+
+    // the keys and values scan_prefix returns, handed to f one at a time instead of collected,
+    // so the caller can stop the scan before it holds too much, f runs with the engine read locked
+    pub fn scan_prefix_with(&self, prefix: Vec<u8>, mut f: impl FnMut(Vec<u8>, &[u8]) -> Result<()>) -> Result<()> {
+        let eng = self.engine.read()?;
         let mut enc_prefix = MvccKeyPrefix::Version(prefix).encode()?;
         // 原始值           编码后
         // 97 98 99     -> 97 98 99 0 0
@@ -281,40 +857,139 @@ impl<E: Engine> MvccTransaction<E> {
         // 去掉最后的 [0, 0] 后缀
         enc_prefix.truncate(enc_prefix.len() - 2);
 
-        let mut iter = eng.scan_prefix(enc_prefix);
-        let mut results = BTreeMap::new();
-        while let Some((key, value)) = iter.next().transpose()? {
-            match MvccKey::decode(key.clone())? {
-                // update key if key is duolicate, so we always have the latest visivle key version
+        // versions of a key come in order, the last visible one is passed on once the next key starts
+        // values are borrowed from the engine, only the visible ones are copied
+        let mut last: Option<(Vec<u8>, Option<Vec<u8>>)> = None;
+        eng.scan_with(prefix_range(enc_prefix), &mut |key, value| match MvccKey::decode(key)? {
+            MvccKey::Version(raw_key, version) => {
+                if self.state.is_visible(version) {
+                    // none means the key was deleted
+                    let value = bincode::deserialize::<Option<&[u8]>>(value)?.map(<[u8]>::to_vec);
+                    match &mut last {
+                        Some((last_key, last_value)) if *last_key == raw_key => *last_value = value,
+                        _ => {
+                            if let Some((key, Some(value))) = last.replace((raw_key, value)) {
+                                f(key, &value)?;
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(Error::Internal(format!("Unexepected key {:?}", String::from_utf8(key.to_vec())))),
+        })?;
+        if let Some((key, Some(value))) = last {
+            f(key, &value)?;
+        }
+        Ok(())
+    }
+
+    // at most limit of the keys scan_prefix returns, starting after the key after, so a large prefix
+    // can be walked a page at a time, the scan stops once the page is full
+    pub fn scan_prefix_after(&self, prefix: Vec<u8>, after: Option<Vec<u8>>, limit: usize) -> Result<Vec<ScanResult>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let eng = self.engine.read()?;
+        let mut enc_prefix = MvccKeyPrefix::Version(prefix).encode()?;
+        enc_prefix.truncate(enc_prefix.len() - 2);
+        let (mut start, end) = prefix_range(enc_prefix);
+        if let Some(after) = after {
+            // every version of after sorts before this one
+            start = Bound::Excluded(MvccKey::Version(after, Version::MAX).encode()?);
+        }
+        let mut results = Vec::new();
+        let mut last: Option<(Vec<u8>, Option<Vec<u8>>)> = None;
+        for item in eng.scan((start, end)) {
+            let (key, value) = item?;
+            match MvccKey::decode(&key)? {
                 MvccKey::Version(raw_key, version) => {
-                    if self.state.is_visible(version) {
-                        match bincode::deserialize(&value)? {
-                            Some(raw_value) => results.insert(raw_key, raw_value),
-                            // if value is none, delete that key
-                            None => results.remove(&raw_key),
-                        };
+                    if !self.state.is_visible(version) {
+                        continue;
+                    }
+                    let value = bincode::deserialize::<Option<Vec<u8>>>(&value)?;
+                    match &mut last {
+                        Some((last_key, last_value)) if *last_key == raw_key => *last_value = value,
+                        _ => {
+                            if let Some((key, Some(value))) = last.replace((raw_key, value)) {
+                                results.push(ScanResult { key, value });
+                                if results.len() >= limit {
+                                    return Ok(results);
+                                }
+                            }
+                        }
                     }
                 }
-                _ => {
-                    return Err(Error::Internal(format!(
-                        "Unexepected key {:?}",
-                        String::from_utf8(key)
-                    )))
+                _ => return Err(Error::Internal(format!("Unexepected key {:?}", String::from_utf8(key)))),
+            }
+        }
+        if let Some((key, Some(value))) = last {
+            results.push(ScanResult { key, value });
+        }
+        Ok(results)
+    }
+
+    // keys under prefix this transaction sees whose value f accepts, like filtering scan_prefix but
+    // values are only borrowed, a prefix the storage engine holds nothing under is answered without a scan
+    pub fn count_prefix(&self, prefix: Vec<u8>, f: &mut dyn FnMut(&[u8]) -> Result<bool>) -> Result<u64> {
+        let eng = self.engine.read()?;
+        let mut enc_prefix = MvccKeyPrefix::Version(prefix).encode()?;
+        enc_prefix.truncate(enc_prefix.len() - 2);
+        if eng.count_prefix(enc_prefix.clone())? == 0 {
+            return Ok(0);
+        }
+        // the last visible version of the key being walked, and whether it is counted
+        let mut last: Option<(Vec<u8>, bool)> = None;
+        let mut count = 0;
+        eng.scan_with(prefix_range(enc_prefix), &mut |key, value| match MvccKey::decode(key)? {
+            MvccKey::Version(raw_key, version) => {
+                if self.state.is_visible(version) {
+                    let counted = match bincode::deserialize::<Option<&[u8]>>(value)? {
+                        Some(value) => f(value)?,
+                        None => false,
+                    };
+                    match &mut last {
+                        Some((last_key, last_counted)) if *last_key == raw_key => *last_counted = counted,
+                        _ => {
+                            if let Some((_, true)) = last.replace((raw_key, counted)) {
+                                count += 1;
+                            }
+                        }
+                    }
                 }
+                Ok(())
             }
+            _ => Err(Error::Internal(format!("Unexepected key {:?}", String::from_utf8(key.to_vec())))),
+        })?;
+        if let Some((_, true)) = last {
+            count += 1;
         }
+        Ok(count)
+    }
 
-        Ok(results
-            .into_iter()
-            .map(|(key, value)| ScanResult { key, value })
-            .collect())
+    // status seen by this transaction, which is active itself
+    pub fn status(&self) -> Result<MvccStatus> {
+        let mut engine = self.engine.write()?;
+        Self::status_inner(&mut engine)
+    }
+
+    fn status_inner(engine: &mut E) -> Result<MvccStatus> {
+        let versions = match engine.get(MvccKey::NextVersion.encode()?)? {
+            Some(value) => bincode::deserialize::<Version>(&value)? - 1,
+            None => 0,
+        };
+        Ok(MvccStatus {
+            versions,
+            active_txns: Self::scan_active(engine)?.len() as u64,
+            storage: engine.status()?,
+        })
     }
 
-    fn scan_active(engine: &mut MutexGuard<E>) -> Result<HashSet<Version>> {
+    fn scan_active(engine: &E) -> Result<HashSet<Version>> {
         let mut active_versions = HashSet::new();
         let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnActive.encode()?);
         while let Some((key, _)) = iter.next().transpose()? {
-            match MvccKey::decode(key.clone())? {
+            match MvccKey::decode(&key)? {
                 MvccKey::TxnActive(version) => {
                     active_versions.insert(version);
                 }
@@ -338,12 +1013,27 @@ pub struct ScanResult {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc, Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
     use crate::{
-        error::Result,
-        storage::{disk::DiskEngine, engine::Engine, memory::MemoryEngine},
+        error::{Error, Result},
+        storage::{
+            engine::{Engine, Status},
+            memory::MemoryEngine,
+            skiplist::SkipListEngine,
+        },
     };
+    #[cfg(feature = "disk")]
+    use crate::storage::disk::DiskEngine;
 
-    use super::Mvcc;
+    use super::{ConflictPolicy, Mvcc, ReadSet};
 
     // 1. Get
     fn get(eng: impl Engine) -> Result<()> {
@@ -368,9 +1058,12 @@ mod tests {
     fn test_get() -> Result<()> {
         get(MemoryEngine::new())?;
 
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        get(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            get(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -404,9 +1097,12 @@ mod tests {
     fn test_get_isolation() -> Result<()> {
         get_isolation(MemoryEngine::new())?;
 
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        get_isolation(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            get_isolation(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -470,15 +1166,27 @@ mod tests {
             },]
         );
 
+        // 分页扫描，删除的 key 不算在一页里
+        let tx2 = mvcc.begin()?;
+        tx2.delete(b"aaca".to_vec())?;
+        let keys = |page: Vec<super::ScanResult>| page.into_iter().map(|r| r.key).collect::<Vec<_>>();
+        assert_eq!(keys(tx2.scan_prefix_after(b"a".to_vec(), None, 2)?), vec![b"aabb".to_vec(), b"abcc".to_vec()]);
+        assert_eq!(keys(tx2.scan_prefix_after(b"a".to_vec(), Some(b"abcc".to_vec()), 2)?), vec![b"acca".to_vec()]);
+        assert!(tx2.scan_prefix_after(b"a".to_vec(), Some(b"acca".to_vec()), 2)?.is_empty());
+        assert_eq!(keys(tx1.scan_prefix_after(b"a".to_vec(), Some(b"aabb".to_vec()), 1)?), vec![b"aaca".to_vec()]);
+
         Ok(())
     }
 
     #[test]
     fn test_scan_prefix() -> Result<()> {
         scan_prefix(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        scan_prefix(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            scan_prefix(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -557,9 +1265,12 @@ mod tests {
     #[test]
     fn test_scan_isolation() -> Result<()> {
         scan_isolation(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        scan_isolation(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            scan_isolation(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -598,9 +1309,12 @@ mod tests {
     #[test]
     fn test_set() -> Result<()> {
         set(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        set(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            set(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -642,9 +1356,12 @@ mod tests {
     #[test]
     fn test_set_conflict() -> Result<()> {
         set_conflict(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        set_conflict(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            set_conflict(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -683,9 +1400,12 @@ mod tests {
     #[test]
     fn test_delete() -> Result<()> {
         delete(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        delete(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            delete(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -717,9 +1437,12 @@ mod tests {
     #[test]
     fn test_delete_conflict() -> Result<()> {
         delete_conflict(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        delete_conflict(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            delete_conflict(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -744,9 +1467,12 @@ mod tests {
     #[test]
     fn test_dirty_read() -> Result<()> {
         dirty_read(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        dirty_read(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            dirty_read(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -773,9 +1499,12 @@ mod tests {
     #[test]
     fn test_unrepeatable_read() -> Result<()> {
         unrepeatable_read(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        unrepeatable_read(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            unrepeatable_read(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -838,9 +1567,12 @@ mod tests {
     #[test]
     fn test_phantom_read() -> Result<()> {
         phantom_read(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        phantom_read(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            phantom_read(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
 
@@ -870,10 +1602,555 @@ mod tests {
     #[test]
     fn test_rollback() -> Result<()> {
         rollback(MemoryEngine::new())?;
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        rollback(DiskEngine::new(p.clone())?)?;
-        std::fs::remove_dir_all(p.parent().unwrap())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            rollback(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
         Ok(())
     }
-}
 
+    // 13. change feed
+    fn change_feed(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+
+        // 只能收到订阅之后提交的变更
+        let feed = mvcc.subscribe()?;
+        let tx1 = mvcc.begin()?;
+        tx1.set(b"key2".to_vec(), b"val2".to_vec())?;
+        tx1.set(b"key1".to_vec(), b"val1-1".to_vec())?;
+
+        // 回滚的事务不会产生变更
+        let tx2 = mvcc.begin()?;
+        tx2.set(b"key3".to_vec(), b"val3".to_vec())?;
+        tx2.rollback()?;
+
+        tx1.commit()?;
+        let tx3 = mvcc.begin()?;
+        tx3.delete(b"key1".to_vec())?;
+        tx3.commit()?;
+
+        let changes = feed.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                super::Change {
+                    version: 2,
+                    key: b"key1".to_vec(),
+                    old_value: Some(b"val1".to_vec()),
+                    new_value: Some(b"val1-1".to_vec()),
+                },
+                super::Change {
+                    version: 2,
+                    key: b"key2".to_vec(),
+                    old_value: None,
+                    new_value: Some(b"val2".to_vec()),
+                },
+                super::Change {
+                    version: 4,
+                    key: b"key1".to_vec(),
+                    old_value: Some(b"val1-1".to_vec()),
+                    new_value: None,
+                },
+            ]
+        );
+
+        // 取消订阅之后提交仍然正常
+        drop(feed);
+        let tx4 = mvcc.begin()?;
+        tx4.set(b"key4".to_vec(), b"val4".to_vec())?;
+        tx4.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_feed() -> Result<()> {
+        change_feed(MemoryEngine::new())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            change_feed(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    // 统计 sync 次数的 engine，sync 比较慢
+    struct SlowSyncEngine {
+        inner: MemoryEngine,
+        syncs: Arc<AtomicUsize>,
+    }
+
+    impl Engine for SlowSyncEngine {
+        type EngineIterator<'a> = <MemoryEngine as Engine>::EngineIterator<'a>;
+
+        fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+            self.inner.set(key, value)
+        }
+
+        fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+
+        fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+            self.inner.delete(key)
+        }
+
+        fn status(&mut self) -> Result<Status> {
+            self.inner.status()
+        }
+
+        fn sync(&mut self) -> Result<()> {
+            thread::sleep(Duration::from_millis(50));
+            self.syncs.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn scan(&self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+            self.inner.scan(range)
+        }
+    }
+
+    // 14. Group commit
+    #[test]
+    fn test_group_commit() -> Result<()> {
+        let syncs = Arc::new(AtomicUsize::new(0));
+        let mvcc = Mvcc::new(SlowSyncEngine {
+            inner: MemoryEngine::new(),
+            syncs: syncs.clone(),
+        });
+        let n = 8;
+        let barrier = Arc::new(Barrier::new(n));
+        let handles = (0..n)
+            .map(|i| {
+                let mvcc = mvcc.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || -> Result<()> {
+                    let tx = mvcc.begin()?;
+                    tx.set(format!("key{}", i).into_bytes(), b"val".to_vec())?;
+                    // 所有事务同时提交
+                    barrier.wait();
+                    tx.commit()
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+
+        // 所有提交都可见，并且多个提交共享了 sync
+        let tx = mvcc.begin()?;
+        assert_eq!(tx.scan_prefix(b"key".to_vec())?.len(), n);
+        assert!(syncs.load(Ordering::SeqCst) < n);
+        Ok(())
+    }
+
+    // 15. Status
+    fn status(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+        let tx1 = mvcc.begin()?;
+        tx1.set(b"key2".to_vec(), b"val2".to_vec())?;
+
+        let status = mvcc.status()?;
+        assert_eq!(status.versions, 2);
+        assert_eq!(status.active_txns, 1);
+        // NextVersion, TxnActive(2), TxnWrite(2, key2), 两个 Version
+        assert_eq!(status.storage.keys, 5);
+        assert_eq!(tx1.status()?, status);
+
+        tx1.rollback()?;
+        let status = mvcc.status()?;
+        assert_eq!(status.active_txns, 0);
+        assert_eq!(status.storage.keys, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_status() -> Result<()> {
+        status(MemoryEngine::new())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            status(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    // 16. read your writes
+    fn read_your_writes(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.set(b"key2".to_vec(), b"val2".to_vec())?;
+        tx.commit()?;
+
+        // 未提交的写入、覆盖和删除，自己的 get 和 scan 都能看到
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        tx1.set(b"key1".to_vec(), b"val3".to_vec())?;
+        tx1.set(b"key1".to_vec(), b"val4".to_vec())?;
+        tx1.delete(b"key2".to_vec())?;
+        tx1.set(b"key3".to_vec(), b"val5".to_vec())?;
+        assert_eq!(tx1.get(b"key1".to_vec())?, Some(b"val4".to_vec()));
+        assert_eq!(tx1.get(b"key2".to_vec())?, None);
+        assert_eq!(
+            tx1.scan_prefix(b"key".to_vec())?,
+            vec![
+                super::ScanResult { key: b"key1".to_vec(), value: b"val4".to_vec() },
+                super::ScanResult { key: b"key3".to_vec(), value: b"val5".to_vec() },
+            ]
+        );
+        // 删除后再写入
+        tx1.set(b"key2".to_vec(), b"val6".to_vec())?;
+        assert_eq!(tx1.get(b"key2".to_vec())?, Some(b"val6".to_vec()));
+        assert_eq!(tx1.scan_prefix(b"key".to_vec())?.len(), 3);
+
+        // 别的事务看不到
+        assert_eq!(tx2.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        assert_eq!(tx2.scan_prefix(b"key".to_vec())?.len(), 2);
+        tx2.set(b"key4".to_vec(), b"val7".to_vec())?;
+        assert_eq!(tx1.get(b"key4".to_vec())?, None);
+        tx2.commit()?;
+        tx1.commit()?;
+
+        let tx3 = mvcc.begin()?;
+        assert_eq!(tx3.scan_prefix(b"key".to_vec())?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_your_writes() -> Result<()> {
+        read_your_writes(MemoryEngine::new())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            read_your_writes(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_visible_own_version() {
+        // 恢复的 prepared 事务自己也在活跃列表里
+        let state = super::TransactionState { version: 5, active_versions: [3, 5].into() };
+        assert!(state.is_visible(5));
+        assert!(!state.is_visible(3));
+        assert!(state.is_visible(4));
+        assert!(!state.is_visible(6));
+    }
+
+    // 17. first committer wins
+    fn first_committer_wins(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        mvcc.set_conflict_policy(ConflictPolicy::FirstCommitterWins)?;
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+
+        // 写同一个 key 不会立刻失败，后提交的事务失败并回滚
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        tx2.set(b"key1".to_vec(), b"val2".to_vec())?;
+        tx1.set(b"key1".to_vec(), b"val3".to_vec())?;
+        tx1.set(b"key2".to_vec(), b"val4".to_vec())?;
+        tx2.commit()?;
+        assert_eq!(tx1.commit(), Err(Error::WriteConflict));
+        let tx3 = mvcc.begin()?;
+        assert_eq!(tx3.get(b"key1".to_vec())?, Some(b"val2".to_vec()));
+        assert_eq!(tx3.get(b"key2".to_vec())?, None);
+        assert_eq!(mvcc.status()?.active_txns, 1);
+        tx3.commit()?;
+
+        // 写不同的 key 都能提交
+        let tx4 = mvcc.begin()?;
+        let tx5 = mvcc.begin()?;
+        tx4.set(b"key3".to_vec(), b"val5".to_vec())?;
+        tx5.set(b"key4".to_vec(), b"val6".to_vec())?;
+        tx5.commit()?;
+        tx4.commit()?;
+
+        // 回滚的写入不算冲突
+        let tx6 = mvcc.begin()?;
+        let tx7 = mvcc.begin()?;
+        tx6.delete(b"key1".to_vec())?;
+        tx7.delete(b"key1".to_vec())?;
+        tx6.rollback()?;
+        tx7.commit()?;
+
+        // prepared 的事务一定能提交，其他事务提交失败
+        let tx8 = mvcc.begin()?;
+        let tx9 = mvcc.begin()?;
+        tx8.set(b"key3".to_vec(), b"val7".to_vec())?;
+        tx9.set(b"key3".to_vec(), b"val8".to_vec())?;
+        tx8.prepare(b"g1")?;
+        assert_eq!(tx9.commit(), Err(Error::WriteConflict));
+        tx8.commit_decision(b"g1")?;
+        // 已提交的写入让 prepare 失败
+        let tx10 = mvcc.begin()?;
+        let tx11 = mvcc.begin()?;
+        tx10.set(b"key3".to_vec(), b"val9".to_vec())?;
+        tx11.set(b"key3".to_vec(), b"val10".to_vec())?;
+        tx11.commit()?;
+        assert_eq!(tx10.prepare(b"g2"), Err(Error::WriteConflict));
+        assert!(mvcc.prepared()?.is_empty());
+
+        // 事务使用开始时的策略
+        let tx12 = mvcc.begin()?;
+        mvcc.set_conflict_policy(ConflictPolicy::FirstWriterWins)?;
+        let tx13 = mvcc.begin()?;
+        tx12.set(b"key4".to_vec(), b"val11".to_vec())?;
+        assert_eq!(tx13.set(b"key4".to_vec(), b"val12".to_vec()), Err(Error::WriteConflict));
+        tx12.commit()?;
+
+        let tx = mvcc.begin()?;
+        assert_eq!(tx.get(b"key1".to_vec())?, None);
+        assert_eq!(tx.get(b"key3".to_vec())?, Some(b"val10".to_vec()));
+        assert_eq!(tx.get(b"key4".to_vec())?, Some(b"val11".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_committer_wins() -> Result<()> {
+        first_committer_wins(MemoryEngine::new())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            first_committer_wins(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    // 18. set many and get many
+    fn set_many(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        tx1.set(b"key3".to_vec(), b"val3".to_vec())?;
+        tx2.set_many(vec![(b"key1".to_vec(), b"val1-1".to_vec()), (b"key2".to_vec(), b"val2".to_vec())])?;
+        assert_eq!(
+            tx2.get_many(vec![b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec()])?,
+            vec![Some(b"val1-1".to_vec()), Some(b"val2".to_vec()), None]
+        );
+        // 有一个键冲突时什么都不写入
+        assert_eq!(
+            tx2.set_many(vec![(b"key4".to_vec(), b"val4".to_vec()), (b"key3".to_vec(), b"val3-1".to_vec())]),
+            Err(Error::WriteConflict)
+        );
+        assert_eq!(tx2.get(b"key4".to_vec())?, None);
+        tx1.commit()?;
+        tx2.commit()?;
+
+        let tx = mvcc.begin()?;
+        assert_eq!(
+            tx.get_many(vec![b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec(), b"key4".to_vec()])?,
+            vec![Some(b"val1-1".to_vec()), Some(b"val2".to_vec()), Some(b"val3".to_vec()), None]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_many() -> Result<()> {
+        set_many(MemoryEngine::new())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            set_many(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    // 19. read validation
+    fn read_validation(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"schema".to_vec(), b"v1".to_vec())?;
+        tx.set(b"row-1".to_vec(), b"a".to_vec())?;
+        tx.commit()?;
+        let reads = |keys: &[&[u8]], prefixes: &[&[u8]]| ReadSet {
+            keys: keys.iter().map(|k| k.to_vec()).collect(),
+            prefixes: prefixes.iter().map(|p| p.to_vec()).collect(),
+        };
+
+        // 读过的键在之后被其他事务修改
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        tx1.set(b"row-2".to_vec(), b"b".to_vec())?;
+        tx2.set(b"schema".to_vec(), b"v2".to_vec())?;
+        tx2.commit()?;
+        assert_eq!(tx1.commit_validated(&reads(&[b"schema"], &[])), Err(Error::WriteConflict));
+        assert_eq!(mvcc.begin()?.get(b"row-2".to_vec())?, None);
+
+        // 修改还没有提交也冲突，没有变化时正常提交
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        tx2.set(b"schema".to_vec(), b"v3".to_vec())?;
+        tx1.set(b"row-2".to_vec(), b"b".to_vec())?;
+        assert_eq!(tx1.commit_validated(&reads(&[b"schema"], &[])), Err(Error::WriteConflict));
+        tx2.rollback()?;
+        let tx1 = mvcc.begin()?;
+        tx1.set(b"row-2".to_vec(), b"b".to_vec())?;
+        tx1.commit_validated(&reads(&[b"schema"], &[b"row-"]))?;
+
+        // 前缀下有看不到的新版本
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        tx1.delete(b"schema".to_vec())?;
+        tx2.set(b"row-3".to_vec(), b"c".to_vec())?;
+        tx2.commit()?;
+        assert_eq!(tx1.commit_validated(&reads(&[], &[b"row-"])), Err(Error::WriteConflict));
+        let tx1 = mvcc.begin()?;
+        tx1.delete(b"schema".to_vec())?;
+        tx1.commit_validated(&reads(&[], &[b"other-"]))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_validation() -> Result<()> {
+        read_validation(MemoryEngine::new())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            read_validation(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    // 20. close
+    fn close(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+        let tx1 = mvcc.begin()?;
+        tx1.set(b"key1".to_vec(), b"val1-1".to_vec())?;
+
+        // 还没结束的事务被回滚，之后的写入、提交和新事务都失败
+        assert_eq!(mvcc.close()?, vec![2]);
+        assert!(mvcc.close()?.is_empty());
+        assert_eq!(tx1.set(b"key2".to_vec(), b"val2".to_vec()), Err(Error::Closed));
+        assert_eq!(tx1.commit(), Err(Error::Closed));
+        assert!(matches!(mvcc.begin(), Err(Error::Closed)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_close() -> Result<()> {
+        close(MemoryEngine::new())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            close(DiskEngine::new(p.clone())?)?;
+            // 关闭时回滚的写入不会在重新打开后出现
+            let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+            assert!(mvcc.recover()?.is_empty());
+            assert_eq!(mvcc.begin()?.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    // 21. compact without unreadable versions
+    fn compact(eng: impl Engine) -> Result<()> {
+        fn count_versions(mvcc: &Mvcc<impl Engine>) -> Result<usize> {
+            let mut prefix = super::MvccKeyPrefix::Version(Vec::new()).encode()?;
+            prefix.truncate(prefix.len() - 2);
+            Ok(mvcc.engine.read()?.scan_prefix(prefix).count())
+        }
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"a".to_vec())?;
+        tx.commit()?;
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"b".to_vec())?;
+        tx.set(b"key2".to_vec(), b"x".to_vec())?;
+        tx.commit()?;
+        let reader = mvcc.begin()?;
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"c".to_vec())?;
+        tx.delete(b"key2".to_vec())?;
+        tx.commit()?;
+        assert_eq!(count_versions(&mvcc)?, 5);
+
+        // 还在运行的事务能读到的版本都保留，只删 key1 最早的版本
+        assert_eq!(mvcc.gc_watermark()?, reader.version());
+        assert_eq!(mvcc.compact()?, 1);
+        assert_eq!(reader.get(b"key1".to_vec())?, Some(b"b".to_vec()));
+        assert_eq!(reader.get(b"key2".to_vec())?, Some(b"x".to_vec()));
+
+        // 事务开始时活跃的事务提交之后，它的写入对这个事务仍不可见，也不能删掉之前的版本
+        reader.rollback()?;
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        tx1.set(b"key1".to_vec(), b"d".to_vec())?;
+        tx1.commit()?;
+        assert_eq!(mvcc.gc_watermark()?, tx1.version());
+        assert_eq!(mvcc.compact()?, 3);
+        assert_eq!(tx2.get(b"key1".to_vec())?, Some(b"c".to_vec()));
+        assert_eq!(tx2.get(b"key2".to_vec())?, None);
+        tx2.commit()?;
+
+        // 没有运行的事务时每个键只剩最新的版本，被删除的键一个版本都不剩
+        assert_eq!(mvcc.compact()?, 1);
+        assert_eq!(count_versions(&mvcc)?, 1);
+        let tx = mvcc.begin()?;
+        assert_eq!(tx.get(b"key1".to_vec())?, Some(b"d".to_vec()));
+        assert_eq!(tx.get(b"key2".to_vec())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> Result<()> {
+        compact(MemoryEngine::new())?;
+        #[cfg(feature = "disk")]
+        {
+            let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+            compact(DiskEngine::new(p.clone())?)?;
+            std::fs::remove_dir_all(p.parent().unwrap())?;
+        }
+        Ok(())
+    }
+
+    // 22. reads share the engine lock
+    fn concurrent_reads(eng: impl Engine + Send + Sync + 'static) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+        let reader = mvcc.begin()?;
+        let mut other = Some(mvcc.begin()?);
+        // 一个扫描还在进行时，另一个线程的读不用等它结束
+        let (send, recv) = mpsc::channel();
+        reader.scan_prefix_with(b"key".to_vec(), |_, _| {
+            let (other, send) = (other.take().unwrap(), send.clone());
+            thread::spawn(move || send.send(other.get(b"key1".to_vec())));
+            let value = recv.recv_timeout(Duration::from_secs(10)).expect("the read waited for the scan");
+            assert_eq!(value?, Some(b"val1".to_vec()));
+            Ok(())
+        })?;
+        assert!(other.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_reads() -> Result<()> {
+        concurrent_reads(MemoryEngine::new())?;
+        concurrent_reads(SkipListEngine::new())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+// deterministic simulation of concurrent mvcc transactions over the storage engines
+// a seeded generator picks the next step: begin, get, set, delete, scan, commit, rollback
+// and for the disk engine a crash, which reopens the log with its unsynced tail torn off
+// every step is checked against a model of snapshot isolation
+// a failing run prints its seed, rerun the seed to reproduce it step by step
+
+use std::collections::BTreeMap;
+#[cfg(feature = "disk")]
+use std::{
+    fs::{self, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    storage::{
+        engine::Engine,
+        memory::MemoryEngine,
+        mvcc::{Mvcc, MvccTransaction},
+    },
+};
+#[cfg(feature = "disk")]
+use crate::storage::disk::{DiskEngine, DiskEngineConfig};
+
+const KEYS: u64 = 8;
+const MAX_ACTIVE: usize = 4;
+
+// splitmix64, the same seed always gives the same steps
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn key(&mut self) -> Vec<u8> {
+        format!("k{}", self.below(KEYS)).into_bytes()
+    }
+}
+
+type Snapshot = BTreeMap<Vec<u8>, Vec<u8>>;
+
+// what a transaction must see: the data committed when it began and its own writes
+struct ModelTxn<E: Engine> {
+    txn: MvccTransaction<E>,
+    begin_seq: u64,
+    snapshot: Snapshot,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<E: Engine> ModelTxn<E> {
+    fn expect(&self) -> Snapshot {
+        let mut data = self.snapshot.clone();
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => data.insert(key.clone(), value.clone()),
+                None => data.remove(key),
+            };
+        }
+        data
+    }
+}
+
+struct Simulation<E: Engine> {
+    seed: u64,
+    rng: Rng,
+    mvcc: Mvcc<E>,
+    committed: Snapshot,
+    // commit sequence and keys of every committed transaction
+    history: Vec<(u64, Vec<Vec<u8>>)>,
+    seq: u64,
+    active: Vec<ModelTxn<E>>,
+}
+
+impl<E: Engine> Simulation<E> {
+    fn new(seed: u64, engine: E) -> Self {
+        Self {
+            seed,
+            rng: Rng(seed),
+            mvcc: Mvcc::new(engine),
+            committed: Snapshot::new(),
+            history: Vec::new(),
+            seq: 0,
+            active: Vec::new(),
+        }
+    }
+
+    fn check(&self, ok: bool, step: u64, what: &str) -> Result<()> {
+        if ok {
+            Ok(())
+        } else {
+            Err(Error::Internal(format!("seed {} step {}: {}", self.seed, step, what)))
+        }
+    }
+
+    // a write conflicts if another active transaction wrote the key,
+    // or a transaction committed it after this one began
+    fn conflicts(&self, i: usize, key: &[u8]) -> bool {
+        let me = &self.active[i];
+        self.active.iter().enumerate().any(|(j, t)| j != i && t.writes.contains_key(key))
+            || self.history.iter().any(|(seq, keys)| *seq > me.begin_seq && keys.iter().any(|k| k == key))
+    }
+
+    fn step(&mut self, step: u64) -> Result<()> {
+        if self.active.is_empty() || (self.active.len() < MAX_ACTIVE && self.rng.below(4) == 0) {
+            let txn = self.mvcc.begin()?;
+            self.active.push(ModelTxn { txn, begin_seq: self.seq, snapshot: self.committed.clone(), writes: BTreeMap::new() });
+            return Ok(());
+        }
+        let i = self.rng.below(self.active.len() as u64) as usize;
+        match self.rng.below(10) {
+            0..=2 => {
+                let key = self.rng.key();
+                let got = self.active[i].txn.get(key.clone())?;
+                self.check(got.as_ref() == self.active[i].expect().get(&key), step, "get does not match the snapshot")?;
+            }
+            3..=5 => {
+                let key = self.rng.key();
+                let value = if self.rng.below(4) == 0 { None } else { Some(self.rng.next().to_be_bytes().to_vec()) };
+                let conflict = self.conflicts(i, &key);
+                let result = match &value {
+                    Some(value) => self.active[i].txn.set(key.clone(), value.clone()),
+                    None => self.active[i].txn.delete(key.clone()),
+                };
+                match result {
+                    Ok(()) => {
+                        self.check(!conflict, step, "write succeeded over a concurrent write")?;
+                        self.active[i].writes.insert(key, value);
+                    }
+                    Err(Error::WriteConflict) => {
+                        self.check(conflict, step, "unexpected write conflict")?;
+                        self.active.remove(i).txn.rollback()?;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            6 => {
+                let got = self.active[i].txn.scan_prefix(b"k".to_vec())?;
+                let got = got.into_iter().map(|r| (r.key, r.value)).collect::<Snapshot>();
+                self.check(got == self.active[i].expect(), step, "scan does not match the snapshot")?;
+            }
+            7 | 8 => {
+                let t = self.active.remove(i);
+                t.txn.commit()?;
+                // only the keys it wrote change, others may have committed since it began
+                self.committed = self.apply(&t);
+                self.seq += 1;
+                self.history.push((self.seq, t.writes.into_keys().collect()));
+            }
+            _ => self.active.remove(i).txn.rollback()?,
+        }
+        Ok(())
+    }
+
+    fn apply(&self, t: &ModelTxn<E>) -> Snapshot {
+        let mut data = self.committed.clone();
+        for (key, value) in &t.writes {
+            match value {
+                Some(value) => data.insert(key.clone(), value.clone()),
+                None => data.remove(key),
+            };
+        }
+        data
+    }
+
+    // a new transaction sees exactly the committed data
+    fn check_committed(&self, step: u64) -> Result<()> {
+        let txn = self.mvcc.begin()?;
+        let got = txn.scan_prefix(b"k".to_vec())?.into_iter().map(|r| (r.key, r.value)).collect::<Snapshot>();
+        txn.rollback()?;
+        self.check(got == self.committed, step, "committed data lost or changed")
+    }
+}
+
+fn run_memory(seed: u64, steps: u64) -> Result<()> {
+    let mut sim = Simulation::new(seed, MemoryEngine::new());
+    for step in 0..steps {
+        sim.step(step)?;
+    }
+    sim.check_committed(steps)
+}
+
+#[cfg(feature = "disk")]
+fn open(path: &Path) -> Result<DiskEngine> {
+    DiskEngineConfig::new(path.to_path_buf()).compact_policy(None).open()
+}
+
+#[cfg(feature = "disk")]
+// all key values of the engine, to compare the keydir built from the log and from the hint
+fn dump(engine: &DiskEngine) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    engine.scan(..).collect()
+}
+
+#[cfg(feature = "disk")]
+// the process dies: active transactions vanish without rollback and the engine is not closed
+fn crash(sim: Simulation<DiskEngine>, path: &PathBuf, safe: u64) -> Result<(Simulation<DiskEngine>, u64)> {
+    let Simulation { seed, mut rng, mvcc, committed, history, seq, active } = sim;
+    drop(active);
+    drop(mvcc);
+    // the hint is written on a clean shutdown only
+    let mut hint = path.clone().into_os_string();
+    hint.push(".hint");
+    let _ = fs::remove_file(&hint);
+    // anything after the last commit was not synced, the tail may be lost at any byte
+    let size = fs::metadata(path)?.len();
+    let len = safe + rng.below(size - safe + 1);
+    OpenOptions::new().write(true).open(path)?.set_len(len)?;
+
+    if rng.below(4) == 0 {
+        drop(DiskEngine::new_compact(path.clone())?);
+    }
+    let engine = open(path)?;
+    let replayed = dump(&engine)?;
+    drop(engine);
+    // reopen from the hint of the clean shutdown above, the keydir must be the same
+    let engine = open(path)?;
+    let same = dump(&engine)? == replayed;
+    let sim = Simulation { seed, rng, mvcc: Mvcc::new(engine), committed, history, seq, active: Vec::new() };
+    sim.check(same, seq, "keydir from the hint differs from the log")?;
+    sim.mvcc.recover()?;
+    sim.check(sim.mvcc.status()?.active_txns == 0, seq, "transactions left active after recovery")?;
+    Ok((sim, fs::metadata(path)?.len()))
+}
+
+#[cfg(feature = "disk")]
+fn run_disk(seed: u64, steps: u64) -> Result<()> {
+    let path = tempfile::tempdir()?.into_path().join("sqldb-log");
+    let mut sim = Simulation::new(seed, open(&path)?);
+    let mut safe = 0;
+    for step in 0..steps {
+        if sim.rng.below(50) == 0 {
+            (sim, safe) = crash(sim, &path, safe)?;
+            sim.check_committed(step)?;
+            continue;
+        }
+        let commits = sim.seq;
+        sim.step(step)?;
+        if sim.seq != commits {
+            safe = fs::metadata(&path)?.len();
+        }
+    }
+    sim.check_committed(steps)?;
+    drop(sim);
+    fs::remove_dir_all(path.parent().unwrap())?;
+    Ok(())
+}
+
+#[test]
+fn test_simulation_memory() -> Result<()> {
+    for seed in 0..50 {
+        run_memory(seed, 500)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "disk")]
+#[test]
+fn test_simulation_disk() -> Result<()> {
+    for seed in 0..20 {
+        run_disk(seed, 300)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use std::{
+    ops::{Bound, RangeBounds},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+};
+
+use crossbeam_skiplist::{map, SkipMap};
+
+use crate::error::Result;
+
+use super::engine::{prefix_range, KeyFn, KeyValue, ScanFn, Status};
+
+type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+// in-memory engine on a concurrent skiplist, it is not lock-free: batches take a lock
+// clones share the same data, give each thread its own clone to read and write concurrently
+// reads and single writes never wait for each other, a batch waits for the reads running
+// and holds new ones back until it is applied, so no reader sees part of it
+// under Mvcc reads share its engine lock and writes take it alone, like any other engine
+// a thread must not write a batch while it holds an iterator of another clone
+#[derive(Clone, Default)]
+pub struct SkipListEngine {
+    data: Arc<SkipMap<Vec<u8>, Vec<u8>>>,
+    // readers share it, a batch takes it exclusively
+    gate: Arc<RwLock<()>>,
+}
+
+impl SkipListEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a panic in the middle of a batch poisons the gate, the data is still readable then
+    fn read_gate(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl super::engine::Engine for SkipListEngine {
+    type EngineIterator<'a> = SkipListEngineIterator<'a>;
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.data.insert(key, value);
+        Ok(())
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let _gate = self.read_gate();
+        Ok(self.data.get(&key).map(|entry| entry.value().clone()))
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        self.data.remove(&key);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        let _gate = self.gate.write()?;
+        for (key, value) in batch {
+            match value {
+                Some(value) => {
+                    self.data.insert(key, value);
+                }
+                None => {
+                    self.data.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn status(&mut self) -> Result<Status> {
+        let _gate = self.read_gate();
+        Ok(Status {
+            name: "skiplist".to_string(),
+            keys: self.data.len() as u64,
+            live_bytes: self.data.iter().map(|e| (e.key().len() + e.value().len()) as u64).sum(),
+            dead_bytes: 0,
+            file_size: 0,
+            last_compaction: None,
+        })
+    }
+
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        SkipListEngineIterator {
+            _gate: self.read_gate(),
+            inner: self.data.range(range),
+        }
+    }
+
+    fn scan_with(&self, range: impl RangeBounds<Vec<u8>>, f: &mut ScanFn<'_>) -> Result<()> {
+        let range: KeyRange = (range.start_bound().cloned(), range.end_bound().cloned());
+        let _gate = self.read_gate();
+        for entry in self.data.range(range) {
+            f(entry.key(), entry.value())?;
+        }
+        Ok(())
+    }
+
+    fn find_last(&self, range: impl RangeBounds<Vec<u8>>, f: &mut KeyFn<'_>) -> Result<Option<KeyValue>> {
+        let range: KeyRange = (range.start_bound().cloned(), range.end_bound().cloned());
+        let _gate = self.read_gate();
+        for entry in self.data.range(range).rev() {
+            if f(entry.key())? {
+                return Ok(Some((entry.key().clone(), entry.value().clone())));
+            }
+        }
+        Ok(None)
+    }
+
+    fn count_prefix(&self, prefix: Vec<u8>) -> Result<u64> {
+        let _gate = self.read_gate();
+        Ok(self.data.range(prefix_range(prefix)).count() as u64)
+    }
+
+    fn size_prefix(&self, prefix: Vec<u8>) -> Result<u64> {
+        let _gate = self.read_gate();
+        Ok(self.data.range(prefix_range(prefix)).map(|e| (e.key().len() + e.value().len()) as u64).sum())
+    }
+}
+
+pub struct SkipListEngineIterator<'a> {
+    // no batch is applied while the iterator is alive
+    _gate: RwLockReadGuard<'a, ()>,
+    inner: map::Range<'a, Vec<u8>, KeyRange, Vec<u8>, Vec<u8>>,
+}
+
+impl<'a> SkipListEngineIterator<'a> {
+    fn map(entry: map::Entry<'_, Vec<u8>, Vec<u8>>) -> <Self as Iterator>::Item {
+        Ok((entry.key().clone(), entry.value().clone()))
+    }
+}
+
+impl<'a> super::engine::EngineIterator for SkipListEngineIterator<'a> {}
+
+impl<'a> Iterator for SkipListEngineIterator<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(Self::map)
+    }
+}
+
+impl<'a> DoubleEndedIterator for SkipListEngineIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(Self::map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SkipListEngine;
+    use crate::{error::Result, storage::engine::Engine};
+
+    #[test]
+    fn test_skiplist_concurrent() -> Result<()> {
+        let eng = SkipListEngine::new();
+        // 多个线程通过各自的 clone 同时读写
+        let handles = (0..4_u8)
+            .map(|t| {
+                let mut eng = eng.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    for i in 0..100_u8 {
+                        eng.set(vec![t, i], vec![i])?;
+                        assert_eq!(eng.get(vec![t, i])?, Some(vec![i]));
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+
+        let mut eng = eng;
+        assert_eq!(eng.scan(..).count(), 400);
+        assert_eq!(eng.scan_prefix(vec![2]).count(), 100);
+        assert_eq!(eng.status()?.keys, 400);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skiplist_batch() -> Result<()> {
+        let eng = SkipListEngine::new();
+        // 读的时候看不到写了一半的 batch，a 和 b 总是相同
+        let mut writer = eng.clone();
+        let handle = std::thread::spawn(move || -> Result<()> {
+            for i in 0..20_000_u32 {
+                let value = i.to_be_bytes().to_vec();
+                writer.write_batch(vec![(b"a".to_vec(), Some(value.clone())), (b"b".to_vec(), Some(value))])?;
+            }
+            writer.write_batch(vec![(b"a".to_vec(), None), (b"b".to_vec(), None)])
+        });
+        while !handle.is_finished() {
+            let values = eng.scan(..).map(|r| r.map(|(_, v)| v)).collect::<Result<Vec<_>>>()?;
+            assert!(values.is_empty() || (values.len() == 2 && values[0] == values[1]));
+        }
+        handle.join().unwrap()?;
+        assert_eq!(eng.scan(..).count(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use std::{ops::RangeBounds, path::PathBuf};
+
+use crate::error::{Error, Result};
+
+use super::engine::Status;
+
+// storage engine backed by sled, enabled by the "sled" feature
+// sled keeps its own log and index, so it takes a directory rather than a file
+pub struct SledEngine {
+    db: sled::Db,
+    // counted once on open and kept up to date by the writes, len() of sled scans the whole tree
+    keys: u64,
+    live_bytes: u64,
+}
+
+impl SledEngine {
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        let db = sled::open(dir)?;
+        let (mut keys, mut live_bytes) = (0, 0);
+        for item in db.iter() {
+            let (k, v) = item?;
+            keys += 1;
+            live_bytes += (k.len() + v.len()) as u64;
+        }
+        Ok(Self { db, keys, live_bytes })
+    }
+
+    // update the counters for the key, from the value it had to the one it has now
+    fn count(&mut self, key: &[u8], old: Option<usize>, new: Option<usize>) {
+        if let Some(old) = old {
+            self.keys -= 1;
+            self.live_bytes -= (key.len() + old) as u64;
+        }
+        if let Some(new) = new {
+            self.keys += 1;
+            self.live_bytes += (key.len() + new) as u64;
+        }
+    }
+}
+
+impl From<sled::Error> for Error {
+    fn from(value: sled::Error) -> Self {
+        Error::Internal(value.to_string())
+    }
+}
+
+impl super::engine::Engine for SledEngine {
+    type EngineIterator<'a> = SledEngineIterator;
+
+    fn open(dir: PathBuf) -> Result<Self> {
+        Self::new(dir)
+    }
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let len = value.len();
+        let old = self.db.insert(&key, value)?;
+        self.count(&key, old.map(|v| v.len()), Some(len));
+        Ok(())
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        let old = self.db.remove(&key)?;
+        self.count(&key, old.map(|v| v.len()), None);
+        Ok(())
+    }
+
+    // sled applies a batch atomically, it does not return the old values,
+    // so they are read first, a key written twice counts its last write only
+    fn write_batch(&mut self, batch: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        let mut sled_batch = sled::Batch::default();
+        let mut changes = std::collections::BTreeMap::new();
+        for (key, value) in batch {
+            changes.insert(key.clone(), value.as_ref().map(|v| v.len()));
+            match value {
+                Some(value) => sled_batch.insert(key, value),
+                None => sled_batch.remove(key),
+            }
+        }
+        let mut olds = Vec::with_capacity(changes.len());
+        for key in changes.keys() {
+            olds.push(self.db.get(key)?.map(|v| v.len()));
+        }
+        self.db.apply_batch(sled_batch)?;
+        for ((key, new), old) in changes.into_iter().zip(olds) {
+            self.count(&key, old, new);
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    // sled does not report garbage, dead bytes are unknown
+    fn status(&mut self) -> Result<Status> {
+        Ok(Status {
+            name: "sled".to_string(),
+            keys: self.keys,
+            live_bytes: self.live_bytes,
+            dead_bytes: 0,
+            file_size: self.db.size_on_disk()?,
+            last_compaction: None,
+        })
+    }
+
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        SledEngineIterator {
+            inner: self.db.range(range),
+        }
+    }
+}
+
+pub struct SledEngineIterator {
+    inner: sled::Iter,
+}
+
+impl SledEngineIterator {
+    fn map(item: sled::Result<(sled::IVec, sled::IVec)>) -> <Self as Iterator>::Item {
+        let (k, v) = item?;
+        Ok((k.to_vec(), v.to_vec()))
+    }
+}
+
+impl super::engine::EngineIterator for SledEngineIterator {}
+
+impl Iterator for SledEngineIterator {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(Self::map)
+    }
+}
+
+impl DoubleEndedIterator for SledEngineIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(Self::map)
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+
+use crate::error::{Error, Result};
+
+use super::{
+    engine::Engine,
+    mvcc::{Mvcc, MvccTransaction, Version},
+};
+
+// two phase commit of one transaction spanning several databases
+// 1. prepare every participant, its writes and a prepared mark become durable
+// 2. commit the first participant together with the commit decision, this is the commit point
+// 3. commit the others
+// 4. forget the decision, no participant needs it anymore
+// a crash leaves prepared transactions behind, recover() finishes them the same way everywhere:
+// commit if some database holds the decision, otherwise roll back
+// the global id ends with the number of participants, so recover() can tell whether it has
+// seen all of them before it rolls back
+
+// a transaction on one database taking part in a two phase commit
+pub trait Participant {
+    fn prepare(&self, gtid: &[u8]) -> Result<()>;
+    // commit, recording the decision of gtid when given
+    fn commit(&self, decision: Option<&[u8]>) -> Result<()>;
+    fn rollback(&self) -> Result<()>;
+    // drop the decision of gtid this participant recorded, called once every participant committed
+    // default: nothing, for participants that keep no decision
+    fn forget(&self, _gtid: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<E: Engine> Participant for MvccTransaction<E> {
+    fn prepare(&self, gtid: &[u8]) -> Result<()> {
+        MvccTransaction::prepare(self, gtid)
+    }
+
+    fn commit(&self, decision: Option<&[u8]>) -> Result<()> {
+        match decision {
+            Some(gtid) => self.commit_decision(gtid),
+            None => MvccTransaction::commit(self),
+        }
+    }
+
+    fn rollback(&self) -> Result<()> {
+        MvccTransaction::rollback(self)
+    }
+
+    fn forget(&self, gtid: &[u8]) -> Result<()> {
+        self.forget_decision(gtid)
+    }
+}
+
+// a database which may hold prepared transactions after a crash
+pub trait Recoverable {
+    fn prepared(&self) -> Result<Vec<(Version, Vec<u8>)>>;
+    fn is_committed(&self, gtid: &[u8]) -> Result<bool>;
+    fn finish(&self, version: Version, commit: bool) -> Result<()>;
+    fn forget(&self, gtid: &[u8]) -> Result<()>;
+}
+
+impl<E: Engine> Recoverable for Mvcc<E> {
+    fn prepared(&self) -> Result<Vec<(Version, Vec<u8>)>> {
+        Mvcc::prepared(self)
+    }
+
+    fn is_committed(&self, gtid: &[u8]) -> Result<bool> {
+        Mvcc::is_committed(self, gtid)
+    }
+
+    fn finish(&self, version: Version, commit: bool) -> Result<()> {
+        let txn = self.resume(version)?;
+        if commit {
+            txn.commit()
+        } else {
+            txn.rollback()
+        }
+    }
+
+    fn forget(&self, gtid: &[u8]) -> Result<()> {
+        self.forget_decision(gtid)
+    }
+}
+
+// random part of the global id
+const GTID_SIZE: usize = 16;
+
+// random bytes | number of participants (4)
+fn new_gtid(participants: usize) -> Vec<u8> {
+    let mut gtid = vec![0; GTID_SIZE];
+    OsRng.fill_bytes(&mut gtid);
+    gtid.extend((participants as u32).to_be_bytes());
+    gtid
+}
+
+// None for a global id not made by new_gtid
+fn participants(gtid: &[u8]) -> Option<usize> {
+    let count = gtid.get(GTID_SIZE..).filter(|count| count.len() == 4)?;
+    Some(u32::from_be_bytes(count.try_into().ok()?) as usize)
+}
+
+// commit on every participant or on none of them
+// an error after the commit point is returned as is, the rest is finished by recover()
+pub fn commit(participants: &[&dyn Participant]) -> Result<()> {
+    let Some((first, rest)) = participants.split_first() else {
+        return Ok(());
+    };
+    let gtid = new_gtid(participants.len());
+    for p in participants {
+        if let Err(err) = p.prepare(&gtid) {
+            // no decision is recorded yet, rolling everything back is safe
+            for p in participants {
+                let _ = p.rollback();
+            }
+            return Err(err);
+        }
+    }
+    first.commit(Some(&gtid))?;
+    for p in rest {
+        p.commit(None)?;
+    }
+    // a crash before this leaves the decision behind for good, it is small and harmless
+    first.forget(&gtid)
+}
+
+// finish prepared transactions left on the databases, call it with every database
+// that can take part in a two phase commit before they are used
+// with no decision found, a transaction is only rolled back if all its participants are still
+// prepared, the first commit records the decision so none of them can have committed then,
+// otherwise its decision may be on a database not passed in: it stays prepared, and an error
+// names it once the other transactions are finished
+pub fn recover(databases: &[&dyn Recoverable]) -> Result<()> {
+    // global id => (database, version) of its prepared participants
+    let mut prepared: BTreeMap<Vec<u8>, Vec<(usize, Version)>> = BTreeMap::new();
+    for (i, db) in databases.iter().enumerate() {
+        for (version, gtid) in db.prepared()? {
+            prepared.entry(gtid).or_default().push((i, version));
+        }
+    }
+    let mut unresolved = Vec::new();
+    for (gtid, txns) in prepared {
+        let mut decided_by = None;
+        for (i, db) in databases.iter().enumerate() {
+            if db.is_committed(&gtid)? {
+                decided_by = Some(i);
+                break;
+            }
+        }
+        let count = participants(&gtid);
+        if decided_by.is_none() && count != Some(txns.len()) {
+            unresolved.push(format!("{:?} ({} of {:?} participants prepared)", gtid, txns.len(), count));
+            continue;
+        }
+        for (i, version) in &txns {
+            databases[*i].finish(*version, decided_by.is_some())?;
+        }
+        // the others committed before the crash unless all but the decider were prepared
+        if let Some(i) = decided_by.filter(|_| count == Some(txns.len() + 1)) {
+            databases[i].forget(&gtid)?;
+        }
+    }
+    if !unresolved.is_empty() {
+        return Err(Error::Internal(format!(
+            "prepared transactions without a commit decision on the given databases, \
+             pass every database they span: {}",
+            unresolved.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{commit, new_gtid, recover, Participant};
+    use crate::{
+        error::{Error, Result},
+        storage::{memory::MemoryEngine, mvcc::Mvcc},
+    };
+
+    // prepare 失败的参与者
+    struct FailPrepare;
+
+    impl Participant for FailPrepare {
+        fn prepare(&self, _: &[u8]) -> Result<()> {
+            Err(Error::Internal("prepare failed".to_string()))
+        }
+        fn commit(&self, _: Option<&[u8]>) -> Result<()> {
+            unreachable!()
+        }
+        fn rollback(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_two_phase_commit() -> Result<()> {
+        let db1 = Mvcc::new(MemoryEngine::new());
+        let db2 = Mvcc::new(MemoryEngine::new());
+
+        let (t1, t2) = (db1.begin()?, db2.begin()?);
+        t1.set(b"a".to_vec(), b"1".to_vec())?;
+        t2.set(b"b".to_vec(), b"2".to_vec())?;
+        commit(&[&t1, &t2])?;
+        assert_eq!(db1.begin()?.get(b"a".to_vec())?, Some(b"1".to_vec()));
+        assert_eq!(db2.begin()?.get(b"b".to_vec())?, Some(b"2".to_vec()));
+        assert!(db1.prepared()?.is_empty() && db2.prepared()?.is_empty());
+        // 所有参与者提交之后，提交决定被删除，db1 不会多出一个 key
+        assert_eq!(db1.status()?.storage.keys, db2.status()?.storage.keys);
+
+        // 任何一个参与者 prepare 失败，所有参与者都回滚
+        let (t1, t2) = (db1.begin()?, db2.begin()?);
+        t1.set(b"a".to_vec(), b"x".to_vec())?;
+        t2.set(b"b".to_vec(), b"x".to_vec())?;
+        assert!(commit(&[&t1, &t2, &FailPrepare]).is_err());
+        assert_eq!(db1.begin()?.get(b"a".to_vec())?, Some(b"1".to_vec()));
+        assert_eq!(db2.begin()?.get(b"b".to_vec())?, Some(b"2".to_vec()));
+        assert!(db1.prepared()?.is_empty() && db2.prepared()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_two_phase_recover() -> Result<()> {
+        let db1 = Mvcc::new(MemoryEngine::new());
+        let db2 = Mvcc::new(MemoryEngine::new());
+
+        // 所有参与者都 prepare 之后崩溃，没有提交决定，全部回滚
+        let g1 = new_gtid(2);
+        let (t1, t2) = (db1.begin()?, db2.begin()?);
+        t1.set(b"a".to_vec(), b"1".to_vec())?;
+        t2.set(b"b".to_vec(), b"1".to_vec())?;
+        t1.prepare(&g1)?;
+        t2.prepare(&g1)?;
+        drop((t1, t2));
+        assert_eq!(db1.prepared()?, vec![(1, g1.clone())]);
+        recover(&[&db1, &db2])?;
+        assert_eq!(db1.begin()?.get(b"a".to_vec())?, None);
+        assert_eq!(db2.begin()?.get(b"b".to_vec())?, None);
+
+        // 第一个参与者已经提交之后崩溃，其余的参与者也要提交，之后提交决定被删除
+        let g2 = new_gtid(2);
+        let (t1, t2) = (db1.begin()?, db2.begin()?);
+        t1.set(b"a".to_vec(), b"2".to_vec())?;
+        t2.set(b"b".to_vec(), b"2".to_vec())?;
+        t1.prepare(&g2)?;
+        t2.prepare(&g2)?;
+        t1.commit_decision(&g2)?;
+        drop((t1, t2));
+        // 提交之前，prepare 的写入对其他事务不可见
+        assert_eq!(db2.begin()?.get(b"b".to_vec())?, None);
+        recover(&[&db1, &db2])?;
+        assert_eq!(db1.begin()?.get(b"a".to_vec())?, Some(b"2".to_vec()));
+        assert_eq!(db2.begin()?.get(b"b".to_vec())?, Some(b"2".to_vec()));
+        assert!(db1.prepared()?.is_empty() && db2.prepared()?.is_empty());
+        assert!(!db1.is_committed(&g2)?);
+
+        // 缺少持有提交决定的数据库时不能回滚，事务保持 prepare 状态
+        let g3 = new_gtid(2);
+        let (t1, t2) = (db1.begin()?, db2.begin()?);
+        t1.set(b"a".to_vec(), b"3".to_vec())?;
+        t2.set(b"b".to_vec(), b"3".to_vec())?;
+        t1.prepare(&g3)?;
+        t2.prepare(&g3)?;
+        t1.commit_decision(&g3)?;
+        drop((t1, t2));
+        assert!(matches!(recover(&[&db2]), Err(Error::Internal(_))));
+        assert_eq!(db2.prepared()?.len(), 1);
+        recover(&[&db1, &db2])?;
+        assert_eq!(db2.begin()?.get(b"b".to_vec())?, Some(b"3".to_vec()));
+        Ok(())
+    }
+}
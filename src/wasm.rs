@@ -0,0 +1,35 @@
+// javascript api, the sql engine over MemoryEngine in the browser
+//   const db = new Database();
+//   db.execute("create table t (a int, b text);");
+//   JSON.parse(db.execute("select * from t;"))  // {version: 1, columns: ["a", "b"], rows: [[1, "x"]]}
+use wasm_bindgen::prelude::*;
+
+use crate::sql::types::json::result_to_json;
+
+#[wasm_bindgen]
+pub struct Database {
+    db: crate::Database,
+}
+
+#[wasm_bindgen]
+impl Database {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Result<Database, JsError> {
+        Ok(Self { db: crate::Database::open_memory().map_err(to_js)? })
+    }
+
+    // the result as json text, see types::json for the encoding
+    // with "warnings", their messages, if the statement had any
+    pub fn execute(&mut self, sql: &str) -> Result<String, JsError> {
+        let result = self.db.execute(sql).map_err(to_js)?;
+        let mut json = result_to_json(&result).map_err(to_js)?;
+        if !self.db.warnings().is_empty() {
+            json["warnings"] = self.db.warnings().iter().map(ToString::to_string).collect();
+        }
+        Ok(json.to_string())
+    }
+}
+
+fn to_js(err: crate::error::Error) -> JsError {
+    JsError::new(&err.to_string())
+}
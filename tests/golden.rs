@@ -0,0 +1,128 @@
+// golden tests of the planner and executors
+// each tests/golden/*.sql runs statement by statement in a new in-memory database,
+// and what they print must equal the checked-in tests/golden/*.out next to it:
+//   > select a from t where a > 1;
+//    a
+//   ---
+//    2
+//   (1 row)
+// EXPLAIN prints the plan lines as they are, errors print as Error: ..., warnings after the result as Warning: ...
+// lines starting with -- are comments, copied to the output
+// after an intended change, write the outputs again and review their diff:
+//   UPDATE_GOLDEN=1 cargo test --test golden
+use std::{env, fs, path::Path};
+
+use sharkdb::{
+    error::Result,
+    sql::{
+        engine::{kv::KVEngine, Engine},
+        executor::ResultSet,
+        types::{format_table, Value},
+    },
+    storage::memory::MemoryEngine,
+};
+
+#[test]
+fn golden() -> Result<()> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut files = fs::read_dir(&dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sql"))
+        .collect::<Vec<_>>();
+    files.sort();
+    assert!(!files.is_empty(), "no .sql files in {}", dir.display());
+
+    let mut failed = Vec::new();
+    for file in files {
+        let output = run(&fs::read_to_string(&file)?)?;
+        let expected_file = file.with_extension("out");
+        if update {
+            fs::write(&expected_file, &output)?;
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_file).unwrap_or_default();
+        if output != expected {
+            let line = output.lines().zip(expected.lines()).position(|(a, b)| a != b).unwrap_or_else(|| {
+                output.lines().count().min(expected.lines().count())
+            });
+            failed.push(format!(
+                "{}:{}\n  expected: {:?}\n  got:      {:?}",
+                expected_file.display(),
+                line + 1,
+                expected.lines().nth(line).unwrap_or(""),
+                output.lines().nth(line).unwrap_or(""),
+            ));
+        }
+    }
+    assert!(failed.is_empty(), "golden files differ, run with UPDATE_GOLDEN=1 to update them\n{}", failed.join("\n"));
+    Ok(())
+}
+
+fn run(script: &str) -> Result<String> {
+    let engine = KVEngine::new(MemoryEngine::new())?;
+    let mut session = engine.session()?;
+    let mut out = String::new();
+    for stmt in split(script) {
+        if stmt.starts_with("--") {
+            out.push_str(&stmt);
+            out.push('\n');
+            continue;
+        }
+        out.push_str(&format!("> {}\n", stmt));
+        match session.execute(&stmt) {
+            Ok(ResultSet::Scan { columns, row }) if columns == ["plan"] => {
+                for row in row {
+                    match row.first() {
+                        Some(Value::String(line)) => out.push_str(line),
+                        value => out.push_str(&format!("{:?}", value)),
+                    }
+                    out.push('\n');
+                }
+            }
+            Ok(ResultSet::Scan { columns, row }) => {
+                out.push_str(&format_table(&columns, &row));
+                out.push_str(&match row.len() {
+                    1 => "(1 row)\n".to_string(),
+                    n => format!("({} rows)\n", n),
+                });
+            }
+            Ok(result) => out.push_str(&format!("{:?}\n", result)),
+            Err(err) => out.push_str(&format!("Error: {}\n", err)),
+        }
+        for warning in session.warnings() {
+            out.push_str(&format!("Warning: {}\n", warning));
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+// the comments and the statements of the script, each statement ends at a ; outside a string
+// a statement over several lines is put on one
+fn split(script: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut stmt = String::new();
+    let mut quoted = false;
+    for line in script.lines().map(str::trim) {
+        if stmt.trim().is_empty() && line.starts_with("--") {
+            items.push(line.to_string());
+            continue;
+        }
+        for ch in line.chars() {
+            stmt.push(ch);
+            match ch {
+                '\'' => quoted = !quoted,
+                ';' if !quoted => items.push(std::mem::take(&mut stmt).trim().to_string()),
+                _ => {}
+            }
+        }
+        stmt.push(' ');
+    }
+    if !stmt.trim().is_empty() {
+        items.push(stmt.trim().to_string());
+    }
+    items
+}
@@ -0,0 +1,20 @@
+create table sales (id int not null, region text, amount float, qty int);
+insert into sales values (1, 'north', 10.0, 1), (2, 'south', 20.5, 2), (3, 'north', 5.0, null), (4, 'east', null, 4), (5, 'south', 1.5, 5);
+
+select count(*), count(amount), sum(amount), min(qty), max(qty), avg(amount) from sales;
+explain select count(*), count(amount), sum(amount), min(qty), max(qty), avg(amount) from sales;
+
+select region, count(*), sum(amount * qty) as total from sales group by region order by total desc;
+explain select region, count(*), sum(amount * qty) as total from sales group by region order by total desc;
+
+select region, count(*) filter (where qty > 1) as big from sales group by 1 order by region;
+explain select region, count(*) filter (where qty > 1) as big from sales group by 1 order by region;
+
+-- no rows is one group without GROUP BY
+select count(*), sum(amount) from sales where id > 10;
+
+-- errors
+select region, count(*) from sales;
+select * from sales group by region;
+select sum(count(*)) from sales;
+select id from sales where sum(qty) > 1;
@@ -0,0 +1,43 @@
+create table t (id int not null, name text default 'none', score float not null default 0.0, active bool);
+explain create table u (a int);
+explain insert into t (id) values (1), (2);
+
+insert into t values (1, 'a', 1.0, true);
+insert into t (id, active) values (2, false), (3, null);
+insert into t (id, score) values (4, 7);
+-- an integer a float can not hold exactly is rounded
+insert into t (id, score) values (8, 9007199254740993);
+-- missing columns at the end get their defaults
+insert into t values (7, 'y');
+select * from t;
+
+-- errors leave the table as it was
+insert into t values (1, 'dup', 0.0, true);
+insert into t (id, name) values (5, 'e'), (5, 'f');
+insert into t (name) values ('no id');
+insert into t values (6, 'x', 'not a float', true);
+insert into t (missing) values (1);
+select count(*) from t;
+show tables;
+
+-- added columns read as their default in the rows stored before them
+alter table t add column rank int not null default 0;
+explain alter table t add note text;
+insert into t (id, rank) values (9, 2);
+select id, name, rank from t where id > 3 order by id;
+alter table t add column bad int not null;
+
+-- an enum column holds one of its labels, CHECK (... IN ...) on a string column is the same
+create table tickets (id int not null, status enum('new', 'open', 'done') not null default 'new', kind string check (kind in ('bug', 'feature')));
+insert into tickets (id, kind) values (1, 'bug');
+insert into tickets values (2, 'done', 'feature'), (3, 'open', null);
+insert into tickets values (4, 'lost', 'bug');
+insert into tickets values (4, 'new', 'Bug');
+select * from tickets where status != 'new' order by status;
+select status, count(*) from tickets group by status;
+
+create table t (a int);
+explain drop table t;
+drop table t;
+select * from t;
+drop table t;
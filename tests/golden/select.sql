@@ -0,0 +1,36 @@
+-- scans, filters, order and limit
+create table t (a int not null, b text, c float, v vector(2));
+insert into t values (1, 'one', 1.5, [1, 0]), (2, 'two', -2.0, [0, 1]), (3, null, null, [1, 1]), (4, 'four', 0.5, null);
+
+select * from t;
+explain select * from t;
+
+select a, b from t where a > 1 order by a desc limit 2;
+explain select a, b from t where a > 1 order by a desc limit 2;
+
+-- expressions and aliases
+select a + 1 as next, a * 2 - c / 2, upper(b), length(b) from t where b != 'two';
+explain select a + 1 as next, a * 2 - c / 2, upper(b), length(b) from t where b != 'two';
+
+select a from t where (a, c) > (1, 0.0) order by 1;
+select a from t where b in ('one', 'four') order by a;
+explain select a from t where b in ('one', 'four') order by a;
+
+-- nulls sort last, and compare as unknown
+select a, c from t order by c;
+select a from t where c = null;
+
+-- vector distance
+select a, v <-> [0, 0] as dist from t where v <-> [1, 0] <= 1 order by dist limit 2;
+explain select a, v <-> [0, 0] as dist from t where v <-> [1, 0] <= 1 order by dist limit 2;
+
+-- max_result_rows leaves rows out with a warning
+set max_result_rows = 2;
+select a from t order by a;
+set max_result_rows = default;
+set no_such_variable = 1;
+
+-- errors
+select d from t;
+select * from missing;
+select a from t limit -1;